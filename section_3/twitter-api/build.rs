@@ -1,5 +1,35 @@
+use std::process::Command;
+
 // generated by `sqlx migrate build-script`
 fn main() {
     // trigger recompilation when a new migration is added
     println!("cargo:rerun-if-changed=migrations");
-}
\ No newline at end of file
+    println!("cargo:rerun-if-changed=migrations_sqlite");
+    // and when `cargo sqlx prepare` refreshes the query metadata the repo macros check against
+    println!("cargo:rerun-if-changed=sqlx-data.json");
+
+    // commit reported by /version, docker builds have no .git so they pass GIT_COMMIT in
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    let commit = std::env::var("GIT_COMMIT").ok()
+        .filter(|commit| !commit.trim().is_empty())
+        .or_else(|| git(&["rev-parse", "--short", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_COMMIT={}", commit.trim());
+
+    // rebuild when HEAD moves so the commit does not go stale
+    for path in ["HEAD", "logs/HEAD"] {
+        if let Some(git_path) = git(&["rev-parse", "--git-path", path]) {
+            if std::path::Path::new(git_path.trim()).exists() {
+                println!("cargo:rerun-if-changed={}", git_path.trim());
+            }
+        }
+    }
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
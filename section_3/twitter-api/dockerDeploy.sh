@@ -1,4 +1,19 @@
 #!/bin/bash
 
 # docker compose down --rmi 'all' --remove-orphans
-docker compose up -d --build
\ No newline at end of file
+export GIT_COMMIT=$(git rev-parse --short HEAD 2>/dev/null || echo unknown)
+docker compose up -d --build || exit 1
+
+# only report success once the server is ready to take traffic
+READY_URL="http://localhost:4001/health/ready"
+for attempt in $(seq 1 30); do
+    if wget -qO- "$READY_URL" > /dev/null 2>&1; then
+        echo "server is ready: $(wget -qO- http://localhost:4001/version)"
+        exit 0
+    fi
+    echo "waiting for server to become ready ($attempt/30)"
+    sleep 2
+done
+
+echo "server did not become ready, see docker compose logs server"
+exit 1
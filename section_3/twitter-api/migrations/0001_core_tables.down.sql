@@ -0,0 +1,5 @@
+drop table message_broadcast;
+drop table message_response;
+drop table message;
+drop table follow;
+drop table profile;
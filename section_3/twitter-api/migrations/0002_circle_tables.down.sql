@@ -0,0 +1,2 @@
+drop table circle_group_member;
+drop table circle_group;
@@ -0,0 +1,10 @@
+-- fails if a value written since the up migration is longer than the old limit
+alter table profile
+    alter column "user_name" type varchar(50),
+    alter column "full_name" type varchar(100),
+    alter column "description" type varchar(250),
+    alter column "region" type varchar(50),
+    alter column "main_url" type varchar(250);
+
+alter table message
+    alter column "body" type varchar(140);
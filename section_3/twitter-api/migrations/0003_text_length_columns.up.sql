@@ -0,0 +1,12 @@
+-- the api limits text in user-perceived characters, which postgres cannot count. Each of those is at
+-- most MAX_CODE_POINTS_PER_CHAR (32) code points, see validator.rs, so a column holds its api limit
+-- times 32 code points and every value the api accepts fits
+alter table profile
+    alter column "user_name" type varchar(1600),
+    alter column "full_name" type varchar(3200),
+    alter column "description" type varchar(8000),
+    alter column "region" type varchar(1600),
+    alter column "main_url" type varchar(8000);
+
+alter table message
+    alter column "body" type varchar(4480);
@@ -0,0 +1,32 @@
+-- each follower's home timeline, written when a message is inserted (fan-out on write).
+-- message_updated_at is copied from the message so a page is read from the index alone
+create table timeline_entry (
+    "id" bigserial primary key,
+    "created_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "owner_id" bigint NOT NULL,
+    "message_id" bigint NOT NULL,
+    "message_updated_at" timestamptz(3) NOT NULL,
+
+    constraint fk_profile foreign key(owner_id) references profile(id),
+    constraint fk_message foreign key(message_id) references message(id),
+    constraint uq_timeline_entry unique (owner_id, message_id)
+);
+
+create index ix_timeline_entry_page on timeline_entry (owner_id, message_updated_at desc, message_id desc);
+-- fan-out joins and the celebrity fallback, which reads followed authors' messages directly
+create index ix_follow_following on follow (following_id);
+create index ix_message_user on message (user_id, updated_at desc);
+
+-- compared against the celebrity threshold on every write and read, kept in step by follow_user
+alter table profile add column "follower_count" bigint NOT NULL DEFAULT 0;
+
+update profile set "follower_count" = (select count(*) from follow f where f.following_id = profile.id);
+
+-- existing follows start with a full timeline. The threshold is config rather than schema, so every
+-- followed author is copied; a celebrity's entries only duplicate the join that reads them, and
+-- `timeline rebuild` drops them
+insert into timeline_entry (owner_id, message_id, message_updated_at)
+    select f.follower_id, m.id, m.updated_at
+        from follow f
+            join message m on m.user_id = f.following_id
+on conflict (owner_id, message_id) do nothing;
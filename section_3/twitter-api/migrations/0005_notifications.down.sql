@@ -0,0 +1,4 @@
+drop index ix_notification_unread;
+drop index ix_notification_recipient;
+drop table notification;
+drop table message_like;
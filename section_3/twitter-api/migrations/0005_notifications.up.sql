@@ -0,0 +1,33 @@
+-- one row per profile liking a message, message.likes counts them
+create table message_like (
+    "id" bigserial primary key,
+    "created_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "user_id" bigint NOT NULL,
+    "message_id" bigint NOT NULL,
+
+    constraint fk_profile foreign key(user_id) references profile(id),
+    constraint fk_message foreign key(message_id) references message(id),
+    constraint uq_message_like unique (user_id, message_id)
+);
+
+-- activity about a profile, written in the transaction of the follow, message or like that caused it.
+-- target_msg_id is the message the activity is about, null for follows, and source_msg_id the reply,
+-- broadcast or mention that caused it. Notifications are listed grouped by kind and target
+create table notification (
+    "id" bigserial primary key,
+    "created_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "recipient_id" bigint NOT NULL,
+    "actor_id" bigint NOT NULL,
+    "kind" int NOT NULL,
+    "target_msg_id" bigint,
+    "source_msg_id" bigint,
+    "read_at" timestamptz(3),
+
+    constraint fk_profile_recipient foreign key(recipient_id) references profile(id),
+    constraint fk_profile_actor foreign key(actor_id) references profile(id),
+    constraint fk_target_message foreign key(target_msg_id) references message(id),
+    constraint fk_source_message foreign key(source_msg_id) references message(id)
+);
+
+create index ix_notification_recipient on notification (recipient_id, id desc);
+create index ix_notification_unread on notification (recipient_id) where read_at is null;
@@ -0,0 +1,39 @@
+-- an integration's endpoint, sent a signed POST for every event of the types it subscribed to.
+-- event_types is a bit set of WebhookEventType values
+create table webhook_subscription (
+    "id" bigserial primary key,
+    "created_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "user_id" bigint NOT NULL,
+    "url" varchar(2048) NOT NULL,
+    "secret" varchar(256) NOT NULL,
+    "event_types" int NOT NULL,
+
+    constraint fk_profile foreign key(user_id) references profile(id)
+);
+
+create index ix_webhook_subscription_user on webhook_subscription (user_id);
+
+-- one event sent to one subscription, attempted until it succeeds or runs out of attempts.
+-- event_key is the event's type and id. The write that causes an event inserts its deliveries in the
+-- same transaction, the unique constraint keeps an event from being delivered twice. Replays have no event_key.
+-- A pending delivery is due at next_attempt_at, which the dispatcher moves forward while it sends it
+create table webhook_delivery (
+    "id" bigserial primary key,
+    "created_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "subscription_id" bigint NOT NULL,
+    "event_type" int NOT NULL,
+    "event_key" varchar(100),
+    "payload" text NOT NULL,
+    "status" int NOT NULL,
+    "attempt_count" int NOT NULL DEFAULT 0,
+    "next_attempt_at" timestamptz(3),
+    "last_attempt_at" timestamptz(3),
+    "last_status_code" int,
+    "last_error" varchar(1000),
+
+    constraint fk_webhook_subscription foreign key(subscription_id) references webhook_subscription(id),
+    constraint uq_webhook_delivery_event unique (subscription_id, event_key)
+);
+
+create index ix_webhook_delivery_subscription on webhook_delivery (subscription_id, id desc);
+create index ix_webhook_delivery_due on webhook_delivery (next_attempt_at) where status = 1;
@@ -0,0 +1,59 @@
+-- mirrors the postgres 0001 migration, timestamps are iso 8601 text in utc with milliseconds
+-- like timestamptz(3), which sorts the same as the instants it stores
+create table profile (
+    "id" integer primary key autoincrement,
+    "created_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "updated_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "user_name" varchar(50) NOT NULL,
+    "full_name" varchar(100) NOT NULL,
+    "description" varchar(250) NOT NULL,
+    "region" varchar(50),
+    "main_url" varchar(250),
+    "avatar" blob
+);
+
+create table follow (
+    "id" integer primary key autoincrement,
+    "created_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "updated_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "follower_id" integer NOT NULL,
+    "following_id" integer NOT NULL,
+
+    constraint fk_profile_follower foreign key(follower_id) references profile(id),
+    constraint fk_profile_following foreign key(following_id) references profile(id)
+);
+
+create table message (
+    "id" integer primary key autoincrement,
+    "created_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "updated_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "user_id" integer NOT NULL,
+    "body"  varchar(140),
+    "likes" integer NOT NULL DEFAULT 0,
+    "image" blob,
+    "msg_group_type" integer,
+
+    constraint fk_profile foreign key(user_id) references profile(id)
+);
+
+create table message_response (
+    "id" integer primary key autoincrement,
+    "created_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "updated_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "original_msg_id" integer NOT NULL,
+    "responding_msg_id" integer NOT NULL,
+
+    constraint fk_original_message foreign key(original_msg_id) references message(id),
+    constraint fk_responding_message foreign key(responding_msg_id) references message(id)
+);
+
+create table message_broadcast (
+    "id" integer primary key autoincrement,
+    "created_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "updated_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "main_msg_id" integer NOT NULL,
+    "broadcasting_msg_id" integer NOT NULL,
+
+    constraint fk_original_message foreign key(main_msg_id) references message(id),
+    constraint fk_broadcasting_message foreign key(broadcasting_msg_id) references message(id)
+);
@@ -0,0 +1,19 @@
+create table circle_group (
+    "id" integer primary key autoincrement,
+    "created_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "updated_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "owner_id" integer NOT NULL,
+
+    constraint fk_profile foreign key(owner_id) references profile(id)
+);
+
+create table circle_group_member (
+    "id" integer primary key autoincrement,
+    "created_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "updated_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "circle_group_id" integer NOT NULL,
+    "member_id" integer NOT NULL,
+
+    constraint fk_circle_group foreign key(circle_group_id) references circle_group(id),
+    constraint fk_profile foreign key(member_id) references profile(id)
+);
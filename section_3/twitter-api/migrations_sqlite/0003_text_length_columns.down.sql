@@ -0,0 +1,4 @@
+drop trigger ck_message_body_length_update;
+drop trigger ck_message_body_length_insert;
+drop trigger ck_profile_length_update;
+drop trigger ck_profile_length_insert;
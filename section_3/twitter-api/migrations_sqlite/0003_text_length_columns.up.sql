@@ -0,0 +1,28 @@
+-- the limits of the postgres 0003 migration, the api limit times MAX_CODE_POINTS_PER_CHAR (32) code points.
+-- sqlite never enforces varchar lengths and cannot add check constraints to existing tables, so triggers
+-- enforce them. length counts code points for text, like postgres varchar
+create trigger ck_profile_length_insert before insert on profile
+    when length(new."user_name") > 1600 or length(new."full_name") > 3200 or length(new."description") > 8000
+        or length(new."region") > 1600 or length(new."main_url") > 8000
+begin
+    select raise(abort, 'profile text exceeds its length limit');
+end;
+
+create trigger ck_profile_length_update before update on profile
+    when length(new."user_name") > 1600 or length(new."full_name") > 3200 or length(new."description") > 8000
+        or length(new."region") > 1600 or length(new."main_url") > 8000
+begin
+    select raise(abort, 'profile text exceeds its length limit');
+end;
+
+create trigger ck_message_body_length_insert before insert on message
+    when length(new."body") > 4480
+begin
+    select raise(abort, 'message body exceeds its length limit');
+end;
+
+create trigger ck_message_body_length_update before update on message
+    when length(new."body") > 4480
+begin
+    select raise(abort, 'message body exceeds its length limit');
+end;
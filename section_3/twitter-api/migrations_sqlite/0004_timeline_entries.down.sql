@@ -0,0 +1,4 @@
+alter table profile drop column "follower_count";
+drop index ix_message_user;
+drop index ix_follow_following;
+drop table timeline_entry;
@@ -0,0 +1,31 @@
+-- one row per profile liking a message, message.likes counts them
+create table message_like (
+    "id" integer primary key autoincrement,
+    "created_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "user_id" integer NOT NULL,
+    "message_id" integer NOT NULL,
+
+    constraint fk_profile foreign key(user_id) references profile(id),
+    constraint fk_message foreign key(message_id) references message(id),
+    constraint uq_message_like unique (user_id, message_id)
+);
+
+-- activity about a profile, see the postgres 0005 migration
+create table notification (
+    "id" integer primary key autoincrement,
+    "created_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "recipient_id" integer NOT NULL,
+    "actor_id" integer NOT NULL,
+    "kind" integer NOT NULL,
+    "target_msg_id" integer,
+    "source_msg_id" integer,
+    "read_at" text,
+
+    constraint fk_profile_recipient foreign key(recipient_id) references profile(id),
+    constraint fk_profile_actor foreign key(actor_id) references profile(id),
+    constraint fk_target_message foreign key(target_msg_id) references message(id),
+    constraint fk_source_message foreign key(source_msg_id) references message(id)
+);
+
+create index ix_notification_recipient on notification (recipient_id, id desc);
+create index ix_notification_unread on notification (recipient_id) where read_at is null;
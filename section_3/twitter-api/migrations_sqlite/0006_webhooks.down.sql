@@ -0,0 +1,5 @@
+drop index ix_webhook_delivery_due;
+drop index ix_webhook_delivery_subscription;
+drop table webhook_delivery;
+drop index ix_webhook_subscription_user;
+drop table webhook_subscription;
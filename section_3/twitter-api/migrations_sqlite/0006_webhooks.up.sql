@@ -0,0 +1,34 @@
+-- an integration's endpoint and the events sent to it, see the postgres 0006 migration
+create table webhook_subscription (
+    "id" integer primary key autoincrement,
+    "created_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "user_id" integer NOT NULL,
+    "url" text NOT NULL,
+    "secret" text NOT NULL,
+    "event_types" integer NOT NULL,
+
+    constraint fk_profile foreign key(user_id) references profile(id)
+);
+
+create index ix_webhook_subscription_user on webhook_subscription (user_id);
+
+create table webhook_delivery (
+    "id" integer primary key autoincrement,
+    "created_at" text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    "subscription_id" integer NOT NULL,
+    "event_type" integer NOT NULL,
+    "event_key" text,
+    "payload" text NOT NULL,
+    "status" integer NOT NULL,
+    "attempt_count" integer NOT NULL DEFAULT 0,
+    "next_attempt_at" text,
+    "last_attempt_at" text,
+    "last_status_code" integer,
+    "last_error" text,
+
+    constraint fk_webhook_subscription foreign key(subscription_id) references webhook_subscription(id),
+    constraint uq_webhook_delivery_event unique (subscription_id, event_key)
+);
+
+create index ix_webhook_delivery_subscription on webhook_delivery (subscription_id, id desc);
+create index ix_webhook_delivery_due on webhook_delivery (next_attempt_at) where status = 1;
@@ -0,0 +1,154 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ ServiceFactory, ServiceRequest, ServiceResponse },
+    http::Method,
+    middleware::{ Compress, Condition, Logger },
+    web, App, Error, FromRequest, Handler, Responder, Route,
+};
+use crate::{
+    common::{
+        app_state::AppState,
+        auth::Authenticator,
+        config::Config,
+        entities::base::Repository,
+        metrics::RequestMetrics,
+        rate_limit::RateLimiter,
+        security::{ get_cors, get_docs_security_headers, get_security_headers, BodyLimit },
+        shutdown::InFlightRequests,
+        telemetry::RequestTracing,
+    },
+    get_root,
+    routes::{
+        health::health_route::{ get_live, get_ready, get_version },
+        messages::message_route::{ create_message, get_message, get_messages, like_message },
+        metrics::metrics_route::get_metrics,
+        notifications::notification_route::{
+            get_notifications, get_unread_notification_count, mark_notification_group_read, mark_notifications_read,
+        },
+        openapi::openapi_route::{ get_docs_service, get_openapi, DOCS_PATH, OPENAPI_PATH },
+        profiles::{ model::MultipartLimits, profile_route::{ create_profile, get_profile, get_profile_by_user } },
+        streams::{ public_stream_route::get_public_stream, stream_route::get_stream },
+        webhooks::webhook_route::{ create_webhook, get_webhook_deliveries, get_webhooks, replay_webhook_delivery },
+    },
+};
+
+/// middleware state shared by every worker, so it is created once per server rather than per app
+#[derive(Clone)]
+pub struct SharedMiddleware {
+    pub rate_limiter: RateLimiter,
+    pub in_flight: InFlightRequests,
+}
+
+impl SharedMiddleware {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            rate_limiter: RateLimiter::new(&config.rate_limit, Authenticator::new(&config.auth)),
+            in_flight: InFlightRequests::new(),
+        }
+    }
+}
+
+/// a handler bound to a path and method, listed by `get_routes` so the routing table can be
+/// inspected, the openapi test checks every entry is documented
+pub struct RouteEntry {
+    pub method: Method,
+    pub path: &'static str,
+    route: Route,
+}
+
+impl RouteEntry {
+    fn new<F, Args>(method: Method, path: &'static str, handler: F) -> Self
+        where F: Handler<Args>, Args: FromRequest + 'static, F::Output: Responder + 'static
+    {
+        Self { route: web::route().method(method.clone()).to(handler), method, path }
+    }
+}
+
+/// every handler the server exposes, the only place handlers are bound to paths
+pub fn get_routes<T: Repository>() -> Vec<RouteEntry> {
+    vec![
+        RouteEntry::new(Method::GET, "/", get_root),
+        RouteEntry::new(Method::GET, "/health/live", get_live),
+        RouteEntry::new(Method::GET, "/health/ready", get_ready::<T>),
+        RouteEntry::new(Method::GET, "/version", get_version),
+        RouteEntry::new(Method::GET, "/metrics", get_metrics::<T>),
+        RouteEntry::new(Method::GET, OPENAPI_PATH, get_openapi),
+        RouteEntry::new(Method::GET, "/v1/msg/{id}", get_message::<T>),
+        RouteEntry::new(Method::POST, "/v1/msg/{id}/like", like_message::<T>),
+        RouteEntry::new(Method::POST, "/v1/msg", create_message::<T>),
+        RouteEntry::new(Method::POST, "/v1/msgs", get_messages::<T>),
+        RouteEntry::new(Method::GET, "/v1/profile/{id}", get_profile::<T>),
+        RouteEntry::new(Method::GET, "/v1/profile/username/{user_name}", get_profile_by_user::<T>),
+        RouteEntry::new(Method::POST, "/v1/profile", create_profile::<T>),
+        RouteEntry::new(Method::GET, "/v1/stream", get_stream::<T>),
+        RouteEntry::new(Method::GET, "/v1/stream/public", get_public_stream::<T>),
+        RouteEntry::new(Method::GET, "/v1/notifications", get_notifications::<T>),
+        RouteEntry::new(Method::GET, "/v1/notifications/unread", get_unread_notification_count::<T>),
+        RouteEntry::new(Method::POST, "/v1/notifications/read", mark_notifications_read::<T>),
+        RouteEntry::new(Method::POST, "/v1/notifications/read/group", mark_notification_group_read::<T>),
+        RouteEntry::new(Method::GET, "/v1/webhooks", get_webhooks::<T>),
+        RouteEntry::new(Method::POST, "/v1/webhooks", create_webhook::<T>),
+        RouteEntry::new(Method::GET, "/v1/webhooks/{id}/deliveries", get_webhook_deliveries::<T>),
+        RouteEntry::new(Method::POST, "/v1/webhooks/{id}/deliveries/{delivery_id}/replay", replay_webhook_delivery::<T>)
+    ]
+}
+
+/// binds `get_routes` to the app, methods sharing a path go on one resource so an unhandled
+/// method answers 405 rather than falling through
+pub fn configure_routes<T: Repository>(cfg: &mut web::ServiceConfig) {
+    // registered before the api, a matching scope does not fall through to later services
+    cfg.service(web::scope(DOCS_PATH).wrap(get_docs_security_headers()).service(get_docs_service()));
+
+    let mut resources: Vec<(&'static str, Vec<Route>)> = vec![];
+    for entry in get_routes::<T>() {
+        match resources.iter_mut().find(|(path, _)| *path == entry.path) {
+            Some((_, routes)) => routes.push(entry.route),
+            None => resources.push((entry.path, vec![entry.route])),
+        }
+    }
+    for (path, routes) in resources {
+        cfg.service(routes.into_iter().fold(web::resource(path), |resource, route| resource.route(route)));
+    }
+}
+
+/// extractor limits and the authenticator derived from config, registered next to the app state
+fn configure_app_data<T: Repository>(app_data: web::Data<AppState<T>>) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        let upload = &app_data.config.upload;
+        cfg
+            .app_data(web::JsonConfig::default().limit(upload.max_json_bytes))
+            .app_data(MultipartLimits { max_field_bytes: upload.max_field_bytes, max_avatar_bytes: upload.max_avatar_bytes })
+            .app_data(Authenticator::new(&app_data.config.auth))
+            .app_data(app_data);
+    }
+}
+
+/// the app each worker runs, also used by the integration tests so they exercise the production
+/// routing, middleware and app data. The last `wrap` is the outermost middleware
+pub fn create_app<T: Repository>(
+    app_data: web::Data<AppState<T>>,
+    shared: SharedMiddleware
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = Error,
+        InitError = ()
+    >
+> {
+    let config = app_data.config.clone();
+
+    App::new()
+        .wrap(Compress::default())
+        .wrap(BodyLimit(config.upload.max_body_bytes))
+        .wrap(shared.rate_limiter)
+        .wrap(get_cors(&config.cors))
+        .wrap(get_security_headers(&config.security))
+        .wrap(Condition::new(config.features.request_logging, Logger::default()))
+        .wrap(RequestMetrics)
+        .wrap(RequestTracing)
+        .wrap(shared.in_flight)
+        .configure(configure_app_data(app_data))
+        .configure(configure_routes::<T>)
+}
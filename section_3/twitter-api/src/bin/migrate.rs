@@ -0,0 +1,135 @@
+use std::process::exit;
+use sqlx::{ migrate::{ Migrate, Migrator }, Database, Pool };
+use twitter_clone_api::common::{
+    config::{ Config, RepositoryBackend },
+    entities::{ base::connect_with_retry, sqlite::connect_sqlite },
+    migration::{ MIGRATOR, SQLITE_MIGRATOR, get_migration_statuses, ensure_schema_current, get_expected_version },
+};
+
+const USAGE: &str = "usage: migrate <up | status | rollback [target_version]>
+  up        apply every pending migration
+  status    list migrations and whether each one is applied
+  rollback  revert the latest migration, or every migration newer than target_version
+the database is the one REPOSITORY_BACKEND selects, postgres or sqlite";
+
+enum Command {
+    Up,
+    Status,
+    Rollback(Option<i64>),
+}
+
+#[actix_web::main]
+async fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let command = match args.first().map(|arg| arg.as_str()) {
+        Some("up") => Command::Up,
+        Some("status") => Command::Status,
+        Some("rollback") => match args.get(1).map(|arg| arg.parse::<i64>()) {
+            Some(Ok(target)) => Command::Rollback(Some(target)),
+            Some(Err(_)) => usage(),
+            None => Command::Rollback(None),
+        },
+        _ => usage(),
+    };
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            exit(1);
+        }
+    };
+
+    let result = match config.repository.backend {
+        RepositoryBackend::Postgres => {
+            let conn = connect_or_exit(connect_with_retry(&config.postgres).await, "postgres");
+            run(&MIGRATOR, &conn, command).await
+        }
+        RepositoryBackend::Sqlite => {
+            let conn = connect_or_exit(connect_sqlite(&config.sqlite).await, "sqlite");
+            run(&SQLITE_MIGRATOR, &conn, command).await
+        }
+        RepositoryBackend::Memory => {
+            eprintln!("The memory backend has no schema to migrate");
+            exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("Migration failed: {}", e);
+        exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(2);
+}
+
+fn connect_or_exit<DB: Database>(conn: Result<Pool<DB>, sqlx::Error>, name: &str) -> Pool<DB> {
+    match conn {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Unable to connect to {}: {}", name, e);
+            exit(1);
+        }
+    }
+}
+
+async fn run<DB>(migrator: &Migrator, conn: &Pool<DB>, command: Command) -> Result<(), sqlx::Error>
+where DB: Database, DB::Connection: Migrate {
+    match command {
+        Command::Up => up(migrator, conn).await,
+        Command::Status => status(migrator, conn).await,
+        Command::Rollback(target) => rollback(migrator, conn, target).await,
+    }
+}
+
+async fn up<DB>(migrator: &Migrator, conn: &Pool<DB>) -> Result<(), sqlx::Error>
+where DB: Database, DB::Connection: Migrate {
+    migrator.run(conn).await?;
+    let version = ensure_schema_current(migrator, conn).await?;
+    println!("Schema is up to date at version {}", version);
+    Ok(())
+}
+
+async fn status<DB>(migrator: &Migrator, conn: &Pool<DB>) -> Result<(), sqlx::Error>
+where DB: Database, DB::Connection: Migrate {
+    for migration in get_migration_statuses(migrator, conn).await? {
+        let state = if migration.applied { "applied" } else { "pending" };
+        println!("{:>4} {:<8} {}", migration.version, state, migration.description);
+    }
+
+    match ensure_schema_current(migrator, conn).await {
+        Ok(version) => println!("Schema matches this build at version {}", version),
+        Err(e) => println!("Schema does not match this build (expects {}): {}", get_expected_version(), e),
+    }
+    Ok(())
+}
+
+async fn rollback<DB>(migrator: &Migrator, conn: &Pool<DB>, target: Option<i64>) -> Result<(), sqlx::Error>
+where DB: Database, DB::Connection: Migrate {
+    let target = match target {
+        Some(target) => target,
+        None => {
+            // default to undoing only the most recently applied migration
+            let applied = get_migration_statuses(migrator, conn).await?
+                .into_iter()
+                .filter(|migration| migration.applied)
+                .map(|migration| migration.version)
+                .collect::<Vec<i64>>();
+            match applied.len() {
+                0 => {
+                    println!("Nothing to roll back");
+                    return Ok(());
+                }
+                1 => 0,
+                len => applied[len - 2],
+            }
+        }
+    };
+
+    migrator.undo(conn, target).await?;
+    println!("Rolled back to version {}", target);
+    Ok(())
+}
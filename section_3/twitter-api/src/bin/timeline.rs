@@ -0,0 +1,68 @@
+use std::process::exit;
+use twitter_clone_api::common::{
+    config::{ Config, RepositoryBackend },
+    entities::{ app_repo::AppRepo, timeline::repo::RebuildTimelinesFn },
+};
+
+const USAGE: &str = "usage: timeline rebuild [user_id]
+  rebuild   rewrite the home timeline of user_id, or of every profile, from follows and messages.
+            Run after a backfill or after changing TIMELINE_CELEBRITY_FOLLOWER_THRESHOLD
+the database is the one REPOSITORY_BACKEND selects, postgres or sqlite";
+
+enum Command {
+    Rebuild(Option<i64>),
+}
+
+#[actix_web::main]
+async fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let command = match args.first().map(|arg| arg.as_str()) {
+        Some("rebuild") => match args.get(1).map(|arg| arg.parse::<i64>()) {
+            Some(Ok(user_id)) => Command::Rebuild(Some(user_id)),
+            Some(Err(_)) => usage(),
+            None => Command::Rebuild(None),
+        },
+        _ => usage(),
+    };
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            exit(1);
+        }
+    };
+    if config.repository.backend == RepositoryBackend::Memory {
+        eprintln!("The memory backend assembles timelines when read, there is nothing to rebuild");
+        exit(2);
+    }
+
+    let db_repo = match AppRepo::init(&config).await {
+        Ok(db_repo) => db_repo,
+        Err(e) => {
+            eprintln!("Unable to initialize database: {}", e);
+            exit(1);
+        }
+    };
+
+    let result = match command {
+        Command::Rebuild(owner_id) => db_repo.rebuild_timelines(owner_id).await,
+    };
+    db_repo.close().await;
+
+    match result {
+        Ok(written) => match command {
+            Command::Rebuild(Some(user_id)) => println!("Rebuilt the timeline of {} with {} entries", user_id, written),
+            Command::Rebuild(None) => println!("Rebuilt every timeline with {} entries", written),
+        },
+        Err(e) => {
+            eprintln!("Rebuild failed: {}", e);
+            exit(1);
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(2);
+}
@@ -0,0 +1,59 @@
+use chrono::Utc;
+use std::process::exit;
+use twitter_clone_api::common::{
+    auth::Authenticator,
+    config::{ Config, RepositoryBackend },
+    entities::{ app_repo::AppRepo, profiles::repo::QueryProfileFn },
+};
+
+const USAGE: &str = "usage: token issue <profile_id>
+  issue   print a bearer token acting as the profile, valid for AUTH_TOKEN_TTL_SECS.
+          Clients send it as Authorization: Bearer <token> to the stream, notification and webhook routes
+the profile is looked up in the database REPOSITORY_BACKEND selects, the memory backend has none to check";
+
+#[actix_web::main]
+async fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let profile_id = match (args.first().map(|arg| arg.as_str()), args.get(1).map(|arg| arg.parse::<i64>())) {
+        (Some("issue"), Some(Ok(profile_id))) => profile_id,
+        _ => usage(),
+    };
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            exit(1);
+        }
+    };
+
+    if config.repository.backend != RepositoryBackend::Memory {
+        let db_repo = match AppRepo::init(&config).await {
+            Ok(db_repo) => db_repo,
+            Err(e) => {
+                eprintln!("Unable to initialize database: {}", e);
+                exit(1);
+            }
+        };
+        let profile = db_repo.query_profile(profile_id).await;
+        db_repo.close().await;
+        match profile {
+            Ok(Some(_)) => (),
+            Ok(None) => {
+                eprintln!("No profile with id {}", profile_id);
+                exit(1);
+            }
+            Err(e) => {
+                eprintln!("Unable to look up the profile: {}", e);
+                exit(1);
+            }
+        }
+    }
+
+    println!("{}", Authenticator::new(&config.auth).issue(profile_id, Utc::now()));
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(2);
+}
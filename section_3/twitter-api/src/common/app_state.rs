@@ -1,4 +1,7 @@
+use crate::common::config::Config;
+
 pub struct AppState<T> {
     pub client: reqwest::Client,
+    pub config: Config,
     pub db_repo: T,
 }
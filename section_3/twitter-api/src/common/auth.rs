@@ -0,0 +1,131 @@
+use actix_web::{ dev::Payload, http::header::{ HeaderMap, AUTHORIZATION }, FromRequest, HttpRequest };
+use chrono::{ DateTime, Utc };
+use hmac::{ Hmac, Mac };
+use sha2::Sha256;
+use std::{ future::{ ready, Ready }, sync::Arc };
+use tracing::error;
+use crate::{ common::config::AuthConfig, routes::errors::error_utils::UserError };
+
+/// issues and checks the bearer tokens of the routes that act as a profile. A token is
+/// "{profile id}.{expiry in unix seconds}.{hex hmac-sha256 of the first two parts}" keyed with AUTH_SECRET,
+/// so every instance sharing the secret accepts it without a lookup. Clones share the secret
+#[derive(Clone)]
+pub struct Authenticator {
+    secret: Arc<[u8]>,
+    token_ttl_secs: i64,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Self {
+        Self {
+            secret: Arc::from(config.secret.as_bytes()),
+            token_ttl_secs: i64::try_from(config.token_ttl_secs).unwrap_or(i64::MAX),
+        }
+    }
+
+    /// a token acting as the profile until AUTH_TOKEN_TTL_SECS after now
+    pub fn issue(&self, profile_id: i64, now: DateTime<Utc>) -> String {
+        let claims = format!("{}.{}", profile_id, now.timestamp().saturating_add(self.token_ttl_secs));
+        let signature = hex::encode(self.get_mac(&claims).finalize().into_bytes());
+        format!("{}.{}", claims, signature)
+    }
+
+    /// the profile the token was issued for, None when it is malformed, signed with another secret or expired
+    pub fn verify(&self, token: &str, now: DateTime<Utc>) -> Option<i64> {
+        let (claims, signature) = token.rsplit_once('.')?;
+        self.get_mac(claims).verify_slice(&hex::decode(signature).ok()?).ok()?;
+
+        let (profile_id, expires_at) = claims.split_once('.')?;
+        if expires_at.parse::<i64>().ok()? <= now.timestamp() {
+            return None;
+        }
+        profile_id.parse().ok()
+    }
+
+    /// the profile of an Authorization: Bearer header, None without a valid token
+    pub fn authenticate(&self, headers: &HeaderMap) -> Option<i64> {
+        let (scheme, token) = headers.get(AUTHORIZATION)?.to_str().ok()?.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return None;
+        }
+        self.verify(token.trim(), Utc::now())
+    }
+
+    fn get_mac(&self, claims: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("hmac accepts keys of any length");
+        mac.update(claims.as_bytes());
+        mac
+    }
+}
+
+/// the profile a request acts as, from its bearer token. Handlers taking it answer 401 to requests without a valid one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AuthenticatedProfile {
+    pub profile_id: i64,
+}
+
+impl FromRequest for AuthenticatedProfile {
+    type Error = UserError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let Some(authenticator) = req.app_data::<Authenticator>() else {
+            error!("no Authenticator is registered with the app");
+            return ready(Err(UserError::InternalError));
+        };
+        ready(
+            authenticator
+                .authenticate(req.headers())
+                .map(|profile_id| AuthenticatedProfile { profile_id })
+                .ok_or(UserError::Unauthorized)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::HeaderValue;
+    use chrono::Duration;
+    use super::*;
+
+    fn get_authenticator(secret: &str) -> Authenticator {
+        Authenticator::new(&AuthConfig { secret: secret.to_string(), token_ttl_secs: 60 })
+    }
+
+    #[test]
+    fn test_token_is_accepted_until_it_expires() {
+        let authenticator = get_authenticator("a test secret that is at least 32 bytes");
+        let now = Utc::now();
+        let token = authenticator.issue(42, now);
+
+        assert_eq!(authenticator.verify(&token, now), Some(42));
+        assert_eq!(authenticator.verify(&token, now + Duration::seconds(59)), Some(42));
+        assert_eq!(authenticator.verify(&token, now + Duration::seconds(60)), None);
+    }
+
+    #[test]
+    fn test_forged_tokens_are_rejected() {
+        let authenticator = get_authenticator("a test secret that is at least 32 bytes");
+        let now = Utc::now();
+        let token = authenticator.issue(42, now);
+        let (_, signature) = token.split_once('.').unwrap();
+
+        assert_eq!(authenticator.verify(&format!("43.{}", signature), now), None);
+        assert_eq!(get_authenticator("another secret that is at least 32 bytes").verify(&token, now), None);
+        assert_eq!(authenticator.verify("42", now), None);
+        assert_eq!(authenticator.verify("", now), None);
+    }
+
+    #[test]
+    fn test_authenticate_reads_the_bearer_header() {
+        let authenticator = get_authenticator("a test secret that is at least 32 bytes");
+        let token = authenticator.issue(7, Utc::now());
+        let mut headers = HeaderMap::new();
+
+        assert_eq!(authenticator.authenticate(&headers), None);
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token)).unwrap());
+        assert_eq!(authenticator.authenticate(&headers), Some(7));
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Basic {}", token)).unwrap());
+        assert_eq!(authenticator.authenticate(&headers), None);
+    }
+}
@@ -0,0 +1,276 @@
+use async_trait::async_trait;
+use chrono::{ DateTime, Utc };
+use tokio::sync::broadcast;
+use crate::common::{
+    config::{ Config, RepositoryBackend },
+    entities::{
+        base::{ DbRepo, PoolStatsFn, QuerySchemaVersionFn, UnitOfWork },
+        circle_group::{
+            model::{ CircleGroupMemberWithProfileQueryResult, CircleGroupWithProfileQueryResult },
+            repo::{ InsertCircleFn, InsertCircleMemberFn, QueryCircleFn, QueryCircleMemberFn },
+        },
+        events::repo::SubscribeEventsFn,
+        memory::{ MemoryRepo, MemoryUnitOfWork },
+        sqlite::{ SqliteRepo, SqliteUnitOfWork },
+        messages::{
+            model::{ MessageCountsQueryResult, MessageStreamFilter, MessageWithFollowingAndBroadcastQueryResult },
+            repo::{
+                InsertMessageFn, InsertResponseMessageFn, LikeMessageFn, QueryMessageCountsFn, QueryMessageFn,
+                QueryMessagesAfterFn, QueryMessagesFn,
+            },
+        },
+        notifications::{
+            model::{ NotificationGroupQueryResult, NotificationKind },
+            repo::{
+                MarkNotificationGroupReadFn, MarkNotificationsReadFn, QueryNotificationGroupsFn,
+                QueryUnreadNotificationCountFn,
+            },
+        },
+        profiles::{
+            model::{ ProfileCreate, ProfileQueryResult },
+            repo::{
+                FollowUserFn, InsertProfileFn, QueryFollowingIdsFn, QueryProfileByUserFn, QueryProfileFn,
+                UpdateProfileAvatarFn,
+            },
+        },
+        timeline::repo::RebuildTimelinesFn,
+        webhooks::{
+            model::{
+                WebhookAttempt, WebhookDeliveryQueryResult, WebhookDueDelivery, WebhookSubscriptionCreate,
+                WebhookSubscriptionQueryResult,
+            },
+            repo::{
+                ClaimWebhookDeliveriesFn, InsertWebhookSubscriptionFn, QueryWebhookDeliveriesFn,
+                QueryWebhookSubscriptionsFn, RecordWebhookAttemptFn, ReplayWebhookDeliveryFn,
+            },
+        },
+    },
+    events::AppEvent,
+    metrics::PoolStats,
+};
+
+/// the repo the server runs on, chosen at startup by REPOSITORY_BACKEND.
+/// Routes stay generic over the repo traits, this is the one concrete type the app is built with
+#[derive(Clone)]
+pub enum AppRepo {
+    Postgres(DbRepo),
+    Sqlite(SqliteRepo),
+    Memory(MemoryRepo),
+}
+
+impl AppRepo {
+    pub async fn init(config: &Config) -> Result<Self, sqlx::Error> {
+        match config.repository.backend {
+            RepositoryBackend::Postgres => Ok(AppRepo::Postgres(DbRepo::init(config).await?)),
+            RepositoryBackend::Sqlite => Ok(AppRepo::Sqlite(SqliteRepo::init(config).await?)),
+            RepositoryBackend::Memory => Ok(AppRepo::Memory(MemoryRepo::new())),
+        }
+    }
+
+    /// closes the connection pool, memory has nothing to release
+    pub async fn close(&self) {
+        match self {
+            AppRepo::Postgres(repo) => repo.close().await,
+            AppRepo::Sqlite(repo) => repo.close().await,
+            AppRepo::Memory(_) => (),
+        }
+    }
+
+    /// opens a unit of work on the selected backend, see AppUnitOfWork
+    pub async fn begin(&self) -> Result<AppUnitOfWork, sqlx::Error> {
+        match self {
+            AppRepo::Postgres(repo) => Ok(AppUnitOfWork::Postgres(Box::new(repo.begin().await?))),
+            AppRepo::Sqlite(repo) => Ok(AppUnitOfWork::Sqlite(repo.begin().await?)),
+            AppRepo::Memory(repo) => Ok(AppUnitOfWork::Memory(repo.begin())),
+        }
+    }
+}
+
+/// several repo operations that commit or roll back together on whichever backend AppRepo runs on. It implements
+/// the write traits and the reads a write usually checks first, dropping it without committing rolls back
+pub enum AppUnitOfWork {
+    // a postgres transaction is several times the size of the others
+    Postgres(Box<UnitOfWork>),
+    Sqlite(SqliteUnitOfWork),
+    Memory(MemoryUnitOfWork),
+}
+
+impl AppUnitOfWork {
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        match self {
+            AppUnitOfWork::Postgres(uow) => (*uow).commit().await,
+            AppUnitOfWork::Sqlite(uow) => uow.commit().await,
+            AppUnitOfWork::Memory(uow) => uow.commit().await,
+        }
+    }
+
+    pub async fn rollback(self) -> Result<(), sqlx::Error> {
+        match self {
+            AppUnitOfWork::Postgres(uow) => (*uow).rollback().await,
+            AppUnitOfWork::Sqlite(uow) => uow.rollback().await,
+            AppUnitOfWork::Memory(uow) => uow.rollback().await,
+        }
+    }
+}
+
+/// implements a repo trait by forwarding each method to the selected backend, for AppRepo unless the types
+/// to implement it for are listed first
+macro_rules! impl_for_app_repo {
+    (@impl $type:ident, $trait:ident { $(async fn $name:ident(&self $(, $arg:ident: $arg_type:ty)*) -> $output:ty;)* }) => {
+        #[async_trait]
+        impl $trait for $type {
+            $(
+                async fn $name(&self $(, $arg: $arg_type)*) -> $output {
+                    match self {
+                        $type::Postgres(repo) => repo.$name($($arg),*).await,
+                        $type::Sqlite(repo) => repo.$name($($arg),*).await,
+                        $type::Memory(repo) => repo.$name($($arg),*).await,
+                    }
+                }
+            )*
+        }
+    };
+    ($($type:ident),+; $trait:ident $methods:tt) => {
+        $(impl_for_app_repo!(@impl $type, $trait $methods);)+
+    };
+    ($trait:ident $methods:tt) => {
+        impl_for_app_repo!(@impl AppRepo, $trait $methods);
+    };
+}
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; InsertMessageFn {
+    async fn insert_message(&self, user_id: i64, body: &str, group_type: i32, broadcasting_msg_id: Option<i64>) -> Result<i64, sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; InsertResponseMessageFn {
+    async fn insert_response_message(&self, user_id: i64, body: &str, group_type: i32, original_msg_id: i64) -> Result<i64, sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; QueryMessageFn {
+    async fn query_message(&self, id: i64) -> Result<Option<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error>;
+});
+
+impl_for_app_repo!(QueryMessagesFn {
+    async fn query_messages(&self, user_id: i64, last_updated_at: DateTime<Utc>, page_size: i16) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error>;
+});
+
+impl_for_app_repo!(QueryMessagesAfterFn {
+    async fn query_messages_after(&self, after_id: i64, filter: &MessageStreamFilter, page_size: i16) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error>;
+    async fn query_last_message_id(&self) -> Result<Option<i64>, sqlx::Error>;
+});
+
+impl_for_app_repo!(QueryMessageCountsFn {
+    async fn query_message_counts(&self, ids: &[i64]) -> Result<Vec<MessageCountsQueryResult>, sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; LikeMessageFn {
+    async fn like_message(&self, user_id: i64, msg_id: i64) -> Result<bool, sqlx::Error>;
+});
+
+impl_for_app_repo!(QueryNotificationGroupsFn {
+    async fn query_notification_groups(&self, recipient_id: i64, before_id: Option<i64>, page_size: i16) -> Result<Vec<NotificationGroupQueryResult>, sqlx::Error>;
+});
+
+impl_for_app_repo!(QueryUnreadNotificationCountFn {
+    async fn query_unread_notification_count(&self, recipient_id: i64) -> Result<i64, sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; MarkNotificationsReadFn {
+    async fn mark_notifications_read(&self, recipient_id: i64, up_to_id: Option<i64>) -> Result<u64, sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; MarkNotificationGroupReadFn {
+    async fn mark_notification_group_read(&self, recipient_id: i64, kind: NotificationKind, target_msg_id: Option<i64>) -> Result<u64, sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; InsertWebhookSubscriptionFn {
+    async fn insert_webhook_subscription(&self, params: WebhookSubscriptionCreate) -> Result<i64, sqlx::Error>;
+});
+
+impl_for_app_repo!(QueryWebhookSubscriptionsFn {
+    async fn query_webhook_subscriptions(&self, user_id: i64) -> Result<Vec<WebhookSubscriptionQueryResult>, sqlx::Error>;
+});
+
+impl_for_app_repo!(ClaimWebhookDeliveriesFn {
+    async fn claim_webhook_deliveries(&self, now: DateTime<Utc>, lease_until: DateTime<Utc>, limit: i16) -> Result<Vec<WebhookDueDelivery>, sqlx::Error>;
+});
+
+impl_for_app_repo!(RecordWebhookAttemptFn {
+    async fn record_webhook_attempt(&self, id: i64, attempt: &WebhookAttempt) -> Result<Option<WebhookDeliveryQueryResult>, sqlx::Error>;
+});
+
+impl_for_app_repo!(QueryWebhookDeliveriesFn {
+    async fn query_webhook_deliveries(&self, subscription_id: i64, page_size: i16) -> Result<Vec<WebhookDeliveryQueryResult>, sqlx::Error>;
+});
+
+impl_for_app_repo!(ReplayWebhookDeliveryFn {
+    async fn replay_webhook_delivery(&self, subscription_id: i64, delivery_id: i64, lease_until: DateTime<Utc>) -> Result<Option<WebhookDueDelivery>, sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; InsertProfileFn {
+    async fn insert_profile(&self, params: ProfileCreate) -> Result<i64, sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; UpdateProfileAvatarFn {
+    async fn update_profile_avatar(&self, user_id: i64, avatar: Vec<u8>) -> Result<(), sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; QueryProfileFn {
+    async fn query_profile(&self, id: i64) -> Result<Option<ProfileQueryResult>, sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; QueryProfileByUserFn {
+    async fn query_profile_by_user(&self, user_name: String) -> Result<Option<ProfileQueryResult>, sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; FollowUserFn {
+    async fn follow_user(&self, follower_id: i64, following_id: i64) -> Result<i64, sqlx::Error>;
+});
+
+impl_for_app_repo!(QueryFollowingIdsFn {
+    async fn query_following_ids(&self, follower_id: i64) -> Result<Vec<i64>, sqlx::Error>;
+});
+
+impl_for_app_repo!(RebuildTimelinesFn {
+    async fn rebuild_timelines(&self, owner_id: Option<i64>) -> Result<u64, sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; InsertCircleFn {
+    async fn insert_circle(&self, circle_owner_id: i64) -> Result<i64, sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; InsertCircleMemberFn {
+    async fn insert_circle_member(&self, circle_group_id: i64, new_member_id: i64) -> Result<i64, sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; QueryCircleFn {
+    async fn query_circle(&self, id: i64) -> Result<Option<CircleGroupWithProfileQueryResult>, sqlx::Error>;
+});
+
+impl_for_app_repo!(AppRepo, AppUnitOfWork; QueryCircleMemberFn {
+    async fn query_circle_member(&self, id: i64) -> Result<Option<CircleGroupMemberWithProfileQueryResult>, sqlx::Error>;
+});
+
+impl_for_app_repo!(QuerySchemaVersionFn {
+    async fn query_schema_version(&self) -> Result<i64, sqlx::Error>;
+});
+
+impl SubscribeEventsFn for AppRepo {
+    fn subscribe_events(&self) -> broadcast::Receiver<AppEvent> {
+        match self {
+            AppRepo::Postgres(repo) => repo.subscribe_events(),
+            AppRepo::Sqlite(repo) => repo.subscribe_events(),
+            AppRepo::Memory(repo) => repo.subscribe_events(),
+        }
+    }
+}
+
+impl PoolStatsFn for AppRepo {
+    fn pool_stats(&self) -> PoolStats {
+        match self {
+            AppRepo::Postgres(repo) => repo.pool_stats(),
+            AppRepo::Sqlite(repo) => repo.pool_stats(),
+            AppRepo::Memory(repo) => repo.pool_stats(),
+        }
+    }
+}
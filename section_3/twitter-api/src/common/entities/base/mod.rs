@@ -1,8 +1,28 @@
+use async_trait::async_trait;
+use mockall::automock;
 use serde::Deserialize;
-use sqlx::{FromRow, Postgres, Pool};
-use std::env;
-use dotenv::dotenv;
-use sqlx::migrate;
+use sqlx::{FromRow, PgConnection, Postgres, Pool, Transaction};
+use sqlx::postgres::PgPoolOptions;
+use std::time::Duration;
+use tokio::sync::{ MappedMutexGuard, Mutex, MutexGuard };
+use tracing::{ info, warn };
+use crate::common::config::{ Config, PostgresConfig };
+use crate::common::entities::events::repo::{ PgEventBus, SubscribeEventsFn };
+use crate::common::entities::messages::repo::{
+    InsertMessageFn, LikeMessageFn, QueryMessageCountsFn, QueryMessageFn, QueryMessagesAfterFn, QueryMessagesFn,
+};
+use crate::common::entities::notifications::repo::{
+    MarkNotificationGroupReadFn, MarkNotificationsReadFn, QueryNotificationGroupsFn, QueryUnreadNotificationCountFn,
+};
+use crate::common::entities::profiles::repo::{ InsertProfileFn, QueryFollowingIdsFn, QueryProfileByUserFn, QueryProfileFn };
+use crate::common::entities::webhooks::repo::{
+    InsertWebhookSubscriptionFn, QueryWebhookDeliveriesFn, QueryWebhookSubscriptionsFn, RecordWebhookAttemptFn,
+    ReplayWebhookDeliveryFn,
+};
+use crate::common::metrics::PoolStats;
+use crate::common::migration::{ MIGRATOR, ensure_schema_current };
+
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(30);
 
 #[allow(unused)]
 #[derive(FromRow, Deserialize)]
@@ -17,12 +37,69 @@ pub trait DbConnGetter {
 
 #[derive(Clone)]
 pub struct DbRepo {
-    conn: Pool<Postgres>
+    conn: Pool<Postgres>,
+    max_connections: u32,
+    celebrity_follower_threshold: i64,
+    events: PgEventBus,
 }
 
 impl DbRepo {
-    pub async fn init() -> Self {
-        Self { conn: get_db_conn().await }
+    pub async fn init(config: &Config) -> Result<Self, sqlx::Error> {
+        let conn = get_db_conn(config).await?;
+        let events = PgEventBus::listen(&conn).await?;
+        Ok(Self {
+            conn,
+            max_connections: config.postgres.max_connections,
+            celebrity_follower_threshold: config.timeline.celebrity_follower_threshold,
+            events,
+        })
+    }
+
+    /// waits for checked out connections to be returned, then closes every connection
+    pub async fn close(&self) {
+        self.conn.close().await;
+    }
+
+    /// opens a transaction that the repo traits implemented for UnitOfWork run inside of
+    pub async fn begin(&self) -> Result<UnitOfWork, sqlx::Error> {
+        Ok(UnitOfWork {
+            tx: Mutex::new(self.conn.begin().await?),
+            celebrity_follower_threshold: self.celebrity_follower_threshold,
+        })
+    }
+
+    pub(crate) fn celebrity_follower_threshold(&self) -> i64 {
+        self.celebrity_follower_threshold
+    }
+
+    pub(crate) fn events(&self) -> &PgEventBus {
+        &self.events
+    }
+}
+
+/// several repo operations run in one transaction, nothing is visible to other connections until commit.
+/// Dropping it without committing rolls everything back, including the events its operations published
+pub struct UnitOfWork {
+    tx: Mutex<Transaction<'static, Postgres>>,
+    celebrity_follower_threshold: i64,
+}
+
+impl UnitOfWork {
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        self.tx.into_inner().commit().await
+    }
+
+    pub async fn rollback(self) -> Result<(), sqlx::Error> {
+        self.tx.into_inner().rollback().await
+    }
+
+    /// the repo traits take &self, so each operation locks the transaction while its queries run
+    pub(crate) async fn lock(&self) -> MappedMutexGuard<'_, PgConnection> {
+        MutexGuard::map(self.tx.lock().await, |tx| &mut **tx)
+    }
+
+    pub(crate) fn celebrity_follower_threshold(&self) -> i64 {
+        self.celebrity_follower_threshold
     }
 }
 
@@ -34,23 +111,165 @@ impl DbConnGetter for DbRepo {
     }
 }
 
-pub async fn get_db_conn() -> Pool<Postgres> {
-    dotenv().ok();
-    let postgres_host = env::var("POSTGRES_HOST").unwrap();
-    let postgres_port = env::var("POSTGRES_PORT").unwrap().parse::<u16>().unwrap();
-    let postgres_password = env::var("POSTGRES_PASSWORD").unwrap();
-    let postgres_user = env::var("POSTGRES_USER").unwrap();
-    let postgres_db = env::var("POSTGRES_DB").unwrap();
+#[automock]
+#[async_trait]
+pub trait QuerySchemaVersionFn {
+    /// acquires a pooled connection and returns the schema version, failing if it does not match this build
+    async fn query_schema_version(&self) -> Result<i64, sqlx::Error>;
+}
+
+#[async_trait]
+impl QuerySchemaVersionFn for DbRepo {
+    async fn query_schema_version(&self) -> Result<i64, sqlx::Error> {
+        ensure_schema_current(&MIGRATOR, self.get_conn()).await
+    }
+}
+
+#[automock]
+pub trait PoolStatsFn {
+    /// current connection counts, exported on /metrics
+    fn pool_stats(&self) -> PoolStats;
+}
+
+impl PoolStatsFn for DbRepo {
+    fn pool_stats(&self) -> PoolStats {
+        let conn = self.get_conn();
+        PoolStats {
+            max: self.max_connections,
+            open: conn.size(),
+            idle: conn.num_idle() as u32,
+        }
+    }
+}
+
+/// every repo trait the registered routes call, implemented by any repo that can back the server
+pub trait Repository:
+    InsertMessageFn
+    + QueryMessageFn
+    + QueryMessagesFn
+    + QueryMessagesAfterFn
+    + QueryMessageCountsFn
+    + LikeMessageFn
+    + SubscribeEventsFn
+    + InsertProfileFn
+    + QueryProfileFn
+    + QueryProfileByUserFn
+    + QueryFollowingIdsFn
+    + QueryNotificationGroupsFn
+    + QueryUnreadNotificationCountFn
+    + MarkNotificationsReadFn
+    + MarkNotificationGroupReadFn
+    + InsertWebhookSubscriptionFn
+    + QueryWebhookSubscriptionsFn
+    + QueryWebhookDeliveriesFn
+    + ReplayWebhookDeliveryFn
+    + RecordWebhookAttemptFn
+    + QuerySchemaVersionFn
+    + PoolStatsFn
+    + Send
+    + Sync
+    + 'static {}
+
+impl<T> Repository for T where T:
+    InsertMessageFn
+    + QueryMessageFn
+    + QueryMessagesFn
+    + QueryMessagesAfterFn
+    + QueryMessageCountsFn
+    + LikeMessageFn
+    + SubscribeEventsFn
+    + InsertProfileFn
+    + QueryProfileFn
+    + QueryProfileByUserFn
+    + QueryFollowingIdsFn
+    + QueryNotificationGroupsFn
+    + QueryUnreadNotificationCountFn
+    + MarkNotificationsReadFn
+    + MarkNotificationGroupReadFn
+    + InsertWebhookSubscriptionFn
+    + QueryWebhookSubscriptionsFn
+    + QueryWebhookDeliveriesFn
+    + ReplayWebhookDeliveryFn
+    + RecordWebhookAttemptFn
+    + QuerySchemaVersionFn
+    + PoolStatsFn
+    + Send
+    + Sync
+    + 'static {}
+
+/// connects and refuses to hand out a pool unless the schema matches the embedded migrations,
+/// pending migrations are only applied here when the run_migrations feature is on
+pub async fn get_db_conn(config: &Config) -> Result<Pool<Postgres>, sqlx::Error> {
+    let conn = connect_with_retry(&config.postgres).await?;
+
+    if config.features.run_migrations {
+        MIGRATOR.run(&conn).await?;
+    }
+    let schema_version = ensure_schema_current(&MIGRATOR, &conn).await?;
+    info!("database schema is at version {}", schema_version);
+
+    Ok(conn)
+}
+
+/// postgres is often still starting when the server comes up (e.g. docker compose),
+/// so the initial connect is retried with exponential backoff before giving up
+pub async fn connect_with_retry(postgres: &PostgresConfig) -> Result<Pool<Postgres>, sqlx::Error> {
+    let url = postgres.url();
+
+    let mut attempt = 0;
+    loop {
+        match get_pool_options(postgres).connect(&url).await {
+            Ok(conn) => return Ok(conn),
+            Err(e) if attempt < postgres.connect_retries => {
+                let delay = get_backoff_delay(postgres.connect_backoff_ms, attempt);
+                warn!(
+                    "postgres connect attempt {} of {} failed: {}, retrying in {:?}",
+                    attempt + 1, postgres.connect_retries + 1, e, delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn get_pool_options(postgres: &PostgresConfig) -> PgPoolOptions {
+    PgPoolOptions::new()
+        .max_connections(postgres.max_connections)
+        .min_connections(postgres.min_connections)
+        .acquire_timeout(Duration::from_secs(postgres.acquire_timeout_secs))
+        .idle_timeout(Duration::from_secs(postgres.idle_timeout_secs))
+}
+
+fn get_backoff_delay(initial_backoff_ms: u64, attempt: u32) -> Duration {
+    let delay = Duration::from_millis(initial_backoff_ms.saturating_mul(2u64.saturating_pow(attempt)));
+    delay.min(MAX_CONNECT_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common_tests::actix_fixture::get_config;
+
+    #[test]
+    fn test_backoff_delay_doubles_and_is_capped() {
+        assert_eq!(get_backoff_delay(500, 0), Duration::from_millis(500));
+        assert_eq!(get_backoff_delay(500, 1), Duration::from_millis(1000));
+        assert_eq!(get_backoff_delay(500, 3), Duration::from_millis(4000));
+        assert_eq!(get_backoff_delay(500, 20), MAX_CONNECT_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn test_init_returns_error_when_postgres_unreachable() {
+        let mut config = get_config();
+        config.postgres.port = 1;
+        config.postgres.acquire_timeout_secs = 1;
+        config.postgres.connect_retries = 1;
+        config.postgres.connect_backoff_ms = 10;
 
-    let postgres_url = format!(
-        "postgres://{postgres_user}:{postgres_password}@{postgres_host}:{postgres_port}/{postgres_db}"
-    );
+        let result = DbRepo::init(&config).await;
 
-    let conn = sqlx::postgres::PgPool::connect(&postgres_url).await.unwrap();
-    let migrate = migrate!("./migrations").run(&conn).await;
-    match migrate {
-        Ok(()) => println!("sqlx migration success"),
-        Err(e) => println!("sqlx migration error: {:?}", e),
+        assert!(result.is_err());
     }
-    conn
 }
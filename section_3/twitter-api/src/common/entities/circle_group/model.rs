@@ -17,7 +17,7 @@ pub struct CircleGroupWithProfileQueryResult {
     pub owner_id: i64,
     pub user_name: String,
     pub full_name: String,
-    pub avatar: Vec<u8>
+    pub avatar: Option<Vec<u8>>
 }
 
 #[derive(Deserialize, Serialize, FromRow, Clone)]
@@ -37,5 +37,5 @@ pub struct CircleGroupMemberWithProfileQueryResult {
     pub member_id: i64,
     pub user_name: String,
     pub full_name: String,
-    pub avatar: Vec<u8>
+    pub avatar: Option<Vec<u8>>
 }
\ No newline at end of file
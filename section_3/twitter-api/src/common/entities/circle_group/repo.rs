@@ -1,21 +1,28 @@
 use async_trait::async_trait;
 use mockall::automock;
-use sqlx::{ Pool, Postgres };
-use crate::common::entities::base::{ EntityId, DbRepo, DbConnGetter };
+use sqlx::{ Executor, Postgres };
+use crate::common::entities::base::{ EntityId, DbRepo, DbConnGetter, UnitOfWork };
 use super::model::{ CircleGroupWithProfileQueryResult, CircleGroupMemberWithProfileQueryResult };
 
 mod private_members {
+    use crate::common::metrics::start_query_timer;
+    use tracing::instrument;
     use super::*;
 
-    pub async fn insert_circle_inner(
-        conn: &Pool<Postgres>,
+    #[instrument(skip_all)]
+    pub async fn insert_circle_inner<'c, E>(
+        conn: E,
         circle_owner_id: i64
-    ) -> Result<i64, sqlx::Error> {
+    ) -> Result<i64, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("insert_circle_inner");
         let insert_result = sqlx
-            ::query_as::<_, EntityId>(
-                "insert into circle_group (owner_id) values ($1) returning id"
+            ::query_as!(
+                EntityId,
+                "insert into circle_group (owner_id) values ($1) returning id",
+                circle_owner_id
             )
-            .bind(circle_owner_id)
             .fetch_one(conn).await;
 
         match insert_result {
@@ -24,17 +31,22 @@ mod private_members {
         }
     }
 
-    pub async fn insert_circle_member_inner(
-        conn: &Pool<Postgres>,
+    #[instrument(skip_all)]
+    pub async fn insert_circle_member_inner<'c, E>(
+        conn: E,
         circle_group_id: i64,
         new_member_id: i64
-    ) -> Result<i64, sqlx::Error> {
+    ) -> Result<i64, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("insert_circle_member_inner");
         let insert_result = sqlx
-            ::query_as::<_, EntityId>(
-                "insert into circle_group_member (circle_group_id, member_id) values ($1, $2) returning id"
+            ::query_as!(
+                EntityId,
+                "insert into circle_group_member (circle_group_id, member_id) values ($1, $2) returning id",
+                circle_group_id,
+                new_member_id
             )
-            .bind(circle_group_id)
-            .bind(new_member_id)
             .fetch_one(conn).await;
 
         match insert_result {
@@ -43,37 +55,47 @@ mod private_members {
         }
     }
 
-    pub async fn query_circle_inner(
-        conn: &Pool<Postgres>,
+    #[instrument(skip_all)]
+    pub async fn query_circle_inner<'c, E>(
+        conn: E,
         id: i64
-    ) -> Result<Option<CircleGroupWithProfileQueryResult>, sqlx::Error> {
+    ) -> Result<Option<CircleGroupWithProfileQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("query_circle_inner");
         sqlx
-            ::query_as::<_, CircleGroupWithProfileQueryResult>(
+            ::query_as!(
+                CircleGroupWithProfileQueryResult,
                 r"
                 select c.id, c.updated_at, c.owner_id, p.user_name, p.full_name, p.avatar
                 from circle_group c
                     join profile p on c.owner_id = p.id
                 where c.id = $1
-            "
+            ",
+                id
             )
-            .bind(id)
             .fetch_optional(conn).await
     }
 
-    pub async fn query_circle_member_inner(
-        conn: &Pool<Postgres>,
+    #[instrument(skip_all)]
+    pub async fn query_circle_member_inner<'c, E>(
+        conn: E,
         id: i64
-    ) -> Result<Option<CircleGroupMemberWithProfileQueryResult>, sqlx::Error> {
+    ) -> Result<Option<CircleGroupMemberWithProfileQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("query_circle_member_inner");
         sqlx
-            ::query_as::<_, CircleGroupMemberWithProfileQueryResult>(
+            ::query_as!(
+                CircleGroupMemberWithProfileQueryResult,
                 r"
                 select c.id, c.updated_at, c.circle_group_id, p.id as member_id, p.user_name, p.full_name, p.avatar
                 from circle_group_member c
                     join profile p on c.member_id = p.id
                 where c.id = $1
-            "
+            ",
+                id
             )
-            .bind(id)
             .fetch_optional(conn).await
     }
 }
@@ -97,6 +119,16 @@ impl InsertCircleFn for DbRepo {
     }
 }
 
+#[async_trait]
+impl InsertCircleFn for UnitOfWork {
+    async fn insert_circle(
+        &self,
+        circle_owner_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        private_members::insert_circle_inner(&mut *self.lock().await, circle_owner_id).await
+    }
+}
+
 #[automock]
 #[async_trait]
 pub trait InsertCircleMemberFn {
@@ -118,6 +150,17 @@ impl InsertCircleMemberFn for DbRepo {
     }
 }
 
+#[async_trait]
+impl InsertCircleMemberFn for UnitOfWork {
+    async fn insert_circle_member(
+        &self,
+        circle_group_id: i64,
+        new_member_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        private_members::insert_circle_member_inner(&mut *self.lock().await, circle_group_id, new_member_id).await
+    }
+}
+
 #[automock]
 #[async_trait]
 pub trait QueryCircleFn {
@@ -137,6 +180,16 @@ impl QueryCircleFn for DbRepo {
     }
 }
 
+#[async_trait]
+impl QueryCircleFn for UnitOfWork {
+    async fn query_circle(
+        &self,
+        id: i64
+    ) -> Result<Option<CircleGroupWithProfileQueryResult>, sqlx::Error> {
+        private_members::query_circle_inner(&mut *self.lock().await, id).await
+    }
+}
+
 #[automock]
 #[async_trait]
 pub trait QueryCircleMemberFn {
@@ -156,6 +209,16 @@ impl QueryCircleMemberFn for DbRepo {
     }
 }
 
+#[async_trait]
+impl QueryCircleMemberFn for UnitOfWork {
+    async fn query_circle_member(
+        &self,
+        id: i64
+    ) -> Result<Option<CircleGroupMemberWithProfileQueryResult>, sqlx::Error> {
+        private_members::query_circle_member_inner(&mut *self.lock().await, id).await
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::common::entities::circle_group::model::{
@@ -167,6 +230,8 @@ mod tests {
             repo::{ InsertProfileFn, QueryProfileFn },
             model::ProfileCreate,
         },
+        common_tests::actix_fixture::get_parity_repos,
+        common::entities::app_repo::AppRepo,
     };
     use super::*;
     use super::{ InsertCircleFn };
@@ -181,12 +246,12 @@ mod tests {
         pub following_profiles: Vec<ProfileQueryResult>,
         pub circle_group: CircleGroupWithProfileQueryResult,
         pub circle_group_members: Vec<CircleGroupMemberWithProfileQueryResult>,
-        pub db_repo: DbRepo,
+        pub db_repo: AppRepo,
     }
 
     const PREFIX: &str = "Test circle";
 
-    async fn setup_data(db_repo: DbRepo) -> Fixtures {
+    async fn setup_data(db_repo: AppRepo) -> Fixtures {
         let follower_result_id = db_repo.insert_profile(ProfileCreate {
             user_name: "follower".to_string(),
             full_name: "Follower Guy".to_string(),
@@ -199,10 +264,7 @@ mod tests {
             match follower_result_id {
                 Ok(id) => {
                     let profile_result = db_repo.query_profile(id).await;
-                    match profile_result {
-                        Ok(profile) => profile,
-                        Err(_) => None,
-                    }
+                    profile_result.unwrap_or_default()
                 }
                 Err(_) => None,
             }
@@ -223,10 +285,7 @@ mod tests {
                 match following_result_id {
                     Ok(id) => {
                         let profile_result = db_repo.query_profile(id).await;
-                        match profile_result {
-                            Ok(profile) => profile,
-                            Err(_) => None,
-                        }
+                        profile_result.unwrap_or_default()
                     }
                     Err(_) => None,
                 }
@@ -244,7 +303,7 @@ mod tests {
 
         let mut circle_group_members = Vec::new();
         for _ in [..11] {
-            let current_following = following_profiles.iter().nth(0).unwrap();
+            let current_following = following_profiles.first().unwrap();
             let insert_circle_member_id = db_repo
                 .insert_circle_member(circle_group.id, current_following.id).await
                 .unwrap();
@@ -265,9 +324,11 @@ mod tests {
     }
 
     lazy_static! {
-        static ref FIXTURES: Arc<RwLock<Option<Fixtures>>> = Arc::new(RwLock::new(None));
+        static ref FIXTURES: Arc<RwLock<Option<Vec<Fixtures>>>> = Arc::new(RwLock::new(None));
     }
 
+    // the write lock is held on purpose so only one test sets up the shared fixtures
+    #[allow(clippy::await_holding_lock)]
     async fn setup_fixtures() {
         let fixtures = Arc::clone(&FIXTURES);
         let mut writeable_fixtures = fixtures
@@ -278,15 +339,18 @@ mod tests {
             Some(_) => (),
             None => {
                 println!("log: start circle setup_fixtures()");
-                let db_repo = DbRepo::init().await;
-
-                *writeable_fixtures = Some(setup_data(db_repo).await);
+                let mut backend_fixtures = vec![];
+                for db_repo in get_parity_repos().await {
+                    backend_fixtures.push(setup_data(db_repo).await);
+                }
+                *writeable_fixtures = Some(backend_fixtures);
                 println!("log: end circle setup_fixtures()");
             }
         };
     }
 
-    fn get_fixtures() -> Fixtures {
+    /// the fixtures of every backend, each test body runs once per backend
+    fn get_fixtures() -> Vec<Fixtures> {
         Arc::clone(&FIXTURES).read().unwrap().clone().unwrap()
     }
 
@@ -306,8 +370,7 @@ mod tests {
         use crate::common::entities::profiles::repo::{ MockInsertProfileFn, MockQueryProfileFn };
         use super::*;
 
-        async fn test_insert_new_circle_group_body() {
-            let fixtures = get_fixtures();
+        async fn test_insert_new_circle_group_body(fixtures: Fixtures) {
             let follower = fixtures.follower.clone();
             let follower_id = follower.clone().id;
             let mut mock_insert_profile = MockInsertProfileFn::new();
@@ -339,7 +402,11 @@ mod tests {
 
         #[test]
         fn test_insert_new_circle_group() {
-            RT.block_on(test_insert_new_circle_group_body());
+            RT.block_on(async {
+                for fixtures in get_fixtures() {
+                    test_insert_new_circle_group_body(fixtures).await;
+                }
+            });
         }
     }
 
@@ -347,10 +414,10 @@ mod tests {
         use crate::common::entities::profiles::repo::MockInsertProfileFn;
         use super::*;
 
-        fn get_insert_profile_mock() -> MockInsertProfileFn {
+        fn get_insert_profile_mock(fixtures: &Fixtures) -> MockInsertProfileFn {
+            let fixtures = fixtures.clone();
             let mut mock_insert_profile = MockInsertProfileFn::new();
             mock_insert_profile.expect_insert_profile().returning(move |params| {
-                let fixtures = get_fixtures();
                 if fixtures.follower.user_name == params.user_name {
                     Ok(fixtures.follower.id)
                 } else {
@@ -365,19 +432,18 @@ mod tests {
             mock_insert_profile
         }
 
-        fn get_insert_circle_mock() -> MockInsertCircleFn {
+        fn get_insert_circle_mock(fixtures: &Fixtures) -> MockInsertCircleFn {
+            let circle_group_id = fixtures.circle_group.id;
             let mut mock_insert_circle = MockInsertCircleFn::new();
             mock_insert_circle
                 .expect_insert_circle()
-                .returning(|_| { Ok(get_fixtures().circle_group.id) });
+                .returning(move |_| { Ok(circle_group_id) });
             mock_insert_circle
         }
 
-        async fn test_insert_new_circle_group_member_body() {
-            let fixtures = get_fixtures();
-
-            let mock_insert_profile = get_insert_profile_mock();
-            let mock_insert_circle = get_insert_circle_mock();
+        async fn test_insert_new_circle_group_member_body(fixtures: Fixtures) {
+            let mock_insert_profile = get_insert_profile_mock(&fixtures);
+            let mock_insert_circle = get_insert_circle_mock(&fixtures);
 
             let follower_id = mock_insert_profile
                 .insert_profile(ProfileCreate {
@@ -413,14 +479,16 @@ mod tests {
 
         #[test]
         fn test_insert_new_circle_group_member() {
-            RT.block_on(test_insert_new_circle_group_member_body());
+            RT.block_on(async {
+                for fixtures in get_fixtures() {
+                    test_insert_new_circle_group_member_body(fixtures).await;
+                }
+            });
         }
 
-        async fn test_insert_new_circle_group_member_and_verify_fields_body() {
-            let fixtures = get_fixtures();
-
-            let mock_insert_profile = get_insert_profile_mock();
-            let mock_insert_circle = get_insert_circle_mock();
+        async fn test_insert_new_circle_group_member_and_verify_fields_body(fixtures: Fixtures) {
+            let mock_insert_profile = get_insert_profile_mock(&fixtures);
+            let mock_insert_circle = get_insert_circle_mock(&fixtures);
 
             let follower_id = mock_insert_profile
                 .insert_profile(ProfileCreate {
@@ -463,7 +531,11 @@ mod tests {
 
         #[test]
         fn test_insert_new_circle_group_member_and_verify_fields() {
-            RT.block_on(test_insert_new_circle_group_member_and_verify_fields_body());
+            RT.block_on(async {
+                for fixtures in get_fixtures() {
+                    test_insert_new_circle_group_member_and_verify_fields_body(fixtures).await;
+                }
+            });
         }
     }
 }
@@ -0,0 +1,136 @@
+use crate::common::entities::base::DbRepo;
+use crate::common::events::{ AppEvent, EventBus, MemoryEventBus };
+use crate::common::metrics::start_query_timer;
+use async_trait::async_trait;
+use mockall::automock;
+use sqlx::{ postgres::PgListener, Executor, Pool, Postgres };
+use std::{ sync::Arc, time::Duration };
+use tokio::{ sync::broadcast, task::JoinHandle };
+use tracing::{ instrument, warn };
+
+/// the postgres channel every instance sends and listens on
+pub const EVENTS_CHANNEL: &str = "app_events";
+/// pause before listening again after the listener connection failed to come back
+const LISTEN_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// sends an event to every instance. Inside a transaction postgres delivers it on commit and drops it on
+/// rollback, so the write paths call this before committing
+#[instrument(skip_all)]
+pub(crate) async fn notify_event_inner<'c, E>(conn: E, event: &AppEvent) -> Result<(), sqlx::Error>
+    where E: Executor<'c, Database = Postgres>
+{
+    let _timer = start_query_timer("notify_event_inner");
+    let payload = serde_json::to_string(event).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+    sqlx
+        ::query!("select pg_notify($1, $2)", EVENTS_CHANNEL, payload)
+        .execute(conn).await
+        .map(|_| ())
+}
+
+/// the bus of replicas sharing one database. Each instance LISTENs on one dedicated connection and fans the
+/// notifications out to its own subscribers. Events sent while that connection is being re-established are lost
+#[derive(Clone)]
+pub struct PgEventBus {
+    conn: Pool<Postgres>,
+    local: MemoryEventBus,
+    _listener: Arc<ListenerTask>,
+}
+
+/// stops listening once the last clone of the bus is dropped
+struct ListenerTask(JoinHandle<()>);
+
+impl Drop for ListenerTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl PgEventBus {
+    /// returns once the channel is listened on, so every event committed afterwards is received
+    pub async fn listen(conn: &Pool<Postgres>) -> Result<Self, sqlx::Error> {
+        let mut listener = PgListener::connect_with(conn).await?;
+        listener.listen(EVENTS_CHANNEL).await?;
+
+        let local = MemoryEventBus::new();
+        let task = tokio::spawn(forward_notifications(listener, local.clone()));
+        Ok(Self { conn: conn.clone(), local, _listener: Arc::new(ListenerTask(task)) })
+    }
+}
+
+async fn forward_notifications(mut listener: PgListener, local: MemoryEventBus) {
+    loop {
+        match listener.recv().await {
+            Ok(notification) => match serde_json::from_str::<AppEvent>(notification.payload()) {
+                Ok(event) => _ = local.publish(event).await,
+                Err(e) => warn!("ignoring an event that does not parse: {}", e),
+            },
+            // recv reconnects and listens again by itself, this is reached when that failed too
+            Err(e) => {
+                warn!("event listener lost its connection: {}", e);
+                tokio::time::sleep(LISTEN_RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl EventBus for PgEventBus {
+    async fn publish(&self, event: AppEvent) -> Result<(), sqlx::Error> {
+        notify_event_inner(&self.conn, &event).await
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<AppEvent> {
+        self.local.subscribe()
+    }
+}
+
+#[automock]
+pub trait SubscribeEventsFn {
+    /// every event published from now on by any instance sharing this repo's bus
+    fn subscribe_events(&self) -> broadcast::Receiver<AppEvent>;
+}
+
+impl SubscribeEventsFn for DbRepo {
+    fn subscribe_events(&self) -> broadcast::Receiver<AppEvent> {
+        self.events().subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{ entities::base::get_db_conn, events::FollowCreated };
+    use crate::common_tests::actix_fixture::get_config;
+    use super::*;
+
+    /// every event received up to and including expected, other tests publish on the same database
+    async fn receive_until(events: &mut broadcast::Receiver<AppEvent>, expected: &AppEvent) -> Vec<AppEvent> {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            let mut received = vec![];
+            while received.last() != Some(expected) {
+                received.push(events.recv().await.unwrap());
+            }
+            received
+        }).await.expect("event not received within 5 seconds")
+    }
+
+    #[tokio::test]
+    async fn test_events_reach_every_instance_only_once_committed() {
+        let conn = get_db_conn(&get_config()).await.unwrap();
+        // two buses on one database stand in for two replicas
+        let publisher = PgEventBus::listen(&conn).await.unwrap();
+        let replica = PgEventBus::listen(&conn).await.unwrap();
+        let mut publisher_events = publisher.subscribe();
+        let mut replica_events = replica.subscribe();
+        let rolled_back = AppEvent::FollowCreated(FollowCreated { id: -2, follower_id: -2, following_id: -2 });
+        let committed = AppEvent::FollowCreated(FollowCreated { id: -1, follower_id: -1, following_id: -1 });
+
+        let mut tx = conn.begin().await.unwrap();
+        notify_event_inner(&mut tx, &rolled_back).await.unwrap();
+        tx.rollback().await.unwrap();
+        publisher.publish(committed.clone()).await.unwrap();
+
+        // notifications arrive in commit order, anything of the rollback would have come first
+        assert!(!receive_until(&mut publisher_events, &committed).await.contains(&rolled_back));
+        assert!(!receive_until(&mut replica_events, &committed).await.contains(&rolled_back));
+    }
+}
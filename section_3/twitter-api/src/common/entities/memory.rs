@@ -0,0 +1,1341 @@
+use async_trait::async_trait;
+use chrono::{ DateTime, SubsecRound, Utc };
+use std::{ cmp::Reverse, collections::{ BTreeMap, HashMap, HashSet }, ops::Deref, sync::{ Arc, RwLock, RwLockReadGuard, RwLockWriteGuard } };
+use tokio::sync::broadcast;
+use tracing::instrument;
+use crate::common::{
+    entities::{
+        base::{ PoolStatsFn, QuerySchemaVersionFn },
+        circle_group::{
+            model::{
+                CircleGroupMemberQueryResult,
+                CircleGroupMemberWithProfileQueryResult,
+                CircleGroupQueryResult,
+                CircleGroupWithProfileQueryResult,
+            },
+            repo::{ InsertCircleFn, InsertCircleMemberFn, QueryCircleFn, QueryCircleMemberFn },
+        },
+        events::repo::SubscribeEventsFn,
+        messages::{
+            model::{
+                mentions_hashtag, MessageCountsQueryResult, MessageQueryResult, MessageStreamFilter,
+                MessageWithFollowingAndBroadcastQueryResult,
+            },
+            repo::{
+                InsertMessageFn, InsertResponseMessageFn, LikeMessageFn, QueryMessageCountsFn, QueryMessageFn,
+                QueryMessagesAfterFn, QueryMessagesFn, MAX_BROADCAST_DEPTH,
+            },
+        },
+        notifications::{
+            model::{ get_mentioned_user_names, NotificationCreate, NotificationGroupQueryResult, NotificationKind },
+            repo::{
+                MarkNotificationGroupReadFn, MarkNotificationsReadFn, QueryNotificationGroupsFn,
+                QueryUnreadNotificationCountFn,
+            },
+        },
+        profiles::{
+            model::{ ProfileCreate, ProfileQueryResult },
+            repo::{
+                FollowUserFn, InsertProfileFn, QueryFollowingIdsFn, QueryProfileByUserFn, QueryProfileFn,
+                UpdateProfileAvatarFn,
+            },
+        },
+        timeline::repo::RebuildTimelinesFn,
+        webhooks::{
+            model::{
+                get_event_key, get_involved_profile_ids, WebhookAttempt, WebhookDeliveryQueryResult, WebhookDeliveryStatus, WebhookDueDelivery, WebhookEventType,
+                WebhookSubscriptionCreate, WebhookSubscriptionQueryResult,
+            },
+            repo::{
+                ClaimWebhookDeliveriesFn, InsertWebhookSubscriptionFn, QueryWebhookDeliveriesFn,
+                QueryWebhookSubscriptionsFn, RecordWebhookAttemptFn, ReplayWebhookDeliveryFn,
+            },
+        },
+    },
+    events::{ AppEvent, EventBus, FollowCreated, MemoryEventBus, MessageCreated, MessageLiked, MessageRef, NotificationCreated },
+    metrics::{ start_query_timer, PoolStats },
+    migration::get_expected_version,
+};
+
+/// rows keyed by id, ids start at 1 and are never reused like a bigserial column
+#[derive(Clone)]
+struct Table<T> {
+    last_id: i64,
+    rows: BTreeMap<i64, T>,
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Self { last_id: 0, rows: BTreeMap::new() }
+    }
+}
+
+impl<T> Table<T> {
+    fn insert(&mut self, row: impl FnOnce(i64) -> T) -> i64 {
+        self.last_id += 1;
+        self.rows.insert(self.last_id, row(self.last_id));
+        self.last_id
+    }
+
+    fn get(&self, id: i64) -> Option<&T> {
+        self.rows.get(&id)
+    }
+
+    fn contains(&self, id: i64) -> bool {
+        self.rows.contains_key(&id)
+    }
+}
+
+#[derive(Clone)]
+struct FollowRow {
+    follower_id: i64,
+    following_id: i64,
+}
+
+#[allow(unused)]
+#[derive(Clone)]
+struct MessageResponseRow {
+    original_msg_id: i64,
+    responding_msg_id: i64,
+}
+
+#[derive(Clone)]
+struct MessageBroadcastRow {
+    main_msg_id: i64,
+    broadcasting_msg_id: i64,
+}
+
+#[derive(Clone)]
+struct MessageLikeRow {
+    user_id: i64,
+    message_id: i64,
+}
+
+#[derive(Clone)]
+struct NotificationRow {
+    created_at: DateTime<Utc>,
+    recipient_id: i64,
+    actor_id: i64,
+    kind: NotificationKind,
+    target_msg_id: Option<i64>,
+    source_msg_id: Option<i64>,
+    read_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone)]
+struct WebhookDeliveryRow {
+    event_key: Option<String>,
+    delivery: WebhookDeliveryQueryResult,
+}
+
+#[derive(Clone, Default)]
+struct MemoryState {
+    /// bumped by every write, a unit of work only commits over the state it copied
+    version: u64,
+    profiles: Table<ProfileQueryResult>,
+    follows: Table<FollowRow>,
+    messages: Table<MessageQueryResult>,
+    message_responses: Table<MessageResponseRow>,
+    message_broadcasts: Table<MessageBroadcastRow>,
+    message_likes: Table<MessageLikeRow>,
+    notifications: Table<NotificationRow>,
+    webhook_subscriptions: Table<WebhookSubscriptionQueryResult>,
+    webhook_deliveries: Table<WebhookDeliveryRow>,
+    circle_groups: Table<CircleGroupQueryResult>,
+    circle_group_members: Table<CircleGroupMemberQueryResult>,
+}
+
+/// postgres stores timestamptz(3), so timestamps are cut to milliseconds to page and tag identically
+fn get_now() -> DateTime<Utc> {
+    Utc::now().trunc_subsecs(3)
+}
+
+/// a missing referenced row, where postgres would report a foreign key violation
+fn get_missing_reference_error() -> sqlx::Error {
+    sqlx::Error::RowNotFound
+}
+
+impl MemoryState {
+    fn insert_message(&mut self, user_id: i64, body: &str, group_type: i32) -> Result<i64, sqlx::Error> {
+        if !self.profiles.contains(user_id) {
+            return Err(get_missing_reference_error());
+        }
+
+        let now = get_now();
+        Ok(
+            self.messages.insert(|id| MessageQueryResult {
+                id,
+                created_at: now,
+                updated_at: now,
+                user_id,
+                body: Some(body.to_string()),
+                image: None,
+                likes: 0,
+                msg_group_type: group_type,
+            })
+        )
+    }
+
+    /// the event to publish for the notification once the write that caused it is done
+    fn insert_notification(&mut self, notification: Option<NotificationCreate>) -> Option<NotificationCreated> {
+        let notification = notification?;
+        let now = get_now();
+        let id = self.notifications.insert(|_| NotificationRow {
+            created_at: now,
+            recipient_id: notification.recipient_id,
+            actor_id: notification.actor_id,
+            kind: notification.kind,
+            target_msg_id: notification.target_msg_id,
+            source_msg_id: notification.source_msg_id,
+            read_at: None,
+        });
+        Some(notification.to_event(id))
+    }
+
+    /// the notifications a new message causes, mentions skip whoever it already notified like the sql backends
+    fn insert_message_notifications(&mut self, event: &MessageCreated, body: &str) -> Vec<NotificationCreated> {
+        let mut notifications = self.insert_notification(NotificationCreate::for_message(event, body))
+            .into_iter()
+            .collect::<Vec<NotificationCreated>>();
+
+        let user_names = get_mentioned_user_names(body);
+        let mentioned_ids = self.profiles.rows
+            .values()
+            .filter(|profile| profile.id != event.user_id && user_names.contains(&profile.user_name))
+            .map(|profile| profile.id)
+            .collect::<Vec<i64>>();
+        for recipient_id in mentioned_ids {
+            let is_notified = self.notifications.rows
+                .values()
+                .any(|notification| notification.source_msg_id == Some(event.id) && notification.recipient_id == recipient_id);
+            if !is_notified {
+                notifications.extend(self.insert_notification(Some(NotificationCreate {
+                    recipient_id,
+                    actor_id: event.user_id,
+                    kind: NotificationKind::Mention,
+                    target_msg_id: Some(event.id),
+                    source_msg_id: Some(event.id),
+                })));
+            }
+        }
+        notifications
+    }
+
+    fn insert_webhook_delivery(
+        &mut self,
+        subscription_id: i64,
+        event_type: WebhookEventType,
+        event_key: Option<String>,
+        payload: String,
+        next_attempt_at: DateTime<Utc>
+    ) -> i64 {
+        let now = get_now();
+        self.webhook_deliveries.insert(|id| WebhookDeliveryRow {
+            event_key,
+            delivery: WebhookDeliveryQueryResult {
+                id,
+                created_at: now,
+                subscription_id,
+                event_type,
+                payload,
+                status: WebhookDeliveryStatus::Pending,
+                attempt_count: 0,
+                next_attempt_at: Some(next_attempt_at),
+                last_attempt_at: None,
+                last_status_code: None,
+                last_error: None,
+            },
+        })
+    }
+
+    /// a pending delivery of the event for each subscription to its type whose profile the event involves, skipping subscriptions that already
+    /// have one for its event_key, like the unique constraint of the sql backends
+    fn insert_webhook_deliveries(&mut self, event: &AppEvent) -> Result<u64, sqlx::Error> {
+        let (Some(event_type), Some(event_key)) = (WebhookEventType::of(event), get_event_key(event)) else {
+            return Ok(0);
+        };
+        let payload = serde_json::to_string(event).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+        let now = get_now();
+        let profile_ids = get_involved_profile_ids(event);
+
+        let subscription_ids = self.webhook_subscriptions.rows
+            .values()
+            .filter(|subscription| subscription.event_types & event_type as i32 != 0)
+            .filter(|subscription| profile_ids.contains(&subscription.user_id))
+            .map(|subscription| subscription.id)
+            .collect::<Vec<i64>>();
+        let mut inserted = 0;
+        for subscription_id in subscription_ids {
+            let is_delivered = self.webhook_deliveries.rows
+                .values()
+                .any(|row| row.delivery.subscription_id == subscription_id && row.event_key.as_deref() == Some(event_key.as_str()));
+            if !is_delivered {
+                self.insert_webhook_delivery(subscription_id, event_type, Some(event_key.clone()), payload.clone(), now);
+                inserted += 1;
+            }
+        }
+        Ok(inserted)
+    }
+
+    /// the delivery with where to send it, None once its subscription is gone
+    fn get_webhook_due_delivery(&self, id: i64) -> Option<WebhookDueDelivery> {
+        let delivery = &self.webhook_deliveries.get(id)?.delivery;
+        let subscription = self.webhook_subscriptions.get(delivery.subscription_id)?;
+        Some(WebhookDueDelivery {
+            id,
+            event_type: delivery.event_type,
+            payload: delivery.payload.clone(),
+            attempt_count: delivery.attempt_count,
+            url: subscription.url.clone(),
+            secret: subscription.secret.clone(),
+        })
+    }
+
+    fn get_message_ref(&self, id: i64) -> Option<MessageRef> {
+        self.messages.get(id).map(|message| MessageRef { id, user_id: message.user_id })
+    }
+
+    fn get_broadcasting_msg_id(&self, main_msg_id: i64) -> Option<i64> {
+        self.message_broadcasts.rows
+            .values()
+            .find(|broadcast| broadcast.main_msg_id == main_msg_id)
+            .map(|broadcast| broadcast.broadcasting_msg_id)
+    }
+
+    /// the root of the chain of broadcasts starting at the message, or the message MAX_BROADCAST_DEPTH steps along it
+    fn resolve_broadcasting_msg_id(&self, main_msg_id: i64) -> Option<i64> {
+        let mut resolved = self.get_broadcasting_msg_id(main_msg_id)?;
+        for _ in 1..MAX_BROADCAST_DEPTH {
+            match self.get_broadcasting_msg_id(resolved) {
+                Some(next) => resolved = next,
+                None => break,
+            }
+        }
+        Some(resolved)
+    }
+
+    /// the message with its author and the original message it broadcasts, like the postgres queries
+    fn get_message_with_broadcast(&self, message: &MessageQueryResult) -> MessageWithFollowingAndBroadcastQueryResult {
+        let profile = self.profiles.get(message.user_id);
+        let broadcast = self
+            .resolve_broadcasting_msg_id(message.id)
+            .and_then(|broadcasting_msg_id| self.messages.get(broadcasting_msg_id))
+            .and_then(|broadcast| self.profiles.get(broadcast.user_id).map(|profile| (broadcast, profile)));
+
+        MessageWithFollowingAndBroadcastQueryResult {
+            id: message.id,
+            updated_at: message.updated_at,
+            body: message.body.clone(),
+            likes: message.likes,
+            image: message.image.clone(),
+            msg_group_type: message.msg_group_type,
+            user_id: message.user_id,
+            user_name: profile.map(|p| p.user_name.clone()).unwrap_or_default(),
+            full_name: profile.map(|p| p.full_name.clone()).unwrap_or_default(),
+            avatar: profile.and_then(|p| p.avatar.clone()),
+            broadcast_msg_id: broadcast.map(|(bm, _)| bm.id),
+            broadcast_msg_updated_at: broadcast.map(|(bm, _)| bm.updated_at),
+            broadcast_msg_body: broadcast.and_then(|(bm, _)| bm.body.clone()),
+            broadcast_msg_likes: broadcast.map(|(bm, _)| bm.likes),
+            broadcast_msg_image: broadcast.and_then(|(bm, _)| bm.image.clone()),
+            broadcast_msg_user_id: broadcast.map(|(bm, _)| bm.user_id),
+            broadcast_msg_user_name: broadcast.map(|(_, bp)| bp.user_name.clone()),
+            broadcast_msg_full_name: broadcast.map(|(_, bp)| bp.full_name.clone()),
+            broadcast_msg_avatar: broadcast.and_then(|(_, bp)| bp.avatar.clone()),
+        }
+    }
+}
+
+/// keeps every table in process memory, for demos and running the http tests without postgres.
+/// Clones share the same data and nothing survives a restart
+#[derive(Clone, Default)]
+pub struct MemoryRepo {
+    state: Arc<RwLock<MemoryState>>,
+    events: MemoryEventBus,
+}
+
+impl MemoryRepo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // a panic while holding the lock cannot leave a table half written, every write is a single insert or update
+    fn read(&self) -> RwLockReadGuard<'_, MemoryState> {
+        self.state.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, MemoryState> {
+        let mut state = self.state.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.version += 1;
+        state
+    }
+
+    /// a unit of work on a copy of the current state, the repo traits implemented for MemoryRepo run on it
+    /// through Deref. Nothing it writes is visible until commit
+    pub fn begin(&self) -> MemoryUnitOfWork {
+        let state = self.read();
+        let repo = MemoryRepo { state: Arc::new(RwLock::new(state.clone())), events: MemoryEventBus::new() };
+        MemoryUnitOfWork {
+            events: repo.events.subscribe(),
+            base_version: state.version,
+            shared: self.clone(),
+            repo,
+        }
+    }
+}
+
+/// the memory UnitOfWork, rolling back is dropping the copy. Commit fails rather than overwrite the writes of
+/// another caller since begin, like a serialization failure in postgres
+pub struct MemoryUnitOfWork {
+    repo: MemoryRepo,
+    // everything the copy published, held back until commit
+    events: broadcast::Receiver<AppEvent>,
+    base_version: u64,
+    shared: MemoryRepo,
+}
+
+impl MemoryUnitOfWork {
+    pub async fn commit(mut self) -> Result<(), sqlx::Error> {
+        let mut events = vec![];
+        loop {
+            match self.events.try_recv() {
+                Ok(event) => events.push(event),
+                Err(broadcast::error::TryRecvError::Empty) => break,
+                Err(e) => return Err(sqlx::Error::Protocol(format!("unit of work lost its events: {}", e))),
+            }
+        }
+
+        {
+            let committed = self.repo.read().clone();
+            let mut shared = self.shared.write();
+            // the write above counted this commit
+            if shared.version != self.base_version + 1 {
+                return Err(sqlx::Error::Protocol("the memory repo changed since the unit of work began".to_string()));
+            }
+            let version = shared.version;
+            *shared = committed;
+            shared.version = version;
+        }
+
+        for event in events {
+            self.shared.events.publish(event).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn rollback(self) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+}
+
+impl Deref for MemoryUnitOfWork {
+    type Target = MemoryRepo;
+
+    fn deref(&self) -> &Self::Target {
+        &self.repo
+    }
+}
+
+#[async_trait]
+impl InsertMessageFn for MemoryRepo {
+    #[instrument(skip_all, fields(user_id = user_id, group_type = group_type))]
+    async fn insert_message(
+        &self,
+        user_id: i64,
+        body: &str,
+        group_type: i32,
+        broadcasting_msg_id: Option<i64>
+    ) -> Result<i64, sqlx::Error> {
+        let _timer = start_query_timer("insert_message_inner");
+        // the lock is released before publishing, a guard held across an await keeps the future from being Send
+        let (event, notifications) = {
+            let mut state = self.write();
+
+            let broadcasting_msg = match broadcasting_msg_id {
+                Some(bm_id) => Some(state.get_message_ref(bm_id).ok_or_else(get_missing_reference_error)?),
+                None => None,
+            };
+            let message_id = state.insert_message(user_id, body, group_type)?;
+            if let Some(bm_id) = broadcasting_msg_id {
+                state.message_broadcasts.insert(|_| MessageBroadcastRow { main_msg_id: message_id, broadcasting_msg_id: bm_id });
+            }
+            let event = MessageCreated { id: message_id, user_id, msg_group_type: group_type, original_msg: None, broadcasting_msg };
+            let notifications = state.insert_message_notifications(&event, body);
+            state.insert_webhook_deliveries(&AppEvent::MessageCreated(event.clone()))?;
+            (event, notifications)
+        };
+
+        let message_id = event.id;
+        for notification in notifications {
+            self.events.publish(AppEvent::NotificationCreated(notification)).await?;
+        }
+        self.events.publish(AppEvent::MessageCreated(event)).await?;
+        Ok(message_id)
+    }
+}
+
+#[async_trait]
+impl InsertResponseMessageFn for MemoryRepo {
+    #[instrument(skip_all, fields(user_id = user_id))]
+    async fn insert_response_message(
+        &self,
+        user_id: i64,
+        body: &str,
+        group_type: i32,
+        original_msg_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        let _timer = start_query_timer("insert_response_message_inner");
+        let (event, notifications) = {
+            let mut state = self.write();
+
+            let original_msg = state.get_message_ref(original_msg_id).ok_or_else(get_missing_reference_error)?;
+            let msg_id = state.insert_message(user_id, body, group_type)?;
+            state.message_responses.insert(|_| MessageResponseRow { original_msg_id, responding_msg_id: msg_id });
+            let event = MessageCreated { id: msg_id, user_id, msg_group_type: group_type, original_msg: Some(original_msg), broadcasting_msg: None };
+            let notifications = state.insert_message_notifications(&event, body);
+            state.insert_webhook_deliveries(&AppEvent::MessageCreated(event.clone()))?;
+            (event, notifications)
+        };
+
+        let msg_id = event.id;
+        for notification in notifications {
+            self.events.publish(AppEvent::NotificationCreated(notification)).await?;
+        }
+        self.events.publish(AppEvent::MessageCreated(event)).await?;
+        Ok(msg_id)
+    }
+}
+
+#[async_trait]
+impl LikeMessageFn for MemoryRepo {
+    #[instrument(skip_all, fields(user_id = user_id, msg_id = msg_id))]
+    async fn like_message(&self, user_id: i64, msg_id: i64) -> Result<bool, sqlx::Error> {
+        let _timer = start_query_timer("like_message_inner");
+        let (event, notification) = {
+            let mut state = self.write();
+
+            if !state.profiles.contains(user_id) {
+                return Err(get_missing_reference_error());
+            }
+            let message = state.get_message_ref(msg_id).ok_or_else(get_missing_reference_error)?;
+            if state.message_likes.rows.values().any(|like| like.user_id == user_id && like.message_id == msg_id) {
+                return Ok(false);
+            }
+            let id = state.message_likes.insert(|_| MessageLikeRow { user_id, message_id: msg_id });
+            if let Some(liked) = state.messages.rows.get_mut(&msg_id) {
+                liked.likes += 1;
+            }
+            let event = MessageLiked { id, user_id, message };
+            let notification = state.insert_notification(NotificationCreate::for_like(&event));
+            state.insert_webhook_deliveries(&AppEvent::MessageLiked(event))?;
+            (event, notification)
+        };
+
+        if let Some(notification) = notification {
+            self.events.publish(AppEvent::NotificationCreated(notification)).await?;
+        }
+        self.events.publish(AppEvent::MessageLiked(event)).await?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl QueryMessageFn for MemoryRepo {
+    #[instrument(skip_all, fields(id = id))]
+    async fn query_message(
+        &self,
+        id: i64
+    ) -> Result<Option<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error> {
+        let _timer = start_query_timer("query_message_inner");
+        let state = self.read();
+
+        Ok(state.messages.get(id).map(|message| state.get_message_with_broadcast(message)))
+    }
+}
+
+#[async_trait]
+impl QueryMessagesFn for MemoryRepo {
+    /// messages of every followed profile older than last_updated_at, newest first
+    #[instrument(skip_all, fields(user_id = user_id, page_size = page_size))]
+    async fn query_messages(
+        &self,
+        user_id: i64,
+        last_updated_at: DateTime<Utc>,
+        page_size: i16
+    ) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error> {
+        let _timer = start_query_timer("query_messages_inner");
+        let state = self.read();
+
+        let mut following_messages = state.follows.rows
+            .values()
+            .filter(|follow| follow.follower_id == user_id)
+            .flat_map(|follow| {
+                state.messages.rows
+                    .values()
+                    .filter(move |message| message.user_id == follow.following_id && message.updated_at < last_updated_at)
+            })
+            .collect::<Vec<&MessageQueryResult>>();
+        // newest id first breaks ties, like the sql backends
+        following_messages.sort_by(|a, b| b.updated_at.cmp(&a.updated_at).then(b.id.cmp(&a.id)));
+        following_messages.truncate(usize::try_from(page_size).unwrap_or(0));
+
+        Ok(
+            following_messages
+                .into_iter()
+                .map(|message| state.get_message_with_broadcast(message))
+                .collect()
+        )
+    }
+}
+
+#[async_trait]
+impl QueryMessagesAfterFn for MemoryRepo {
+    #[instrument(skip_all, fields(after_id = after_id, page_size = page_size))]
+    async fn query_messages_after(
+        &self,
+        after_id: i64,
+        filter: &MessageStreamFilter,
+        page_size: i16
+    ) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error> {
+        let _timer = start_query_timer("query_messages_after_inner");
+        let state = self.read();
+
+        Ok(
+            state.messages.rows
+                .range(after_id + 1..)
+                .map(|(_, message)| message)
+                .filter(|message| message.msg_group_type == filter.group_type)
+                .filter(|message| filter.user_id.is_none_or(|user_id| message.user_id == user_id))
+                .filter(|message| {
+                    filter.tag.as_ref().is_none_or(|tag| message.body.as_deref().is_some_and(|body| mentions_hashtag(body, tag)))
+                })
+                .take(usize::try_from(page_size).unwrap_or(0))
+                .map(|message| state.get_message_with_broadcast(message))
+                .collect()
+        )
+    }
+
+    #[instrument(skip_all)]
+    async fn query_last_message_id(&self) -> Result<Option<i64>, sqlx::Error> {
+        let _timer = start_query_timer("query_last_message_id_inner");
+        Ok(self.read().messages.rows.keys().next_back().copied())
+    }
+}
+
+#[async_trait]
+impl QueryMessageCountsFn for MemoryRepo {
+    #[instrument(skip_all, fields(count = ids.len()))]
+    async fn query_message_counts(
+        &self,
+        ids: &[i64]
+    ) -> Result<Vec<MessageCountsQueryResult>, sqlx::Error> {
+        let _timer = start_query_timer("query_message_counts_inner");
+        let state = self.read();
+
+        Ok(
+            ids
+                .iter()
+                .map(|&id| {
+                    let broadcasts = state.message_broadcasts.rows
+                        .values()
+                        .filter(|broadcast| broadcast.broadcasting_msg_id == id)
+                        .filter_map(|broadcast| state.messages.get(broadcast.main_msg_id))
+                        .collect::<Vec<&MessageQueryResult>>();
+                    let quote_count = broadcasts
+                        .iter()
+                        .filter(|broadcast| !broadcast.body.as_deref().unwrap_or_default().is_empty())
+                        .count() as i64;
+
+                    MessageCountsQueryResult {
+                        id,
+                        reply_count: state.message_responses.rows
+                            .values()
+                            .filter(|response| response.original_msg_id == id)
+                            .count() as i64,
+                        rebroadcast_count: broadcasts.len() as i64 - quote_count,
+                        quote_count,
+                    }
+                })
+                .collect()
+        )
+    }
+}
+
+#[async_trait]
+impl InsertProfileFn for MemoryRepo {
+    #[instrument(skip_all)]
+    async fn insert_profile(
+        &self,
+        params: ProfileCreate
+    ) -> Result<i64, sqlx::Error> {
+        let _timer = start_query_timer("insert_profile_inner");
+        let now = get_now();
+
+        Ok(
+            self.write().profiles.insert(|id| ProfileQueryResult {
+                id,
+                created_at: now,
+                updated_at: now,
+                user_name: params.user_name,
+                full_name: params.full_name,
+                description: params.description,
+                region: params.region,
+                main_url: params.main_url,
+                avatar: params.avatar,
+            })
+        )
+    }
+}
+
+#[async_trait]
+impl UpdateProfileAvatarFn for MemoryRepo {
+    /// like an update matching no rows, an unknown user is not an error
+    #[instrument(skip_all, fields(user_id = user_id))]
+    async fn update_profile_avatar(
+        &self,
+        user_id: i64,
+        avatar: Vec<u8>
+    ) -> Result<(), sqlx::Error> {
+        let _timer = start_query_timer("update_profile_avatar_inner");
+
+        if let Some(profile) = self.write().profiles.rows.get_mut(&user_id) {
+            profile.avatar = Some(avatar);
+            profile.updated_at = get_now();
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl QueryProfileFn for MemoryRepo {
+    #[instrument(skip_all, fields(id = id))]
+    async fn query_profile(
+        &self,
+        id: i64
+    ) -> Result<Option<ProfileQueryResult>, sqlx::Error> {
+        let _timer = start_query_timer("query_profile_inner");
+
+        Ok(self.read().profiles.get(id).cloned())
+    }
+}
+
+#[async_trait]
+impl QueryProfileByUserFn for MemoryRepo {
+    #[instrument(skip_all)]
+    async fn query_profile_by_user(
+        &self,
+        user_name: String
+    ) -> Result<Option<ProfileQueryResult>, sqlx::Error> {
+        let _timer = start_query_timer("query_profile_by_user_inner");
+
+        Ok(
+            self.read().profiles.rows
+                .values()
+                .find(|profile| profile.user_name == user_name)
+                .cloned()
+        )
+    }
+}
+
+#[async_trait]
+impl FollowUserFn for MemoryRepo {
+    #[instrument(skip_all, fields(follower_id = follower_id, following_id = following_id))]
+    async fn follow_user(
+        &self,
+        follower_id: i64,
+        following_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        let _timer = start_query_timer("follow_user_inner");
+        let (event, notification) = {
+            let mut state = self.write();
+
+            if !state.profiles.contains(follower_id) || !state.profiles.contains(following_id) {
+                return Err(get_missing_reference_error());
+            }
+            let id = state.follows.insert(|_| FollowRow { follower_id, following_id });
+            let event = FollowCreated { id, follower_id, following_id };
+            let notification = state.insert_notification(NotificationCreate::for_follow(&event));
+            state.insert_webhook_deliveries(&AppEvent::FollowCreated(event))?;
+            (event, notification)
+        };
+
+        if let Some(notification) = notification {
+            self.events.publish(AppEvent::NotificationCreated(notification)).await?;
+        }
+        self.events.publish(AppEvent::FollowCreated(event)).await?;
+        Ok(event.id)
+    }
+}
+
+#[async_trait]
+impl InsertCircleFn for MemoryRepo {
+    #[instrument(skip_all)]
+    async fn insert_circle(
+        &self,
+        circle_owner_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        let _timer = start_query_timer("insert_circle_inner");
+        let mut state = self.write();
+
+        if !state.profiles.contains(circle_owner_id) {
+            return Err(get_missing_reference_error());
+        }
+        let now = get_now();
+        Ok(
+            state.circle_groups.insert(|id| CircleGroupQueryResult {
+                id,
+                created_at: now,
+                updated_at: now,
+                owner_id: circle_owner_id,
+            })
+        )
+    }
+}
+
+#[async_trait]
+impl InsertCircleMemberFn for MemoryRepo {
+    #[instrument(skip_all)]
+    async fn insert_circle_member(
+        &self,
+        circle_group_id: i64,
+        new_member_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        let _timer = start_query_timer("insert_circle_member_inner");
+        let mut state = self.write();
+
+        if !state.circle_groups.contains(circle_group_id) || !state.profiles.contains(new_member_id) {
+            return Err(get_missing_reference_error());
+        }
+        let now = get_now();
+        Ok(
+            state.circle_group_members.insert(|id| CircleGroupMemberQueryResult {
+                id,
+                created_at: now,
+                updated_at: now,
+                circle_group_id,
+                member_id: new_member_id,
+            })
+        )
+    }
+}
+
+#[async_trait]
+impl QueryCircleFn for MemoryRepo {
+    #[instrument(skip_all)]
+    async fn query_circle(
+        &self,
+        id: i64
+    ) -> Result<Option<CircleGroupWithProfileQueryResult>, sqlx::Error> {
+        let _timer = start_query_timer("query_circle_inner");
+        let state = self.read();
+
+        Ok(
+            state.circle_groups.get(id).and_then(|circle| {
+                state.profiles.get(circle.owner_id).map(|owner| CircleGroupWithProfileQueryResult {
+                    id: circle.id,
+                    updated_at: circle.updated_at,
+                    owner_id: circle.owner_id,
+                    user_name: owner.user_name.clone(),
+                    full_name: owner.full_name.clone(),
+                    avatar: owner.avatar.clone(),
+                })
+            })
+        )
+    }
+}
+
+#[async_trait]
+impl QueryCircleMemberFn for MemoryRepo {
+    #[instrument(skip_all)]
+    async fn query_circle_member(
+        &self,
+        id: i64
+    ) -> Result<Option<CircleGroupMemberWithProfileQueryResult>, sqlx::Error> {
+        let _timer = start_query_timer("query_circle_member_inner");
+        let state = self.read();
+
+        Ok(
+            state.circle_group_members.get(id).and_then(|member| {
+                state.profiles.get(member.member_id).map(|profile| CircleGroupMemberWithProfileQueryResult {
+                    id: member.id,
+                    updated_at: member.updated_at,
+                    circle_group_id: member.circle_group_id,
+                    member_id: profile.id,
+                    user_name: profile.user_name.clone(),
+                    full_name: profile.full_name.clone(),
+                    avatar: profile.avatar.clone(),
+                })
+            })
+        )
+    }
+}
+
+impl SubscribeEventsFn for MemoryRepo {
+    fn subscribe_events(&self) -> broadcast::Receiver<AppEvent> {
+        self.events.subscribe()
+    }
+}
+
+#[async_trait]
+impl QueryFollowingIdsFn for MemoryRepo {
+    #[instrument(skip_all, fields(follower_id = follower_id))]
+    async fn query_following_ids(&self, follower_id: i64) -> Result<Vec<i64>, sqlx::Error> {
+        let _timer = start_query_timer("query_following_ids_inner");
+        let mut following_ids = self.read().follows.rows
+            .values()
+            .filter(|follow| follow.follower_id == follower_id)
+            .map(|follow| follow.following_id)
+            .collect::<Vec<i64>>();
+        following_ids.sort_unstable();
+        following_ids.dedup();
+        Ok(following_ids)
+    }
+}
+
+#[async_trait]
+impl RebuildTimelinesFn for MemoryRepo {
+    /// timelines are assembled from follows when read, there is nothing stored to rebuild
+    async fn rebuild_timelines(&self, _owner_id: Option<i64>) -> Result<u64, sqlx::Error> {
+        Ok(0)
+    }
+}
+
+#[async_trait]
+impl QueryNotificationGroupsFn for MemoryRepo {
+    #[instrument(skip_all, fields(recipient_id = recipient_id, page_size = page_size))]
+    async fn query_notification_groups(
+        &self,
+        recipient_id: i64,
+        before_id: Option<i64>,
+        page_size: i16
+    ) -> Result<Vec<NotificationGroupQueryResult>, sqlx::Error> {
+        let _timer = start_query_timer("query_notification_groups_inner");
+        let state = self.read();
+
+        // rows are visited oldest first, so the last id seen of a group is its latest
+        let mut groups: HashMap<(NotificationKind, Option<i64>), (i64, HashSet<i64>, i64)> = HashMap::new();
+        for (&id, notification) in state.notifications.rows.iter().filter(|(_, n)| n.recipient_id == recipient_id) {
+            let (latest_id, actor_ids, unread_count) = groups
+                .entry((notification.kind, notification.target_msg_id))
+                .or_insert_with(|| (id, HashSet::new(), 0));
+            *latest_id = id;
+            actor_ids.insert(notification.actor_id);
+            if notification.read_at.is_none() {
+                *unread_count += 1;
+            }
+        }
+
+        let mut page = groups
+            .into_iter()
+            .filter(|(_, (latest_id, _, _))| before_id.is_none_or(|before_id| *latest_id < before_id))
+            .filter_map(|((kind, target_msg_id), (latest_id, actor_ids, unread_count))| {
+                let latest = state.notifications.get(latest_id)?;
+                let actor = state.profiles.get(latest.actor_id)?;
+                Some(NotificationGroupQueryResult {
+                    latest_id,
+                    latest_created_at: latest.created_at,
+                    kind,
+                    target_msg_id,
+                    source_msg_id: latest.source_msg_id,
+                    actor_count: actor_ids.len() as i64,
+                    unread_count,
+                    actor_id: actor.id,
+                    user_name: actor.user_name.clone(),
+                    full_name: actor.full_name.clone(),
+                })
+            })
+            .collect::<Vec<NotificationGroupQueryResult>>();
+        page.sort_by_key(|group| Reverse(group.latest_id));
+        page.truncate(usize::try_from(page_size).unwrap_or(0));
+        Ok(page)
+    }
+}
+
+#[async_trait]
+impl QueryUnreadNotificationCountFn for MemoryRepo {
+    #[instrument(skip_all, fields(recipient_id = recipient_id))]
+    async fn query_unread_notification_count(&self, recipient_id: i64) -> Result<i64, sqlx::Error> {
+        let _timer = start_query_timer("query_unread_notification_count_inner");
+
+        Ok(
+            self.read().notifications.rows
+                .values()
+                .filter(|notification| notification.recipient_id == recipient_id && notification.read_at.is_none())
+                .count() as i64
+        )
+    }
+}
+
+#[async_trait]
+impl MarkNotificationsReadFn for MemoryRepo {
+    #[instrument(skip_all, fields(recipient_id = recipient_id, up_to_id = up_to_id))]
+    async fn mark_notifications_read(&self, recipient_id: i64, up_to_id: Option<i64>) -> Result<u64, sqlx::Error> {
+        let _timer = start_query_timer("mark_notifications_read_inner");
+        let now = get_now();
+
+        let mut state = self.write();
+        let mut marked = 0;
+        for (_, notification) in state.notifications.rows.range_mut(..=up_to_id.unwrap_or(i64::MAX)) {
+            if notification.recipient_id == recipient_id && notification.read_at.is_none() {
+                notification.read_at = Some(now);
+                marked += 1;
+            }
+        }
+        Ok(marked)
+    }
+}
+
+#[async_trait]
+impl MarkNotificationGroupReadFn for MemoryRepo {
+    #[instrument(skip_all, fields(recipient_id = recipient_id, target_msg_id = target_msg_id))]
+    async fn mark_notification_group_read(
+        &self,
+        recipient_id: i64,
+        kind: NotificationKind,
+        target_msg_id: Option<i64>
+    ) -> Result<u64, sqlx::Error> {
+        let _timer = start_query_timer("mark_notification_group_read_inner");
+        let now = get_now();
+
+        let mut state = self.write();
+        let mut marked = 0;
+        for notification in state.notifications.rows.values_mut() {
+            if notification.recipient_id == recipient_id
+                && notification.read_at.is_none()
+                && notification.kind == kind
+                && notification.target_msg_id == target_msg_id
+            {
+                notification.read_at = Some(now);
+                marked += 1;
+            }
+        }
+        Ok(marked)
+    }
+}
+
+#[async_trait]
+impl InsertWebhookSubscriptionFn for MemoryRepo {
+    #[instrument(skip_all, fields(user_id = params.user_id))]
+    async fn insert_webhook_subscription(&self, params: WebhookSubscriptionCreate) -> Result<i64, sqlx::Error> {
+        let _timer = start_query_timer("insert_webhook_subscription_inner");
+        let mut state = self.write();
+        if !state.profiles.contains(params.user_id) {
+            return Err(get_missing_reference_error());
+        }
+
+        let now = get_now();
+        Ok(
+            state.webhook_subscriptions.insert(|id| WebhookSubscriptionQueryResult {
+                id,
+                created_at: now,
+                user_id: params.user_id,
+                url: params.url,
+                secret: params.secret,
+                event_types: params.event_types,
+            })
+        )
+    }
+}
+
+#[async_trait]
+impl QueryWebhookSubscriptionsFn for MemoryRepo {
+    #[instrument(skip_all, fields(user_id = user_id))]
+    async fn query_webhook_subscriptions(&self, user_id: i64) -> Result<Vec<WebhookSubscriptionQueryResult>, sqlx::Error> {
+        let _timer = start_query_timer("query_webhook_subscriptions_inner");
+
+        Ok(
+            self.read().webhook_subscriptions.rows
+                .values()
+                .filter(|subscription| subscription.user_id == user_id)
+                .cloned()
+                .collect()
+        )
+    }
+}
+
+#[async_trait]
+impl ClaimWebhookDeliveriesFn for MemoryRepo {
+    #[instrument(skip_all, fields(limit = limit))]
+    async fn claim_webhook_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: i16
+    ) -> Result<Vec<WebhookDueDelivery>, sqlx::Error> {
+        let _timer = start_query_timer("claim_webhook_deliveries_inner");
+        let mut state = self.write();
+
+        let mut due = state.webhook_deliveries.rows
+            .values()
+            .map(|row| &row.delivery)
+            .filter(|delivery| {
+                delivery.status == WebhookDeliveryStatus::Pending && delivery.next_attempt_at.is_some_and(|at| at <= now)
+            })
+            .map(|delivery| (delivery.next_attempt_at, delivery.id))
+            .collect::<Vec<(Option<DateTime<Utc>>, i64)>>();
+        due.sort();
+        due.truncate(usize::try_from(limit).unwrap_or(0));
+
+        let mut claimed = vec![];
+        for (_, id) in due {
+            if let Some(due_delivery) = state.get_webhook_due_delivery(id) {
+                if let Some(row) = state.webhook_deliveries.rows.get_mut(&id) {
+                    row.delivery.next_attempt_at = Some(lease_until);
+                }
+                claimed.push(due_delivery);
+            }
+        }
+        Ok(claimed)
+    }
+}
+
+#[async_trait]
+impl RecordWebhookAttemptFn for MemoryRepo {
+    #[instrument(skip_all, fields(id = id))]
+    async fn record_webhook_attempt(
+        &self,
+        id: i64,
+        attempt: &WebhookAttempt
+    ) -> Result<Option<WebhookDeliveryQueryResult>, sqlx::Error> {
+        let _timer = start_query_timer("record_webhook_attempt_inner");
+        let mut state = self.write();
+
+        Ok(
+            state.webhook_deliveries.rows.get_mut(&id).map(|row| {
+                let delivery = &mut row.delivery;
+                delivery.status = attempt.status;
+                delivery.attempt_count += 1;
+                delivery.last_attempt_at = Some(attempt.attempted_at);
+                delivery.last_status_code = attempt.status_code;
+                delivery.last_error = attempt.error.clone();
+                delivery.next_attempt_at = attempt.next_attempt_at;
+                delivery.clone()
+            })
+        )
+    }
+}
+
+#[async_trait]
+impl QueryWebhookDeliveriesFn for MemoryRepo {
+    #[instrument(skip_all, fields(subscription_id = subscription_id, page_size = page_size))]
+    async fn query_webhook_deliveries(
+        &self,
+        subscription_id: i64,
+        page_size: i16
+    ) -> Result<Vec<WebhookDeliveryQueryResult>, sqlx::Error> {
+        let _timer = start_query_timer("query_webhook_deliveries_inner");
+
+        Ok(
+            self.read().webhook_deliveries.rows
+                .values()
+                .rev()
+                .filter(|row| row.delivery.subscription_id == subscription_id)
+                .take(usize::try_from(page_size).unwrap_or(0))
+                .map(|row| row.delivery.clone())
+                .collect()
+        )
+    }
+}
+
+#[async_trait]
+impl ReplayWebhookDeliveryFn for MemoryRepo {
+    #[instrument(skip_all, fields(subscription_id = subscription_id, delivery_id = delivery_id))]
+    async fn replay_webhook_delivery(
+        &self,
+        subscription_id: i64,
+        delivery_id: i64,
+        lease_until: DateTime<Utc>
+    ) -> Result<Option<WebhookDueDelivery>, sqlx::Error> {
+        let _timer = start_query_timer("replay_webhook_delivery_inner");
+        let mut state = self.write();
+
+        let Some((event_type, payload)) = state.webhook_deliveries
+            .get(delivery_id)
+            .map(|row| &row.delivery)
+            .filter(|delivery| delivery.subscription_id == subscription_id)
+            .map(|delivery| (delivery.event_type, delivery.payload.clone())) else {
+            return Ok(None);
+        };
+        let id = state.insert_webhook_delivery(subscription_id, event_type, None, payload, lease_until);
+        Ok(state.get_webhook_due_delivery(id))
+    }
+}
+
+#[async_trait]
+impl QuerySchemaVersionFn for MemoryRepo {
+    /// there is no schema to drift, the data always has the shape this build expects
+    async fn query_schema_version(&self) -> Result<i64, sqlx::Error> {
+        Ok(get_expected_version())
+    }
+}
+
+impl PoolStatsFn for MemoryRepo {
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats { max: 0, open: 0, idle: 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common_tests::actix_fixture::PUBLIC_GROUP_TYPE;
+
+    fn get_profile_create(user_name: &str) -> ProfileCreate {
+        ProfileCreate {
+            user_name: user_name.to_string(),
+            full_name: format!("{} Full", user_name),
+            description: "a description".to_string(),
+            region: Some("usa".to_string()),
+            main_url: None,
+            avatar: Some(vec![1, 2, 3]),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_insert_and_query_profile() {
+        let repo = MemoryRepo::new();
+
+        let first_id = repo.insert_profile(get_profile_create("first")).await.unwrap();
+        let second_id = repo.insert_profile(get_profile_create("second")).await.unwrap();
+        repo.update_profile_avatar(second_id, vec![9]).await.unwrap();
+
+        assert_eq!((first_id, second_id), (1, 2));
+        assert_eq!(repo.query_profile(first_id).await.unwrap().unwrap().user_name, "first");
+        assert_eq!(repo.query_profile_by_user("second".to_string()).await.unwrap().unwrap().avatar, Some(vec![9]));
+        assert!(repo.query_profile(3).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_insert_message_requires_existing_references() {
+        let repo = MemoryRepo::new();
+        let user_id = repo.insert_profile(get_profile_create("author")).await.unwrap();
+
+        assert!(repo.insert_message(user_id + 1, "body", PUBLIC_GROUP_TYPE, None).await.is_err());
+        assert!(repo.insert_message(user_id, "body", PUBLIC_GROUP_TYPE, Some(42)).await.is_err());
+        assert!(repo.insert_response_message(user_id, "body", PUBLIC_GROUP_TYPE, 42).await.is_err());
+        // nothing was written by the failed inserts
+        assert_eq!(repo.insert_message(user_id, "body", PUBLIC_GROUP_TYPE, None).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_query_message_includes_broadcast_message() {
+        let repo = MemoryRepo::new();
+        let author_id = repo.insert_profile(get_profile_create("author")).await.unwrap();
+        let broadcaster_id = repo.insert_profile(get_profile_create("broadcaster")).await.unwrap();
+        let original_id = repo.insert_message(author_id, "original", PUBLIC_GROUP_TYPE, None).await.unwrap();
+
+        let broadcast_id = repo.insert_message(broadcaster_id, "look", PUBLIC_GROUP_TYPE, Some(original_id)).await.unwrap();
+        let message = repo.query_message(broadcast_id).await.unwrap().unwrap();
+
+        assert_eq!(message.user_name, "broadcaster");
+        assert_eq!(message.broadcast_msg_id, Some(original_id));
+        assert_eq!(message.broadcast_msg_body.as_deref(), Some("original"));
+        assert_eq!(message.broadcast_msg_user_id, Some(author_id));
+        assert_eq!(message.broadcast_msg_user_name.as_deref(), Some("author"));
+        assert!(repo.query_message(original_id).await.unwrap().unwrap().broadcast_msg_id.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_query_message_counts_splits_rebroadcasts_and_quotes() {
+        let repo = MemoryRepo::new();
+        let author_id = repo.insert_profile(get_profile_create("author")).await.unwrap();
+        let original_id = repo.insert_message(author_id, "original", PUBLIC_GROUP_TYPE, None).await.unwrap();
+        repo.insert_response_message(author_id, "reply", PUBLIC_GROUP_TYPE, original_id).await.unwrap();
+        repo.insert_message(author_id, "", PUBLIC_GROUP_TYPE, Some(original_id)).await.unwrap();
+        repo.insert_message(author_id, "quote", PUBLIC_GROUP_TYPE, Some(original_id)).await.unwrap();
+
+        let counts = repo.query_message_counts(&[original_id]).await.unwrap();
+
+        assert_eq!(counts, vec![MessageCountsQueryResult { id: original_id, reply_count: 1, rebroadcast_count: 1, quote_count: 1 }]);
+    }
+
+    #[tokio::test]
+    async fn test_query_messages_pages_followed_messages_newest_first() {
+        let repo = MemoryRepo::new();
+        let follower_id = repo.insert_profile(get_profile_create("follower")).await.unwrap();
+        let following_id = repo.insert_profile(get_profile_create("following")).await.unwrap();
+        let stranger_id = repo.insert_profile(get_profile_create("stranger")).await.unwrap();
+        repo.follow_user(follower_id, following_id).await.unwrap();
+        for index in 0..5 {
+            repo.insert_message(following_id, &format!("followed {}", index), PUBLIC_GROUP_TYPE, None).await.unwrap();
+            repo.insert_message(stranger_id, "not followed", PUBLIC_GROUP_TYPE, None).await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+
+        let first_page = repo.query_messages(follower_id, Utc::now(), 3).await.unwrap();
+        let second_page = repo.query_messages(follower_id, first_page.last().unwrap().updated_at, 3).await.unwrap();
+
+        let bodies = first_page
+            .iter()
+            .chain(second_page.iter())
+            .map(|message| message.body.clone().unwrap())
+            .collect::<Vec<String>>();
+        assert_eq!(bodies, ["followed 4", "followed 3", "followed 2", "followed 1", "followed 0"]);
+        assert!(first_page.iter().all(|message| message.user_id == following_id));
+    }
+
+    #[tokio::test]
+    async fn test_unit_of_work_publishes_its_events_on_commit() {
+        let repo = MemoryRepo::new();
+        let author_id = repo.insert_profile(get_profile_create("author")).await.unwrap();
+        let mut events = repo.subscribe_events();
+
+        let uow = repo.begin();
+        let message_id = uow.insert_message(author_id, "held back", PUBLIC_GROUP_TYPE, None).await.unwrap();
+        assert!(events.try_recv().is_err());
+        uow.commit().await.unwrap();
+
+        assert!(matches!(events.try_recv(), Ok(AppEvent::MessageCreated(event)) if event.id == message_id));
+        assert!(repo.query_message(message_id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_unit_of_work_does_not_commit_over_a_concurrent_write() {
+        let repo = MemoryRepo::new();
+        let author_id = repo.insert_profile(get_profile_create("author")).await.unwrap();
+
+        let uow = repo.begin();
+        let uow_message_id = uow.insert_message(author_id, "in the unit of work", PUBLIC_GROUP_TYPE, None).await.unwrap();
+        let message_id = repo.insert_message(author_id, "meanwhile", PUBLIC_GROUP_TYPE, None).await.unwrap();
+
+        assert!(uow.commit().await.is_err());
+        assert!(repo.query_message(message_id).await.unwrap().is_some_and(|message| message.body.as_deref() == Some("meanwhile")));
+        // both got the same id, the one written outside the unit of work is kept
+        assert_eq!(uow_message_id, message_id);
+    }
+
+    #[tokio::test]
+    async fn test_query_messages_after_filters_oldest_first() {
+        let repo = MemoryRepo::new();
+        let author_id = repo.insert_profile(get_profile_create("author")).await.unwrap();
+        let other_id = repo.insert_profile(get_profile_create("other")).await.unwrap();
+        let first_id = repo.insert_message(author_id, "#Rust first", PUBLIC_GROUP_TYPE, None).await.unwrap();
+        repo.insert_message(other_id, "#rust by another author", PUBLIC_GROUP_TYPE, None).await.unwrap();
+        repo.insert_message(author_id, "#rust in a circle", 2, None).await.unwrap();
+        let second_id = repo.insert_message(author_id, "second #rust", PUBLIC_GROUP_TYPE, None).await.unwrap();
+        let filter = MessageStreamFilter { group_type: PUBLIC_GROUP_TYPE, user_id: Some(author_id), tag: Some("rust".to_string()) };
+
+        let ids = |messages: Vec<MessageWithFollowingAndBroadcastQueryResult>| messages.iter().map(|message| message.id).collect::<Vec<i64>>();
+        assert_eq!(ids(repo.query_messages_after(0, &filter, 10).await.unwrap()), [first_id, second_id]);
+        assert_eq!(ids(repo.query_messages_after(first_id, &filter, 10).await.unwrap()), [second_id]);
+    }
+
+    #[tokio::test]
+    async fn test_insert_and_query_circle_and_member() {
+        let repo = MemoryRepo::new();
+        let owner_id = repo.insert_profile(get_profile_create("owner")).await.unwrap();
+        let member_id = repo.insert_profile(get_profile_create("member")).await.unwrap();
+
+        let circle_id = repo.insert_circle(owner_id).await.unwrap();
+        let circle_member_id = repo.insert_circle_member(circle_id, member_id).await.unwrap();
+        let circle = repo.query_circle(circle_id).await.unwrap().unwrap();
+        let circle_member = repo.query_circle_member(circle_member_id).await.unwrap().unwrap();
+
+        assert_eq!(circle.owner_id, owner_id);
+        assert_eq!(circle.user_name, "owner");
+        assert_eq!(circle_member.circle_group_id, circle_id);
+        assert_eq!(circle_member.member_id, member_id);
+        assert_eq!(circle_member.avatar, Some(vec![1, 2, 3]));
+        assert!(repo.insert_circle_member(circle_id + 1, member_id).await.is_err());
+    }
+}
@@ -15,24 +15,6 @@ pub struct MessageQueryResult {
     pub msg_group_type: i32
 }
 
-#[derive(Deserialize, Serialize, FromRow, Clone, Debug)]
-pub struct MessageWithProfileQueryResult {
-    // messsage fields
-    pub id: i64,
-    pub updated_at: DateTime<Utc>,
-    pub body: Option<String>,
-    pub likes: i32,
-    pub image: Option<Vec<u8>>,    
-    pub msg_group_type: i32,
-    // profile fields
-    pub user_id: i64,
-    pub user_name: String,
-    pub full_name: String,
-    pub avatar: Option<Vec<u8>>,
-    // broadcast message fields
-    pub broadcast_msg_id: Option<i64>    
-}
-
 #[derive(Deserialize, Serialize, FromRow, Clone, Debug)]
 pub struct MessageWithFollowingAndBroadcastQueryResult {
     // messsage fields
@@ -57,4 +39,60 @@ pub struct MessageWithFollowingAndBroadcastQueryResult {
     pub broadcast_msg_user_name: Option<String>,
     pub broadcast_msg_full_name: Option<String>,
     pub broadcast_msg_avatar: Option<Vec<u8>>
-}
\ No newline at end of file
+}
+/// engagement of one message, queried for a whole page at once
+#[derive(Deserialize, Serialize, FromRow, Clone, Debug, Default, PartialEq)]
+pub struct MessageCountsQueryResult {
+    pub id: i64,
+    pub reply_count: i64,
+    // broadcasts without a body of their own
+    pub rebroadcast_count: i64,
+    // broadcasts that add a body
+    pub quote_count: i64
+}
+
+/// which messages a stream follows, every set field must match
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageStreamFilter {
+    pub group_type: i32,
+    pub user_id: Option<i64>,
+    // lowercase and without its #, matched as a whole hashtag like mentions_hashtag
+    pub tag: Option<String>,
+}
+
+/// the characters a hashtag runs over after its #
+pub fn is_hashtag_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// whether body has #tag as a whole hashtag, case insensitive, so #rust matches "#Rust!" but not "#rustlang" or "a#rust"
+pub fn mentions_hashtag(body: &str, tag: &str) -> bool {
+    let mut previous = None;
+    for (index, c) in body.char_indices() {
+        if c == '#' && !previous.is_some_and(is_hashtag_char) {
+            let name = &body[index + 1..];
+            let end = name.find(|c: char| !is_hashtag_char(c)).unwrap_or(name.len());
+            if name[..end].eq_ignore_ascii_case(tag) {
+                return true;
+            }
+        }
+        previous = Some(c);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mentions_hashtag_matches_whole_tags_only() {
+        assert!(mentions_hashtag("#rust", "rust"));
+        assert!(mentions_hashtag("learning #Rust!", "rust"));
+        assert!(mentions_hashtag("##rust", "rust"));
+        assert!(!mentions_hashtag("#rustlang", "rust"));
+        assert!(!mentions_hashtag("a#rust", "rust"));
+        assert!(!mentions_hashtag("rust", "rust"));
+        assert!(!mentions_hashtag("#r_st", "r%st"));
+    }
+}
@@ -1,329 +1,459 @@
-use crate::common::entities::{ base::{ EntityId, DbRepo, DbConnGetter } };
+use crate::common::entities::{
+    base::{ EntityId, DbRepo, DbConnGetter, UnitOfWork },
+    events::repo::notify_event_inner,
+    notifications::{
+        model::{ get_mentioned_user_names, NotificationCreate },
+        repo::{ insert_mention_notifications_inner, insert_notification_inner },
+    },
+    timeline::repo::fan_out_message_inner,
+    webhooks::repo::insert_webhook_deliveries_inner,
+};
+use crate::common::events::{ AppEvent, MessageCreated, MessageLiked, MessageRef };
 use mockall::automock;
-use sqlx::{ Pool, Postgres };
-use super::model::MessageWithFollowingAndBroadcastQueryResult;
+use sqlx::{ Acquire, Executor, Postgres };
+use super::model::{ is_hashtag_char, MessageCountsQueryResult, MessageStreamFilter, MessageWithFollowingAndBroadcastQueryResult };
 use async_trait::async_trait;
 use chrono::{ DateTime, Utc };
 
 // 1. we create a single logical container where multiple related members can exist
 // 2. we create repeatable structure to our code
 // 3. we can hide some members even from our parent module
+// queries are checked against the schema at compile time, see sqlx-data.json.
+// msg_group_type is a nullable column that every insert sets, hence the "!" override,
+// the other overrides are columns of the page cte and of the left joined broadcast.
+// the inner fns run against a pool when standalone or the open transaction of a UnitOfWork,
+// where the inserts' own begin() becomes a savepoint.
+// the home timeline pages through timeline_entry, see timeline/repo.rs, plus the followed celebrities
+// that are not fanned out on write
 mod private_members {
-    use crate::common::entities::messages::model::MessageWithProfileQueryResult;
+    use crate::common::metrics::start_query_timer;
+    use tracing::{ error, instrument };
     use super::*;
 
-    pub async fn insert_message_inner(
-        conn: &Pool<Postgres>,
+    #[instrument(skip_all, fields(user_id = user_id, group_type = group_type))]
+    pub async fn insert_message_inner<'c, A>(
+        conn: A,
         user_id: i64,
         body: &str,
         group_type: i32,
-        broadcasting_msg_id: Option<i64>
-    ) -> Result<i64, sqlx::Error> {
-        let mut tx = conn.begin().await.unwrap();
+        broadcasting_msg_id: Option<i64>,
+        celebrity_follower_threshold: i64
+    ) -> Result<i64, sqlx::Error>
+        where A: Acquire<'c, Database = Postgres> + Send
+    {
+        let _timer = start_query_timer("insert_message_inner");
+        let mut tx = conn.begin().await?;
 
         let insert_msg_result = sqlx
-            ::query_as::<_, EntityId>(
-                "insert into message (user_id, body, msg_group_type) values ($1, $2, $3) returning id"
+            ::query_as!(
+                EntityId,
+                "insert into message (user_id, body, msg_group_type) values ($1, $2, $3) returning id",
+                user_id,
+                body,
+                group_type
             )
-            .bind(user_id)
-            .bind(body)
-            .bind(group_type)
             .fetch_one(&mut tx).await;
 
-        let message_id_result = match insert_msg_result {
-            Ok(r) => Ok(r.id),
+        let message_id = match insert_msg_result {
+            Ok(r) => r.id,
             Err(e) => {
-                println!("insert_message error: {}", e);
-                Err(e)
+                error!("insert_message error: {}", e);
+                return Err(e);
             }
         };
-        if message_id_result.is_err() {
-            return message_id_result;
-        }
 
+        let mut broadcasting_msg = None;
         if let Some(bm_id) = broadcasting_msg_id {
             let message_broadcast_result = sqlx
-                ::query_as::<_, EntityId>(
-                    "insert into message_broadcast (main_msg_id, broadcasting_msg_id) values ($1, $2) returning id"
+                ::query_as!(
+                    EntityId,
+                    "insert into message_broadcast (main_msg_id, broadcasting_msg_id) values ($1, $2) returning id",
+                    message_id,
+                    bm_id
                 )
-                .bind(message_id_result.as_ref().unwrap())
-                .bind(bm_id)
                 .fetch_one(&mut tx).await;
 
             if message_broadcast_result.is_err() {
                 _ = tx.rollback().await;
                 return Err(message_broadcast_result.err().unwrap());
             }
+            broadcasting_msg = Some(query_message_ref_inner(&mut tx, bm_id).await?);
         }
 
-        _ = tx.commit().await;
+        fan_out_message_inner(&mut tx, message_id, celebrity_follower_threshold).await?;
+        let event = MessageCreated { id: message_id, user_id, msg_group_type: group_type, original_msg: None, broadcasting_msg };
+        if let Some(notification) = NotificationCreate::for_message(&event, body) {
+            insert_notification_inner(&mut tx, &notification).await?;
+        }
+        insert_mention_notifications_inner(&mut tx, message_id, user_id, &get_mentioned_user_names(body)).await?;
+        let event = AppEvent::MessageCreated(event);
+        insert_webhook_deliveries_inner(&mut tx, &event).await?;
+        notify_event_inner(&mut tx, &event).await?;
+        tx.commit().await?;
 
-        message_id_result
+        Ok(message_id)
     }
 
-    pub async fn insert_response_message_inner(
-        conn: &Pool<Postgres>,
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn insert_response_message_inner<'c, A>(
+        conn: A,
         user_id: i64,
         body: &str,
         group_type: i32,
-        original_msg_id: i64
-    ) -> Result<i64, sqlx::Error> {
-        let mut tx = conn.begin().await.unwrap();
+        original_msg_id: i64,
+        celebrity_follower_threshold: i64
+    ) -> Result<i64, sqlx::Error>
+        where A: Acquire<'c, Database = Postgres> + Send
+    {
+        let _timer = start_query_timer("insert_response_message_inner");
+        let mut tx = conn.begin().await?;
 
         let insert_result = sqlx
-            ::query_as::<_, EntityId>(
-                "insert into message (user_id, body, msg_group_type) values ($1, $2, $3) returning id"
+            ::query_as!(
+                EntityId,
+                "insert into message (user_id, body, msg_group_type) values ($1, $2, $3) returning id",
+                user_id,
+                body,
+                group_type
             )
-            .bind(user_id)
-            .bind(body)
-            .bind(group_type)
             .fetch_one(&mut tx).await;
-        let msg_id_result = match insert_result {
-            Ok(r) => Ok(r.id),
+        let msg_id = match insert_result {
+            Ok(r) => r.id,
             Err(e) => {
-                println!("insert_message error: {}", e);
-                Err(e)
+                error!("insert_message error: {}", e);
+                return Err(e);
             }
         };
-        if msg_id_result.is_err() {
-            return msg_id_result;
-        }
-        let msg_id: i64 = msg_id_result.unwrap();
 
         let insert_msg_response_result = sqlx
-            ::query_as::<_, EntityId>(
-                "insert into message_response (original_msg_id, responding_msg_id) values ($1, $2) returning id"
+            ::query_as!(
+                EntityId,
+                "insert into message_response (original_msg_id, responding_msg_id) values ($1, $2) returning id",
+                original_msg_id,
+                msg_id
             )
-            .bind(original_msg_id)
-            .bind(msg_id)
             .fetch_one(&mut tx).await;
 
-        let msg_response_id_result = match insert_msg_response_result {
-            Ok(row) => Ok(row.id),
-            Err(e) => Err(e),
-        };
-        if msg_response_id_result.is_err() {
+        if let Err(e) = insert_msg_response_result {
             _ = tx.rollback().await;
-            return msg_response_id_result;
+            return Err(e);
         }
+        let original_msg = query_message_ref_inner(&mut tx, original_msg_id).await?;
 
-        _ = tx.commit().await;
+        fan_out_message_inner(&mut tx, msg_id, celebrity_follower_threshold).await?;
+        let event = MessageCreated { id: msg_id, user_id, msg_group_type: group_type, original_msg: Some(original_msg), broadcasting_msg: None };
+        if let Some(notification) = NotificationCreate::for_message(&event, body) {
+            insert_notification_inner(&mut tx, &notification).await?;
+        }
+        insert_mention_notifications_inner(&mut tx, msg_id, user_id, &get_mentioned_user_names(body)).await?;
+        let event = AppEvent::MessageCreated(event);
+        insert_webhook_deliveries_inner(&mut tx, &event).await?;
+        notify_event_inner(&mut tx, &event).await?;
+        tx.commit().await?;
 
         Ok(msg_id)
     }
 
-    pub async fn query_message_inner(
-        conn: &Pool<Postgres>,
-        id: i64
-    ) -> Result<Option<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error> {
-        let message_result = sqlx
-            ::query_as::<_, MessageWithProfileQueryResult>(
+    /// counts a like once per profile and notifies the author, false when the profile already liked the message.
+    /// updated_at is left alone, the like count is part of a message's ETag
+    #[instrument(skip_all, fields(user_id = user_id, msg_id = msg_id))]
+    pub async fn like_message_inner<'c, A>(
+        conn: A,
+        user_id: i64,
+        msg_id: i64
+    ) -> Result<bool, sqlx::Error>
+        where A: Acquire<'c, Database = Postgres> + Send
+    {
+        let _timer = start_query_timer("like_message_inner");
+        let mut tx = conn.begin().await?;
+
+        let like_id = sqlx
+            ::query_scalar!(
                 r"
-                select m.id, m.updated_at, m.body, m.likes, m.image, m.msg_group_type, m.user_id, p.user_name, p.full_name, p.avatar, mb.id as broadcast_msg_id                    
-                    from message m 
-                        join profile p on m.user_id = p.id
-                        left join message_broadcast mb on m.id = mb.main_msg_id
-                    where
-                        m.id = $1
-            "
+                insert into message_like (user_id, message_id) values ($1, $2)
+                    on conflict (user_id, message_id) do nothing
+                    returning id
+            ",
+                user_id,
+                msg_id
             )
-            .bind(id)
-            .fetch_optional(conn).await;
-
-        match message_result {
-            Ok(message) => {
-                if let Some(msg) = message {
-                    let optional_matching_broadcast_message = get_broadcasting_message_of_message(
-                        conn,
-                        &msg
-                    ).await;
-                    let final_message = append_broadcast_msg_to_msg(
-                        optional_matching_broadcast_message.as_ref(),
-                        &msg
-                    );
-                    Ok(Some(final_message))
-                } else {
-                    Ok(None)
-                }
-            }
-            Err(e) => Err(e),
+            .fetch_optional(&mut tx).await?;
+        let Some(like_id) = like_id else {
+            return Ok(false);
+        };
+        let author_id = sqlx
+            ::query_scalar!("update message set likes = likes + 1 where id = $1 returning user_id", msg_id)
+            .fetch_one(&mut tx).await?;
+
+        let event = MessageLiked { id: like_id, user_id, message: MessageRef { id: msg_id, user_id: author_id } };
+        if let Some(notification) = NotificationCreate::for_like(&event) {
+            insert_notification_inner(&mut tx, &notification).await?;
         }
+        let event = AppEvent::MessageLiked(event);
+        insert_webhook_deliveries_inner(&mut tx, &event).await?;
+        notify_event_inner(&mut tx, &event).await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// the author of a message that is being replied to or broadcast, who is notified
+    async fn query_message_ref_inner<'c, E>(conn: E, id: i64) -> Result<MessageRef, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        sqlx
+            ::query_as!(MessageRef, "select id, user_id from message where id = $1", id)
+            .fetch_one(conn).await
+    }
+    #[instrument(skip_all, fields(id = id))]
+    pub async fn query_message_inner<'c, E>(
+        conn: E,
+        id: i64
+    ) -> Result<Option<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("query_message_inner");
+        sqlx
+            ::query_as!(
+                MessageWithFollowingAndBroadcastQueryResult,
+                r#"
+                with recursive page as (
+                    select m.id, m.updated_at, m.body, m.likes, m.image, m.msg_group_type, m.user_id
+                        from message m
+                        where m.id = $1
+                ),
+                broadcast_chain (main_msg_id, broadcasting_msg_id, depth) as (
+                    select mb.main_msg_id, mb.broadcasting_msg_id, 1
+                        from message_broadcast mb
+                            join page on page.id = mb.main_msg_id
+                    union all
+                    select bc.main_msg_id, mb.broadcasting_msg_id, bc.depth + 1
+                        from broadcast_chain bc
+                            join message_broadcast mb on mb.main_msg_id = bc.broadcasting_msg_id
+                        where bc.depth < $2
+                ),
+                resolved_broadcast as (
+                    select bc.main_msg_id, bc.broadcasting_msg_id
+                        from broadcast_chain bc
+                        where bc.depth = (select max(depth) from broadcast_chain where main_msg_id = bc.main_msg_id)
+                )
+                select m.id as "id!", m.updated_at as "updated_at!", m.body, m.likes as "likes!", m.image,
+                    m.msg_group_type as "msg_group_type!", m.user_id as "user_id!", p.user_name, p.full_name, p.avatar,
+                    bm.id as "broadcast_msg_id?", bm.updated_at as "broadcast_msg_updated_at?", bm.body as "broadcast_msg_body?",
+                    bm.likes as "broadcast_msg_likes?", bm.image as "broadcast_msg_image?", bm.user_id as "broadcast_msg_user_id?",
+                    bp.user_name as "broadcast_msg_user_name?", bp.full_name as "broadcast_msg_full_name?", bp.avatar as "broadcast_msg_avatar?"
+                    from page m
+                        join profile p on m.user_id = p.id
+                        left join resolved_broadcast rb on rb.main_msg_id = m.id
+                        left join message bm on bm.id = rb.broadcasting_msg_id
+                        left join profile bp on bp.id = bm.user_id
+            "#,
+                id,
+                MAX_BROADCAST_DEPTH
+            )
+            .fetch_optional(conn).await
     }
 
-    pub async fn query_messages_inner(
-        conn: &Pool<Postgres>,
+    #[instrument(skip_all, fields(user_id = user_id, page_size = page_size))]
+    pub async fn query_messages_inner<'c, E>(
+        conn: E,
         user_id: i64,
         last_updated_at: DateTime<Utc>,
-        page_size: i16
-    ) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error> {
-        let following_messages_with_profiles_result = sqlx
-            ::query_as::<_, MessageWithProfileQueryResult>(
-                r"
-                select m.id, m.updated_at, m.body, m.likes, m.image, m.msg_group_type, m.user_id, p.user_name, p.full_name, p.avatar, mb.id as broadcast_msg_id                    
-                    from message m 
-                        join follow f on m.user_id = f.following_id
-                        join profile p on p.id = f.following_id
-                        left join message_broadcast mb on m.id = mb.main_msg_id
+        page_size: i16,
+        celebrity_follower_threshold: i64
+    ) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("query_messages_inner");
+        sqlx
+            ::query_as!(
+                MessageWithFollowingAndBroadcastQueryResult,
+                r#"
+                with recursive celebrity as (
+                    select f.following_id as id
+                        from follow f
+                            join profile p on p.id = f.following_id
+                        where
+                            f.follower_id = $1
+                            and p.follower_count > $5
+                ),
+                timeline as (
+                    select te.message_id as id
+                        from timeline_entry te
                         where
-                            f.follower_id = $1 
+                            te.owner_id = $1
+                            and te.message_updated_at < $2
+                        order by te.message_updated_at desc, te.message_id desc
+                        limit $3
+                ),
+                celebrity_timeline as (
+                    select m.id
+                        from message m
+                        where
+                            m.user_id in (select id from celebrity)
                             and m.updated_at < $2
-                        order by m.updated_at desc 
+                        order by m.updated_at desc, m.id desc
                         limit $3
-            "
-            )
-            .bind(user_id)
-            .bind(last_updated_at)
-            .bind(page_size)
-            .fetch_all(conn).await;
-
-        match following_messages_with_profiles_result {
-            Ok(following_messages) => {
-                let following_messages_with_broadcasts = following_messages
-                    .clone()
-                    .into_iter()
-                    .filter(|msg| {
-                        msg.broadcast_msg_id.is_some() && msg.broadcast_msg_id.unwrap() > 0
-                    })
-                    .collect::<Vec<MessageWithProfileQueryResult>>();
-
-                let optional_matching_broadcast_messages = get_broadcasting_messages_of_messages(
-                    conn,
-                    &following_messages_with_broadcasts
-                ).await;
-                let final_message_list = append_broadcast_msgs_to_msgs(
-                    &optional_matching_broadcast_messages,
-                    following_messages
-                );
-                Ok(final_message_list)
-            }
-            Err(e) => Err(e),
-        }
-    }
-
-    async fn get_broadcasting_messages_of_messages(
-        conn: &Pool<Postgres>,
-        following_messages_with_broadcasts: &Vec<MessageWithProfileQueryResult>
-    ) -> Option<Vec<MessageWithProfileQueryResult>> {
-        let following_broadcast_message_ids = following_messages_with_broadcasts
-            .iter()
-            .map(|msg| { msg.broadcast_msg_id.unwrap() })
-            .collect::<Vec<i64>>();
-
-        let broadcasting_msg_result = sqlx
-            ::query_as::<_, MessageWithProfileQueryResult>(
-                r"
-                select m.id, m.updated_at, m.body, m.likes, m.image, m.msg_group_type, m.user_id, p.user_name, p.full_name, p.avatar, mb.id as broadcast_msg_id
-                    from message m 
+                ),
+                page as (
+                    select m.id, m.updated_at, m.body, m.likes, m.image, m.msg_group_type, m.user_id
+                        from message m
+                        where m.id in (select id from timeline union select id from celebrity_timeline)
+                        order by m.updated_at desc, m.id desc
+                        limit $3
+                ),
+                broadcast_chain (main_msg_id, broadcasting_msg_id, depth) as (
+                    select mb.main_msg_id, mb.broadcasting_msg_id, 1
+                        from message_broadcast mb
+                            join page on page.id = mb.main_msg_id
+                    union all
+                    select bc.main_msg_id, mb.broadcasting_msg_id, bc.depth + 1
+                        from broadcast_chain bc
+                            join message_broadcast mb on mb.main_msg_id = bc.broadcasting_msg_id
+                        where bc.depth < $4
+                ),
+                resolved_broadcast as (
+                    select bc.main_msg_id, bc.broadcasting_msg_id
+                        from broadcast_chain bc
+                        where bc.depth = (select max(depth) from broadcast_chain where main_msg_id = bc.main_msg_id)
+                )
+                select m.id as "id!", m.updated_at as "updated_at!", m.body, m.likes as "likes!", m.image,
+                    m.msg_group_type as "msg_group_type!", m.user_id as "user_id!", p.user_name, p.full_name, p.avatar,
+                    bm.id as "broadcast_msg_id?", bm.updated_at as "broadcast_msg_updated_at?", bm.body as "broadcast_msg_body?",
+                    bm.likes as "broadcast_msg_likes?", bm.image as "broadcast_msg_image?", bm.user_id as "broadcast_msg_user_id?",
+                    bp.user_name as "broadcast_msg_user_name?", bp.full_name as "broadcast_msg_full_name?", bp.avatar as "broadcast_msg_avatar?"
+                    from page m
                         join profile p on m.user_id = p.id
-                        left join message_broadcast mb on m.id = mb.main_msg_id
-                    where m.id = ANY($1)
-            "
+                        left join resolved_broadcast rb on rb.main_msg_id = m.id
+                        left join message bm on bm.id = rb.broadcasting_msg_id
+                        left join profile bp on bp.id = bm.user_id
+                    order by m.updated_at desc, m.id desc
+            "#,
+                user_id,
+                last_updated_at,
+                page_size as i64,
+                MAX_BROADCAST_DEPTH,
+                celebrity_follower_threshold
             )
-            .bind(following_broadcast_message_ids)
-            .fetch_all(conn).await;
-
-        match broadcasting_msg_result {
-            Ok(broadcast_messages) => { Some(broadcast_messages) }
-            Err(e) => {
-                println!("get_broadcasting_messages_of_messages: {}", e);
-                None
-            }
-        }
+            .fetch_all(conn).await
     }
 
-    async fn get_broadcasting_message_of_message(
-        conn: &Pool<Postgres>,
-        message: &MessageWithProfileQueryResult
-    ) -> Option<MessageWithProfileQueryResult> {
-        let broadcasting_msg_result = sqlx
-            ::query_as::<_, MessageWithProfileQueryResult>(
-                r"
-                select m.id, m.updated_at, m.body, m.likes, m.image, m.msg_group_type, m.user_id, p.user_name, p.full_name, p.avatar, mb.id as broadcast_msg_id
-                    from message m 
+    #[instrument(skip_all, fields(after_id = after_id, page_size = page_size))]
+    pub async fn query_messages_after_inner<'c, E>(
+        conn: E,
+        after_id: i64,
+        filter: &MessageStreamFilter,
+        page_size: i16
+    ) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("query_messages_after_inner");
+        sqlx
+            ::query_as!(
+                MessageWithFollowingAndBroadcastQueryResult,
+                r#"
+                with recursive page as (
+                    select m.id, m.updated_at, m.body, m.likes, m.image, m.msg_group_type, m.user_id
+                        from message m
+                        where
+                            m.id > $1
+                            and m.msg_group_type = $2
+                            and ($3::bigint is null or m.user_id = $3)
+                            and ($4::text is null or m.body ~* $4)
+                        order by m.id
+                        limit $5
+                ),
+                broadcast_chain (main_msg_id, broadcasting_msg_id, depth) as (
+                    select mb.main_msg_id, mb.broadcasting_msg_id, 1
+                        from message_broadcast mb
+                            join page on page.id = mb.main_msg_id
+                    union all
+                    select bc.main_msg_id, mb.broadcasting_msg_id, bc.depth + 1
+                        from broadcast_chain bc
+                            join message_broadcast mb on mb.main_msg_id = bc.broadcasting_msg_id
+                        where bc.depth < $6
+                ),
+                resolved_broadcast as (
+                    select bc.main_msg_id, bc.broadcasting_msg_id
+                        from broadcast_chain bc
+                        where bc.depth = (select max(depth) from broadcast_chain where main_msg_id = bc.main_msg_id)
+                )
+                select m.id as "id!", m.updated_at as "updated_at!", m.body, m.likes as "likes!", m.image,
+                    m.msg_group_type as "msg_group_type!", m.user_id as "user_id!", p.user_name, p.full_name, p.avatar,
+                    bm.id as "broadcast_msg_id?", bm.updated_at as "broadcast_msg_updated_at?", bm.body as "broadcast_msg_body?",
+                    bm.likes as "broadcast_msg_likes?", bm.image as "broadcast_msg_image?", bm.user_id as "broadcast_msg_user_id?",
+                    bp.user_name as "broadcast_msg_user_name?", bp.full_name as "broadcast_msg_full_name?", bp.avatar as "broadcast_msg_avatar?"
+                    from page m
                         join profile p on m.user_id = p.id
-                        left join message_broadcast mb on m.id = mb.main_msg_id
-                    where m.id = $1
-            "
+                        left join resolved_broadcast rb on rb.main_msg_id = m.id
+                        left join message bm on bm.id = rb.broadcasting_msg_id
+                        left join profile bp on bp.id = bm.user_id
+                    order by m.id
+            "#,
+                after_id,
+                filter.group_type,
+                filter.user_id,
+                filter.tag.as_deref().map(get_tag_pattern),
+                page_size as i64,
+                MAX_BROADCAST_DEPTH
             )
-            .bind(message.broadcast_msg_id)
-            .fetch_optional(conn).await;
-
-        match broadcasting_msg_result {
-            Ok(broadcast_message) => { broadcast_message }
-            Err(e) => {
-                println!("get_broadcasting_messages_of_messages: {}", e);
-                None
-            }
-        }
+            .fetch_all(conn).await
     }
 
-    fn append_broadcast_msgs_to_msgs(
-        optional_broadcast_messages: &Option<Vec<MessageWithProfileQueryResult>>,
-        following_messages_with_broadcasts: Vec<MessageWithProfileQueryResult>
-    ) -> Vec<MessageWithFollowingAndBroadcastQueryResult> {
-        let mut final_list_of_messages: Vec<MessageWithFollowingAndBroadcastQueryResult> = vec![];
-
-        following_messages_with_broadcasts.iter().for_each(|following_message_with_broadcast| {
-            let matching_broadcast_msg = if
-                let Some(broadcast_messages) = optional_broadcast_messages
-            {
-                broadcast_messages
-                    .iter()
-                    .find(|bm| { Some(bm.id) == following_message_with_broadcast.broadcast_msg_id })
-            } else {
-                None
-            };
-
-            final_list_of_messages.push(
-                append_broadcast_msg_to_msg(
-                    matching_broadcast_msg,
-                    following_message_with_broadcast
-                )
-            );
-        });
-
-        final_list_of_messages
-    }
-
-    fn append_broadcast_msg_to_msg(
-        broadcast_message: Option<&MessageWithProfileQueryResult>,
-        message_with_broadcast: &MessageWithProfileQueryResult
-    ) -> MessageWithFollowingAndBroadcastQueryResult {
-        let mut final_message = MessageWithFollowingAndBroadcastQueryResult {
-            id: message_with_broadcast.id,
-            updated_at: message_with_broadcast.updated_at,
-            body: message_with_broadcast.body.clone(),
-            likes: message_with_broadcast.likes,
-            image: message_with_broadcast.image.clone(),
-            msg_group_type: message_with_broadcast.msg_group_type,
-            user_id: message_with_broadcast.user_id,
-            user_name: message_with_broadcast.user_name.clone(),
-            full_name: message_with_broadcast.full_name.clone(),
-            avatar: message_with_broadcast.avatar.clone(),
-            broadcast_msg_id: None,
-            broadcast_msg_updated_at: None,
-            broadcast_msg_user_id: None,
-            broadcast_msg_body: None,
-            broadcast_msg_likes: None,
-            broadcast_msg_image: None,
-            broadcast_msg_user_name: None,
-            broadcast_msg_full_name: None,
-            broadcast_msg_avatar: None,
-        };
+    /// a case insensitive regex for tag as a whole hashtag, the rule of mentions_hashtag. The tag is escaped so
+    /// only its own characters match
+    fn get_tag_pattern(tag: &str) -> String {
+        let escaped = tag
+            .chars()
+            .map(|c| if is_hashtag_char(c) { c.to_string() } else { format!("\\{}", c) })
+            .collect::<String>();
+        format!("(^|[^A-Za-z0-9_])#{}([^A-Za-z0-9_]|$)", escaped)
+    }
 
-        if let Some(matching_broadcast) = broadcast_message {
-            final_message.broadcast_msg_id = Some(matching_broadcast.id);
-            final_message.broadcast_msg_updated_at = Some(matching_broadcast.updated_at);
-            final_message.broadcast_msg_body = matching_broadcast.body.to_owned();
-            final_message.broadcast_msg_likes = Some(matching_broadcast.likes);
-            final_message.broadcast_msg_image = matching_broadcast.image.to_owned();
-            final_message.broadcast_msg_user_id = Some(matching_broadcast.user_id);
-            final_message.broadcast_msg_user_name = Some(matching_broadcast.user_name.to_string());
-            final_message.broadcast_msg_full_name = Some(matching_broadcast.full_name.to_string());
-            final_message.broadcast_msg_avatar = matching_broadcast.avatar.to_owned();
-        }
+    #[instrument(skip_all)]
+    pub async fn query_last_message_id_inner<'c, E>(conn: E) -> Result<Option<i64>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("query_last_message_id_inner");
+        sqlx::query_scalar!("select max(id) from message").fetch_one(conn).await
+    }
 
-        final_message
+    #[instrument(skip_all, fields(count = ids.len()))]
+    pub async fn query_message_counts_inner<'c, E>(
+        conn: E,
+        ids: &[i64]
+    ) -> Result<Vec<MessageCountsQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("query_message_counts_inner");
+        sqlx
+            ::query_as!(
+                MessageCountsQueryResult,
+                r#"
+                select ids.id as "id!",
+                    coalesce(r.reply_count, 0) as "reply_count!",
+                    coalesce(b.rebroadcast_count, 0) as "rebroadcast_count!",
+                    coalesce(b.quote_count, 0) as "quote_count!"
+                    from unnest($1::bigint[]) as ids(id)
+                        left join (
+                            select mr.original_msg_id, count(*) as reply_count
+                                from message_response mr
+                                where mr.original_msg_id = any($1)
+                                group by mr.original_msg_id
+                        ) r on r.original_msg_id = ids.id
+                        left join (
+                            select mb.broadcasting_msg_id,
+                                count(*) filter (where coalesce(m.body, '') = '') as rebroadcast_count,
+                                count(*) filter (where coalesce(m.body, '') <> '') as quote_count
+                                from message_broadcast mb
+                                    join message m on m.id = mb.main_msg_id
+                                where mb.broadcasting_msg_id = any($1)
+                                group by mb.broadcasting_msg_id
+                        ) b on b.broadcasting_msg_id = ids.id
+            "#,
+                ids
+            )
+            .fetch_all(conn).await
     }
 }
 
@@ -353,7 +483,28 @@ impl InsertMessageFn for DbRepo {
             user_id,
             body,
             group_type,
-            broadcasting_msg_id
+            broadcasting_msg_id,
+            self.celebrity_follower_threshold()
+        ).await
+    }
+}
+
+#[async_trait]
+impl InsertMessageFn for UnitOfWork {
+    async fn insert_message(
+        &self,
+        user_id: i64,
+        body: &str,
+        group_type: i32,
+        broadcasting_msg_id: Option<i64>
+    ) -> Result<i64, sqlx::Error> {
+        private_members::insert_message_inner(
+            &mut *self.lock().await,
+            user_id,
+            body,
+            group_type,
+            broadcasting_msg_id,
+            self.celebrity_follower_threshold()
         ).await
     }
 }
@@ -384,11 +535,57 @@ impl InsertResponseMessageFn for DbRepo {
             user_id,
             body,
             group_type,
-            original_msg_id
+            original_msg_id,
+            self.celebrity_follower_threshold()
+        ).await
+    }
+}
+
+#[async_trait]
+impl InsertResponseMessageFn for UnitOfWork {
+    async fn insert_response_message(
+        &self,
+        user_id: i64,
+        body: &str,
+        group_type: i32,
+        original_msg_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        private_members::insert_response_message_inner(
+            &mut *self.lock().await,
+            user_id,
+            body,
+            group_type,
+            original_msg_id,
+            self.celebrity_follower_threshold()
         ).await
     }
 }
 
+#[automock]
+#[async_trait]
+pub trait LikeMessageFn {
+    /// true when the profile had not liked the message yet, liking twice is not an error
+    async fn like_message(&self, user_id: i64, msg_id: i64) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl LikeMessageFn for DbRepo {
+    async fn like_message(&self, user_id: i64, msg_id: i64) -> Result<bool, sqlx::Error> {
+        private_members::like_message_inner(self.get_conn(), user_id, msg_id).await
+    }
+}
+
+#[async_trait]
+impl LikeMessageFn for UnitOfWork {
+    async fn like_message(&self, user_id: i64, msg_id: i64) -> Result<bool, sqlx::Error> {
+        private_members::like_message_inner(&mut *self.lock().await, user_id, msg_id).await
+    }
+}
+
+/// how many rebroadcasts of rebroadcasts are followed to reach the original message.
+/// Every backend resolves a message's broadcast to the root of its chain, or to the message at this depth
+pub const MAX_BROADCAST_DEPTH: i32 = 8;
+
 #[automock]
 #[async_trait]
 pub trait QueryMessageFn {
@@ -408,6 +605,16 @@ impl QueryMessageFn for DbRepo {
     }
 }
 
+#[async_trait]
+impl QueryMessageFn for UnitOfWork {
+    async fn query_message(
+        &self,
+        id: i64
+    ) -> Result<Option<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error> {
+        private_members::query_message_inner(&mut *self.lock().await, id).await
+    }
+}
+
 #[automock]
 #[async_trait]
 pub trait QueryMessagesFn {
@@ -427,7 +634,108 @@ impl QueryMessagesFn for DbRepo {
         last_updated_at: DateTime<Utc>,
         page_size: i16
     ) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error> {
-        private_members::query_messages_inner(self.get_conn(), user_id, last_updated_at, page_size).await
+        private_members::query_messages_inner(
+            self.get_conn(),
+            user_id,
+            last_updated_at,
+            page_size,
+            self.celebrity_follower_threshold()
+        ).await
+    }
+}
+
+#[async_trait]
+impl QueryMessagesFn for UnitOfWork {
+    async fn query_messages(
+        &self,
+        user_id: i64,
+        last_updated_at: DateTime<Utc>,
+        page_size: i16
+    ) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error> {
+        private_members::query_messages_inner(
+            &mut *self.lock().await,
+            user_id,
+            last_updated_at,
+            page_size,
+            self.celebrity_follower_threshold()
+        ).await
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait QueryMessagesAfterFn {
+    /// messages with an id after after_id that match filter, oldest first. Streams resume from the last id a client saw
+    async fn query_messages_after(
+        &self,
+        after_id: i64,
+        filter: &MessageStreamFilter,
+        page_size: i16
+    ) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error>;
+
+    /// the newest message id, where a stream without a last id picks up after missing events
+    async fn query_last_message_id(&self) -> Result<Option<i64>, sqlx::Error>;
+}
+
+#[async_trait]
+impl QueryMessagesAfterFn for DbRepo {
+    async fn query_messages_after(
+        &self,
+        after_id: i64,
+        filter: &MessageStreamFilter,
+        page_size: i16
+    ) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error> {
+        private_members::query_messages_after_inner(self.get_conn(), after_id, filter, page_size).await
+    }
+
+    async fn query_last_message_id(&self) -> Result<Option<i64>, sqlx::Error> {
+        private_members::query_last_message_id_inner(self.get_conn()).await
+    }
+}
+
+#[async_trait]
+impl QueryMessagesAfterFn for UnitOfWork {
+    async fn query_messages_after(
+        &self,
+        after_id: i64,
+        filter: &MessageStreamFilter,
+        page_size: i16
+    ) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error> {
+        private_members::query_messages_after_inner(&mut *self.lock().await, after_id, filter, page_size).await
+    }
+
+    async fn query_last_message_id(&self) -> Result<Option<i64>, sqlx::Error> {
+        private_members::query_last_message_id_inner(&mut *self.lock().await).await
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait QueryMessageCountsFn {
+    /// reply, rebroadcast and quote counts of every message in ids, in one query for a whole page
+    async fn query_message_counts(
+        &self,
+        ids: &[i64]
+    ) -> Result<Vec<MessageCountsQueryResult>, sqlx::Error>;
+}
+
+#[async_trait]
+impl QueryMessageCountsFn for DbRepo {
+    async fn query_message_counts(
+        &self,
+        ids: &[i64]
+    ) -> Result<Vec<MessageCountsQueryResult>, sqlx::Error> {
+        private_members::query_message_counts_inner(self.get_conn(), ids).await
+    }
+}
+
+#[async_trait]
+impl QueryMessageCountsFn for UnitOfWork {
+    async fn query_message_counts(
+        &self,
+        ids: &[i64]
+    ) -> Result<Vec<MessageCountsQueryResult>, sqlx::Error> {
+        private_members::query_message_counts_inner(&mut *self.lock().await, ids).await
     }
 }
 
@@ -437,11 +745,13 @@ mod tests {
     use fake::{ faker::name::en::{ Name, FirstName, LastName }, Fake };
     use lazy_static::lazy_static;
     use crate::{
-        common_tests::actix_fixture::PUBLIC_GROUP_TYPE,
-        common::entities::{profiles::{
+        common_tests::actix_fixture::{ PUBLIC_GROUP_TYPE, get_parity_repos },
+        common::entities::{
+            app_repo::AppRepo,
+            profiles::{
                 repo::{ InsertProfileFn, QueryProfileFn, MockInsertProfileFn },
                 model::ProfileCreate,
-            }
+            },
         }
     };
     use super::*;
@@ -451,16 +761,16 @@ mod tests {
         pub original_msg_id: i64,
         pub profile_id: i64,
         pub profile_create: ProfileCreate,
-        pub db_repo: DbRepo
+        pub db_repo: AppRepo
     }
 
     const PREFIX: &str = "Test message";
 
     lazy_static! {
-        static ref FIXTURES: Arc<RwLock<Option<Fixtures>>> = Arc::new(RwLock::new(None));
+        static ref FIXTURES: Arc<RwLock<Option<Vec<Fixtures>>>> = Arc::new(RwLock::new(None));
     }
 
-    async fn setup_data(db_repo: DbRepo) -> Fixtures {
+    async fn setup_data(db_repo: AppRepo) -> Fixtures {
         let first_name: String = FirstName().fake();
         let last_name: String = LastName().fake();
         let profile_create = ProfileCreate {
@@ -485,15 +795,19 @@ mod tests {
         }
     }
 
+    // the write lock is held on purpose so only one test sets up the shared fixtures
+    #[allow(clippy::await_holding_lock)]
     async fn setup_fixtures() {
         let fixtures = Arc::clone(&FIXTURES);
         let mut fx = fixtures.write().unwrap();
         match fx.clone() {
             Some(_) => (),
             None => {
-                let db_repo = DbRepo::init().await;
-
-                *fx = Some(setup_data(db_repo).await);
+                let mut backend_fixtures = vec![];
+                for db_repo in get_parity_repos().await {
+                    backend_fixtures.push(setup_data(db_repo).await);
+                }
+                *fx = Some(backend_fixtures);
             }
         }
     }
@@ -510,33 +824,34 @@ mod tests {
         };
     }
 
-    fn get_fixtures() -> Fixtures {
+    /// the fixtures of every backend, each test body runs once per backend
+    fn get_fixtures() -> Vec<Fixtures> {
         Arc::clone(&FIXTURES).read().unwrap().clone().unwrap()
     }
 
-    fn get_insert_profile_mock() -> MockInsertProfileFn {
+    fn get_insert_profile_mock(fixtures: &Fixtures) -> MockInsertProfileFn {
+        let profile_id = fixtures.profile_id;
         let mut mock_insert_profile = MockInsertProfileFn::new();
         mock_insert_profile
             .expect_insert_profile()
-            .returning(move |_| { Ok(get_fixtures().profile_id) });
+            .returning(move |_| { Ok(profile_id) });
         mock_insert_profile
     }
 
-    fn get_insert_message_mock() -> MockInsertMessageFn {
+    fn get_insert_message_mock(fixtures: &Fixtures) -> MockInsertMessageFn {
+        let original_msg_id = fixtures.original_msg_id;
         let mut mock_insert_message = MockInsertMessageFn::new();
         mock_insert_message
             .expect_insert_message()
-            .returning(|_, _, _, _| { Ok(get_fixtures().original_msg_id) });
+            .returning(move |_, _, _, _| { Ok(original_msg_id) });
         mock_insert_message
     }
 
     mod test_mod_insert_message {
         use super::*;
 
-        async fn test_insert_message_body() {
-            let fixtures = get_fixtures();
-
-            let mock_insert_profile = get_insert_profile_mock();
+        async fn test_insert_message_body(fixtures: Fixtures) {
+            let mock_insert_profile = get_insert_profile_mock(&fixtures);
 
             let profile_id = mock_insert_profile
                 .insert_profile(ProfileCreate {
@@ -563,17 +878,20 @@ mod tests {
 
         #[test]
         fn test_insert_message() {
-            RT.block_on(test_insert_message_body())
+            RT.block_on(async {
+                for fixtures in get_fixtures() {
+                    test_insert_message_body(fixtures).await;
+                }
+            })
         }
     }
 
     mod test_mod_query_message {
         use super::*;
 
-        async fn test_query_message_body() {
-            let fixtures = get_fixtures();
-            let mock_insert_profile = get_insert_profile_mock();
-            let mock_insert_message = get_insert_message_mock();
+        async fn test_query_message_body(fixtures: Fixtures) {
+            let mock_insert_profile = get_insert_profile_mock(&fixtures);
+            let mock_insert_message = get_insert_message_mock(&fixtures);
 
             let profile_id = mock_insert_profile
                 .insert_profile(fixtures.profile_create.clone()).await
@@ -598,17 +916,20 @@ mod tests {
 
         #[test]
         fn test_query_message() {
-            RT.block_on(test_query_message_body())
+            RT.block_on(async {
+                for fixtures in get_fixtures() {
+                    test_query_message_body(fixtures).await;
+                }
+            })
         }
     }
 
     mod test_mod_insert_response_message {
         use super::*;
 
-        async fn test_insert_response_message_body() {
-            let fixtures = get_fixtures();
-            let mock_insert_profile = get_insert_profile_mock();
-            let mock_insert_message = get_insert_message_mock();
+        async fn test_insert_response_message_body(fixtures: Fixtures) {
+            let mock_insert_profile = get_insert_profile_mock(&fixtures);
+            let mock_insert_message = get_insert_message_mock(&fixtures);
 
             let profile_id_result = mock_insert_profile.insert_profile(
                 ProfileCreate {
@@ -639,7 +960,326 @@ mod tests {
 
         #[test]
         fn test_insert_response_message() {
-            RT.block_on(test_insert_response_message_body())
+            RT.block_on(async {
+                for fixtures in get_fixtures() {
+                    test_insert_response_message_body(fixtures).await;
+                }
+            })
+        }
+    }
+
+    mod test_mod_query_message_counts {
+        use super::*;
+
+        async fn test_query_message_counts_body(fixtures: Fixtures) {
+            let db_repo = &fixtures.db_repo;
+            let msg_id = db_repo
+                .insert_message(fixtures.profile_id, "engaging", PUBLIC_GROUP_TYPE, None).await
+                .unwrap();
+            let quiet_msg_id = db_repo
+                .insert_message(fixtures.profile_id, "quiet", PUBLIC_GROUP_TYPE, None).await
+                .unwrap();
+            for body in ["reply 1", "reply 2"] {
+                db_repo.insert_response_message(fixtures.profile_id, body, PUBLIC_GROUP_TYPE, msg_id).await.unwrap();
+            }
+            db_repo.insert_message(fixtures.profile_id, "", PUBLIC_GROUP_TYPE, Some(msg_id)).await.unwrap();
+            for body in ["quote 1", "quote 2", "quote 3"] {
+                db_repo.insert_message(fixtures.profile_id, body, PUBLIC_GROUP_TYPE, Some(msg_id)).await.unwrap();
+            }
+
+            let mut counts = db_repo.query_message_counts(&[msg_id, quiet_msg_id]).await.unwrap();
+            counts.sort_by_key(|count| count.id);
+
+            assert_eq!(counts, vec![
+                MessageCountsQueryResult { id: msg_id, reply_count: 2, rebroadcast_count: 1, quote_count: 3 },
+                MessageCountsQueryResult { id: quiet_msg_id, reply_count: 0, rebroadcast_count: 0, quote_count: 0 },
+            ]);
+            assert!(db_repo.query_message_counts(&[]).await.unwrap().is_empty());
+        }
+
+        #[test]
+        fn test_query_message_counts() {
+            RT.block_on(async {
+                for fixtures in get_fixtures() {
+                    test_query_message_counts_body(fixtures).await;
+                }
+            })
+        }
+    }
+
+    mod test_mod_broadcast_resolution {
+        use crate::common::entities::profiles::repo::FollowUserFn;
+        use super::*;
+
+        async fn insert_broadcaster(db_repo: &AppRepo) -> i64 {
+            db_repo
+                .insert_profile(ProfileCreate {
+                    user_name: format!("broadcaster_{}", FirstName().fake::<String>()),
+                    full_name: Name().fake(),
+                    description: format!("{} a description", PREFIX),
+                    region: None,
+                    main_url: None,
+                    avatar: None,
+                }).await
+                .unwrap()
+        }
+
+        /// asserts the message broadcasts the fixtures' original message, with its author
+        fn assert_broadcasts_original(message: &MessageWithFollowingAndBroadcastQueryResult, fixtures: &Fixtures) {
+            assert_eq!(message.broadcast_msg_id, Some(fixtures.original_msg_id));
+            assert_eq!(message.broadcast_msg_body.as_deref(), Some("Testing body 123"));
+            assert_eq!(message.broadcast_msg_user_id, Some(fixtures.profile_id));
+            assert_eq!(message.broadcast_msg_user_name.as_ref(), Some(&fixtures.profile_create.user_name));
+            assert_eq!(message.broadcast_msg_full_name.as_ref(), Some(&fixtures.profile_create.full_name));
+        }
+
+        async fn test_query_message_resolves_broadcast_body(fixtures: Fixtures) {
+            let broadcaster_id = insert_broadcaster(&fixtures.db_repo).await;
+            let broadcast_id = fixtures.db_repo
+                .insert_message(broadcaster_id, "look at this", PUBLIC_GROUP_TYPE, Some(fixtures.original_msg_id)).await
+                .unwrap();
+
+            let message = fixtures.db_repo.query_message(broadcast_id).await.unwrap().unwrap();
+
+            assert_eq!(message.id, broadcast_id);
+            assert_eq!(message.user_id, broadcaster_id);
+            assert_broadcasts_original(&message, &fixtures);
+        }
+
+        #[test]
+        fn test_query_message_resolves_broadcast() {
+            RT.block_on(async {
+                for fixtures in get_fixtures() {
+                    test_query_message_resolves_broadcast_body(fixtures).await;
+                }
+            })
+        }
+
+        async fn test_query_message_resolves_rebroadcast_of_rebroadcast_body(fixtures: Fixtures) {
+            let first_broadcaster_id = insert_broadcaster(&fixtures.db_repo).await;
+            let second_broadcaster_id = insert_broadcaster(&fixtures.db_repo).await;
+            let first_broadcast_id = fixtures.db_repo
+                .insert_message(first_broadcaster_id, "first", PUBLIC_GROUP_TYPE, Some(fixtures.original_msg_id)).await
+                .unwrap();
+            let second_broadcast_id = fixtures.db_repo
+                .insert_message(second_broadcaster_id, "second", PUBLIC_GROUP_TYPE, Some(first_broadcast_id)).await
+                .unwrap();
+
+            let message = fixtures.db_repo.query_message(second_broadcast_id).await.unwrap().unwrap();
+
+            assert_eq!(message.user_id, second_broadcaster_id);
+            assert_broadcasts_original(&message, &fixtures);
+        }
+
+        #[test]
+        fn test_query_message_resolves_rebroadcast_of_rebroadcast() {
+            RT.block_on(async {
+                for fixtures in get_fixtures() {
+                    test_query_message_resolves_rebroadcast_of_rebroadcast_body(fixtures).await;
+                }
+            })
+        }
+
+        async fn test_query_message_stops_at_max_broadcast_depth_body(fixtures: Fixtures) {
+            let broadcaster_id = insert_broadcaster(&fixtures.db_repo).await;
+            let mut chain = vec![fixtures.original_msg_id];
+            for i in 0..=MAX_BROADCAST_DEPTH {
+                let broadcast_id = fixtures.db_repo
+                    .insert_message(broadcaster_id, &format!("hop {}", i), PUBLIC_GROUP_TYPE, Some(*chain.last().unwrap())).await
+                    .unwrap();
+                chain.push(broadcast_id);
+            }
+
+            // the chain is one longer than the bound, so resolution stops at the first broadcast instead of the original
+            let message = fixtures.db_repo.query_message(*chain.last().unwrap()).await.unwrap().unwrap();
+
+            assert_eq!(message.broadcast_msg_id, Some(chain[1]));
+            assert_eq!(message.broadcast_msg_user_id, Some(broadcaster_id));
+        }
+
+        #[test]
+        fn test_query_message_stops_at_max_broadcast_depth() {
+            RT.block_on(async {
+                for fixtures in get_fixtures() {
+                    test_query_message_stops_at_max_broadcast_depth_body(fixtures).await;
+                }
+            })
+        }
+
+        async fn test_query_messages_resolves_broadcasts_body(fixtures: Fixtures) {
+            let follower_id = insert_broadcaster(&fixtures.db_repo).await;
+            let broadcaster_id = insert_broadcaster(&fixtures.db_repo).await;
+            fixtures.db_repo.follow_user(follower_id, broadcaster_id).await.unwrap();
+            let first_broadcast_id = fixtures.db_repo
+                .insert_message(broadcaster_id, "first", PUBLIC_GROUP_TYPE, Some(fixtures.original_msg_id)).await
+                .unwrap();
+            let second_broadcast_id = fixtures.db_repo
+                .insert_message(broadcaster_id, "second", PUBLIC_GROUP_TYPE, Some(first_broadcast_id)).await
+                .unwrap();
+
+            let messages = fixtures.db_repo.query_messages(follower_id, Utc::now(), 10).await.unwrap();
+
+            assert_eq!(messages.iter().map(|msg| msg.id).collect::<Vec<i64>>(), vec![second_broadcast_id, first_broadcast_id]);
+            for message in &messages {
+                assert_eq!(message.user_id, broadcaster_id);
+                assert_broadcasts_original(message, &fixtures);
+            }
+        }
+
+        #[test]
+        fn test_query_messages_resolves_broadcasts() {
+            RT.block_on(async {
+                for fixtures in get_fixtures() {
+                    test_query_messages_resolves_broadcasts_body(fixtures).await;
+                }
+            })
+        }
+    }
+
+    mod test_mod_home_timeline {
+        use crate::{
+            common::{ config::RepositoryBackend, entities::{ profiles::repo::FollowUserFn, timeline::repo::RebuildTimelinesFn } },
+            common_tests::actix_fixture::get_backend_config,
+        };
+        use super::*;
+
+        /// parity repos where an author with a second follower becomes a celebrity
+        async fn get_low_threshold_repos() -> Vec<AppRepo> {
+            let mut repos = vec![];
+            for backend in [RepositoryBackend::Postgres, RepositoryBackend::Sqlite] {
+                let mut config = get_backend_config(backend);
+                config.timeline.celebrity_follower_threshold = 1;
+                repos.push(AppRepo::init(&config).await.unwrap());
+            }
+            repos
+        }
+
+        async fn insert_user(db_repo: &AppRepo, prefix: &str) -> i64 {
+            db_repo
+                .insert_profile(ProfileCreate {
+                    user_name: format!("{}_{}", prefix, FirstName().fake::<String>()),
+                    full_name: Name().fake(),
+                    description: format!("{} a description", PREFIX),
+                    region: None,
+                    main_url: None,
+                    avatar: None,
+                }).await
+                .unwrap()
+        }
+
+        async fn get_timeline_ids(db_repo: &AppRepo, user_id: i64) -> Vec<i64> {
+            db_repo
+                .query_messages(user_id, Utc::now(), 10).await
+                .unwrap()
+                .iter()
+                .map(|msg| msg.id)
+                .collect()
+        }
+
+        async fn test_celebrity_messages_are_read_with_a_join_body(db_repo: AppRepo) {
+            let author_id = insert_user(&db_repo, "author").await;
+            let first_follower_id = insert_user(&db_repo, "first_follower").await;
+            let second_follower_id = insert_user(&db_repo, "second_follower").await;
+            db_repo.follow_user(first_follower_id, author_id).await.unwrap();
+            let fanned_out_id = db_repo
+                .insert_message(author_id, "before the second follower", PUBLIC_GROUP_TYPE, None).await
+                .unwrap();
+            db_repo.follow_user(second_follower_id, author_id).await.unwrap();
+            let celebrity_msg_id = db_repo
+                .insert_message(author_id, "after the second follower", PUBLIC_GROUP_TYPE, None).await
+                .unwrap();
+
+            // the first message is both stored and joined for the first follower, and appears once
+            assert_eq!(get_timeline_ids(&db_repo, first_follower_id).await, vec![celebrity_msg_id, fanned_out_id]);
+            assert_eq!(get_timeline_ids(&db_repo, second_follower_id).await, vec![celebrity_msg_id, fanned_out_id]);
+        }
+
+        #[test]
+        fn test_celebrity_messages_are_read_with_a_join() {
+            RT.block_on(async {
+                for db_repo in get_low_threshold_repos().await {
+                    test_celebrity_messages_are_read_with_a_join_body(db_repo).await;
+                }
+            })
+        }
+
+        async fn test_rebuild_timelines_body(db_repo: AppRepo) {
+            let author_id = insert_user(&db_repo, "author").await;
+            let follower_id = insert_user(&db_repo, "follower").await;
+            let celebrity_id = insert_user(&db_repo, "celebrity").await;
+            let fan_id = insert_user(&db_repo, "fan").await;
+            let msg_ids = [
+                db_repo.insert_message(author_id, "posted before the follow", PUBLIC_GROUP_TYPE, None).await.unwrap(),
+                db_repo.insert_message(celebrity_id, "posted by a celebrity", PUBLIC_GROUP_TYPE, None).await.unwrap(),
+            ];
+            db_repo.follow_user(follower_id, author_id).await.unwrap();
+            db_repo.follow_user(follower_id, celebrity_id).await.unwrap();
+            db_repo.follow_user(fan_id, celebrity_id).await.unwrap();
+            let reply_id = db_repo
+                .insert_response_message(author_id, "a reply", PUBLIC_GROUP_TYPE, msg_ids[1]).await
+                .unwrap();
+
+            // only the author's two messages are stored, the celebrity's are joined when read
+            assert_eq!(db_repo.rebuild_timelines(Some(follower_id)).await.unwrap(), 2);
+            assert_eq!(db_repo.rebuild_timelines(Some(fan_id)).await.unwrap(), 0);
+            assert_eq!(get_timeline_ids(&db_repo, follower_id).await, vec![reply_id, msg_ids[1], msg_ids[0]]);
+            assert_eq!(get_timeline_ids(&db_repo, fan_id).await, vec![msg_ids[1]]);
+        }
+
+        #[test]
+        fn test_rebuild_timelines() {
+            RT.block_on(async {
+                for db_repo in get_low_threshold_repos().await {
+                    test_rebuild_timelines_body(db_repo).await;
+                }
+            })
+        }
+    }
+
+    mod test_mod_query_messages_after {
+        use super::*;
+
+        async fn test_query_messages_after_body(fixtures: Fixtures) {
+            let db_repo = fixtures.db_repo;
+            let author_id = db_repo
+                .insert_profile(ProfileCreate { user_name: Name().fake(), ..fixtures.profile_create.clone() }).await
+                .unwrap();
+            // the tag is unique to this run, other tests and earlier runs share the database
+            let tag = format!("tag{}", author_id);
+            let tagged_id = db_repo
+                .insert_message(author_id, &format!("hello #{}", tag), PUBLIC_GROUP_TYPE, None).await
+                .unwrap();
+            db_repo.insert_message(author_id, &format!("circle #{}", tag), 2, None).await.unwrap();
+            db_repo.insert_message(author_id, "untagged", PUBLIC_GROUP_TYPE, None).await.unwrap();
+            db_repo.insert_message(author_id, &format!("#{}_more", tag), PUBLIC_GROUP_TYPE, None).await.unwrap();
+            let shouted_id = db_repo
+                .insert_message(author_id, &format!("#{}!", tag.to_uppercase()), PUBLIC_GROUP_TYPE, None).await
+                .unwrap();
+            let filter = MessageStreamFilter { group_type: PUBLIC_GROUP_TYPE, user_id: Some(author_id), tag: Some(tag.clone()) };
+
+            let get_ids = |messages: Vec<MessageWithFollowingAndBroadcastQueryResult>| {
+                messages.iter().map(|msg| msg.id).collect::<Vec<i64>>()
+            };
+            // whole hashtags only, in any case
+            assert_eq!(get_ids(db_repo.query_messages_after(0, &filter, 10).await.unwrap()), vec![tagged_id, shouted_id]);
+            assert_eq!(get_ids(db_repo.query_messages_after(tagged_id, &filter, 10).await.unwrap()), vec![shouted_id]);
+            assert_eq!(get_ids(db_repo.query_messages_after(0, &filter, 1).await.unwrap()), vec![tagged_id]);
+            // _ is part of the tag rather than a wildcard
+            let wildcard = MessageStreamFilter { tag: Some(tag.replacen('g', "_", 1)), ..filter.clone() };
+            assert!(db_repo.query_messages_after(0, &wildcard, 10).await.unwrap().is_empty());
+            assert!(db_repo.query_last_message_id().await.unwrap() >= Some(shouted_id));
+
+            let untagged = MessageStreamFilter { tag: None, ..filter };
+            assert_eq!(db_repo.query_messages_after(0, &untagged, 10).await.unwrap().len(), 4);
+        }
+
+        #[test]
+        fn test_query_messages_after() {
+            RT.block_on(async {
+                for fixtures in get_fixtures() {
+                    test_query_messages_after_body(fixtures).await;
+                }
+            })
         }
     }
 
@@ -654,14 +1294,14 @@ mod tests {
             pub follower_user: ProfileQueryResult,
             pub following_users: Vec<ProfileQueryResult>,
             pub following_users_messages: Vec<MessageWithFollowingAndBroadcastQueryResult>,
-            pub db_repo: DbRepo,
+            pub db_repo: AppRepo,
         }
 
         lazy_static! {
-            static ref LOCAL_FIXTURES: Arc<RwLock<Option<QueryMsgFollowingFixtures>>> = Arc::new(RwLock::new(None));
+            static ref LOCAL_FIXTURES: Arc<RwLock<Option<Vec<QueryMsgFollowingFixtures>>>> = Arc::new(RwLock::new(None));
         }
 
-        async fn setup(db_repo: DbRepo) -> QueryMsgFollowingFixtures {
+        async fn setup(db_repo: AppRepo) -> QueryMsgFollowingFixtures {
             let follower_id = db_repo
                 .insert_profile(ProfileCreate {
                     user_name: "follower".to_string(),
@@ -734,28 +1374,39 @@ mod tests {
             }
         }
 
+        // the write lock is held on purpose so only one test sets up the shared fixtures
+        #[allow(clippy::await_holding_lock)]
         async fn setup_fixtures() {
             let fixtures = Arc::clone(&LOCAL_FIXTURES);
             let mut fx = fixtures.write().unwrap();
             match fx.clone() {
                 Some(_) => (),
                 None => {
-                    let db_repo = DbRepo::init().await;
-                    *fx = Some(setup(db_repo.clone()).await);
+                    let mut backend_fixtures = vec![];
+                    for db_repo in get_parity_repos().await {
+                        backend_fixtures.push(setup(db_repo).await);
+                    }
+                    *fx = Some(backend_fixtures);
                 }
             }
         }
 
-        async fn get_local_fixtures() -> QueryMsgFollowingFixtures {
+        async fn get_local_fixtures() -> Vec<QueryMsgFollowingFixtures> {
             Arc::clone(&LOCAL_FIXTURES).read().unwrap().clone().unwrap()
         }
 
         #[tokio::test]
         async fn test_query_messages_by_following() {
             setup_fixtures().await;
-            let insert_profile_fixtures = get_local_fixtures().await;
-            let insert_message_fixtures = get_local_fixtures().await;
-            let query_messages_fixtures = get_local_fixtures().await;
+            for fixtures in get_local_fixtures().await {
+                test_query_messages_by_following_body(fixtures).await;
+            }
+        }
+
+        async fn test_query_messages_by_following_body(fixtures: QueryMsgFollowingFixtures) {
+            let insert_profile_fixtures = fixtures.clone();
+            let insert_message_fixtures = fixtures.clone();
+            let query_messages_fixtures = fixtures;
 
             // create a single profile that will follow other profiles
             let mut mock_insert_profile = MockInsertProfileFn::new();
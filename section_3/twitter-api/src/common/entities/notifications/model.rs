@@ -0,0 +1,135 @@
+use chrono::{ DateTime, Utc };
+use serde::{ Deserialize, Serialize };
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use crate::common::events::{ FollowCreated, MessageCreated, MessageLiked, NotificationCreated };
+
+/// what happened to the recipient, stored as an int in notification.kind
+#[derive(Serialize, Deserialize, ToSchema, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+#[repr(i32)]
+pub enum NotificationKind {
+    Follow = 1,
+    Reply = 2,
+    /// a broadcast without a body of its own
+    Rebroadcast = 3,
+    /// a broadcast that adds a body
+    Quote = 4,
+    Like = 5,
+    Mention = 6,
+}
+
+/// one notification to write, the constructors return None for activity on the actor's own profile or messages
+#[derive(Clone, Debug, PartialEq)]
+pub struct NotificationCreate {
+    pub recipient_id: i64,
+    pub actor_id: i64,
+    pub kind: NotificationKind,
+    pub target_msg_id: Option<i64>,
+    pub source_msg_id: Option<i64>,
+}
+
+impl NotificationCreate {
+    fn new(
+        recipient_id: i64,
+        actor_id: i64,
+        kind: NotificationKind,
+        target_msg_id: Option<i64>,
+        source_msg_id: Option<i64>
+    ) -> Option<Self> {
+        (recipient_id != actor_id).then_some(Self { recipient_id, actor_id, kind, target_msg_id, source_msg_id })
+    }
+
+    pub fn for_follow(event: &FollowCreated) -> Option<Self> {
+        Self::new(event.following_id, event.follower_id, NotificationKind::Follow, None, None)
+    }
+
+    /// the author of the message replied to or broadcast, mentions are written separately
+    pub fn for_message(event: &MessageCreated, body: &str) -> Option<Self> {
+        if let Some(original_msg) = event.original_msg {
+            return Self::new(original_msg.user_id, event.user_id, NotificationKind::Reply, Some(original_msg.id), Some(event.id));
+        }
+        let broadcasting_msg = event.broadcasting_msg?;
+        let kind = if body.is_empty() { NotificationKind::Rebroadcast } else { NotificationKind::Quote };
+        Self::new(broadcasting_msg.user_id, event.user_id, kind, Some(broadcasting_msg.id), Some(event.id))
+    }
+
+    pub fn for_like(event: &MessageLiked) -> Option<Self> {
+        Self::new(event.message.user_id, event.user_id, NotificationKind::Like, Some(event.message.id), None)
+    }
+
+    /// the event announcing this notification once it is written with id
+    pub fn to_event(&self, id: i64) -> NotificationCreated {
+        NotificationCreated {
+            id,
+            recipient_id: self.recipient_id,
+            actor_id: self.actor_id,
+            kind: self.kind,
+            target_msg_id: self.target_msg_id,
+            source_msg_id: self.source_msg_id,
+        }
+    }
+}
+
+/// the user names a body mentions as @user_name, each once. A mention runs over ascii letters, digits and
+/// underscores and is not preceded by one, so an email address mentions nobody
+pub fn get_mentioned_user_names(body: &str) -> Vec<String> {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut user_names: Vec<String> = vec![];
+    let mut previous = None;
+    for (index, c) in body.char_indices() {
+        if c == '@' && !previous.is_some_and(is_name_char) {
+            let name = &body[index + 1..];
+            let end = name.find(|c: char| !is_name_char(c)).unwrap_or(name.len());
+            if end > 0 && !user_names.iter().any(|user_name| user_name == &name[..end]) {
+                user_names.push(name[..end].to_string());
+            }
+        }
+        previous = Some(c);
+    }
+    user_names
+}
+
+/// the notifications of one kind about one target, represented by the latest of them and its actor
+#[derive(Deserialize, Serialize, FromRow, Clone, Debug, PartialEq)]
+pub struct NotificationGroupQueryResult {
+    pub latest_id: i64,
+    pub latest_created_at: DateTime<Utc>,
+    pub kind: NotificationKind,
+    pub target_msg_id: Option<i64>,
+    pub source_msg_id: Option<i64>,
+    // distinct profiles across the group, a profile replying twice counts once
+    pub actor_count: i64,
+    pub unread_count: i64,
+    pub actor_id: i64,
+    pub user_name: String,
+    pub full_name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::events::MessageRef;
+    use super::*;
+
+    #[test]
+    fn test_message_notifications_go_to_the_replied_or_broadcast_author() {
+        let original_msg = MessageRef { id: 10, user_id: 1 };
+        let reply = MessageCreated { id: 20, user_id: 2, msg_group_type: 1, original_msg: Some(original_msg), broadcasting_msg: None };
+        let broadcast = MessageCreated { id: 30, user_id: 2, msg_group_type: 1, original_msg: None, broadcasting_msg: Some(original_msg) };
+        let own_reply = MessageCreated { user_id: 1, ..reply.clone() };
+
+        assert_eq!(
+            NotificationCreate::for_message(&reply, "a reply"),
+            Some(NotificationCreate { recipient_id: 1, actor_id: 2, kind: NotificationKind::Reply, target_msg_id: Some(10), source_msg_id: Some(20) })
+        );
+        assert_eq!(NotificationCreate::for_message(&broadcast, "").unwrap().kind, NotificationKind::Rebroadcast);
+        assert_eq!(NotificationCreate::for_message(&broadcast, "look").unwrap().kind, NotificationKind::Quote);
+        assert!(NotificationCreate::for_message(&own_reply, "a reply").is_none());
+    }
+
+    #[test]
+    fn test_mentioned_user_names_are_whole_names_listed_once() {
+        assert_eq!(get_mentioned_user_names("@dave and @jo_2, hi @dave"), ["dave", "jo_2"]);
+        assert!(get_mentioned_user_names("mail dave@example.com or @ alone").is_empty());
+    }
+}
@@ -0,0 +1,443 @@
+use crate::common::entities::{ base::{ DbRepo, DbConnGetter, UnitOfWork }, events::repo::notify_event_inner };
+use crate::common::{ events::AppEvent, metrics::start_query_timer };
+use async_trait::async_trait;
+use mockall::automock;
+use sqlx::{ Executor, PgConnection, Postgres };
+use tracing::instrument;
+use super::model::{ NotificationCreate, NotificationGroupQueryResult, NotificationKind };
+
+// notifications are written by the follow, message and like inserts inside their own transactions,
+// so a rolled back write never notifies anyone. Each is announced in the same transaction as a
+// NotificationCreated event for the recipient's stream. They are read grouped by kind and target, a group
+// is ordered and paged by its latest notification, which is also the one shown with its actor
+mod private_members {
+    use super::*;
+
+    #[instrument(skip_all, fields(recipient_id = recipient_id, page_size = page_size))]
+    pub async fn query_notification_groups_inner<'c, E>(
+        conn: E,
+        recipient_id: i64,
+        before_id: Option<i64>,
+        page_size: i16
+    ) -> Result<Vec<NotificationGroupQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("query_notification_groups_inner");
+        sqlx
+            ::query_as!(
+                NotificationGroupQueryResult,
+                r#"
+                select g.latest_id as "latest_id!", n.created_at as latest_created_at, g.kind as "kind: NotificationKind",
+                    g.target_msg_id, n.source_msg_id, g.actor_count as "actor_count!", g.unread_count as "unread_count!",
+                    p.id as actor_id, p.user_name, p.full_name
+                    from (
+                        select kind, target_msg_id, max(id) as latest_id, count(distinct actor_id) as actor_count,
+                            count(*) filter (where read_at is null) as unread_count
+                            from notification
+                            where recipient_id = $1
+                            group by kind, target_msg_id
+                            having $2::bigint is null or max(id) < $2
+                    ) g
+                        join notification n on n.id = g.latest_id
+                        join profile p on p.id = n.actor_id
+                    order by g.latest_id desc
+                    limit $3
+            "#,
+                recipient_id,
+                before_id,
+                page_size as i64
+            )
+            .fetch_all(conn).await
+    }
+
+    #[instrument(skip_all, fields(recipient_id = recipient_id))]
+    pub async fn query_unread_notification_count_inner<'c, E>(
+        conn: E,
+        recipient_id: i64
+    ) -> Result<i64, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("query_unread_notification_count_inner");
+        sqlx
+            ::query_scalar!(
+                r#"select count(*) as "count!" from notification where recipient_id = $1 and read_at is null"#,
+                recipient_id
+            )
+            .fetch_one(conn).await
+    }
+
+    #[instrument(skip_all, fields(recipient_id = recipient_id, up_to_id = up_to_id))]
+    pub async fn mark_notifications_read_inner<'c, E>(
+        conn: E,
+        recipient_id: i64,
+        up_to_id: Option<i64>
+    ) -> Result<u64, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("mark_notifications_read_inner");
+        sqlx
+            ::query!(
+                r"
+                update notification set read_at = current_timestamp
+                    where recipient_id = $1 and read_at is null and ($2::bigint is null or id <= $2)
+            ",
+                recipient_id,
+                up_to_id
+            )
+            .execute(conn).await
+            .map(|r| r.rows_affected())
+    }
+
+    #[instrument(skip_all, fields(recipient_id = recipient_id, target_msg_id = target_msg_id))]
+    pub async fn mark_notification_group_read_inner<'c, E>(
+        conn: E,
+        recipient_id: i64,
+        kind: NotificationKind,
+        target_msg_id: Option<i64>
+    ) -> Result<u64, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("mark_notification_group_read_inner");
+        sqlx
+            ::query!(
+                r"
+                update notification set read_at = current_timestamp
+                    where
+                        recipient_id = $1
+                        and read_at is null
+                        and kind = $2
+                        and target_msg_id is not distinct from $3
+            ",
+                recipient_id,
+                kind as i32,
+                target_msg_id
+            )
+            .execute(conn).await
+            .map(|r| r.rows_affected())
+    }
+}
+
+/// writes one notification and announces it, runs inside the transaction of the write that caused it
+#[instrument(skip_all)]
+pub(crate) async fn insert_notification_inner(conn: &mut PgConnection, notification: &NotificationCreate) -> Result<(), sqlx::Error> {
+    let _timer = start_query_timer("insert_notification_inner");
+    let id = sqlx
+        ::query_scalar!(
+            r"
+            insert into notification (recipient_id, actor_id, kind, target_msg_id, source_msg_id)
+                values ($1, $2, $3, $4, $5)
+                returning id
+        ",
+            notification.recipient_id,
+            notification.actor_id,
+            notification.kind as i32,
+            notification.target_msg_id,
+            notification.source_msg_id
+        )
+        .fetch_one(&mut *conn).await?;
+    notify_event_inner(conn, &AppEvent::NotificationCreated(notification.to_event(id))).await
+}
+
+/// notifies the profiles a new message mentions, except its author and anyone the message already notified
+/// as the author it replies to or broadcasts. Runs inside the transaction that inserts the message
+#[instrument(skip_all, fields(message_id = message_id))]
+pub(crate) async fn insert_mention_notifications_inner(
+    conn: &mut PgConnection,
+    message_id: i64,
+    user_id: i64,
+    user_names: &[String]
+) -> Result<u64, sqlx::Error> {
+    if user_names.is_empty() {
+        return Ok(0);
+    }
+    let _timer = start_query_timer("insert_mention_notifications_inner");
+    let inserted = sqlx
+        ::query!(
+            r"
+            insert into notification (recipient_id, actor_id, kind, target_msg_id, source_msg_id)
+                select p.id, $2, $3, $1, $1
+                    from profile p
+                    where
+                        p.user_name = any($4)
+                        and p.id <> $2
+                        and not exists (select 1 from notification n where n.source_msg_id = $1 and n.recipient_id = p.id)
+                returning id, recipient_id
+        ",
+            message_id,
+            user_id,
+            NotificationKind::Mention as i32,
+            user_names
+        )
+        .fetch_all(&mut *conn).await?;
+    for row in &inserted {
+        let notification = NotificationCreate {
+            recipient_id: row.recipient_id,
+            actor_id: user_id,
+            kind: NotificationKind::Mention,
+            target_msg_id: Some(message_id),
+            source_msg_id: Some(message_id),
+        };
+        notify_event_inner(&mut *conn, &AppEvent::NotificationCreated(notification.to_event(row.id))).await?;
+    }
+    Ok(inserted.len() as u64)
+}
+
+#[automock]
+#[async_trait]
+pub trait QueryNotificationGroupsFn {
+    /// groups of the recipient's notifications, the most recently active first. A page continues
+    /// before the latest_id of the last group of the previous one
+    async fn query_notification_groups(
+        &self,
+        recipient_id: i64,
+        before_id: Option<i64>,
+        page_size: i16
+    ) -> Result<Vec<NotificationGroupQueryResult>, sqlx::Error>;
+}
+
+#[async_trait]
+impl QueryNotificationGroupsFn for DbRepo {
+    async fn query_notification_groups(
+        &self,
+        recipient_id: i64,
+        before_id: Option<i64>,
+        page_size: i16
+    ) -> Result<Vec<NotificationGroupQueryResult>, sqlx::Error> {
+        private_members::query_notification_groups_inner(self.get_conn(), recipient_id, before_id, page_size).await
+    }
+}
+
+#[async_trait]
+impl QueryNotificationGroupsFn for UnitOfWork {
+    async fn query_notification_groups(
+        &self,
+        recipient_id: i64,
+        before_id: Option<i64>,
+        page_size: i16
+    ) -> Result<Vec<NotificationGroupQueryResult>, sqlx::Error> {
+        private_members::query_notification_groups_inner(&mut *self.lock().await, recipient_id, before_id, page_size).await
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait QueryUnreadNotificationCountFn {
+    async fn query_unread_notification_count(&self, recipient_id: i64) -> Result<i64, sqlx::Error>;
+}
+
+#[async_trait]
+impl QueryUnreadNotificationCountFn for DbRepo {
+    async fn query_unread_notification_count(&self, recipient_id: i64) -> Result<i64, sqlx::Error> {
+        private_members::query_unread_notification_count_inner(self.get_conn(), recipient_id).await
+    }
+}
+
+#[async_trait]
+impl QueryUnreadNotificationCountFn for UnitOfWork {
+    async fn query_unread_notification_count(&self, recipient_id: i64) -> Result<i64, sqlx::Error> {
+        private_members::query_unread_notification_count_inner(&mut *self.lock().await, recipient_id).await
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait MarkNotificationsReadFn {
+    /// marks the recipient's unread notifications up to and including up_to_id, or all of them,
+    /// and returns how many were marked
+    async fn mark_notifications_read(&self, recipient_id: i64, up_to_id: Option<i64>) -> Result<u64, sqlx::Error>;
+}
+
+#[async_trait]
+impl MarkNotificationsReadFn for DbRepo {
+    async fn mark_notifications_read(&self, recipient_id: i64, up_to_id: Option<i64>) -> Result<u64, sqlx::Error> {
+        private_members::mark_notifications_read_inner(self.get_conn(), recipient_id, up_to_id).await
+    }
+}
+
+#[async_trait]
+impl MarkNotificationsReadFn for UnitOfWork {
+    async fn mark_notifications_read(&self, recipient_id: i64, up_to_id: Option<i64>) -> Result<u64, sqlx::Error> {
+        private_members::mark_notifications_read_inner(&mut *self.lock().await, recipient_id, up_to_id).await
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait MarkNotificationGroupReadFn {
+    /// marks the unread notifications of one group, follows have no target message
+    async fn mark_notification_group_read(
+        &self,
+        recipient_id: i64,
+        kind: NotificationKind,
+        target_msg_id: Option<i64>
+    ) -> Result<u64, sqlx::Error>;
+}
+
+#[async_trait]
+impl MarkNotificationGroupReadFn for DbRepo {
+    async fn mark_notification_group_read(
+        &self,
+        recipient_id: i64,
+        kind: NotificationKind,
+        target_msg_id: Option<i64>
+    ) -> Result<u64, sqlx::Error> {
+        private_members::mark_notification_group_read_inner(self.get_conn(), recipient_id, kind, target_msg_id).await
+    }
+}
+
+#[async_trait]
+impl MarkNotificationGroupReadFn for UnitOfWork {
+    async fn mark_notification_group_read(
+        &self,
+        recipient_id: i64,
+        kind: NotificationKind,
+        target_msg_id: Option<i64>
+    ) -> Result<u64, sqlx::Error> {
+        private_members::mark_notification_group_read_inner(
+            &mut *self.lock().await,
+            recipient_id,
+            kind,
+            target_msg_id
+        ).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lazy_static::lazy_static;
+    use uuid::Uuid;
+    use std::time::Duration;
+    use crate::{
+        common::entities::{
+            app_repo::AppRepo,
+            events::repo::SubscribeEventsFn,
+            messages::repo::{ InsertMessageFn, InsertResponseMessageFn, LikeMessageFn },
+            profiles::{ model::ProfileCreate, repo::{ FollowUserFn, InsertProfileFn } },
+        },
+        common_tests::actix_fixture::{ PUBLIC_GROUP_TYPE, get_parity_repos },
+    };
+    use super::*;
+
+    lazy_static! {
+        static ref RT: tokio::runtime::Runtime = tokio::runtime::Builder
+            ::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+    }
+
+    async fn insert_profile(db_repo: &AppRepo, user_name: &str) -> i64 {
+        db_repo
+            .insert_profile(ProfileCreate {
+                user_name: user_name.to_string(),
+                full_name: "Test notifications".to_string(),
+                description: "Test notifications description".to_string(),
+                region: Some("usa".to_string()),
+                main_url: Some("http://whatever.com".to_string()),
+                avatar: Some(vec![]),
+            }).await
+            .unwrap()
+    }
+
+    mod test_mod_notification_groups {
+        use super::*;
+
+        async fn test_activity_notifies_the_profile_in_groups_body(db_repo: AppRepo) {
+            // the mentioned name must be unique, the postgres database outlives a test run
+            let mentioned_name = format!("mentioned_{}", Uuid::new_v4().simple());
+            let author_id = insert_profile(&db_repo, "author").await;
+            let fan_id = insert_profile(&db_repo, "fan").await;
+            let mentioned_id = insert_profile(&db_repo, &mentioned_name).await;
+            let msg_id = db_repo.insert_message(author_id, "Notify me", PUBLIC_GROUP_TYPE, None).await.unwrap();
+
+            db_repo.follow_user(fan_id, author_id).await.unwrap();
+            let reply_id = db_repo
+                .insert_response_message(fan_id, &format!("Agreed @{}", mentioned_name), PUBLIC_GROUP_TYPE, msg_id).await
+                .unwrap();
+            let quote_id = db_repo.insert_message(mentioned_id, "Look at this", PUBLIC_GROUP_TYPE, Some(msg_id)).await.unwrap();
+            db_repo.insert_message(fan_id, "", PUBLIC_GROUP_TYPE, Some(msg_id)).await.unwrap();
+            assert!(db_repo.like_message(fan_id, msg_id).await.unwrap());
+            assert!(!db_repo.like_message(fan_id, msg_id).await.unwrap());
+            assert!(db_repo.like_message(author_id, msg_id).await.unwrap());
+
+            let groups = db_repo.query_notification_groups(author_id, None, 10).await.unwrap();
+            let kinds: Vec<NotificationKind> = groups.iter().map(|g| g.kind).collect();
+            assert_eq!(kinds, [
+                NotificationKind::Like,
+                NotificationKind::Rebroadcast,
+                NotificationKind::Quote,
+                NotificationKind::Reply,
+                NotificationKind::Follow,
+            ]);
+            assert!(groups.iter().all(|g| g.actor_count == 1 && g.unread_count == 1));
+            assert_eq!(groups[2].actor_id, mentioned_id);
+            assert_eq!(groups[2].source_msg_id, Some(quote_id));
+            assert_eq!(groups[3].target_msg_id, Some(msg_id));
+            assert_eq!(groups[3].source_msg_id, Some(reply_id));
+            assert_eq!(groups[4].target_msg_id, None);
+
+            let mentions = db_repo.query_notification_groups(mentioned_id, None, 10).await.unwrap();
+            assert_eq!(mentions.len(), 1);
+            assert_eq!(mentions[0].kind, NotificationKind::Mention);
+            assert_eq!(mentions[0].target_msg_id, Some(reply_id));
+
+            let next_page = db_repo.query_notification_groups(author_id, Some(groups[1].latest_id), 2).await.unwrap();
+            assert_eq!(next_page, groups[2..4]);
+
+            assert_eq!(db_repo.query_unread_notification_count(author_id).await.unwrap(), 5);
+            assert_eq!(db_repo.mark_notification_group_read(author_id, NotificationKind::Follow, None).await.unwrap(), 1);
+            assert_eq!(db_repo.mark_notifications_read(author_id, Some(groups[2].latest_id)).await.unwrap(), 2);
+            assert_eq!(db_repo.query_unread_notification_count(author_id).await.unwrap(), 2);
+            assert_eq!(db_repo.mark_notifications_read(author_id, None).await.unwrap(), 2);
+            assert_eq!(db_repo.query_unread_notification_count(author_id).await.unwrap(), 0);
+        }
+
+        #[test]
+        fn test_activity_notifies_the_profile_in_groups() {
+            RT.block_on(async {
+                for db_repo in get_parity_repos().await {
+                    test_activity_notifies_the_profile_in_groups_body(db_repo).await;
+                }
+            })
+        }
+
+        async fn test_written_notifications_are_published_body(db_repo: AppRepo) {
+            let mentioned_name = format!("mentioned_{}", Uuid::new_v4().simple());
+            let author_id = insert_profile(&db_repo, "author").await;
+            let mentioned_id = insert_profile(&db_repo, &mentioned_name).await;
+            let mut events = db_repo.subscribe_events();
+
+            db_repo.follow_user(mentioned_id, author_id).await.unwrap();
+            let msg_id = db_repo.insert_message(author_id, &format!("Hi @{}", mentioned_name), PUBLIC_GROUP_TYPE, None).await.unwrap();
+
+            // other tests publish on the same postgres database, so only these profiles' notifications are kept
+            let notifications = tokio::time::timeout(Duration::from_secs(5), async {
+                let mut notifications = vec![];
+                loop {
+                    match events.recv().await.unwrap() {
+                        AppEvent::NotificationCreated(n) if [author_id, mentioned_id].contains(&n.recipient_id) => notifications.push(n),
+                        AppEvent::MessageCreated(created) if created.id == msg_id => return notifications,
+                        _ => (),
+                    }
+                }
+            }).await.expect("message not published within 5 seconds");
+            let published: Vec<(i64, NotificationKind, Option<i64>)> = notifications
+                .iter()
+                .map(|n| (n.recipient_id, n.kind, n.source_msg_id))
+                .collect();
+            assert_eq!(published, [(author_id, NotificationKind::Follow, None), (mentioned_id, NotificationKind::Mention, Some(msg_id))]);
+
+            let groups = db_repo.query_notification_groups(mentioned_id, None, 10).await.unwrap();
+            assert_eq!(groups[0].latest_id, notifications[1].id);
+        }
+
+        #[test]
+        fn test_written_notifications_are_published() {
+            RT.block_on(async {
+                for db_repo in get_parity_repos().await {
+                    test_written_notifications_are_published_body(db_repo).await;
+                }
+            })
+        }
+    }
+}
@@ -1,52 +1,68 @@
-use crate::common::entities::base::{ EntityId, DbRepo, DbConnGetter };
+use crate::common::entities::{
+    base::{ EntityId, DbRepo, DbConnGetter, UnitOfWork },
+    events::repo::notify_event_inner,
+    notifications::{ model::NotificationCreate, repo::insert_notification_inner },
+    timeline::repo::backfill_timeline_inner,
+    webhooks::repo::insert_webhook_deliveries_inner,
+};
+use crate::common::events::{ AppEvent, FollowCreated };
 use super::model::{ ProfileCreate, ProfileQueryResult };
 use async_trait::async_trait;
-use sqlx::{ Pool, Postgres };
+use sqlx::{ Acquire, Executor, Postgres };
 use mockall::automock;
 use mockall::predicate::*;
 
 mod private_members {
+    use crate::common::metrics::start_query_timer;
+    use tracing::{ error, instrument };
     use super::*;
 
-    pub async fn insert_profile_inner(
-        conn: &Pool<Postgres>,
+    #[instrument(skip_all)]
+    pub async fn insert_profile_inner<'c, E>(
+        conn: E,
         params: ProfileCreate
-    ) -> Result<i64, sqlx::Error> {
+    ) -> Result<i64, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("insert_profile_inner");
         let result = sqlx
-            ::query_as::<_, EntityId>(
+            ::query_as!(
+                EntityId,
                 r"
                 insert into Profile 
                     (user_name, full_name, description, region, main_url, avatar) 
                     values 
                     ($1, $2, $3, $4, $5, $6)
-                returning id"
+                returning id",
+                params.user_name,
+                params.full_name,
+                params.description,
+                params.region,
+                params.main_url,
+                params.avatar
             )
-            .bind(&params.user_name)
-            .bind(&params.full_name)
-            .bind(&params.description)
-            .bind(&params.region)
-            .bind(&params.main_url)
-            .bind(&params.avatar)
             .fetch_one(conn).await;
 
         match result {
             Ok(r) => Ok(r.id),
             Err(e) => {
-                println!("create_profile error: {}", e);
+                error!("create_profile error: {}", e);
                 Err(e)
             }
         }
     }
 
-    pub async fn update_profile_avatar_inner(
-        conn: &Pool<Postgres>,
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn update_profile_avatar_inner<'c, E>(
+        conn: E,
         user_id: i64,
         avatar: Vec<u8>
-    ) -> Result<(), sqlx::Error> {
+    ) -> Result<(), sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("update_profile_avatar_inner");
         let update_result = sqlx
-            ::query::<_>("update profile set avatar = $1 where id = $2")
-            .bind(avatar)
-            .bind(user_id)
+            ::query!("update profile set avatar = $1, updated_at = now() where id = $2", avatar, user_id)
             .execute(conn).await;
 
         match update_result {
@@ -55,42 +71,95 @@ mod private_members {
         }
     }
 
-    pub async fn follow_user_inner(
-        conn: &Pool<Postgres>,
+    #[instrument(skip_all, fields(follower_id = follower_id, following_id = following_id))]
+    pub async fn follow_user_inner<'c, A>(
+        conn: A,
         follower_id: i64,
-        following_id: i64
-    ) -> Result<i64, sqlx::Error> {
-        let id_result = sqlx
-            ::query_as::<_, EntityId>(
-                "insert into follow (follower_id, following_id) values ($1, $2) returning id"
+        following_id: i64,
+        celebrity_follower_threshold: i64
+    ) -> Result<i64, sqlx::Error>
+        where A: Acquire<'c, Database = Postgres> + Send
+    {
+        let _timer = start_query_timer("follow_user_inner");
+        let mut tx = conn.begin().await?;
+
+        let follow_id = sqlx
+            ::query_as!(
+                EntityId,
+                "insert into follow (follower_id, following_id) values ($1, $2) returning id",
+                follower_id,
+                following_id
             )
-            .bind(follower_id)
-            .bind(following_id)
-            .fetch_one(conn).await;
-
-        match id_result {
-            Ok(row) => Ok(row.id),
-            Err(e) => Err(e),
+            .fetch_one(&mut tx).await?
+            .id;
+        sqlx
+            ::query!("update profile set follower_count = follower_count + 1 where id = $1", following_id)
+            .execute(&mut tx).await?;
+        backfill_timeline_inner(&mut tx, follower_id, following_id, celebrity_follower_threshold).await?;
+        let event = FollowCreated { id: follow_id, follower_id, following_id };
+        if let Some(notification) = NotificationCreate::for_follow(&event) {
+            insert_notification_inner(&mut tx, &notification).await?;
         }
+        let event = AppEvent::FollowCreated(event);
+        insert_webhook_deliveries_inner(&mut tx, &event).await?;
+        notify_event_inner(&mut tx, &event).await?;
+
+        tx.commit().await?;
+        Ok(follow_id)
+    }
+
+    #[instrument(skip_all, fields(follower_id = follower_id))]
+    pub async fn query_following_ids_inner<'c, E>(
+        conn: E,
+        follower_id: i64
+    ) -> Result<Vec<i64>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("query_following_ids_inner");
+        sqlx
+            ::query_scalar!("select distinct following_id from follow where follower_id = $1", follower_id)
+            .fetch_all(conn).await
     }
 
-    pub async fn query_profile_inner(
-        conn: &Pool<Postgres>,
+    #[instrument(skip_all, fields(id = id))]
+    pub async fn query_profile_inner<'c, E>(
+        conn: E,
         id: i64
-    ) -> Result<Option<ProfileQueryResult>, sqlx::Error> {
+    ) -> Result<Option<ProfileQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("query_profile_inner");
         sqlx
-            ::query_as::<_, ProfileQueryResult>("select * from profile where id = $1")
-            .bind(id)
+            ::query_as!(
+                ProfileQueryResult,
+                r"
+                select id, created_at, updated_at, user_name, full_name, description, region, main_url, avatar
+                from profile
+                where id = $1
+            ",
+                id
+            )
             .fetch_optional(conn).await
     }
 
-    pub async fn query_profile_by_user_inner(
-        conn: &Pool<Postgres>,
+    #[instrument(skip_all)]
+    pub async fn query_profile_by_user_inner<'c, E>(
+        conn: E,
         user_name: String
-    ) -> Result<Option<ProfileQueryResult>, sqlx::Error> {
+    ) -> Result<Option<ProfileQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("query_profile_by_user_inner");
         sqlx
-            ::query_as::<_, ProfileQueryResult>("select * from profile where user_name = $1")
-            .bind(user_name)
+            ::query_as!(
+                ProfileQueryResult,
+                r"
+                select id, created_at, updated_at, user_name, full_name, description, region, main_url, avatar
+                from profile
+                where user_name = $1
+            ",
+                user_name
+            )
             .fetch_optional(conn).await
     }
 }
@@ -114,6 +183,16 @@ impl InsertProfileFn for DbRepo {
     }
 }
 
+#[async_trait]
+impl InsertProfileFn for UnitOfWork {
+    async fn insert_profile(
+        &self,
+        params: ProfileCreate
+    ) -> Result<i64, sqlx::Error> {
+        private_members::insert_profile_inner(&mut *self.lock().await, params).await
+    }
+}
+
 #[automock]
 #[async_trait]
 pub trait UpdateProfileAvatarFn {
@@ -135,6 +214,17 @@ impl UpdateProfileAvatarFn for DbRepo {
     }
 }
 
+#[async_trait]
+impl UpdateProfileAvatarFn for UnitOfWork {
+    async fn update_profile_avatar(
+        &self,
+        user_id: i64,
+        avatar: Vec<u8>
+    ) -> Result<(), sqlx::Error> {
+        private_members::update_profile_avatar_inner(&mut *self.lock().await, user_id, avatar).await
+    }
+}
+
 #[automock]
 #[async_trait]
 pub trait QueryProfileFn {
@@ -154,6 +244,16 @@ impl QueryProfileFn for DbRepo {
     }
 }
 
+#[async_trait]
+impl QueryProfileFn for UnitOfWork {
+    async fn query_profile(
+        &self,
+        id: i64
+    ) -> Result<Option<ProfileQueryResult>, sqlx::Error> {
+        private_members::query_profile_inner(&mut *self.lock().await, id).await
+    }
+}
+
 #[automock]
 #[async_trait]
 pub trait QueryProfileByUserFn {
@@ -173,6 +273,16 @@ impl QueryProfileByUserFn for DbRepo {
     }
 }
 
+#[async_trait]
+impl QueryProfileByUserFn for UnitOfWork {
+    async fn query_profile_by_user(
+        &self,
+        user_name: String
+    ) -> Result<Option<ProfileQueryResult>, sqlx::Error> {
+        private_members::query_profile_by_user_inner(&mut *self.lock().await, user_name).await
+    }
+}
+
 #[automock]
 #[async_trait]
 pub trait FollowUserFn {
@@ -190,7 +300,49 @@ impl FollowUserFn for DbRepo {
         follower_id: i64,
         following_id: i64
     ) -> Result<i64, sqlx::Error> {
-        private_members::follow_user_inner(self.get_conn(), follower_id, following_id).await
+        private_members::follow_user_inner(
+            self.get_conn(),
+            follower_id,
+            following_id,
+            self.celebrity_follower_threshold()
+        ).await
+    }
+}
+
+#[async_trait]
+impl FollowUserFn for UnitOfWork {
+    async fn follow_user(
+        &self,
+        follower_id: i64,
+        following_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        private_members::follow_user_inner(
+            &mut *self.lock().await,
+            follower_id,
+            following_id,
+            self.celebrity_follower_threshold()
+        ).await
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait QueryFollowingIdsFn {
+    /// ids of every profile follower_id follows
+    async fn query_following_ids(&self, follower_id: i64) -> Result<Vec<i64>, sqlx::Error>;
+}
+
+#[async_trait]
+impl QueryFollowingIdsFn for DbRepo {
+    async fn query_following_ids(&self, follower_id: i64) -> Result<Vec<i64>, sqlx::Error> {
+        private_members::query_following_ids_inner(self.get_conn(), follower_id).await
+    }
+}
+
+#[async_trait]
+impl QueryFollowingIdsFn for UnitOfWork {
+    async fn query_following_ids(&self, follower_id: i64) -> Result<Vec<i64>, sqlx::Error> {
+        private_members::query_following_ids_inner(&mut *self.lock().await, follower_id).await
     }
 }
 
@@ -200,10 +352,10 @@ mod tests {
         common_tests::actix_fixture::{
             PUBLIC_GROUP_TYPE,
             get_fake_message_body,
-            FixtureError,
-            MessageResponse,
+            get_parity_repos,
         },
         common::entities::{
+            app_repo::AppRepo,
             messages::repo::{ InsertMessageFn, InsertResponseMessageFn },
             circle_group::repo::{ InsertCircleFn, InsertCircleMemberFn }, 
         },
@@ -222,63 +374,25 @@ mod tests {
     #[allow(unused)]
     struct Fixtures {
         profiles: Vec<ProfileQueryResult>,
-        db_repo: DbRepo
+        db_repo: AppRepo
     }
 
     const PREFIX: &str = "Test profile";
 
     lazy_static! {
-        static ref FIXTURES: Arc<RwLock<Option<Fixtures>>> = Arc::new(RwLock::new(None));
+        static ref FIXTURES: Arc<RwLock<Option<Vec<Fixtures>>>> = Arc::new(RwLock::new(None));
     }
 
-    async fn setup_db_data(db_repo: DbRepo) -> Result<(), Box<dyn std::error::Error>> {
-        let current_user = "current_user".to_string();
-        let current_user_profile_result = db_repo.query_profile_by_user(
-            current_user.clone()
-        ).await;
-        if let Err(_) = current_user_profile_result {
-            return Err(
-                Box::new(FixtureError::QueryFailed("Get current user profile failed".to_string()))
-            );
-        } else if let Ok(profile) = current_user_profile_result {
-            if let Some(_) = profile {
-                let message_reponse_result = sqlx
-                    ::query_as::<_, MessageResponse>("select * from message_response")
-                    .fetch_all(db_repo.get_conn()).await;
-                match message_reponse_result {
-                    Ok(row) => {
-                        if row.len() > 0 {
-                            println!("log: Necessary test data already set, exiting");
-                            return Ok(());
-                        }
-                        return Err(
-                            Box::new(
-                                FixtureError::MissingData(
-                                    "Message Response data missing".to_string()
-                                )
-                            )
-                        );
-                    }
-                    Err(_) => {
-                        return Err(
-                            Box::new(
-                                FixtureError::QueryFailed(
-                                    "Message Response query failed".to_string()
-                                )
-                            )
-                        );
-                    }
-                }
-            }
-        }
-
+    /// a current user following ten profiles that post, broadcast and respond to each other,
+    /// returns every created profile
+    async fn setup_db_data(db_repo: AppRepo) -> Vec<ProfileQueryResult> {
         println!("log: Need to setup test data");
-        let tx = db_repo.get_conn().begin().await.unwrap();
 
         let description: String = Sentence(Range { start: 5, end: 8 }).fake();
         let current_profile_id = db_repo
             .insert_profile(ProfileCreate {
-                user_name: current_user,
+                // postgres keeps the profiles of earlier runs, so names looked up by tests must be unique
+                user_name: format!("current_user_{}", Username().fake::<String>()),
                 full_name: "Current User".to_string(),
                 description: format!("Test profile {} ", description),
                 region: Some(CountryName().fake()),
@@ -290,7 +404,7 @@ mod tests {
         let circle_group_id = db_repo.insert_circle(current_profile_id).await.unwrap();
 
         let mut following_profiles_and_messages: BTreeMap<i64, Vec<i64>> = BTreeMap::new();
-        let local_prefix = PREFIX.clone();
+        let local_prefix = PREFIX;
         for _ in 1..11 {
             let first_name: String = FirstName().fake();
             let last_name: String = LastName().fake();
@@ -301,7 +415,7 @@ mod tests {
                     full_name: format!("{} {}", first_name, last_name),
                     description: format!(
                         "{} {}",
-                        local_prefix.clone(),
+                        local_prefix,
                         Sentence(Range { start: 5, end: 8 }).fake::<String>()
                     ),
                     region: Some("usa".to_string()),
@@ -319,7 +433,7 @@ mod tests {
                 let following_message_id = db_repo
                     .insert_message(
                         following_profile_id,
-                        &get_fake_message_body(Some(local_prefix.clone().to_string())),
+                        &get_fake_message_body(Some(local_prefix.to_string())),
                         PUBLIC_GROUP_TYPE,
                         None
                     ).await
@@ -348,7 +462,7 @@ mod tests {
                 // randomly select some messages of this profile
                 // and have current profile create messages that broadcast the fhollowing user messages
                 let broadcast_message_ids = following_profiles_and_messages
-                    .get(&profile_id_to_broadcast)
+                    .get(profile_id_to_broadcast)
                     .unwrap();
                 for _ in [..4] {
                     let selected_message_id = broadcast_message_ids
@@ -356,7 +470,7 @@ mod tests {
                         .unwrap();
                     _ = db_repo.insert_message(
                         *following_pm.0,
-                        &get_fake_message_body(Some(local_prefix.clone().to_string())),
+                        &get_fake_message_body(Some(local_prefix.to_string())),
                         PUBLIC_GROUP_TYPE,
                         Some(*selected_message_id)
                     ).await;
@@ -370,7 +484,7 @@ mod tests {
                     .unwrap();
 
                 let response_message_ids = following_profiles_and_messages
-                    .get(&profile_id_to_respond_to)
+                    .get(profile_id_to_respond_to)
                     .unwrap();
                 for _ in [..4] {
                     let selected_message_id = response_message_ids
@@ -378,7 +492,7 @@ mod tests {
                         .unwrap();
                     _ = db_repo.insert_response_message(
                         *following_pm.0,
-                        &get_fake_message_body(Some(local_prefix.clone().to_string())),
+                        &get_fake_message_body(Some(local_prefix.to_string())),
                         PUBLIC_GROUP_TYPE,
                         *selected_message_id
                     ).await;
@@ -386,37 +500,31 @@ mod tests {
             }
         }
 
-        println!("log: Test data setup complete");
-        _ = tx.commit().await;
-
-        Ok(())
-    }
-
-    async fn setup_local_data(db_repo: DbRepo) -> Fixtures {
-        setup_db_data(db_repo.clone()).await.unwrap();
-
-        let profiles = sqlx
-            ::query_as::<_, ProfileQueryResult>(
-                "select * from profile where description like 'Test profile%'"
-            )
-            .fetch_all(db_repo.get_conn()).await
-            .unwrap();
-
-        Fixtures {
-            profiles,
-            db_repo,
+        let mut profiles = vec![];
+        for profile_id in std::iter::once(current_profile_id).chain(following_profiles_and_messages.into_keys()) {
+            profiles.push(db_repo.query_profile(profile_id).await.unwrap().unwrap());
         }
+
+        println!("log: Test data setup complete");
+        profiles
     }
 
+    // the write lock is held on purpose so only one test sets up the shared fixtures
+    #[allow(clippy::await_holding_lock)]
     async fn setup_fixtures() {
         let fixtures = Arc::clone(&FIXTURES);
         let mut fx = fixtures.write().unwrap();
         match fx.clone() {
             Some(_) => (),
             None => {
-                let db_repo = DbRepo::init().await;
-
-                *fx = Some(setup_local_data(db_repo).await);
+                let mut backend_fixtures = vec![];
+                for db_repo in get_parity_repos().await {
+                    backend_fixtures.push(Fixtures {
+                        profiles: setup_db_data(db_repo.clone()).await,
+                        db_repo,
+                    });
+                }
+                *fx = Some(backend_fixtures);
             }
         }
     }
@@ -433,15 +541,15 @@ mod tests {
         };
     }
 
-    fn fixtures() -> Fixtures {
+    /// the fixtures of every backend, each test body runs once per backend
+    fn fixtures() -> Vec<Fixtures> {
         Arc::clone(&FIXTURES).read().unwrap().clone().unwrap()
     }
 
     mod test_mod_insert_profile {
         use super::*;
 
-        async fn test_insert_profile_body() {
-            let fixtures = fixtures();
+        async fn test_insert_profile_body(fixtures: Fixtures) {
 
             let profile_id = fixtures.db_repo
                 .insert_profile(ProfileCreate {
@@ -459,7 +567,11 @@ mod tests {
 
         #[test]
         fn test_insert_profile() {
-            RT.block_on(test_insert_profile_body())
+            RT.block_on(async {
+                for fixtures in fixtures() {
+                    test_insert_profile_body(fixtures).await;
+                }
+            })
         }
     }
 
@@ -468,8 +580,7 @@ mod tests {
 
         use super::*;
 
-        async fn test_update_profile_avatar_body() {
-            let fixtures = fixtures();
+        async fn test_update_profile_avatar_body(fixtures: Fixtures) {
 
             let mut mock_query_profile = MockQueryProfileFn::new();
             let profiles = fixtures.profiles.clone();
@@ -486,25 +597,33 @@ mod tests {
                 .unwrap()
                 .unwrap();
 
+            // timestamps keep milliseconds, the update has to land in a later one
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
             let profile_result = fixtures.db_repo.update_profile_avatar(
                 profile.id,
                 get_profile_avatar()
             ).await;
 
-            assert!(profile_result.is_ok() == true);
+            assert!(profile_result.is_ok());
+            // the profile's etag is built from updated_at, so a new avatar has to move it
+            let updated = fixtures.db_repo.query_profile(profile.id).await.unwrap().unwrap();
+            assert!(updated.updated_at > profile.updated_at);
         }
 
         #[test]
         fn test_update_profile_avatar() {
-            RT.block_on(test_update_profile_avatar_body())
+            RT.block_on(async {
+                for fixtures in fixtures() {
+                    test_update_profile_avatar_body(fixtures).await;
+                }
+            })
         }
     }
 
     mod test_mod_query_profile {
         use super::*;
 
-        async fn test_insert_profile_and_get_profile_body() {
-            let fixtures = fixtures();
+        async fn test_insert_profile_and_get_profile_body(fixtures: Fixtures) {
 
             let selected_profile = fixtures.profiles[0].clone(); // arbitrarily get first profile
             let profile_to_create = ProfileCreate {
@@ -547,15 +666,18 @@ mod tests {
 
         #[test]
         fn test_insert_profile_and_get_profile() {
-            RT.block_on(test_insert_profile_and_get_profile_body())
+            RT.block_on(async {
+                for fixtures in fixtures() {
+                    test_insert_profile_and_get_profile_body(fixtures).await;
+                }
+            })
         }
     }
 
     mod test_mod_query_profile_by_user {
         use super::*;
 
-        async fn test_insert_profile_and_get_profile_by_user_body() {
-            let fixtures = fixtures();
+        async fn test_insert_profile_and_get_profile_by_user_body(fixtures: Fixtures) {
 
             let selected_profile = fixtures.profiles[0].clone();
             let profile_to_create = ProfileCreate {
@@ -600,15 +722,18 @@ mod tests {
 
         #[test]
         fn test_insert_profile_and_get_profile_by_user() {
-            RT.block_on(test_insert_profile_and_get_profile_by_user_body())
+            RT.block_on(async {
+                for fixtures in fixtures() {
+                    test_insert_profile_and_get_profile_by_user_body(fixtures).await;
+                }
+            })
         }
     }
 
     mod test_mod_insert_follower {
         use super::*;
 
-        async fn test_insert_follow_user_body() {
-            let fixtures = fixtures();
+        async fn test_insert_follow_user_body(fixtures: Fixtures) {
 
             let mut mock_insert_repo = MockInsertProfileFn::new();
             let profiles = fixtures.profiles.clone();
@@ -658,7 +783,11 @@ mod tests {
 
         #[test]
         fn test_insert_follow_user() {
-            RT.block_on(test_insert_follow_user_body())
+            RT.block_on(async {
+                for fixtures in fixtures() {
+                    test_insert_follow_user_body(fixtures).await;
+                }
+            })
         }
     }
 }
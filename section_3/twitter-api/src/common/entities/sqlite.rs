@@ -0,0 +1,1778 @@
+use async_trait::async_trait;
+use chrono::{ DateTime, Duration, SubsecRound, Utc };
+use sqlx::{ Acquire, Executor, Pool, Sqlite, SqliteConnection, Transaction, sqlite::{ SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions } };
+use tokio::sync::{ broadcast, MappedMutexGuard, Mutex, MutexGuard };
+use tracing::info;
+use crate::common::{
+    config::{ Config, SqliteConfig },
+    entities::{
+        base::{ DbConnGetter, PoolStatsFn, QuerySchemaVersionFn },
+        circle_group::{
+            model::{ CircleGroupMemberWithProfileQueryResult, CircleGroupWithProfileQueryResult },
+            repo::{ InsertCircleFn, InsertCircleMemberFn, QueryCircleFn, QueryCircleMemberFn },
+        },
+        events::repo::SubscribeEventsFn,
+        messages::{
+            model::{ is_hashtag_char, MessageCountsQueryResult, MessageStreamFilter, MessageWithFollowingAndBroadcastQueryResult },
+            repo::{
+                InsertMessageFn, InsertResponseMessageFn, LikeMessageFn, QueryMessageCountsFn, QueryMessageFn,
+                QueryMessagesAfterFn, QueryMessagesFn, MAX_BROADCAST_DEPTH,
+            },
+        },
+        notifications::{
+            model::{ get_mentioned_user_names, NotificationCreate, NotificationGroupQueryResult, NotificationKind },
+            repo::{
+                MarkNotificationGroupReadFn, MarkNotificationsReadFn, QueryNotificationGroupsFn,
+                QueryUnreadNotificationCountFn,
+            },
+        },
+        profiles::{
+            model::{ ProfileCreate, ProfileQueryResult },
+            repo::{
+                FollowUserFn, InsertProfileFn, QueryFollowingIdsFn, QueryProfileByUserFn, QueryProfileFn,
+                UpdateProfileAvatarFn,
+            },
+        },
+        timeline::repo::RebuildTimelinesFn,
+        webhooks::{
+            model::{
+                get_event_key, get_involved_profile_ids, WebhookAttempt, WebhookDeliveryQueryResult, WebhookDeliveryStatus, WebhookDueDelivery, WebhookEventType,
+                WebhookSubscriptionCreate, WebhookSubscriptionQueryResult,
+            },
+            repo::{
+                ClaimWebhookDeliveriesFn, InsertWebhookSubscriptionFn, QueryWebhookDeliveriesFn,
+                QueryWebhookSubscriptionsFn, RecordWebhookAttemptFn, ReplayWebhookDeliveryFn,
+            },
+        },
+    },
+    events::{
+        AppEvent, EventBus, FollowCreated, MemoryEventBus, MessageCreated, MessageLiked, MessageRef, NotificationCreated, PendingEvents,
+    },
+    metrics::PoolStats,
+    migration::{ ensure_schema_current, SQLITE_MIGRATOR },
+};
+
+/// timestamps are stored as text like 2023-05-01T12:00:00.123Z, which sorts like the instants it holds.
+/// Sub-millisecond values are rounded up so `<` against a stored value behaves like postgres comparing to timestamptz(3)
+fn to_sqlite_timestamp(value: DateTime<Utc>) -> String {
+    let truncated = value.trunc_subsecs(3);
+    let rounded = if truncated < value { truncated + Duration::milliseconds(1) } else { truncated };
+    rounded.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+// inserts take the id from last_insert_rowid, `returning` read with fetch_one leaves the statement
+// unfinished, and until it is reset the write is not visible to other pooled connections
+mod private_members {
+    use crate::common::metrics::start_query_timer;
+    use tracing::{ error, instrument };
+    use super::*;
+
+    /// follows the chain of broadcasts from each message of a `page` cte the query defines first, binds
+    /// MAX_BROADCAST_DEPTH, and selects the page messages with their author and the root of their chain
+    const PAGE_WITH_RESOLVED_BROADCAST: &str = r"
+        broadcast_chain (main_msg_id, broadcasting_msg_id, depth) as (
+            select mb.main_msg_id, mb.broadcasting_msg_id, 1
+                from message_broadcast mb
+                    join page on page.id = mb.main_msg_id
+            union all
+            select bc.main_msg_id, mb.broadcasting_msg_id, bc.depth + 1
+                from broadcast_chain bc
+                    join message_broadcast mb on mb.main_msg_id = bc.broadcasting_msg_id
+                where bc.depth < ?
+        ),
+        resolved_broadcast as (
+            select bc.main_msg_id, bc.broadcasting_msg_id
+                from broadcast_chain bc
+                where bc.depth = (select max(depth) from broadcast_chain where main_msg_id = bc.main_msg_id)
+        )
+        select m.id, m.updated_at, m.body, m.likes, m.image, m.msg_group_type, m.user_id, p.user_name, p.full_name, p.avatar,
+            bm.id as broadcast_msg_id, bm.updated_at as broadcast_msg_updated_at, bm.body as broadcast_msg_body,
+            bm.likes as broadcast_msg_likes, bm.image as broadcast_msg_image, bm.user_id as broadcast_msg_user_id,
+            bp.user_name as broadcast_msg_user_name, bp.full_name as broadcast_msg_full_name, bp.avatar as broadcast_msg_avatar
+            from page m
+                join profile p on m.user_id = p.id
+                left join resolved_broadcast rb on rb.main_msg_id = m.id
+                left join message bm on bm.id = rb.broadcasting_msg_id
+                left join profile bp on bp.id = bm.user_id
+            order by m.updated_at desc, m.id desc
+    ";
+
+    /// like the postgres fan-out in timeline/repo.rs, runs inside the transaction that inserts the message
+    #[instrument(skip_all, fields(message_id = message_id))]
+    async fn fan_out_message_inner(
+        conn: &mut SqliteConnection,
+        message_id: i64,
+        celebrity_follower_threshold: i64
+    ) -> Result<u64, sqlx::Error> {
+        let _timer = start_query_timer("fan_out_message_inner");
+        sqlx
+            ::query::<_>(
+                r"
+                insert or ignore into timeline_entry (owner_id, message_id, message_updated_at)
+                    select f.follower_id, m.id, m.updated_at
+                        from message m
+                            join follow f on f.following_id = m.user_id
+                            join profile p on p.id = m.user_id
+                        where
+                            m.id = ?
+                            and p.follower_count <= ?
+            "
+            )
+            .bind(message_id)
+            .bind(celebrity_follower_threshold)
+            .execute(conn).await
+            .map(|r| r.rows_affected())
+    }
+
+    /// the author of a message that is being replied to or broadcast, who is notified
+    async fn query_message_ref_inner(conn: &mut SqliteConnection, id: i64) -> Result<MessageRef, sqlx::Error> {
+        let (id, user_id) = sqlx
+            ::query_as::<_, (i64, i64)>("select id, user_id from message where id = ?")
+            .bind(id)
+            .fetch_one(conn).await?;
+        Ok(MessageRef { id, user_id })
+    }
+
+    /// like the postgres insert in notifications/repo.rs, runs inside the transaction of the write that caused it.
+    /// Returns the event to publish once that transaction commits
+    async fn insert_notification_inner(
+        conn: &mut SqliteConnection,
+        notification: &NotificationCreate
+    ) -> Result<NotificationCreated, sqlx::Error> {
+        sqlx
+            ::query::<_>(
+                "insert into notification (recipient_id, actor_id, kind, target_msg_id, source_msg_id) values (?, ?, ?, ?, ?)"
+            )
+            .bind(notification.recipient_id)
+            .bind(notification.actor_id)
+            .bind(notification.kind)
+            .bind(notification.target_msg_id)
+            .bind(notification.source_msg_id)
+            .execute(conn).await
+            .map(|r| notification.to_event(r.last_insert_rowid()))
+    }
+
+    /// like the postgres insert in webhooks/repo.rs, runs inside the transaction of the write that caused the event.
+    /// The involved profiles are bound as a json array
+    async fn insert_webhook_deliveries_inner(conn: &mut SqliteConnection, event: &AppEvent) -> Result<u64, sqlx::Error> {
+        let (Some(event_type), Some(event_key)) = (WebhookEventType::of(event), get_event_key(event)) else {
+            return Ok(0);
+        };
+        let payload = serde_json::to_string(event).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+        let profile_ids = serde_json::to_string(&get_involved_profile_ids(event)).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+        sqlx
+            ::query::<_>(
+                r"
+                insert or ignore into webhook_delivery (subscription_id, event_type, event_key, payload, status, next_attempt_at)
+                    select s.id, ?1, ?2, ?3, ?4, strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                        from webhook_subscription s
+                        where s.event_types & ?1 <> 0 and s.user_id in (select value from json_each(?5))
+            "
+            )
+            .bind(event_type)
+            .bind(event_key)
+            .bind(payload)
+            .bind(WebhookDeliveryStatus::Pending)
+            .bind(profile_ids)
+            .execute(conn).await
+            .map(|r| r.rows_affected())
+    }
+
+    /// like the postgres mention notifications, the user names are bound as a json array
+    async fn insert_mention_notifications_inner(
+        conn: &mut SqliteConnection,
+        message_id: i64,
+        user_id: i64,
+        user_names: &[String]
+    ) -> Result<Vec<NotificationCreated>, sqlx::Error> {
+        if user_names.is_empty() {
+            return Ok(vec![]);
+        }
+        let user_names = serde_json::to_string(user_names).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+        let inserted = sqlx
+            ::query_as::<_, (i64, i64)>(
+                r"
+                insert into notification (recipient_id, actor_id, kind, target_msg_id, source_msg_id)
+                    select p.id, ?2, ?3, ?1, ?1
+                        from profile p
+                        where
+                            p.user_name in (select value from json_each(?4))
+                            and p.id <> ?2
+                            and not exists (select 1 from notification n where n.source_msg_id = ?1 and n.recipient_id = p.id)
+                    returning id, recipient_id
+            "
+            )
+            .bind(message_id)
+            .bind(user_id)
+            .bind(NotificationKind::Mention)
+            .bind(user_names)
+            .fetch_all(conn).await?;
+        Ok(
+            inserted
+                .into_iter()
+                .map(|(id, recipient_id)| NotificationCreated {
+                    id,
+                    recipient_id,
+                    actor_id: user_id,
+                    kind: NotificationKind::Mention,
+                    target_msg_id: Some(message_id),
+                    source_msg_id: Some(message_id),
+                })
+                .collect()
+        )
+    }
+
+    /// the notifications a new message causes, written before its transaction commits
+    async fn insert_message_notifications_inner(
+        conn: &mut SqliteConnection,
+        event: &MessageCreated,
+        body: &str
+    ) -> Result<Vec<NotificationCreated>, sqlx::Error> {
+        let mut notifications = vec![];
+        if let Some(notification) = NotificationCreate::for_message(event, body) {
+            notifications.push(insert_notification_inner(&mut *conn, &notification).await?);
+        }
+        notifications.extend(insert_mention_notifications_inner(conn, event.id, event.user_id, &get_mentioned_user_names(body)).await?);
+        Ok(notifications)
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id, group_type = group_type))]
+    pub async fn insert_message_inner<'c, A>(
+        conn: A,
+        user_id: i64,
+        body: &str,
+        group_type: i32,
+        broadcasting_msg_id: Option<i64>,
+        celebrity_follower_threshold: i64
+    ) -> Result<(MessageCreated, Vec<NotificationCreated>), sqlx::Error>
+        where A: Acquire<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("insert_message_inner");
+        let mut tx = conn.begin().await?;
+
+        let message_id = match sqlx
+            ::query::<_>("insert into message (user_id, body, msg_group_type) values (?, ?, ?)")
+            .bind(user_id)
+            .bind(body)
+            .bind(group_type)
+            .execute(&mut tx).await
+        {
+            Ok(r) => r.last_insert_rowid(),
+            Err(e) => {
+                error!("insert_message error: {}", e);
+                return Err(e);
+            }
+        };
+
+        let mut broadcasting_msg = None;
+        if let Some(bm_id) = broadcasting_msg_id {
+            sqlx
+                ::query::<_>("insert into message_broadcast (main_msg_id, broadcasting_msg_id) values (?, ?)")
+                .bind(message_id)
+                .bind(bm_id)
+                .execute(&mut tx).await?;
+            broadcasting_msg = Some(query_message_ref_inner(&mut tx, bm_id).await?);
+        }
+
+        fan_out_message_inner(&mut tx, message_id, celebrity_follower_threshold).await?;
+        let event = MessageCreated { id: message_id, user_id, msg_group_type: group_type, original_msg: None, broadcasting_msg };
+        let notifications = insert_message_notifications_inner(&mut tx, &event, body).await?;
+        insert_webhook_deliveries_inner(&mut tx, &AppEvent::MessageCreated(event.clone())).await?;
+        tx.commit().await?;
+        Ok((event, notifications))
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn insert_response_message_inner<'c, A>(
+        conn: A,
+        user_id: i64,
+        body: &str,
+        group_type: i32,
+        original_msg_id: i64,
+        celebrity_follower_threshold: i64
+    ) -> Result<(MessageCreated, Vec<NotificationCreated>), sqlx::Error>
+        where A: Acquire<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("insert_response_message_inner");
+        let mut tx = conn.begin().await?;
+
+        let msg_id = match sqlx
+            ::query::<_>("insert into message (user_id, body, msg_group_type) values (?, ?, ?)")
+            .bind(user_id)
+            .bind(body)
+            .bind(group_type)
+            .execute(&mut tx).await
+        {
+            Ok(r) => r.last_insert_rowid(),
+            Err(e) => {
+                error!("insert_message error: {}", e);
+                return Err(e);
+            }
+        };
+
+        sqlx
+            ::query::<_>("insert into message_response (original_msg_id, responding_msg_id) values (?, ?)")
+            .bind(original_msg_id)
+            .bind(msg_id)
+            .execute(&mut tx).await?;
+        let original_msg = query_message_ref_inner(&mut tx, original_msg_id).await?;
+
+        fan_out_message_inner(&mut tx, msg_id, celebrity_follower_threshold).await?;
+        let event = MessageCreated { id: msg_id, user_id, msg_group_type: group_type, original_msg: Some(original_msg), broadcasting_msg: None };
+        let notifications = insert_message_notifications_inner(&mut tx, &event, body).await?;
+        insert_webhook_deliveries_inner(&mut tx, &AppEvent::MessageCreated(event.clone())).await?;
+        tx.commit().await?;
+        Ok((event, notifications))
+    }
+
+    /// like the postgres like, None when the profile already liked the message
+    #[instrument(skip_all, fields(user_id = user_id, msg_id = msg_id))]
+    pub async fn like_message_inner<'c, A>(
+        conn: A,
+        user_id: i64,
+        msg_id: i64
+    ) -> Result<Option<(MessageLiked, Option<NotificationCreated>)>, sqlx::Error>
+        where A: Acquire<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("like_message_inner");
+        let mut tx = conn.begin().await?;
+
+        let inserted = sqlx
+            ::query::<_>("insert or ignore into message_like (user_id, message_id) values (?, ?)")
+            .bind(user_id)
+            .bind(msg_id)
+            .execute(&mut tx).await?;
+        if inserted.rows_affected() == 0 {
+            return Ok(None);
+        }
+        sqlx
+            ::query::<_>("update message set likes = likes + 1 where id = ?")
+            .bind(msg_id)
+            .execute(&mut tx).await?;
+        let message = query_message_ref_inner(&mut tx, msg_id).await?;
+
+        let event = MessageLiked { id: inserted.last_insert_rowid(), user_id, message };
+        let notification = match NotificationCreate::for_like(&event) {
+            Some(notification) => Some(insert_notification_inner(&mut tx, &notification).await?),
+            None => None,
+        };
+        insert_webhook_deliveries_inner(&mut tx, &AppEvent::MessageLiked(event)).await?;
+        tx.commit().await?;
+        Ok(Some((event, notification)))
+    }
+
+    #[instrument(skip_all, fields(id = id))]
+    pub async fn query_message_inner<'c, E>(
+        conn: E,
+        id: i64
+    ) -> Result<Option<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("query_message_inner");
+        sqlx
+            ::query_as::<_, MessageWithFollowingAndBroadcastQueryResult>(
+                &format!(
+                    r"
+                    with recursive page as (
+                        select m.id, m.updated_at, m.body, m.likes, m.image, m.msg_group_type, m.user_id
+                            from message m
+                            where m.id = ?
+                    ),
+                    {}",
+                    PAGE_WITH_RESOLVED_BROADCAST
+                )
+            )
+            .bind(id)
+            .bind(MAX_BROADCAST_DEPTH)
+            .fetch_optional(conn).await
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id, page_size = page_size))]
+    pub async fn query_messages_inner<'c, E>(
+        conn: E,
+        user_id: i64,
+        last_updated_at: DateTime<Utc>,
+        page_size: i16,
+        celebrity_follower_threshold: i64
+    ) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("query_messages_inner");
+        sqlx
+            ::query_as::<_, MessageWithFollowingAndBroadcastQueryResult>(
+                &format!(
+                    r"
+                    with recursive celebrity as (
+                        select f.following_id as id
+                            from follow f
+                                join profile p on p.id = f.following_id
+                            where
+                                f.follower_id = ?1
+                                and p.follower_count > ?4
+                    ),
+                    timeline as (
+                        select te.message_id as id
+                            from timeline_entry te
+                            where
+                                te.owner_id = ?1
+                                and te.message_updated_at < ?2
+                            order by te.message_updated_at desc, te.message_id desc
+                            limit ?3
+                    ),
+                    celebrity_timeline as (
+                        select m.id
+                            from message m
+                            where
+                                m.user_id in (select id from celebrity)
+                                and m.updated_at < ?2
+                            order by m.updated_at desc, m.id desc
+                            limit ?3
+                    ),
+                    page as (
+                        select m.id, m.updated_at, m.body, m.likes, m.image, m.msg_group_type, m.user_id
+                            from message m
+                            where m.id in (select id from timeline union select id from celebrity_timeline)
+                            order by m.updated_at desc, m.id desc
+                            limit ?3
+                    ),
+                    {}",
+                    PAGE_WITH_RESOLVED_BROADCAST
+                )
+            )
+            .bind(user_id)
+            .bind(to_sqlite_timestamp(last_updated_at))
+            .bind(page_size)
+            .bind(celebrity_follower_threshold)
+            .bind(MAX_BROADCAST_DEPTH)
+            .fetch_all(conn).await
+    }
+
+    #[instrument(skip_all, fields(after_id = after_id, page_size = page_size))]
+    pub async fn query_messages_after_inner<'c, E>(
+        conn: E,
+        after_id: i64,
+        filter: &MessageStreamFilter,
+        page_size: i16
+    ) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("query_messages_after_inner");
+        let mut messages = sqlx
+            ::query_as::<_, MessageWithFollowingAndBroadcastQueryResult>(
+                &format!(
+                    r"
+                    with recursive page as (
+                        select m.id, m.updated_at, m.body, m.likes, m.image, m.msg_group_type, m.user_id
+                            from message m
+                            where
+                                m.id > ?1
+                                and m.msg_group_type = ?2
+                                and (?3 is null or m.user_id = ?3)
+                                and (?4 is null or ' ' || lower(m.body) || ' ' glob ?4)
+                            order by m.id
+                            limit ?5
+                    ),
+                    {}",
+                    PAGE_WITH_RESOLVED_BROADCAST
+                )
+            )
+            .bind(after_id)
+            .bind(filter.group_type)
+            .bind(filter.user_id)
+            .bind(filter.tag.as_deref().map(get_tag_pattern))
+            .bind(page_size)
+            .bind(MAX_BROADCAST_DEPTH)
+            .fetch_all(conn).await?;
+        // the shared select orders newest first
+        messages.sort_by_key(|message| message.id);
+        Ok(messages)
+    }
+
+    /// a glob for tag as a whole hashtag in the lowercase body padded with a space on each side, the rule of
+    /// mentions_hashtag. Characters glob treats specially are bracketed so only the tag's own characters match
+    fn get_tag_pattern(tag: &str) -> String {
+        let escaped = tag
+            .to_lowercase()
+            .chars()
+            .map(|c| if is_hashtag_char(c) { c.to_string() } else { format!("[{}]", c) })
+            .collect::<String>();
+        format!("*[^a-z0-9_]#{}[^a-z0-9_]*", escaped)
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_last_message_id_inner<'c, E>(conn: E) -> Result<Option<i64>, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("query_last_message_id_inner");
+        sqlx::query_scalar::<_, Option<i64>>("select max(id) from message").fetch_one(conn).await
+    }
+
+    #[instrument(skip_all, fields(count = ids.len()))]
+    pub async fn query_message_counts_inner<'c, E>(
+        conn: E,
+        ids: &[i64]
+    ) -> Result<Vec<MessageCountsQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("query_message_counts_inner");
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        // sqlite has no array binds, the ids go in as a values list instead
+        let query = format!(
+            r"
+            with ids (id) as (values {})
+            select ids.id,
+                coalesce(r.reply_count, 0) as reply_count,
+                coalesce(b.rebroadcast_count, 0) as rebroadcast_count,
+                coalesce(b.quote_count, 0) as quote_count
+                from ids
+                    left join (
+                        select mr.original_msg_id, count(*) as reply_count
+                            from message_response mr
+                            where mr.original_msg_id in (select id from ids)
+                            group by mr.original_msg_id
+                    ) r on r.original_msg_id = ids.id
+                    left join (
+                        select mb.broadcasting_msg_id,
+                            count(*) filter (where coalesce(m.body, '') = '') as rebroadcast_count,
+                            count(*) filter (where coalesce(m.body, '') <> '') as quote_count
+                            from message_broadcast mb
+                                join message m on m.id = mb.main_msg_id
+                            where mb.broadcasting_msg_id in (select id from ids)
+                            group by mb.broadcasting_msg_id
+                    ) b on b.broadcasting_msg_id = ids.id
+            ",
+            vec!["(?)"; ids.len()].join(", ")
+        );
+        let mut counts_query = sqlx::query_as::<_, MessageCountsQueryResult>(&query);
+        for id in ids {
+            counts_query = counts_query.bind(id);
+        }
+        counts_query.fetch_all(conn).await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn insert_profile_inner<'c, E>(
+        conn: E,
+        params: ProfileCreate
+    ) -> Result<i64, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("insert_profile_inner");
+        let result = sqlx
+            ::query::<_>(
+                r"
+                insert into profile
+                    (user_name, full_name, description, region, main_url, avatar)
+                    values
+                    (?, ?, ?, ?, ?, ?)"
+            )
+            .bind(&params.user_name)
+            .bind(&params.full_name)
+            .bind(&params.description)
+            .bind(&params.region)
+            .bind(&params.main_url)
+            .bind(&params.avatar)
+            .execute(conn).await;
+
+        match result {
+            Ok(r) => Ok(r.last_insert_rowid()),
+            Err(e) => {
+                error!("create_profile error: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn update_profile_avatar_inner<'c, E>(
+        conn: E,
+        user_id: i64,
+        avatar: Vec<u8>
+    ) -> Result<(), sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("update_profile_avatar_inner");
+        sqlx
+            ::query::<_>("update profile set avatar = ?, updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') where id = ?")
+            .bind(avatar)
+            .bind(user_id)
+            .execute(conn).await
+            .map(|_| ())
+    }
+
+    #[instrument(skip_all, fields(follower_id = follower_id, following_id = following_id))]
+    pub async fn follow_user_inner<'c, A>(
+        conn: A,
+        follower_id: i64,
+        following_id: i64,
+        celebrity_follower_threshold: i64
+    ) -> Result<(FollowCreated, Option<NotificationCreated>), sqlx::Error>
+        where A: Acquire<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("follow_user_inner");
+        let mut tx = conn.begin().await?;
+
+        let follow_id = sqlx
+            ::query::<_>("insert into follow (follower_id, following_id) values (?, ?)")
+            .bind(follower_id)
+            .bind(following_id)
+            .execute(&mut tx).await?
+            .last_insert_rowid();
+        sqlx
+            ::query::<_>("update profile set follower_count = follower_count + 1 where id = ?")
+            .bind(following_id)
+            .execute(&mut tx).await?;
+        sqlx
+            ::query::<_>(
+                r"
+                insert or ignore into timeline_entry (owner_id, message_id, message_updated_at)
+                    select ?1, m.id, m.updated_at
+                        from message m
+                            join profile p on p.id = m.user_id
+                        where
+                            m.user_id = ?2
+                            and p.follower_count <= ?3
+            "
+            )
+            .bind(follower_id)
+            .bind(following_id)
+            .bind(celebrity_follower_threshold)
+            .execute(&mut tx).await?;
+        let event = FollowCreated { id: follow_id, follower_id, following_id };
+        let notification = match NotificationCreate::for_follow(&event) {
+            Some(notification) => Some(insert_notification_inner(&mut tx, &notification).await?),
+            None => None,
+        };
+        insert_webhook_deliveries_inner(&mut tx, &AppEvent::FollowCreated(event)).await?;
+
+        tx.commit().await?;
+        Ok((event, notification))
+    }
+
+    #[instrument(skip_all, fields(owner_id = owner_id))]
+    pub async fn rebuild_timelines_inner<'c, A>(
+        conn: A,
+        owner_id: Option<i64>,
+        celebrity_follower_threshold: i64
+    ) -> Result<u64, sqlx::Error>
+        where A: Acquire<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("rebuild_timelines_inner");
+        let mut tx = conn.begin().await?;
+
+        sqlx
+            ::query::<_>("delete from timeline_entry where ?1 is null or owner_id = ?1")
+            .bind(owner_id)
+            .execute(&mut tx).await?;
+        let inserted = sqlx
+            ::query::<_>(
+                r"
+                insert or ignore into timeline_entry (owner_id, message_id, message_updated_at)
+                    select f.follower_id, m.id, m.updated_at
+                        from follow f
+                            join message m on m.user_id = f.following_id
+                            join profile p on p.id = f.following_id
+                        where
+                            (?1 is null or f.follower_id = ?1)
+                            and p.follower_count <= ?2
+            "
+            )
+            .bind(owner_id)
+            .bind(celebrity_follower_threshold)
+            .execute(&mut tx).await?;
+
+        tx.commit().await?;
+        Ok(inserted.rows_affected())
+    }
+
+    #[instrument(skip_all, fields(follower_id = follower_id))]
+    pub async fn query_following_ids_inner<'c, E>(
+        conn: E,
+        follower_id: i64
+    ) -> Result<Vec<i64>, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("query_following_ids_inner");
+        sqlx
+            ::query_scalar::<_, i64>("select distinct following_id from follow where follower_id = ?")
+            .bind(follower_id)
+            .fetch_all(conn).await
+    }
+
+    #[instrument(skip_all, fields(id = id))]
+    pub async fn query_profile_inner<'c, E>(
+        conn: E,
+        id: i64
+    ) -> Result<Option<ProfileQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("query_profile_inner");
+        sqlx
+            ::query_as::<_, ProfileQueryResult>("select * from profile where id = ?")
+            .bind(id)
+            .fetch_optional(conn).await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_profile_by_user_inner<'c, E>(
+        conn: E,
+        user_name: String
+    ) -> Result<Option<ProfileQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("query_profile_by_user_inner");
+        sqlx
+            ::query_as::<_, ProfileQueryResult>("select * from profile where user_name = ?")
+            .bind(user_name)
+            .fetch_optional(conn).await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn insert_circle_inner<'c, E>(
+        conn: E,
+        circle_owner_id: i64
+    ) -> Result<i64, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("insert_circle_inner");
+        sqlx
+            ::query::<_>("insert into circle_group (owner_id) values (?)")
+            .bind(circle_owner_id)
+            .execute(conn).await
+            .map(|r| r.last_insert_rowid())
+    }
+
+    #[instrument(skip_all)]
+    pub async fn insert_circle_member_inner<'c, E>(
+        conn: E,
+        circle_group_id: i64,
+        new_member_id: i64
+    ) -> Result<i64, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("insert_circle_member_inner");
+        sqlx
+            ::query::<_>("insert into circle_group_member (circle_group_id, member_id) values (?, ?)")
+            .bind(circle_group_id)
+            .bind(new_member_id)
+            .execute(conn).await
+            .map(|r| r.last_insert_rowid())
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_circle_inner<'c, E>(
+        conn: E,
+        id: i64
+    ) -> Result<Option<CircleGroupWithProfileQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("query_circle_inner");
+        sqlx
+            ::query_as::<_, CircleGroupWithProfileQueryResult>(
+                r"
+                select c.id, c.updated_at, c.owner_id, p.user_name, p.full_name, p.avatar
+                from circle_group c
+                    join profile p on c.owner_id = p.id
+                where c.id = ?
+            "
+            )
+            .bind(id)
+            .fetch_optional(conn).await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_circle_member_inner<'c, E>(
+        conn: E,
+        id: i64
+    ) -> Result<Option<CircleGroupMemberWithProfileQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("query_circle_member_inner");
+        sqlx
+            ::query_as::<_, CircleGroupMemberWithProfileQueryResult>(
+                r"
+                select c.id, c.updated_at, c.circle_group_id, p.id as member_id, p.user_name, p.full_name, p.avatar
+                from circle_group_member c
+                    join profile p on c.member_id = p.id
+                where c.id = ?
+            "
+            )
+            .bind(id)
+            .fetch_optional(conn).await
+    }
+
+    #[instrument(skip_all, fields(recipient_id = recipient_id, page_size = page_size))]
+    pub async fn query_notification_groups_inner<'c, E>(
+        conn: E,
+        recipient_id: i64,
+        before_id: Option<i64>,
+        page_size: i16
+    ) -> Result<Vec<NotificationGroupQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("query_notification_groups_inner");
+        sqlx
+            ::query_as::<_, NotificationGroupQueryResult>(
+                r"
+                select g.latest_id, n.created_at as latest_created_at, g.kind, g.target_msg_id, n.source_msg_id,
+                    g.actor_count, g.unread_count, p.id as actor_id, p.user_name, p.full_name
+                    from (
+                        select kind, target_msg_id, max(id) as latest_id, count(distinct actor_id) as actor_count,
+                            sum(read_at is null) as unread_count
+                            from notification
+                            where recipient_id = ?1
+                            group by kind, target_msg_id
+                            having ?2 is null or max(id) < ?2
+                    ) g
+                        join notification n on n.id = g.latest_id
+                        join profile p on p.id = n.actor_id
+                    order by g.latest_id desc
+                    limit ?3
+            "
+            )
+            .bind(recipient_id)
+            .bind(before_id)
+            .bind(page_size)
+            .fetch_all(conn).await
+    }
+
+    #[instrument(skip_all, fields(recipient_id = recipient_id))]
+    pub async fn query_unread_notification_count_inner<'c, E>(
+        conn: E,
+        recipient_id: i64
+    ) -> Result<i64, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("query_unread_notification_count_inner");
+        sqlx
+            ::query_scalar::<_, i64>("select count(*) from notification where recipient_id = ? and read_at is null")
+            .bind(recipient_id)
+            .fetch_one(conn).await
+    }
+
+    #[instrument(skip_all, fields(recipient_id = recipient_id, up_to_id = up_to_id))]
+    pub async fn mark_notifications_read_inner<'c, E>(
+        conn: E,
+        recipient_id: i64,
+        up_to_id: Option<i64>
+    ) -> Result<u64, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("mark_notifications_read_inner");
+        sqlx
+            ::query::<_>(
+                r"
+                update notification set read_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                    where recipient_id = ?1 and read_at is null and (?2 is null or id <= ?2)
+            "
+            )
+            .bind(recipient_id)
+            .bind(up_to_id)
+            .execute(conn).await
+            .map(|r| r.rows_affected())
+    }
+
+    #[instrument(skip_all, fields(recipient_id = recipient_id, target_msg_id = target_msg_id))]
+    pub async fn mark_notification_group_read_inner<'c, E>(
+        conn: E,
+        recipient_id: i64,
+        kind: NotificationKind,
+        target_msg_id: Option<i64>
+    ) -> Result<u64, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("mark_notification_group_read_inner");
+        sqlx
+            ::query::<_>(
+                r"
+                update notification set read_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                    where recipient_id = ? and read_at is null and kind = ? and target_msg_id is ?
+            "
+            )
+            .bind(recipient_id)
+            .bind(kind)
+            .bind(target_msg_id)
+            .execute(conn).await
+            .map(|r| r.rows_affected())
+    }
+
+    /// the columns of a WebhookDueDelivery, from webhook_delivery d joined to its subscription s
+    const DUE_DELIVERY_COLUMNS: &str = "d.id, d.event_type, d.payload, d.attempt_count, s.url, s.secret";
+
+    const DELIVERY_COLUMNS: &str = r"
+        id, created_at, subscription_id, event_type, payload, status, attempt_count, next_attempt_at, last_attempt_at,
+        last_status_code, last_error
+    ";
+
+    #[instrument(skip_all, fields(user_id = params.user_id))]
+    pub async fn insert_webhook_subscription_inner<'c, E>(
+        conn: E,
+        params: WebhookSubscriptionCreate
+    ) -> Result<i64, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("insert_webhook_subscription_inner");
+        sqlx
+            ::query::<_>("insert into webhook_subscription (user_id, url, secret, event_types) values (?, ?, ?, ?)")
+            .bind(params.user_id)
+            .bind(params.url)
+            .bind(params.secret)
+            .bind(params.event_types)
+            .execute(conn).await
+            .map(|r| r.last_insert_rowid())
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn query_webhook_subscriptions_inner<'c, E>(
+        conn: E,
+        user_id: i64
+    ) -> Result<Vec<WebhookSubscriptionQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("query_webhook_subscriptions_inner");
+        sqlx
+            ::query_as::<_, WebhookSubscriptionQueryResult>(
+                "select id, created_at, user_id, url, secret, event_types from webhook_subscription where user_id = ? order by id"
+            )
+            .bind(user_id)
+            .fetch_all(conn).await
+    }
+
+    /// like the postgres claim, a single instance needs no row locks so the due rows are read and
+    /// leased in one transaction
+    #[instrument(skip_all, fields(limit = limit))]
+    pub async fn claim_webhook_deliveries_inner<'c, A>(
+        conn: A,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: i16
+    ) -> Result<Vec<WebhookDueDelivery>, sqlx::Error>
+        where A: Acquire<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("claim_webhook_deliveries_inner");
+        let mut tx = conn.begin().await?;
+
+        let due = sqlx
+            ::query_as::<_, WebhookDueDelivery>(
+                &format!(
+                    r"
+                    select {}
+                        from webhook_delivery d
+                            join webhook_subscription s on s.id = d.subscription_id
+                        where d.status = ? and d.next_attempt_at <= ?
+                        order by d.next_attempt_at
+                        limit ?
+                ",
+                    DUE_DELIVERY_COLUMNS
+                )
+            )
+            .bind(WebhookDeliveryStatus::Pending)
+            .bind(to_sqlite_timestamp(now))
+            .bind(limit)
+            .fetch_all(&mut tx).await?;
+        if due.is_empty() {
+            return Ok(due);
+        }
+
+        let ids = serde_json::to_string(&due.iter().map(|delivery| delivery.id).collect::<Vec<i64>>())
+            .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+        sqlx
+            ::query::<_>("update webhook_delivery set next_attempt_at = ? where id in (select value from json_each(?))")
+            .bind(to_sqlite_timestamp(lease_until))
+            .bind(ids)
+            .execute(&mut tx).await?;
+        tx.commit().await?;
+        Ok(due)
+    }
+
+    async fn query_webhook_delivery_inner<'c, E>(
+        conn: E,
+        id: i64
+    ) -> Result<Option<WebhookDeliveryQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        sqlx
+            ::query_as::<_, WebhookDeliveryQueryResult>(&format!("select {} from webhook_delivery where id = ?", DELIVERY_COLUMNS))
+            .bind(id)
+            .fetch_optional(conn).await
+    }
+
+    #[instrument(skip_all, fields(id = id))]
+    pub async fn record_webhook_attempt_inner<'c, A>(
+        conn: A,
+        id: i64,
+        attempt: &WebhookAttempt
+    ) -> Result<Option<WebhookDeliveryQueryResult>, sqlx::Error>
+        where A: Acquire<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("record_webhook_attempt_inner");
+        let mut conn = conn.acquire().await?;
+        let updated = sqlx
+            ::query::<_>(
+                r"
+                update webhook_delivery
+                    set
+                        status = ?,
+                        attempt_count = attempt_count + 1,
+                        last_attempt_at = ?,
+                        last_status_code = ?,
+                        last_error = ?,
+                        next_attempt_at = ?
+                    where id = ?
+            "
+            )
+            .bind(attempt.status)
+            .bind(to_sqlite_timestamp(attempt.attempted_at))
+            .bind(attempt.status_code)
+            .bind(attempt.error.as_deref())
+            .bind(attempt.next_attempt_at.map(to_sqlite_timestamp))
+            .bind(id)
+            .execute(&mut *conn).await?;
+        if updated.rows_affected() == 0 {
+            return Ok(None);
+        }
+        query_webhook_delivery_inner(&mut *conn, id).await
+    }
+
+    #[instrument(skip_all, fields(subscription_id = subscription_id, page_size = page_size))]
+    pub async fn query_webhook_deliveries_inner<'c, E>(
+        conn: E,
+        subscription_id: i64,
+        page_size: i16
+    ) -> Result<Vec<WebhookDeliveryQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("query_webhook_deliveries_inner");
+        sqlx
+            ::query_as::<_, WebhookDeliveryQueryResult>(
+                &format!(
+                    "select {} from webhook_delivery where subscription_id = ? order by id desc limit ?",
+                    DELIVERY_COLUMNS
+                )
+            )
+            .bind(subscription_id)
+            .bind(page_size)
+            .fetch_all(conn).await
+    }
+
+    #[instrument(skip_all, fields(subscription_id = subscription_id, delivery_id = delivery_id))]
+    pub async fn replay_webhook_delivery_inner<'c, A>(
+        conn: A,
+        subscription_id: i64,
+        delivery_id: i64,
+        lease_until: DateTime<Utc>
+    ) -> Result<Option<WebhookDueDelivery>, sqlx::Error>
+        where A: Acquire<'c, Database = Sqlite>
+    {
+        let _timer = start_query_timer("replay_webhook_delivery_inner");
+        let mut tx = conn.begin().await?;
+
+        let inserted = sqlx
+            ::query::<_>(
+                r"
+                insert into webhook_delivery (subscription_id, event_type, payload, status, next_attempt_at)
+                    select d.subscription_id, d.event_type, d.payload, ?, ?
+                        from webhook_delivery d
+                        where d.id = ? and d.subscription_id = ?
+            "
+            )
+            .bind(WebhookDeliveryStatus::Pending)
+            .bind(to_sqlite_timestamp(lease_until))
+            .bind(delivery_id)
+            .bind(subscription_id)
+            .execute(&mut tx).await?;
+        if inserted.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let replayed = sqlx
+            ::query_as::<_, WebhookDueDelivery>(
+                &format!(
+                    r"
+                    select {}
+                        from webhook_delivery d
+                            join webhook_subscription s on s.id = d.subscription_id
+                        where d.id = ?
+                ",
+                    DUE_DELIVERY_COLUMNS
+                )
+            )
+            .bind(inserted.last_insert_rowid())
+            .fetch_one(&mut tx).await?;
+        tx.commit().await?;
+        Ok(Some(replayed))
+    }
+}
+
+/// opens the database file, creating it if needed. WAL lets readers continue while a write is in progress
+pub async fn connect_sqlite(sqlite: &SqliteConfig) -> Result<Pool<Sqlite>, sqlx::Error> {
+    let options = SqliteConnectOptions::new()
+        .filename(&sqlite.path)
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal)
+        .foreign_keys(true);
+
+    SqlitePoolOptions::new()
+        .max_connections(sqlite.max_connections)
+        .connect_with(options).await
+}
+
+/// the repo traits on a single sqlite file, for deployments that do not want to run postgres
+#[derive(Clone)]
+pub struct SqliteRepo {
+    conn: Pool<Sqlite>,
+    max_connections: u32,
+    celebrity_follower_threshold: i64,
+    events: MemoryEventBus,
+}
+
+impl SqliteRepo {
+    /// like the postgres repo, refuses to start unless the schema matches the embedded sqlite migrations
+    pub async fn init(config: &Config) -> Result<Self, sqlx::Error> {
+        let conn = connect_sqlite(&config.sqlite).await?;
+
+        if config.features.run_migrations {
+            SQLITE_MIGRATOR.run(&conn).await?;
+        }
+        let schema_version = ensure_schema_current(&SQLITE_MIGRATOR, &conn).await?;
+        info!("sqlite schema is at version {}", schema_version);
+
+        Ok(Self {
+            conn,
+            max_connections: config.sqlite.max_connections,
+            celebrity_follower_threshold: config.timeline.celebrity_follower_threshold,
+            events: MemoryEventBus::new(),
+        })
+    }
+
+    pub async fn close(&self) {
+        self.conn.close().await;
+    }
+
+    /// opens a transaction that the repo traits implemented for SqliteUnitOfWork run inside of
+    pub async fn begin(&self) -> Result<SqliteUnitOfWork, sqlx::Error> {
+        Ok(SqliteUnitOfWork {
+            tx: Mutex::new(self.conn.begin().await?),
+            celebrity_follower_threshold: self.celebrity_follower_threshold,
+            events: PendingEvents::default(),
+            bus: self.events.clone(),
+        })
+    }
+}
+
+/// the sqlite UnitOfWork, several repo operations in one transaction. Sqlite has no NOTIFY, so the events its
+/// operations publish are held back until commit and dropping it without committing discards them with the writes
+pub struct SqliteUnitOfWork {
+    tx: Mutex<Transaction<'static, Sqlite>>,
+    celebrity_follower_threshold: i64,
+    events: PendingEvents,
+    bus: MemoryEventBus,
+}
+
+impl SqliteUnitOfWork {
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        self.tx.into_inner().commit().await?;
+        self.events.flush(&self.bus).await
+    }
+
+    pub async fn rollback(self) -> Result<(), sqlx::Error> {
+        self.tx.into_inner().rollback().await
+    }
+
+    /// the repo traits take &self, so each operation locks the transaction while its queries run
+    async fn lock(&self) -> MappedMutexGuard<'_, SqliteConnection> {
+        MutexGuard::map(self.tx.lock().await, |tx| &mut **tx)
+    }
+}
+
+impl DbConnGetter for SqliteRepo {
+    type Output = Pool<Sqlite>;
+
+    fn get_conn(&self) -> &Self::Output {
+        &self.conn
+    }
+}
+
+#[async_trait]
+impl InsertMessageFn for SqliteRepo {
+    async fn insert_message(
+        &self,
+        user_id: i64,
+        body: &str,
+        group_type: i32,
+        broadcasting_msg_id: Option<i64>
+    ) -> Result<i64, sqlx::Error> {
+        let (event, notifications) = private_members::insert_message_inner(
+            self.get_conn(),
+            user_id,
+            body,
+            group_type,
+            broadcasting_msg_id,
+            self.celebrity_follower_threshold
+        ).await?;
+        let id = event.id;
+        for notification in notifications {
+            self.events.publish(AppEvent::NotificationCreated(notification)).await?;
+        }
+        self.events.publish(AppEvent::MessageCreated(event)).await?;
+        Ok(id)
+    }
+}
+
+#[async_trait]
+impl InsertResponseMessageFn for SqliteRepo {
+    async fn insert_response_message(
+        &self,
+        user_id: i64,
+        body: &str,
+        group_type: i32,
+        original_msg_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        let (event, notifications) = private_members::insert_response_message_inner(
+            self.get_conn(),
+            user_id,
+            body,
+            group_type,
+            original_msg_id,
+            self.celebrity_follower_threshold
+        ).await?;
+        let id = event.id;
+        for notification in notifications {
+            self.events.publish(AppEvent::NotificationCreated(notification)).await?;
+        }
+        self.events.publish(AppEvent::MessageCreated(event)).await?;
+        Ok(id)
+    }
+}
+
+#[async_trait]
+impl LikeMessageFn for SqliteRepo {
+    async fn like_message(&self, user_id: i64, msg_id: i64) -> Result<bool, sqlx::Error> {
+        match private_members::like_message_inner(self.get_conn(), user_id, msg_id).await? {
+            Some((event, notification)) => {
+                if let Some(notification) = notification {
+                    self.events.publish(AppEvent::NotificationCreated(notification)).await?;
+                }
+                self.events.publish(AppEvent::MessageLiked(event)).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl QueryMessageFn for SqliteRepo {
+    async fn query_message(
+        &self,
+        id: i64
+    ) -> Result<Option<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error> {
+        private_members::query_message_inner(self.get_conn(), id).await
+    }
+}
+
+#[async_trait]
+impl QueryMessagesFn for SqliteRepo {
+    async fn query_messages(
+        &self,
+        user_id: i64,
+        last_updated_at: DateTime<Utc>,
+        page_size: i16
+    ) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error> {
+        private_members::query_messages_inner(
+            self.get_conn(),
+            user_id,
+            last_updated_at,
+            page_size,
+            self.celebrity_follower_threshold
+        ).await
+    }
+}
+
+#[async_trait]
+impl QueryMessagesAfterFn for SqliteRepo {
+    async fn query_messages_after(
+        &self,
+        after_id: i64,
+        filter: &MessageStreamFilter,
+        page_size: i16
+    ) -> Result<Vec<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error> {
+        private_members::query_messages_after_inner(self.get_conn(), after_id, filter, page_size).await
+    }
+
+    async fn query_last_message_id(&self) -> Result<Option<i64>, sqlx::Error> {
+        private_members::query_last_message_id_inner(self.get_conn()).await
+    }
+}
+
+#[async_trait]
+impl QueryMessageCountsFn for SqliteRepo {
+    async fn query_message_counts(
+        &self,
+        ids: &[i64]
+    ) -> Result<Vec<MessageCountsQueryResult>, sqlx::Error> {
+        private_members::query_message_counts_inner(self.get_conn(), ids).await
+    }
+}
+
+impl SubscribeEventsFn for SqliteRepo {
+    fn subscribe_events(&self) -> broadcast::Receiver<AppEvent> {
+        self.events.subscribe()
+    }
+}
+
+#[async_trait]
+impl InsertProfileFn for SqliteRepo {
+    async fn insert_profile(
+        &self,
+        params: ProfileCreate
+    ) -> Result<i64, sqlx::Error> {
+        private_members::insert_profile_inner(self.get_conn(), params).await
+    }
+}
+
+#[async_trait]
+impl UpdateProfileAvatarFn for SqliteRepo {
+    async fn update_profile_avatar(
+        &self,
+        user_id: i64,
+        avatar: Vec<u8>
+    ) -> Result<(), sqlx::Error> {
+        private_members::update_profile_avatar_inner(self.get_conn(), user_id, avatar).await
+    }
+}
+
+#[async_trait]
+impl QueryProfileFn for SqliteRepo {
+    async fn query_profile(
+        &self,
+        id: i64
+    ) -> Result<Option<ProfileQueryResult>, sqlx::Error> {
+        private_members::query_profile_inner(self.get_conn(), id).await
+    }
+}
+
+#[async_trait]
+impl QueryProfileByUserFn for SqliteRepo {
+    async fn query_profile_by_user(
+        &self,
+        user_name: String
+    ) -> Result<Option<ProfileQueryResult>, sqlx::Error> {
+        private_members::query_profile_by_user_inner(self.get_conn(), user_name).await
+    }
+}
+
+#[async_trait]
+impl FollowUserFn for SqliteRepo {
+    async fn follow_user(
+        &self,
+        follower_id: i64,
+        following_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        let (event, notification) = private_members::follow_user_inner(
+            self.get_conn(),
+            follower_id,
+            following_id,
+            self.celebrity_follower_threshold
+        ).await?;
+        if let Some(notification) = notification {
+            self.events.publish(AppEvent::NotificationCreated(notification)).await?;
+        }
+        self.events.publish(AppEvent::FollowCreated(event)).await?;
+        Ok(event.id)
+    }
+}
+
+#[async_trait]
+impl QueryFollowingIdsFn for SqliteRepo {
+    async fn query_following_ids(&self, follower_id: i64) -> Result<Vec<i64>, sqlx::Error> {
+        private_members::query_following_ids_inner(self.get_conn(), follower_id).await
+    }
+}
+
+#[async_trait]
+impl RebuildTimelinesFn for SqliteRepo {
+    async fn rebuild_timelines(&self, owner_id: Option<i64>) -> Result<u64, sqlx::Error> {
+        private_members::rebuild_timelines_inner(self.get_conn(), owner_id, self.celebrity_follower_threshold).await
+    }
+}
+
+#[async_trait]
+impl InsertCircleFn for SqliteRepo {
+    async fn insert_circle(
+        &self,
+        circle_owner_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        private_members::insert_circle_inner(self.get_conn(), circle_owner_id).await
+    }
+}
+
+#[async_trait]
+impl InsertCircleMemberFn for SqliteRepo {
+    async fn insert_circle_member(
+        &self,
+        circle_group_id: i64,
+        new_member_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        private_members::insert_circle_member_inner(self.get_conn(), circle_group_id, new_member_id).await
+    }
+}
+
+#[async_trait]
+impl QueryCircleFn for SqliteRepo {
+    async fn query_circle(
+        &self,
+        id: i64
+    ) -> Result<Option<CircleGroupWithProfileQueryResult>, sqlx::Error> {
+        private_members::query_circle_inner(self.get_conn(), id).await
+    }
+}
+
+#[async_trait]
+impl QueryCircleMemberFn for SqliteRepo {
+    async fn query_circle_member(
+        &self,
+        id: i64
+    ) -> Result<Option<CircleGroupMemberWithProfileQueryResult>, sqlx::Error> {
+        private_members::query_circle_member_inner(self.get_conn(), id).await
+    }
+}
+
+#[async_trait]
+impl QueryNotificationGroupsFn for SqliteRepo {
+    async fn query_notification_groups(
+        &self,
+        recipient_id: i64,
+        before_id: Option<i64>,
+        page_size: i16
+    ) -> Result<Vec<NotificationGroupQueryResult>, sqlx::Error> {
+        private_members::query_notification_groups_inner(self.get_conn(), recipient_id, before_id, page_size).await
+    }
+}
+
+#[async_trait]
+impl QueryUnreadNotificationCountFn for SqliteRepo {
+    async fn query_unread_notification_count(&self, recipient_id: i64) -> Result<i64, sqlx::Error> {
+        private_members::query_unread_notification_count_inner(self.get_conn(), recipient_id).await
+    }
+}
+
+#[async_trait]
+impl MarkNotificationsReadFn for SqliteRepo {
+    async fn mark_notifications_read(&self, recipient_id: i64, up_to_id: Option<i64>) -> Result<u64, sqlx::Error> {
+        private_members::mark_notifications_read_inner(self.get_conn(), recipient_id, up_to_id).await
+    }
+}
+
+#[async_trait]
+impl MarkNotificationGroupReadFn for SqliteRepo {
+    async fn mark_notification_group_read(
+        &self,
+        recipient_id: i64,
+        kind: NotificationKind,
+        target_msg_id: Option<i64>
+    ) -> Result<u64, sqlx::Error> {
+        private_members::mark_notification_group_read_inner(self.get_conn(), recipient_id, kind, target_msg_id).await
+    }
+}
+
+#[async_trait]
+impl InsertWebhookSubscriptionFn for SqliteRepo {
+    async fn insert_webhook_subscription(&self, params: WebhookSubscriptionCreate) -> Result<i64, sqlx::Error> {
+        private_members::insert_webhook_subscription_inner(self.get_conn(), params).await
+    }
+}
+
+#[async_trait]
+impl QueryWebhookSubscriptionsFn for SqliteRepo {
+    async fn query_webhook_subscriptions(&self, user_id: i64) -> Result<Vec<WebhookSubscriptionQueryResult>, sqlx::Error> {
+        private_members::query_webhook_subscriptions_inner(self.get_conn(), user_id).await
+    }
+}
+
+#[async_trait]
+impl ClaimWebhookDeliveriesFn for SqliteRepo {
+    async fn claim_webhook_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: i16
+    ) -> Result<Vec<WebhookDueDelivery>, sqlx::Error> {
+        private_members::claim_webhook_deliveries_inner(self.get_conn(), now, lease_until, limit).await
+    }
+}
+
+#[async_trait]
+impl RecordWebhookAttemptFn for SqliteRepo {
+    async fn record_webhook_attempt(
+        &self,
+        id: i64,
+        attempt: &WebhookAttempt
+    ) -> Result<Option<WebhookDeliveryQueryResult>, sqlx::Error> {
+        private_members::record_webhook_attempt_inner(self.get_conn(), id, attempt).await
+    }
+}
+
+#[async_trait]
+impl QueryWebhookDeliveriesFn for SqliteRepo {
+    async fn query_webhook_deliveries(
+        &self,
+        subscription_id: i64,
+        page_size: i16
+    ) -> Result<Vec<WebhookDeliveryQueryResult>, sqlx::Error> {
+        private_members::query_webhook_deliveries_inner(self.get_conn(), subscription_id, page_size).await
+    }
+}
+
+#[async_trait]
+impl ReplayWebhookDeliveryFn for SqliteRepo {
+    async fn replay_webhook_delivery(
+        &self,
+        subscription_id: i64,
+        delivery_id: i64,
+        lease_until: DateTime<Utc>
+    ) -> Result<Option<WebhookDueDelivery>, sqlx::Error> {
+        private_members::replay_webhook_delivery_inner(self.get_conn(), subscription_id, delivery_id, lease_until).await
+    }
+}
+
+#[async_trait]
+impl InsertMessageFn for SqliteUnitOfWork {
+    async fn insert_message(
+        &self,
+        user_id: i64,
+        body: &str,
+        group_type: i32,
+        broadcasting_msg_id: Option<i64>
+    ) -> Result<i64, sqlx::Error> {
+        let (event, notifications) = private_members::insert_message_inner(
+            &mut *self.lock().await,
+            user_id,
+            body,
+            group_type,
+            broadcasting_msg_id,
+            self.celebrity_follower_threshold
+        ).await?;
+        let id = event.id;
+        for notification in notifications {
+            self.events.publish(AppEvent::NotificationCreated(notification)).await?;
+        }
+        self.events.publish(AppEvent::MessageCreated(event)).await?;
+        Ok(id)
+    }
+}
+
+#[async_trait]
+impl InsertResponseMessageFn for SqliteUnitOfWork {
+    async fn insert_response_message(
+        &self,
+        user_id: i64,
+        body: &str,
+        group_type: i32,
+        original_msg_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        let (event, notifications) = private_members::insert_response_message_inner(
+            &mut *self.lock().await,
+            user_id,
+            body,
+            group_type,
+            original_msg_id,
+            self.celebrity_follower_threshold
+        ).await?;
+        let id = event.id;
+        for notification in notifications {
+            self.events.publish(AppEvent::NotificationCreated(notification)).await?;
+        }
+        self.events.publish(AppEvent::MessageCreated(event)).await?;
+        Ok(id)
+    }
+}
+
+#[async_trait]
+impl LikeMessageFn for SqliteUnitOfWork {
+    async fn like_message(&self, user_id: i64, msg_id: i64) -> Result<bool, sqlx::Error> {
+        match private_members::like_message_inner(&mut *self.lock().await, user_id, msg_id).await? {
+            Some((event, notification)) => {
+                if let Some(notification) = notification {
+                    self.events.publish(AppEvent::NotificationCreated(notification)).await?;
+                }
+                self.events.publish(AppEvent::MessageLiked(event)).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl QueryMessageFn for SqliteUnitOfWork {
+    async fn query_message(
+        &self,
+        id: i64
+    ) -> Result<Option<MessageWithFollowingAndBroadcastQueryResult>, sqlx::Error> {
+        private_members::query_message_inner(&mut *self.lock().await, id).await
+    }
+}
+
+#[async_trait]
+impl InsertProfileFn for SqliteUnitOfWork {
+    async fn insert_profile(
+        &self,
+        params: ProfileCreate
+    ) -> Result<i64, sqlx::Error> {
+        private_members::insert_profile_inner(&mut *self.lock().await, params).await
+    }
+}
+
+#[async_trait]
+impl UpdateProfileAvatarFn for SqliteUnitOfWork {
+    async fn update_profile_avatar(
+        &self,
+        user_id: i64,
+        avatar: Vec<u8>
+    ) -> Result<(), sqlx::Error> {
+        private_members::update_profile_avatar_inner(&mut *self.lock().await, user_id, avatar).await
+    }
+}
+
+#[async_trait]
+impl QueryProfileFn for SqliteUnitOfWork {
+    async fn query_profile(
+        &self,
+        id: i64
+    ) -> Result<Option<ProfileQueryResult>, sqlx::Error> {
+        private_members::query_profile_inner(&mut *self.lock().await, id).await
+    }
+}
+
+#[async_trait]
+impl QueryProfileByUserFn for SqliteUnitOfWork {
+    async fn query_profile_by_user(
+        &self,
+        user_name: String
+    ) -> Result<Option<ProfileQueryResult>, sqlx::Error> {
+        private_members::query_profile_by_user_inner(&mut *self.lock().await, user_name).await
+    }
+}
+
+#[async_trait]
+impl FollowUserFn for SqliteUnitOfWork {
+    async fn follow_user(
+        &self,
+        follower_id: i64,
+        following_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        let (event, notification) = private_members::follow_user_inner(
+            &mut *self.lock().await,
+            follower_id,
+            following_id,
+            self.celebrity_follower_threshold
+        ).await?;
+        if let Some(notification) = notification {
+            self.events.publish(AppEvent::NotificationCreated(notification)).await?;
+        }
+        self.events.publish(AppEvent::FollowCreated(event)).await?;
+        Ok(event.id)
+    }
+}
+
+#[async_trait]
+impl InsertCircleFn for SqliteUnitOfWork {
+    async fn insert_circle(
+        &self,
+        circle_owner_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        private_members::insert_circle_inner(&mut *self.lock().await, circle_owner_id).await
+    }
+}
+
+#[async_trait]
+impl InsertCircleMemberFn for SqliteUnitOfWork {
+    async fn insert_circle_member(
+        &self,
+        circle_group_id: i64,
+        new_member_id: i64
+    ) -> Result<i64, sqlx::Error> {
+        private_members::insert_circle_member_inner(&mut *self.lock().await, circle_group_id, new_member_id).await
+    }
+}
+
+#[async_trait]
+impl QueryCircleFn for SqliteUnitOfWork {
+    async fn query_circle(
+        &self,
+        id: i64
+    ) -> Result<Option<CircleGroupWithProfileQueryResult>, sqlx::Error> {
+        private_members::query_circle_inner(&mut *self.lock().await, id).await
+    }
+}
+
+#[async_trait]
+impl QueryCircleMemberFn for SqliteUnitOfWork {
+    async fn query_circle_member(
+        &self,
+        id: i64
+    ) -> Result<Option<CircleGroupMemberWithProfileQueryResult>, sqlx::Error> {
+        private_members::query_circle_member_inner(&mut *self.lock().await, id).await
+    }
+}
+
+#[async_trait]
+impl MarkNotificationsReadFn for SqliteUnitOfWork {
+    async fn mark_notifications_read(&self, recipient_id: i64, up_to_id: Option<i64>) -> Result<u64, sqlx::Error> {
+        private_members::mark_notifications_read_inner(&mut *self.lock().await, recipient_id, up_to_id).await
+    }
+}
+
+#[async_trait]
+impl MarkNotificationGroupReadFn for SqliteUnitOfWork {
+    async fn mark_notification_group_read(
+        &self,
+        recipient_id: i64,
+        kind: NotificationKind,
+        target_msg_id: Option<i64>
+    ) -> Result<u64, sqlx::Error> {
+        private_members::mark_notification_group_read_inner(&mut *self.lock().await, recipient_id, kind, target_msg_id).await
+    }
+}
+
+#[async_trait]
+impl InsertWebhookSubscriptionFn for SqliteUnitOfWork {
+    async fn insert_webhook_subscription(&self, params: WebhookSubscriptionCreate) -> Result<i64, sqlx::Error> {
+        private_members::insert_webhook_subscription_inner(&mut *self.lock().await, params).await
+    }
+}
+
+#[async_trait]
+impl QuerySchemaVersionFn for SqliteRepo {
+    async fn query_schema_version(&self) -> Result<i64, sqlx::Error> {
+        ensure_schema_current(&SQLITE_MIGRATOR, self.get_conn()).await
+    }
+}
+
+impl PoolStatsFn for SqliteRepo {
+    fn pool_stats(&self) -> PoolStats {
+        let conn = self.get_conn();
+        PoolStats {
+            max: self.max_connections,
+            open: conn.size(),
+            idle: conn.num_idle() as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_sqlite_timestamp_rounds_up_to_stored_precision() {
+        let stored = DateTime::parse_from_rfc3339("2023-05-01T12:00:00.123Z").unwrap().with_timezone(&Utc);
+
+        assert_eq!(to_sqlite_timestamp(stored), "2023-05-01T12:00:00.123Z");
+        assert_eq!(to_sqlite_timestamp(stored + Duration::microseconds(1)), "2023-05-01T12:00:00.124Z");
+    }
+}
@@ -0,0 +1,138 @@
+use crate::common::entities::base::{ DbRepo, DbConnGetter, UnitOfWork };
+use crate::common::metrics::start_query_timer;
+use async_trait::async_trait;
+use mockall::automock;
+use sqlx::{ Acquire, Executor, Postgres };
+use tracing::instrument;
+
+// home timelines are written when a message is inserted (fan-out on write) and read from timeline_entry.
+// Authors with more followers than the celebrity threshold are skipped on write, query_messages reads
+// their messages with a join instead, so one post never writes millions of rows
+mod private_members {
+    use super::*;
+
+    #[instrument(skip_all, fields(owner_id = owner_id))]
+    pub async fn rebuild_timelines_inner<'c, A>(
+        conn: A,
+        owner_id: Option<i64>,
+        celebrity_follower_threshold: i64
+    ) -> Result<u64, sqlx::Error>
+        where A: Acquire<'c, Database = Postgres> + Send
+    {
+        let _timer = start_query_timer("rebuild_timelines_inner");
+        let mut tx = conn.begin().await?;
+
+        sqlx
+            ::query!("delete from timeline_entry where $1::bigint is null or owner_id = $1", owner_id)
+            .execute(&mut tx).await?;
+        let inserted = sqlx
+            ::query!(
+                r"
+                insert into timeline_entry (owner_id, message_id, message_updated_at)
+                    select f.follower_id, m.id, m.updated_at
+                        from follow f
+                            join message m on m.user_id = f.following_id
+                            join profile p on p.id = f.following_id
+                        where
+                            ($1::bigint is null or f.follower_id = $1)
+                            and p.follower_count <= $2
+                on conflict (owner_id, message_id) do nothing
+            ",
+                owner_id,
+                celebrity_follower_threshold
+            )
+            .execute(&mut tx).await?;
+
+        tx.commit().await?;
+        Ok(inserted.rows_affected())
+    }
+}
+
+/// copies a new message into the timeline of each of its author's followers, unless the author is a celebrity.
+/// Runs inside the transaction that inserts the message
+#[instrument(skip_all, fields(message_id = message_id))]
+pub(crate) async fn fan_out_message_inner<'c, E>(
+    conn: E,
+    message_id: i64,
+    celebrity_follower_threshold: i64
+) -> Result<u64, sqlx::Error>
+    where E: Executor<'c, Database = Postgres>
+{
+    let _timer = start_query_timer("fan_out_message_inner");
+    sqlx
+        ::query!(
+            r"
+            insert into timeline_entry (owner_id, message_id, message_updated_at)
+                select f.follower_id, m.id, m.updated_at
+                    from message m
+                        join follow f on f.following_id = m.user_id
+                        join profile p on p.id = m.user_id
+                    where
+                        m.id = $1
+                        and p.follower_count <= $2
+            on conflict (owner_id, message_id) do nothing
+        ",
+            message_id,
+            celebrity_follower_threshold
+        )
+        .execute(conn).await
+        .map(|r| r.rows_affected())
+}
+
+/// copies the messages of a newly followed author into the follower's timeline, unless the author is a celebrity.
+/// Runs inside the transaction that inserts the follow, so the new follower is already counted
+#[instrument(skip_all, fields(follower_id = follower_id, following_id = following_id))]
+pub(crate) async fn backfill_timeline_inner<'c, E>(
+    conn: E,
+    follower_id: i64,
+    following_id: i64,
+    celebrity_follower_threshold: i64
+) -> Result<u64, sqlx::Error>
+    where E: Executor<'c, Database = Postgres>
+{
+    let _timer = start_query_timer("backfill_timeline_inner");
+    sqlx
+        ::query!(
+            r"
+            insert into timeline_entry (owner_id, message_id, message_updated_at)
+                select $1, m.id, m.updated_at
+                    from message m
+                        join profile p on p.id = m.user_id
+                    where
+                        m.user_id = $2
+                        and p.follower_count <= $3
+            on conflict (owner_id, message_id) do nothing
+        ",
+            follower_id,
+            following_id,
+            celebrity_follower_threshold
+        )
+        .execute(conn).await
+        .map(|r| r.rows_affected())
+}
+
+#[automock]
+#[async_trait]
+pub trait RebuildTimelinesFn {
+    /// rewrites the timeline of owner_id, or of every profile, from follows and messages and returns the entries written.
+    /// Used for backfills and after the celebrity threshold changes
+    async fn rebuild_timelines(&self, owner_id: Option<i64>) -> Result<u64, sqlx::Error>;
+}
+
+#[async_trait]
+impl RebuildTimelinesFn for DbRepo {
+    async fn rebuild_timelines(&self, owner_id: Option<i64>) -> Result<u64, sqlx::Error> {
+        private_members::rebuild_timelines_inner(self.get_conn(), owner_id, self.celebrity_follower_threshold()).await
+    }
+}
+
+#[async_trait]
+impl RebuildTimelinesFn for UnitOfWork {
+    async fn rebuild_timelines(&self, owner_id: Option<i64>) -> Result<u64, sqlx::Error> {
+        private_members::rebuild_timelines_inner(
+            &mut *self.lock().await,
+            owner_id,
+            self.celebrity_follower_threshold()
+        ).await
+    }
+}
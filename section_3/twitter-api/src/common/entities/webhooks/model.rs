@@ -0,0 +1,185 @@
+use chrono::{ DateTime, Utc };
+use serde::{ Deserialize, Serialize };
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use crate::common::events::AppEvent;
+
+/// the events a subscription can receive, each a bit of webhook_subscription.event_types
+#[derive(Serialize, Deserialize, ToSchema, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+#[repr(i32)]
+pub enum WebhookEventType {
+    MessageCreated = 1,
+    FollowCreated = 2,
+    MessageLiked = 4,
+}
+
+impl WebhookEventType {
+    pub const ALL: [WebhookEventType; 3] = [
+        WebhookEventType::MessageCreated,
+        WebhookEventType::FollowCreated,
+        WebhookEventType::MessageLiked,
+    ];
+
+    /// None for notifications, they are only streamed to their recipient
+    pub fn of(event: &AppEvent) -> Option<Self> {
+        match event {
+            AppEvent::MessageCreated(_) => Some(WebhookEventType::MessageCreated),
+            AppEvent::FollowCreated(_) => Some(WebhookEventType::FollowCreated),
+            AppEvent::MessageLiked(_) => Some(WebhookEventType::MessageLiked),
+            AppEvent::NotificationCreated(_) => None,
+        }
+    }
+
+    /// the name sent in the X-Webhook-Event header, the same as the payload's type
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebhookEventType::MessageCreated => "messageCreated",
+            WebhookEventType::FollowCreated => "followCreated",
+            WebhookEventType::MessageLiked => "messageLiked",
+        }
+    }
+
+    pub fn to_mask(event_types: &[WebhookEventType]) -> i32 {
+        event_types.iter().fold(0, |mask, event_type| mask | *event_type as i32)
+    }
+
+    pub fn from_mask(mask: i32) -> Vec<WebhookEventType> {
+        Self::ALL.into_iter().filter(|event_type| mask & *event_type as i32 != 0).collect()
+    }
+}
+
+/// identifies an event across instances, so each subscription gets one delivery per event.
+/// None for the events no subscription can receive
+pub fn get_event_key(event: &AppEvent) -> Option<String> {
+    let id = match event {
+        AppEvent::MessageCreated(created) => created.id,
+        AppEvent::FollowCreated(created) => created.id,
+        AppEvent::MessageLiked(liked) => liked.id,
+        AppEvent::NotificationCreated(_) => return None,
+    };
+    WebhookEventType::of(event).map(|event_type| format!("{}:{}", event_type.name(), id))
+}
+
+/// the profiles an event involves, a subscription only receives the events of its own profile. A message involves
+/// its author and the authors of the messages it responds to or rebroadcasts, a follow both profiles and a like
+/// the liker and the author of the liked message
+pub fn get_involved_profile_ids(event: &AppEvent) -> Vec<i64> {
+    let mut profile_ids = match event {
+        AppEvent::MessageCreated(created) => {
+            [Some(created.user_id), created.original_msg.map(|m| m.user_id), created.broadcasting_msg.map(|m| m.user_id)]
+                .into_iter()
+                .flatten()
+                .collect()
+        }
+        AppEvent::FollowCreated(created) => vec![created.follower_id, created.following_id],
+        AppEvent::MessageLiked(liked) => vec![liked.user_id, liked.message.user_id],
+        AppEvent::NotificationCreated(_) => vec![],
+    };
+    profile_ids.sort_unstable();
+    profile_ids.dedup();
+    profile_ids
+}
+
+#[derive(Serialize, Deserialize, ToSchema, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+#[repr(i32)]
+pub enum WebhookDeliveryStatus {
+    /// not sent yet, or failed with attempts left
+    Pending = 1,
+    Succeeded = 2,
+    /// failed on its last attempt, it can still be replayed
+    Failed = 3,
+}
+
+#[derive(Clone, Debug)]
+pub struct WebhookSubscriptionCreate {
+    pub user_id: i64,
+    pub url: String,
+    pub secret: String,
+    /// a bit set of WebhookEventType
+    pub event_types: i32,
+}
+
+#[derive(Deserialize, Serialize, FromRow, Clone, Debug, PartialEq)]
+pub struct WebhookSubscriptionQueryResult {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub user_id: i64,
+    pub url: String,
+    pub secret: String,
+    pub event_types: i32,
+}
+
+#[derive(Deserialize, Serialize, FromRow, Clone, Debug, PartialEq)]
+pub struct WebhookDeliveryQueryResult {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub subscription_id: i64,
+    pub event_type: WebhookEventType,
+    pub payload: String,
+    pub status: WebhookDeliveryStatus,
+    pub attempt_count: i32,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+}
+
+/// a pending delivery the dispatcher claimed, with where to send it and how to sign it
+#[derive(Deserialize, Serialize, FromRow, Clone, Debug, PartialEq)]
+pub struct WebhookDueDelivery {
+    pub id: i64,
+    pub event_type: WebhookEventType,
+    pub payload: String,
+    pub attempt_count: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+/// the outcome of sending a delivery once, next_attempt_at is set while it stays pending
+#[derive(Clone, Debug, PartialEq)]
+pub struct WebhookAttempt {
+    pub status: WebhookDeliveryStatus,
+    pub attempted_at: DateTime<Utc>,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::events::{ FollowCreated, MessageCreated, MessageRef };
+    use super::*;
+
+    #[test]
+    fn test_event_types_round_trip_through_the_mask() {
+        let event_types = [WebhookEventType::MessageCreated, WebhookEventType::MessageLiked];
+
+        let mask = WebhookEventType::to_mask(&event_types);
+
+        assert_eq!(mask, 5);
+        assert_eq!(WebhookEventType::from_mask(mask), event_types);
+        assert!(WebhookEventType::from_mask(0).is_empty());
+    }
+
+    #[test]
+    fn test_event_key_names_the_type_and_id() {
+        let event = AppEvent::FollowCreated(FollowCreated { id: 7, follower_id: 1, following_id: 2 });
+
+        assert_eq!(get_event_key(&event).as_deref(), Some("followCreated:7"));
+    }
+
+    #[test]
+    fn test_involved_profiles_are_the_author_and_the_authors_it_refers_to() {
+        let event = AppEvent::MessageCreated(MessageCreated {
+            id: 9,
+            user_id: 3,
+            msg_group_type: 1,
+            original_msg: Some(MessageRef { id: 4, user_id: 1 }),
+            broadcasting_msg: Some(MessageRef { id: 5, user_id: 3 }),
+        });
+
+        assert_eq!(get_involved_profile_ids(&event), [1, 3]);
+    }
+}
@@ -0,0 +1,578 @@
+use crate::common::entities::base::{ DbRepo, DbConnGetter, UnitOfWork };
+use crate::common::events::AppEvent;
+use crate::common::metrics::start_query_timer;
+use async_trait::async_trait;
+use chrono::{ DateTime, Utc };
+use mockall::automock;
+use sqlx::{ Executor, Postgres };
+use tracing::instrument;
+use super::model::{
+    get_event_key,
+    get_involved_profile_ids,
+    WebhookAttempt,
+    WebhookDeliveryQueryResult,
+    WebhookDeliveryStatus,
+    WebhookDueDelivery,
+    WebhookEventType,
+    WebhookSubscriptionCreate,
+    WebhookSubscriptionQueryResult,
+};
+
+// deliveries are inserted by the write that causes their event, in its transaction, so none is lost
+// when no instance is listening. A pending delivery is claimed by moving its next_attempt_at past the time it takes
+// to send it, skip locked lets instances claim side by side, and a delivery whose sender died is
+// claimed again once that lease has passed
+mod private_members {
+    use super::*;
+
+    #[instrument(skip_all, fields(user_id = params.user_id))]
+    pub async fn insert_webhook_subscription_inner<'c, E>(
+        conn: E,
+        params: WebhookSubscriptionCreate
+    ) -> Result<i64, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("insert_webhook_subscription_inner");
+        sqlx
+            ::query_scalar!(
+                "insert into webhook_subscription (user_id, url, secret, event_types) values ($1, $2, $3, $4) returning id",
+                params.user_id,
+                params.url,
+                params.secret,
+                params.event_types
+            )
+            .fetch_one(conn).await
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn query_webhook_subscriptions_inner<'c, E>(
+        conn: E,
+        user_id: i64
+    ) -> Result<Vec<WebhookSubscriptionQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("query_webhook_subscriptions_inner");
+        sqlx
+            ::query_as!(
+                WebhookSubscriptionQueryResult,
+                r"
+                select id, created_at, user_id, url, secret, event_types
+                    from webhook_subscription
+                    where user_id = $1
+                    order by id
+            ",
+                user_id
+            )
+            .fetch_all(conn).await
+    }
+
+    #[instrument(skip_all, fields(limit = limit))]
+    pub async fn claim_webhook_deliveries_inner<'c, E>(
+        conn: E,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: i16
+    ) -> Result<Vec<WebhookDueDelivery>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("claim_webhook_deliveries_inner");
+        sqlx
+            ::query_as!(
+                WebhookDueDelivery,
+                r#"
+                with due as (
+                    select id
+                        from webhook_delivery
+                        where status = $1 and next_attempt_at <= $2
+                        order by next_attempt_at
+                        limit $4
+                        for update skip locked
+                )
+                update webhook_delivery d set next_attempt_at = $3
+                    from due, webhook_subscription s
+                    where d.id = due.id and s.id = d.subscription_id
+                    returning d.id, d.event_type as "event_type: WebhookEventType", d.payload, d.attempt_count, s.url, s.secret
+            "#,
+                WebhookDeliveryStatus::Pending as i32,
+                now,
+                lease_until,
+                limit as i64
+            )
+            .fetch_all(conn).await
+    }
+
+    #[instrument(skip_all, fields(id = id))]
+    pub async fn record_webhook_attempt_inner<'c, E>(
+        conn: E,
+        id: i64,
+        attempt: &WebhookAttempt
+    ) -> Result<Option<WebhookDeliveryQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("record_webhook_attempt_inner");
+        sqlx
+            ::query_as!(
+                WebhookDeliveryQueryResult,
+                r#"
+                update webhook_delivery
+                    set
+                        status = $2,
+                        attempt_count = attempt_count + 1,
+                        last_attempt_at = $3,
+                        last_status_code = $4,
+                        last_error = $5,
+                        next_attempt_at = $6
+                    where id = $1
+                    returning id, created_at, subscription_id, event_type as "event_type: WebhookEventType", payload,
+                        status as "status: WebhookDeliveryStatus", attempt_count, next_attempt_at, last_attempt_at,
+                        last_status_code, last_error
+            "#,
+                id,
+                attempt.status as i32,
+                attempt.attempted_at,
+                attempt.status_code,
+                attempt.error,
+                attempt.next_attempt_at
+            )
+            .fetch_optional(conn).await
+    }
+
+    #[instrument(skip_all, fields(subscription_id = subscription_id, page_size = page_size))]
+    pub async fn query_webhook_deliveries_inner<'c, E>(
+        conn: E,
+        subscription_id: i64,
+        page_size: i16
+    ) -> Result<Vec<WebhookDeliveryQueryResult>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("query_webhook_deliveries_inner");
+        sqlx
+            ::query_as!(
+                WebhookDeliveryQueryResult,
+                r#"
+                select id, created_at, subscription_id, event_type as "event_type: WebhookEventType", payload,
+                    status as "status: WebhookDeliveryStatus", attempt_count, next_attempt_at, last_attempt_at,
+                    last_status_code, last_error
+                    from webhook_delivery
+                    where subscription_id = $1
+                    order by id desc
+                    limit $2
+            "#,
+                subscription_id,
+                page_size as i64
+            )
+            .fetch_all(conn).await
+    }
+
+    #[instrument(skip_all, fields(subscription_id = subscription_id, delivery_id = delivery_id))]
+    pub async fn replay_webhook_delivery_inner<'c, E>(
+        conn: E,
+        subscription_id: i64,
+        delivery_id: i64,
+        lease_until: DateTime<Utc>
+    ) -> Result<Option<WebhookDueDelivery>, sqlx::Error>
+        where E: Executor<'c, Database = Postgres>
+    {
+        let _timer = start_query_timer("replay_webhook_delivery_inner");
+        sqlx
+            ::query_as!(
+                WebhookDueDelivery,
+                r#"
+                with replayed as (
+                    insert into webhook_delivery (subscription_id, event_type, payload, status, next_attempt_at)
+                        select d.subscription_id, d.event_type, d.payload, $3, $4
+                            from webhook_delivery d
+                            where d.id = $2 and d.subscription_id = $1
+                        returning id, subscription_id, event_type, payload, attempt_count
+                )
+                select r.id as "id!", r.event_type as "event_type!: WebhookEventType", r.payload as "payload!",
+                    r.attempt_count as "attempt_count!", s.url, s.secret
+                    from replayed r
+                        join webhook_subscription s on s.id = r.subscription_id
+            "#,
+                subscription_id,
+                delivery_id,
+                WebhookDeliveryStatus::Pending as i32,
+                lease_until
+            )
+            .fetch_optional(conn).await
+    }
+}
+
+/// a pending delivery of the event for each subscription to its type whose profile the event involves, due now. Runs inside the transaction
+/// of the write that caused the event, like its notification. The unique event_key makes a repeated event a no-op
+#[instrument(skip_all)]
+pub(crate) async fn insert_webhook_deliveries_inner<'c, E>(conn: E, event: &AppEvent) -> Result<u64, sqlx::Error>
+    where E: Executor<'c, Database = Postgres>
+{
+    let (Some(event_type), Some(event_key)) = (WebhookEventType::of(event), get_event_key(event)) else {
+        return Ok(0);
+    };
+    let _timer = start_query_timer("insert_webhook_deliveries_inner");
+    let payload = serde_json::to_string(event).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+    let profile_ids = get_involved_profile_ids(event);
+    sqlx
+        ::query!(
+            r"
+            insert into webhook_delivery (subscription_id, event_type, event_key, payload, status, next_attempt_at)
+                select s.id, $1, $2, $3, $4, now()
+                    from webhook_subscription s
+                    where s.event_types & $1 <> 0 and s.user_id = any($5)
+                on conflict (subscription_id, event_key) do nothing
+        ",
+            event_type as i32,
+            event_key,
+            payload,
+            WebhookDeliveryStatus::Pending as i32,
+            &profile_ids
+        )
+        .execute(conn).await
+        .map(|r| r.rows_affected())
+}
+
+#[automock]
+#[async_trait]
+pub trait InsertWebhookSubscriptionFn {
+    async fn insert_webhook_subscription(&self, params: WebhookSubscriptionCreate) -> Result<i64, sqlx::Error>;
+}
+
+#[async_trait]
+impl InsertWebhookSubscriptionFn for DbRepo {
+    async fn insert_webhook_subscription(&self, params: WebhookSubscriptionCreate) -> Result<i64, sqlx::Error> {
+        private_members::insert_webhook_subscription_inner(self.get_conn(), params).await
+    }
+}
+
+#[async_trait]
+impl InsertWebhookSubscriptionFn for UnitOfWork {
+    async fn insert_webhook_subscription(&self, params: WebhookSubscriptionCreate) -> Result<i64, sqlx::Error> {
+        private_members::insert_webhook_subscription_inner(&mut *self.lock().await, params).await
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait QueryWebhookSubscriptionsFn {
+    /// the profile's subscriptions, oldest first
+    async fn query_webhook_subscriptions(&self, user_id: i64) -> Result<Vec<WebhookSubscriptionQueryResult>, sqlx::Error>;
+}
+
+#[async_trait]
+impl QueryWebhookSubscriptionsFn for DbRepo {
+    async fn query_webhook_subscriptions(&self, user_id: i64) -> Result<Vec<WebhookSubscriptionQueryResult>, sqlx::Error> {
+        private_members::query_webhook_subscriptions_inner(self.get_conn(), user_id).await
+    }
+}
+
+#[async_trait]
+impl QueryWebhookSubscriptionsFn for UnitOfWork {
+    async fn query_webhook_subscriptions(&self, user_id: i64) -> Result<Vec<WebhookSubscriptionQueryResult>, sqlx::Error> {
+        private_members::query_webhook_subscriptions_inner(&mut *self.lock().await, user_id).await
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait ClaimWebhookDeliveriesFn {
+    /// up to limit pending deliveries due by now, the longest waiting first. They are not due again
+    /// until lease_until, by which time their attempt should have been recorded
+    async fn claim_webhook_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: i16
+    ) -> Result<Vec<WebhookDueDelivery>, sqlx::Error>;
+}
+
+#[async_trait]
+impl ClaimWebhookDeliveriesFn for DbRepo {
+    async fn claim_webhook_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: i16
+    ) -> Result<Vec<WebhookDueDelivery>, sqlx::Error> {
+        private_members::claim_webhook_deliveries_inner(self.get_conn(), now, lease_until, limit).await
+    }
+}
+
+#[async_trait]
+impl ClaimWebhookDeliveriesFn for UnitOfWork {
+    async fn claim_webhook_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: i16
+    ) -> Result<Vec<WebhookDueDelivery>, sqlx::Error> {
+        private_members::claim_webhook_deliveries_inner(&mut *self.lock().await, now, lease_until, limit).await
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait RecordWebhookAttemptFn {
+    /// counts the attempt and stores its outcome, None when there is no such delivery
+    async fn record_webhook_attempt(
+        &self,
+        id: i64,
+        attempt: &WebhookAttempt
+    ) -> Result<Option<WebhookDeliveryQueryResult>, sqlx::Error>;
+}
+
+#[async_trait]
+impl RecordWebhookAttemptFn for DbRepo {
+    async fn record_webhook_attempt(
+        &self,
+        id: i64,
+        attempt: &WebhookAttempt
+    ) -> Result<Option<WebhookDeliveryQueryResult>, sqlx::Error> {
+        private_members::record_webhook_attempt_inner(self.get_conn(), id, attempt).await
+    }
+}
+
+#[async_trait]
+impl RecordWebhookAttemptFn for UnitOfWork {
+    async fn record_webhook_attempt(
+        &self,
+        id: i64,
+        attempt: &WebhookAttempt
+    ) -> Result<Option<WebhookDeliveryQueryResult>, sqlx::Error> {
+        private_members::record_webhook_attempt_inner(&mut *self.lock().await, id, attempt).await
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait QueryWebhookDeliveriesFn {
+    /// the subscription's most recent deliveries, newest first
+    async fn query_webhook_deliveries(
+        &self,
+        subscription_id: i64,
+        page_size: i16
+    ) -> Result<Vec<WebhookDeliveryQueryResult>, sqlx::Error>;
+}
+
+#[async_trait]
+impl QueryWebhookDeliveriesFn for DbRepo {
+    async fn query_webhook_deliveries(
+        &self,
+        subscription_id: i64,
+        page_size: i16
+    ) -> Result<Vec<WebhookDeliveryQueryResult>, sqlx::Error> {
+        private_members::query_webhook_deliveries_inner(self.get_conn(), subscription_id, page_size).await
+    }
+}
+
+#[async_trait]
+impl QueryWebhookDeliveriesFn for UnitOfWork {
+    async fn query_webhook_deliveries(
+        &self,
+        subscription_id: i64,
+        page_size: i16
+    ) -> Result<Vec<WebhookDeliveryQueryResult>, sqlx::Error> {
+        private_members::query_webhook_deliveries_inner(&mut *self.lock().await, subscription_id, page_size).await
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait ReplayWebhookDeliveryFn {
+    /// copies one of the subscription's deliveries into a new pending delivery with no attempts, claimed
+    /// until lease_until for the caller to send. None when the subscription has no such delivery
+    async fn replay_webhook_delivery(
+        &self,
+        subscription_id: i64,
+        delivery_id: i64,
+        lease_until: DateTime<Utc>
+    ) -> Result<Option<WebhookDueDelivery>, sqlx::Error>;
+}
+
+#[async_trait]
+impl ReplayWebhookDeliveryFn for DbRepo {
+    async fn replay_webhook_delivery(
+        &self,
+        subscription_id: i64,
+        delivery_id: i64,
+        lease_until: DateTime<Utc>
+    ) -> Result<Option<WebhookDueDelivery>, sqlx::Error> {
+        private_members::replay_webhook_delivery_inner(self.get_conn(), subscription_id, delivery_id, lease_until).await
+    }
+}
+
+#[async_trait]
+impl ReplayWebhookDeliveryFn for UnitOfWork {
+    async fn replay_webhook_delivery(
+        &self,
+        subscription_id: i64,
+        delivery_id: i64,
+        lease_until: DateTime<Utc>
+    ) -> Result<Option<WebhookDueDelivery>, sqlx::Error> {
+        private_members::replay_webhook_delivery_inner(
+            &mut *self.lock().await,
+            subscription_id,
+            delivery_id,
+            lease_until
+        ).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use lazy_static::lazy_static;
+    use crate::{
+        common::entities::{
+            app_repo::AppRepo,
+            profiles::{ model::ProfileCreate, repo::{ FollowUserFn, InsertProfileFn } },
+            webhooks::model::{ WebhookAttempt, WebhookDeliveryStatus, WebhookEventType },
+        },
+        common::events::FollowCreated,
+        common_tests::actix_fixture::get_parity_repos,
+    };
+    use super::*;
+
+    lazy_static! {
+        static ref RT: tokio::runtime::Runtime = tokio::runtime::Builder
+            ::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+    }
+
+    async fn insert_profile(db_repo: &AppRepo) -> i64 {
+        db_repo
+            .insert_profile(ProfileCreate {
+                user_name: "webhook_owner".to_string(),
+                full_name: "Test webhooks".to_string(),
+                description: "Test webhooks description".to_string(),
+                region: Some("usa".to_string()),
+                main_url: Some("http://whatever.com".to_string()),
+                avatar: Some(vec![]),
+            }).await
+            .unwrap()
+    }
+
+    /// the delivery of one event among the subscription's others
+    fn find_delivery<'a>(deliveries: &'a [WebhookDeliveryQueryResult], event: &AppEvent) -> Option<&'a WebhookDeliveryQueryResult> {
+        deliveries.iter().find(|d| serde_json::from_str::<AppEvent>(&d.payload).ok().as_ref() == Some(event))
+    }
+
+    /// claims until the delivery comes up, the postgres database keeps other runs' pending deliveries
+    async fn claim_delivery(
+        db_repo: &AppRepo,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        delivery_id: i64
+    ) -> Option<WebhookDueDelivery> {
+        loop {
+            let claimed = db_repo.claim_webhook_deliveries(now, lease_until, 100).await.unwrap();
+            if claimed.is_empty() {
+                return None;
+            }
+            if let Some(due) = claimed.into_iter().find(|d| d.id == delivery_id) {
+                return Some(due);
+            }
+        }
+    }
+
+    mod test_mod_webhook_deliveries {
+        use super::*;
+
+        async fn test_deliveries_are_claimed_once_retried_and_replayed_body(db_repo: AppRepo) {
+            let user_id = insert_profile(&db_repo).await;
+            let follows_id = db_repo
+                .insert_webhook_subscription(WebhookSubscriptionCreate {
+                    user_id,
+                    url: "http://localhost/follows".to_string(),
+                    secret: "follows secret value".to_string(),
+                    event_types: WebhookEventType::to_mask(&[WebhookEventType::FollowCreated]),
+                }).await
+                .unwrap();
+            let likes_id = db_repo
+                .insert_webhook_subscription(WebhookSubscriptionCreate {
+                    user_id,
+                    url: "http://localhost/likes".to_string(),
+                    secret: "likes secret value".to_string(),
+                    event_types: WebhookEventType::to_mask(&[WebhookEventType::MessageLiked]),
+                }).await
+                .unwrap();
+            let subscriptions = db_repo.query_webhook_subscriptions(user_id).await.unwrap();
+            assert_eq!(subscriptions.iter().map(|s| s.id).collect::<Vec<_>>(), [follows_id, likes_id]);
+
+            // the follow commits its delivery with it, no dispatcher has to hear the event
+            let now = Utc::now();
+            let follower_id = insert_profile(&db_repo).await;
+            let follow_id = db_repo.follow_user(follower_id, user_id).await.unwrap();
+            let event = AppEvent::FollowCreated(FollowCreated { id: follow_id, follower_id, following_id: user_id });
+            let deliveries = db_repo.query_webhook_deliveries(follows_id, 100).await.unwrap();
+            let delivery = find_delivery(&deliveries, &event).unwrap();
+            assert_eq!(delivery.event_type, WebhookEventType::FollowCreated);
+            assert_eq!(delivery.status, WebhookDeliveryStatus::Pending);
+            assert!(find_delivery(&db_repo.query_webhook_deliveries(likes_id, 100).await.unwrap(), &event).is_none());
+            let delivery_id = delivery.id;
+
+            // a follow between other profiles is not the subscriber's business
+            let other_id = insert_profile(&db_repo).await;
+            let other_follow_id = db_repo.follow_user(other_id, follower_id).await.unwrap();
+            let other_event = AppEvent::FollowCreated(FollowCreated { id: other_follow_id, follower_id: other_id, following_id: follower_id });
+            assert!(find_delivery(&db_repo.query_webhook_deliveries(follows_id, 100).await.unwrap(), &other_event).is_none());
+
+            // postgres keeps milliseconds, next_attempt_at can round up past now
+            let claim_at = now + Duration::seconds(1);
+            let lease_until = now + Duration::seconds(60);
+            let due = claim_delivery(&db_repo, claim_at, lease_until, delivery_id).await.unwrap();
+            assert_eq!(due.url, "http://localhost/follows");
+            assert_eq!(due.secret, "follows secret value");
+            assert_eq!(due.attempt_count, 0);
+            assert!(claim_delivery(&db_repo, claim_at, lease_until, delivery_id).await.is_none());
+
+            let retry_at = now + Duration::seconds(10);
+            let failed = db_repo
+                .record_webhook_attempt(delivery_id, &WebhookAttempt {
+                    status: WebhookDeliveryStatus::Pending,
+                    attempted_at: now,
+                    status_code: Some(500),
+                    error: Some("subscriber responded 500".to_string()),
+                    next_attempt_at: Some(retry_at),
+                }).await
+                .unwrap()
+                .unwrap();
+            assert_eq!(failed.attempt_count, 1);
+            assert_eq!(failed.last_status_code, Some(500));
+            let retried = claim_delivery(&db_repo, retry_at + Duration::seconds(1), retry_at + Duration::seconds(60), delivery_id).await;
+            assert_eq!(retried.unwrap().attempt_count, 1);
+
+            let succeeded = db_repo
+                .record_webhook_attempt(delivery_id, &WebhookAttempt {
+                    status: WebhookDeliveryStatus::Succeeded,
+                    attempted_at: retry_at,
+                    status_code: Some(200),
+                    error: None,
+                    next_attempt_at: None,
+                }).await
+                .unwrap()
+                .unwrap();
+            assert_eq!(succeeded.status, WebhookDeliveryStatus::Succeeded);
+            assert_eq!(succeeded.attempt_count, 2);
+            assert_eq!(succeeded.last_error, None);
+
+            assert!(db_repo.replay_webhook_delivery(likes_id, delivery_id, lease_until).await.unwrap().is_none());
+            let replayed = db_repo.replay_webhook_delivery(follows_id, delivery_id, lease_until).await.unwrap().unwrap();
+            assert_ne!(replayed.id, delivery_id);
+            assert_eq!(replayed.attempt_count, 0);
+            let deliveries = db_repo.query_webhook_deliveries(follows_id, 100).await.unwrap();
+            let ids = deliveries.iter().map(|d| d.id).filter(|id| [replayed.id, delivery_id].contains(id)).collect::<Vec<_>>();
+            assert_eq!(ids, [replayed.id, delivery_id]);
+            assert_eq!(deliveries.iter().find(|d| d.id == replayed.id).unwrap().status, WebhookDeliveryStatus::Pending);
+        }
+
+        #[test]
+        fn test_deliveries_are_claimed_once_retried_and_replayed() {
+            RT.block_on(async {
+                for db_repo in get_parity_repos().await {
+                    test_deliveries_are_claimed_once_retried_and_replayed_body(db_repo).await;
+                }
+            })
+        }
+    }
+}
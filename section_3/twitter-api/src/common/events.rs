@@ -0,0 +1,160 @@
+use async_trait::async_trait;
+use serde::{ Deserialize, Serialize };
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use crate::common::entities::notifications::model::NotificationKind;
+
+/// events a subscriber has not received yet before it starts missing them, see RecvError::Lagged
+pub const EVENT_BUS_CAPACITY: usize = 1024;
+
+/// a message another message replies to or broadcasts, with its author who is notified
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct MessageRef {
+    pub id: i64,
+    pub user_id: i64,
+}
+
+/// a message that was just inserted, published once its transaction has committed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MessageCreated {
+    pub id: i64,
+    pub user_id: i64,
+    pub msg_group_type: i32,
+    /// the message this one responds to
+    pub original_msg: Option<MessageRef>,
+    /// the message this one rebroadcasts or quotes
+    pub broadcasting_msg: Option<MessageRef>,
+}
+
+/// a profile that started following another, published once its transaction has committed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct FollowCreated {
+    pub id: i64,
+    pub follower_id: i64,
+    pub following_id: i64,
+}
+
+/// a profile that liked a message for the first time, published once its transaction has committed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct MessageLiked {
+    pub id: i64,
+    pub user_id: i64,
+    pub message: MessageRef,
+}
+
+/// a notification that was just written, published once its transaction has committed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct NotificationCreated {
+    pub id: i64,
+    pub recipient_id: i64,
+    pub actor_id: i64,
+    pub kind: NotificationKind,
+    pub target_msg_id: Option<i64>,
+    pub source_msg_id: Option<i64>,
+}
+
+/// everything the repo layer publishes, serialized as json where it crosses instances
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AppEvent {
+    MessageCreated(MessageCreated),
+    FollowCreated(FollowCreated),
+    MessageLiked(MessageLiked),
+    NotificationCreated(NotificationCreated),
+}
+
+/// carries repo events to every subscriber, either within one process or across every instance sharing a database
+#[async_trait]
+pub trait EventBus {
+    async fn publish(&self, event: AppEvent) -> Result<(), sqlx::Error>;
+
+    /// receives every event published after this call
+    fn subscribe(&self) -> broadcast::Receiver<AppEvent>;
+}
+
+/// in-process pub/sub, for the repos that only ever run as one instance and for tests.
+/// Clones share the channel
+#[derive(Clone)]
+pub struct MemoryEventBus {
+    sender: broadcast::Sender<AppEvent>,
+}
+
+impl MemoryEventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        Self { sender }
+    }
+}
+
+impl Default for MemoryEventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl EventBus for MemoryEventBus {
+    /// sends to every current subscriber, having none is not an error
+    async fn publish(&self, event: AppEvent) -> Result<(), sqlx::Error> {
+        _ = self.sender.send(event);
+        Ok(())
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<AppEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// events published inside a unit of work, held back until it commits so subscribers never see a write that
+/// is rolled back, like a postgres NOTIFY
+#[derive(Default)]
+pub struct PendingEvents {
+    events: Mutex<Vec<AppEvent>>,
+}
+
+impl PendingEvents {
+    pub async fn publish(&self, event: AppEvent) -> Result<(), sqlx::Error> {
+        self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(event);
+        Ok(())
+    }
+
+    /// publishes every held back event to the bus in order
+    pub async fn flush(self, events: &(impl EventBus + Sync)) -> Result<(), sqlx::Error> {
+        for event in self.events.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+            events.publish(event).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_event(id: i64) -> AppEvent {
+        AppEvent::MessageCreated(MessageCreated { id, user_id: 1, msg_group_type: 1, original_msg: None, broadcasting_msg: None })
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_receive_events_published_after_subscribing() {
+        let events = MemoryEventBus::new();
+        events.publish(get_event(1)).await.unwrap();
+        let mut first = events.subscribe();
+        let mut second = events.clone().subscribe();
+
+        events.publish(get_event(2)).await.unwrap();
+
+        assert_eq!(first.recv().await.unwrap(), get_event(2));
+        assert_eq!(second.recv().await.unwrap(), get_event(2));
+        assert!(first.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_event_json_is_tagged_by_type() {
+        let event = AppEvent::FollowCreated(FollowCreated { id: 3, follower_id: 1, following_id: 2 });
+        let json = serde_json::to_string(&event).unwrap();
+
+        assert_eq!(json, r#"{"type":"followCreated","id":3,"follower_id":1,"following_id":2}"#);
+        assert_eq!(serde_json::from_str::<AppEvent>(&json).unwrap(), event);
+    }
+}
@@ -0,0 +1,157 @@
+use actix_web::{ dev::{ Service, ServiceRequest, ServiceResponse, Transform }, Error };
+use futures::future::{ ready, LocalBoxFuture, Ready };
+use lazy_static::lazy_static;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramTimer, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::{ rc::Rc, time::Instant };
+
+/// label used for requests that matched no route, so random paths cannot explode the series count
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+lazy_static! {
+    pub static ref REGISTRY: Registry = Registry::new();
+
+    static ref HTTP_REQUESTS_TOTAL: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("http_requests_total", "HTTP requests by route template, method and status"),
+        &["method", "route", "status"]
+    ));
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route template and method"),
+        &["method", "route"]
+    ));
+    static ref DB_QUERY_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new("db_query_duration_seconds", "Time spent in each repository function"),
+        &["function"]
+    ));
+    static ref DB_POOL_CONNECTIONS: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("db_pool_connections", "Postgres pool connections by state"),
+        &["state"]
+    ));
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<T>) -> T {
+    let metric = metric.expect("metric definition is invalid");
+    REGISTRY.register(Box::new(metric.clone())).expect("metric registered twice");
+    metric
+}
+
+/// starts timing a repository function, the duration is recorded when the timer drops
+pub fn start_query_timer(function: &str) -> HistogramTimer {
+    DB_QUERY_DURATION.with_label_values(&[function]).start_timer()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolStats {
+    pub max: u32,
+    pub open: u32,
+    pub idle: u32,
+}
+
+pub fn record_pool_stats(stats: PoolStats) {
+    DB_POOL_CONNECTIONS.with_label_values(&["max"]).set(stats.max.into());
+    DB_POOL_CONNECTIONS.with_label_values(&["open"]).set(stats.open.into());
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(stats.idle.into());
+    DB_POOL_CONNECTIONS.with_label_values(&["in_use"]).set(stats.open.saturating_sub(stats.idle).into());
+}
+
+/// every registered metric in the prometheus text format
+pub fn encode_metrics() -> Result<String, prometheus::Error> {
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer)?;
+    String::from_utf8(buffer).map_err(|e| prometheus::Error::Msg(e.to_string()))
+}
+
+/// middleware counting and timing requests per route template, e.g. /v1/msg/{id} rather than /v1/msg/12
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware { service: Rc::new(service) }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let method = req.method().to_string();
+        let started = Instant::now();
+
+        Box::pin(async move {
+            let result = service.call(req).await;
+
+            let (route, status) = match &result {
+                Ok(res) => (
+                    res.request().match_pattern().unwrap_or_else(|| UNMATCHED_ROUTE.to_string()),
+                    res.status().as_u16().to_string(),
+                ),
+                // errors that never became a response have no matched route, take the status they map to
+                Err(e) => (UNMATCHED_ROUTE.to_string(), e.as_response_error().status_code().as_u16().to_string()),
+            };
+            HTTP_REQUESTS_TOTAL.with_label_values(&[&method, &route, &status]).inc();
+            HTTP_REQUEST_DURATION
+                .with_label_values(&[&method, &route])
+                .observe(started.elapsed().as_secs_f64());
+
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{ test::{ call_service, init_service, TestRequest }, web, App, HttpResponse };
+
+    #[tokio::test]
+    async fn test_request_metrics_use_route_template() {
+        let app = init_service(
+            App::new()
+                .wrap(RequestMetrics)
+                .route("/metrics_test/{id}", web::get().to(HttpResponse::Ok))
+        ).await;
+
+        call_service(&app, TestRequest::get().uri("/metrics_test/12").to_request()).await;
+        call_service(&app, TestRequest::get().uri("/metrics_test/13").to_request()).await;
+        call_service(&app, TestRequest::get().uri("/metrics_test_none").to_request()).await;
+
+        let metrics = encode_metrics().unwrap();
+        assert!(metrics.contains(r#"http_requests_total{method="GET",route="/metrics_test/{id}",status="200"} 2"#));
+        assert!(!metrics.contains("/metrics_test/12"));
+        assert!(metrics.contains(r#"route="unmatched",status="404""#));
+    }
+
+    #[test]
+    fn test_query_timer_and_pool_stats_are_exported() {
+        drop(start_query_timer("test_query_inner"));
+        record_pool_stats(PoolStats { max: 10, open: 4, idle: 1 });
+
+        let metrics = encode_metrics().unwrap();
+        assert!(metrics.contains(r#"db_query_duration_seconds_count{function="test_query_inner"} 1"#));
+        assert!(metrics.contains(r#"db_pool_connections{state="in_use"} 3"#));
+    }
+}
@@ -0,0 +1,263 @@
+use derive_more::{ Display, Error };
+use sqlx::{ Database, Pool, migrate::{ Migrate, MigrateError, Migrator } };
+use std::collections::HashMap;
+
+/// migrations embedded at build time, this is the schema version the binary expects
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// the same schema for the sqlite backend, versions match MIGRATOR one for one
+pub static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations_sqlite");
+
+/// how the database schema compares to the migrations embedded in this binary
+#[derive(Debug, Display, Error, PartialEq)]
+pub enum SchemaError {
+    #[display(fmt = "schema is at version {} but this build expects {}, run `migrate up`", applied, expected)]
+    Pending { applied: i64, expected: i64 },
+    #[display(fmt = "schema has migration {} which this build does not know, deploy a newer build or roll back", version)]
+    Unknown { version: i64 },
+    #[display(fmt = "migration {} was changed after it was applied", version)]
+    Modified { version: i64 },
+    #[display(fmt = "migration {} failed part way, fix the schema manually before retrying", version)]
+    Dirty { version: i64 },
+}
+
+impl From<SchemaError> for sqlx::Error {
+    fn from(e: SchemaError) -> Self {
+        sqlx::Error::Configuration(Box::new(e))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+/// latest migration version embedded in this binary
+pub fn get_expected_version() -> i64 {
+    get_up_migrations(&MIGRATOR).map(|(version, _)| version).max().unwrap_or(0)
+}
+
+/// every embedded migration and whether the database has applied it
+pub async fn get_migration_statuses<DB>(
+    migrator: &Migrator,
+    conn: &Pool<DB>
+) -> Result<Vec<MigrationStatus>, sqlx::Error> where DB: Database, DB::Connection: Migrate {
+    let (applied, _) = get_applied_migrations(conn).await?;
+
+    Ok(
+        migrator.iter()
+            .filter(|migration| !migration.migration_type.is_down_migration())
+            .map(|migration| MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                applied: applied.contains_key(&migration.version),
+            })
+            .collect()
+    )
+}
+
+/// fails unless every embedded migration is applied unchanged and nothing newer exists,
+/// returns the current schema version
+pub async fn ensure_schema_current<DB>(
+    migrator: &Migrator,
+    conn: &Pool<DB>
+) -> Result<i64, sqlx::Error> where DB: Database, DB::Connection: Migrate {
+    let (applied, dirty_version) = get_applied_migrations(conn).await?;
+    let expected = get_up_migrations(migrator).collect::<Vec<(i64, &[u8])>>();
+
+    Ok(check_schema(&expected, &applied, dirty_version)?)
+}
+
+fn check_schema(
+    expected: &[(i64, &[u8])],
+    applied: &HashMap<i64, Vec<u8>>,
+    dirty_version: Option<i64>
+) -> Result<i64, SchemaError> {
+    if let Some(version) = dirty_version {
+        return Err(SchemaError::Dirty { version });
+    }
+
+    if let Some(version) = applied.keys().filter(|v| !expected.iter().any(|(e, _)| e == *v)).min() {
+        return Err(SchemaError::Unknown { version: *version });
+    }
+
+    for (version, checksum) in expected {
+        match applied.get(version) {
+            Some(applied_checksum) if applied_checksum.as_slice() != *checksum => {
+                return Err(SchemaError::Modified { version: *version });
+            }
+            Some(_) => (),
+            None => {
+                return Err(SchemaError::Pending {
+                    applied: applied.keys().max().copied().unwrap_or(0),
+                    expected: expected.iter().map(|(v, _)| *v).max().unwrap_or(0),
+                });
+            }
+        }
+    }
+
+    Ok(applied.keys().max().copied().unwrap_or(0))
+}
+
+fn get_up_migrations(migrator: &Migrator) -> impl Iterator<Item = (i64, &[u8])> {
+    migrator.iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| (migration.version, migration.checksum.as_ref()))
+}
+
+/// applied versions with their checksums, plus the version of a failed migration if there is one
+async fn get_applied_migrations<DB>(
+    conn: &Pool<DB>
+) -> Result<(HashMap<i64, Vec<u8>>, Option<i64>), sqlx::Error> where DB: Database, DB::Connection: Migrate {
+    let mut migrate_conn = conn.acquire().await?;
+    migrate_conn.ensure_migrations_table().await.map_err(into_sqlx_error)?;
+    let dirty_version = migrate_conn.dirty_version().await.map_err(into_sqlx_error)?;
+    let applied = migrate_conn.list_applied_migrations().await.map_err(into_sqlx_error)?;
+
+    Ok((
+        applied
+            .into_iter()
+            .map(|migration| (migration.version, migration.checksum.into_owned()))
+            .collect(),
+        dirty_version,
+    ))
+}
+
+fn into_sqlx_error(e: MigrateError) -> sqlx::Error {
+    sqlx::Error::Migrate(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECKSUM_A: &[u8] = &[1, 2, 3];
+    const CHECKSUM_B: &[u8] = &[4, 5, 6];
+
+    fn get_applied(migrations: &[(i64, &[u8])]) -> HashMap<i64, Vec<u8>> {
+        migrations.iter().map(|(version, checksum)| (*version, checksum.to_vec())).collect()
+    }
+
+    #[test]
+    fn test_check_schema_current() {
+        let expected = [(1, CHECKSUM_A), (2, CHECKSUM_B)];
+
+        assert_eq!(check_schema(&expected, &get_applied(&expected), None), Ok(2));
+    }
+
+    #[test]
+    fn test_check_schema_pending() {
+        let expected = [(1, CHECKSUM_A), (2, CHECKSUM_B)];
+
+        assert_eq!(
+            check_schema(&expected, &get_applied(&[(1, CHECKSUM_A)]), None),
+            Err(SchemaError::Pending { applied: 1, expected: 2 })
+        );
+    }
+
+    #[test]
+    fn test_check_schema_unknown_newer_migration() {
+        let expected = [(1, CHECKSUM_A)];
+
+        assert_eq!(
+            check_schema(&expected, &get_applied(&[(1, CHECKSUM_A), (2, CHECKSUM_B)]), None),
+            Err(SchemaError::Unknown { version: 2 })
+        );
+    }
+
+    #[test]
+    fn test_check_schema_modified_and_dirty() {
+        let expected = [(1, CHECKSUM_A)];
+
+        assert_eq!(
+            check_schema(&expected, &get_applied(&[(1, CHECKSUM_B)]), None),
+            Err(SchemaError::Modified { version: 1 })
+        );
+        assert_eq!(
+            check_schema(&expected, &get_applied(&expected), Some(1)),
+            Err(SchemaError::Dirty { version: 1 })
+        );
+    }
+
+    #[test]
+    fn test_embedded_migrations_are_reversible() {
+        for migrator in [&MIGRATOR, &SQLITE_MIGRATOR] {
+            let up_versions = get_up_migrations(migrator).map(|(version, _)| version).collect::<Vec<i64>>();
+            let down_versions = migrator.iter()
+                .filter(|migration| migration.migration_type.is_down_migration())
+                .map(|migration| migration.version)
+                .collect::<Vec<i64>>();
+
+            assert_eq!(up_versions, down_versions);
+            assert_eq!(get_expected_version(), *up_versions.last().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_sqlite_migrations_mirror_postgres_migrations() {
+        let describe = |migrator: &Migrator| migrator.iter()
+            .map(|migration| (migration.version, migration.description.to_string()))
+            .collect::<Vec<(i64, String)>>();
+
+        assert_eq!(describe(&MIGRATOR), describe(&SQLITE_MIGRATOR));
+    }
+
+    /// the column limits of 0003 are the api limits times the code points a character may take
+    #[test]
+    fn test_text_length_migration_follows_the_validation_rule() {
+        use crate::routes::{
+            messages::model::MESSAGE_BODY_MAX_LENGTH,
+            profiles::model::{ DESCRIPTION_MAX_LENGTH, FULL_NAME_MAX_LENGTH, MAIN_URL_MAX_LENGTH, REGION_MAX_LENGTH, USER_NAME_MAX_LENGTH },
+            validation::validator::MAX_CODE_POINTS_PER_CHAR,
+        };
+
+        let get_sql = |migrator: &Migrator| migrator.iter()
+            .find(|migration| migration.version == 3 && !migration.migration_type.is_down_migration())
+            .unwrap()
+            .sql
+            .to_string();
+        let (postgres, sqlite) = (get_sql(&MIGRATOR), get_sql(&SQLITE_MIGRATOR));
+        for (column, max_length) in [
+            ("user_name", USER_NAME_MAX_LENGTH),
+            ("full_name", FULL_NAME_MAX_LENGTH),
+            ("description", DESCRIPTION_MAX_LENGTH),
+            ("region", REGION_MAX_LENGTH),
+            ("main_url", MAIN_URL_MAX_LENGTH),
+            ("body", MESSAGE_BODY_MAX_LENGTH),
+        ] {
+            let limit = max_length * MAX_CODE_POINTS_PER_CHAR;
+            assert!(postgres.contains(&format!("\"{}\" type varchar({})", column, limit)), "{}", column);
+            assert!(sqlite.contains(&format!("length(new.\"{}\") > {}", column, limit)), "{}", column);
+        }
+    }
+
+    /// 0004 counts existing followers and fills the timelines of existing follows, so home
+    /// timelines are not empty after an upgrade
+    #[tokio::test]
+    async fn test_timeline_migration_backfills_existing_follows() {
+        let conn = sqlx::sqlite::SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        SQLITE_MIGRATOR.run(&conn).await.unwrap();
+        SQLITE_MIGRATOR.undo(&conn, 3).await.unwrap();
+
+        for statement in [
+            "insert into profile (user_name, full_name, description) values ('author', 'author', '')",
+            "insert into profile (user_name, full_name, description) values ('follower', 'follower', '')",
+            "insert into follow (follower_id, following_id) values (2, 1)",
+            "insert into message (user_id, body) values (1, 'posted before the upgrade')",
+        ] {
+            sqlx::query(statement).execute(&conn).await.unwrap();
+        }
+        SQLITE_MIGRATOR.run(&conn).await.unwrap();
+
+        let follower_count = sqlx::query_scalar::<_, i64>("select follower_count from profile where id = 1")
+            .fetch_one(&conn).await
+            .unwrap();
+        let timeline = sqlx::query_scalar::<_, i64>("select message_id from timeline_entry where owner_id = 2")
+            .fetch_all(&conn).await
+            .unwrap();
+        assert_eq!(follower_count, 1);
+        assert_eq!(timeline, vec![1]);
+    }
+}
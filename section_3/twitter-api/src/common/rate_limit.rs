@@ -0,0 +1,356 @@
+use actix_web::{
+    body::EitherBody,
+    dev::{ ResourceDef, Service, ServiceRequest, ServiceResponse, Transform },
+    http::{ header::{ HeaderName, HeaderValue }, Method },
+    Error, ResponseError,
+};
+use futures::future::{ ready, LocalBoxFuture, Ready };
+use std::{
+    collections::HashMap,
+    rc::Rc,
+    sync::{ Arc, Mutex },
+    time::{ Duration, Instant },
+};
+use crate::{
+    common::{ auth::Authenticator, config::RateLimitConfig, shutdown::ShutdownSignal },
+    routes::errors::error_utils::UserError,
+};
+
+pub const RATE_LIMIT_LIMIT: &str = "ratelimit-limit";
+pub const RATE_LIMIT_REMAINING: &str = "ratelimit-remaining";
+pub const RATE_LIMIT_RESET: &str = "ratelimit-reset";
+
+/// how often buckets that have refilled completely are dropped
+pub const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// paths that are never limited, probes and scrapers poll them from a single address
+const EXEMPT_PATHS: [&str; 3] = ["/health/", "/metrics", "/version"];
+
+/// every route with its own budget, anything else falls back to the configured default
+fn get_route_budgets(config: &RateLimitConfig) -> Vec<RouteBudget> {
+    vec![
+        RouteBudget::new("create_message", Method::POST, "/v1/msg", config.create_message),
+        RouteBudget::new("create_profile", Method::POST, "/v1/profile", config.create_profile),
+        RouteBudget::new("get_messages", Method::POST, "/v1/msgs", config.get_messages),
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Budget {
+    /// requests that can be made at once after a quiet period
+    pub burst: u32,
+    /// sustained rate the bucket refills at
+    pub per_minute: u32,
+}
+
+impl Budget {
+    fn refill_per_sec(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+}
+
+struct RouteBudget {
+    name: &'static str,
+    method: Method,
+    route: ResourceDef,
+    budget: Budget,
+}
+
+impl RouteBudget {
+    fn new(name: &'static str, method: Method, route: &str, budget: Budget) -> Self {
+        Self { name, method, route: ResourceDef::new(route), budget }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// outcome of taking a token, carries what the RateLimit-* headers report
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// seconds until the bucket is full again, or until the next token when rejected
+    pub reset_secs: u64,
+}
+
+/// token buckets per (route budget, client), shared by every worker
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    authenticator: Authenticator,
+    routes: Arc<Vec<RouteBudget>>,
+    buckets: Arc<Mutex<HashMap<(&'static str, String), Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig, authenticator: Authenticator) -> Self {
+        Self {
+            config: config.clone(),
+            authenticator,
+            routes: Arc::new(get_route_budgets(config)),
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// budget name and budget for a request, None when the path is exempt
+    fn get_budget(&self, method: &Method, path: &str) -> Option<(&'static str, Budget)> {
+        if EXEMPT_PATHS.iter().any(|exempt| path.starts_with(exempt)) {
+            return None;
+        }
+
+        match self.routes.iter().find(|route| route.method == method && route.route.is_match(path)) {
+            Some(route) => Some((route.name, route.budget)),
+            None => Some(("default", self.config.default)),
+        }
+    }
+
+    pub fn check(&self, name: &'static str, client: &str, budget: Budget, now: Instant) -> RateLimitDecision {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets
+            .entry((name, client.to_string()))
+            .or_insert(Bucket { tokens: budget.burst as f64, updated_at: now });
+
+        let elapsed = now.saturating_duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * budget.refill_per_sec()).min(budget.burst as f64);
+        bucket.updated_at = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        let reset_secs = if allowed {
+            ((budget.burst as f64 - bucket.tokens) / budget.refill_per_sec()).ceil() as u64
+        } else {
+            ((1.0 - bucket.tokens) / budget.refill_per_sec()).ceil().max(1.0) as u64
+        };
+
+        RateLimitDecision { allowed, limit: budget.burst, remaining: bucket.tokens.floor() as u32, reset_secs }
+    }
+
+    /// drops buckets that have refilled completely, they behave the same as a missing bucket
+    pub fn purge_full_buckets(&self, now: Instant) {
+        let longest_refill = self.routes
+            .iter()
+            .map(|route| route.budget)
+            .chain(std::iter::once(self.config.default))
+            .map(|budget| budget.burst as f64 / budget.refill_per_sec())
+            .fold(0.0, f64::max);
+
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|_, bucket| now.saturating_duration_since(bucket.updated_at).as_secs_f64() < longest_refill);
+    }
+
+    /// background task keeping the bucket map from growing with every client ever seen
+    pub async fn run_purge(self, mut shutdown: ShutdownSignal) {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.purge_full_buckets(Instant::now()),
+                _ = shutdown.recv() => break,
+            }
+        }
+    }
+
+    /// clients with a valid bearer token are keyed by profile, so they keep one budget across addresses
+    /// and do not share it with everyone behind the same NAT. Anyone else is keyed by ip
+    fn get_client_key(&self, req: &ServiceRequest) -> String {
+        if let Some(profile_id) = self.authenticator.authenticate(req.headers()) {
+            return format!("profile:{}", profile_id);
+        }
+
+        let conn_info = req.connection_info();
+        let ip = if self.config.trust_proxy { conn_info.realip_remote_addr() } else { conn_info.peer_addr() };
+        // peer_addr includes the port, which changes per connection
+        let ip = ip.map(|addr| addr.parse::<std::net::SocketAddr>().map(|a| a.ip().to_string()).unwrap_or(addr.to_string()));
+        format!("ip:{}", ip.unwrap_or_else(|| "unknown".to_string()))
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware { service: Rc::new(service), limiter: self.clone() }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limiter: RateLimiter,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        let budget = match self.limiter.get_budget(req.method(), req.path()) {
+            Some(budget) if self.limiter.config.enabled => budget,
+            _ => return Box::pin(async move { Ok(service.call(req).await?.map_into_left_body()) }),
+        };
+        let (name, budget) = budget;
+        let client = self.limiter.get_client_key(&req);
+        let decision = self.limiter.check(name, &client, budget, Instant::now());
+
+        if !decision.allowed {
+            let error = UserError::TooManyRequests { limit: decision.limit, retry_after_secs: decision.reset_secs };
+            let res = req.into_response(error.error_response()).map_into_right_body();
+            return Box::pin(async move { Ok(res) });
+        }
+
+        Box::pin(async move {
+            let mut res = service.call(req).await?;
+            let headers = res.headers_mut();
+            for (name, value) in [
+                (RATE_LIMIT_LIMIT, decision.limit as u64),
+                (RATE_LIMIT_REMAINING, decision.remaining as u64),
+                (RATE_LIMIT_RESET, decision.reset_secs),
+            ] {
+                headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+            }
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{ test::{ call_service, init_service, TestRequest }, web, App, HttpResponse, http::{ header::AUTHORIZATION, StatusCode } };
+    use chrono::Utc;
+    use crate::common::config::AuthConfig;
+
+    fn get_config() -> RateLimitConfig {
+        RateLimitConfig {
+            enabled: true,
+            trust_proxy: false,
+            default: Budget { burst: 2, per_minute: 60 },
+            create_message: Budget { burst: 1, per_minute: 1 },
+            create_profile: Budget { burst: 1, per_minute: 1 },
+            get_messages: Budget { burst: 1, per_minute: 1 },
+        }
+    }
+
+    fn get_authenticator() -> Authenticator {
+        Authenticator::new(&AuthConfig { secret: "a test secret that is at least 32 bytes".to_string(), token_ttl_secs: 60 })
+    }
+
+    fn get_limiter() -> RateLimiter {
+        RateLimiter::new(&get_config(), get_authenticator())
+    }
+
+    #[test]
+    fn test_bucket_allows_burst_then_refills() {
+        let limiter = get_limiter();
+        let budget = Budget { burst: 2, per_minute: 60 };
+        let start = Instant::now();
+
+        assert!(limiter.check("test", "a", budget, start).allowed);
+        assert_eq!(limiter.check("test", "a", budget, start).remaining, 0);
+        let rejected = limiter.check("test", "a", budget, start);
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.reset_secs, 1);
+
+        // other clients have their own bucket
+        assert!(limiter.check("test", "b", budget, start).allowed);
+        assert!(limiter.check("test", "a", budget, start + Duration::from_secs(1)).allowed);
+    }
+
+    #[test]
+    fn test_get_budget_uses_route_table_default_and_exemptions() {
+        let limiter = get_limiter();
+
+        assert_eq!(limiter.get_budget(&Method::POST, "/v1/msg").unwrap(), ("create_message", Budget { burst: 1, per_minute: 1 }));
+        assert_eq!(limiter.get_budget(&Method::GET, "/v1/msg/12").unwrap().0, "default");
+        assert!(limiter.get_budget(&Method::GET, "/health/ready").is_none());
+    }
+
+    #[test]
+    fn test_purge_drops_only_full_buckets() {
+        let limiter = get_limiter();
+        let budget = Budget { burst: 2, per_minute: 60 };
+        let start = Instant::now();
+        limiter.check("test", "a", budget, start);
+
+        limiter.purge_full_buckets(start);
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+
+        limiter.purge_full_buckets(start + Duration::from_secs(3600));
+        assert!(limiter.buckets.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_middleware_rejects_with_429_and_headers() {
+        let app = init_service(
+            App::new()
+                .wrap(get_limiter())
+                .route("/limited", web::get().to(HttpResponse::Ok))
+        ).await;
+        let get_req = || TestRequest::get().uri("/limited").peer_addr("10.0.0.1:5000".parse().unwrap()).to_request();
+
+        let first = call_service(&app, get_req()).await;
+        assert_eq!(first.headers().get(RATE_LIMIT_LIMIT).unwrap(), "2");
+        assert_eq!(first.headers().get(RATE_LIMIT_REMAINING).unwrap(), "1");
+        call_service(&app, get_req()).await;
+
+        let rejected = call_service(&app, get_req()).await;
+        assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(rejected.headers().get("retry-after").unwrap(), "1");
+        assert_eq!(rejected.headers().get(RATE_LIMIT_REMAINING).unwrap(), "0");
+    }
+
+    #[tokio::test]
+    async fn test_middleware_keys_authenticated_requests_by_profile() {
+        let app = init_service(
+            App::new()
+                .wrap(get_limiter())
+                .route("/limited", web::get().to(HttpResponse::Ok))
+        ).await;
+        let token = format!("Bearer {}", get_authenticator().issue(1, Utc::now()));
+        let get_req = |peer: &str, token: Option<&str>| {
+            let req = TestRequest::get().uri("/limited").peer_addr(peer.parse().unwrap());
+            match token {
+                Some(token) => req.insert_header((AUTHORIZATION, token)).to_request(),
+                None => req.to_request(),
+            }
+        };
+
+        // the profile keeps its budget across addresses
+        call_service(&app, get_req("10.0.0.1:5000", Some(&token))).await;
+        call_service(&app, get_req("10.0.0.2:5000", Some(&token))).await;
+        let rejected = call_service(&app, get_req("10.0.0.3:5000", Some(&token))).await;
+        assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // and does not spend the budget of its address, nor does an invalid token
+        let anonymous = call_service(&app, get_req("10.0.0.1:5000", None)).await;
+        assert_eq!(anonymous.status(), StatusCode::OK);
+        let forged = call_service(&app, get_req("10.0.0.1:5000", Some("Bearer 1.0.00"))).await;
+        assert_eq!(forged.status(), StatusCode::OK);
+        assert_eq!(forged.headers().get(RATE_LIMIT_REMAINING).unwrap(), "0");
+    }
+}
@@ -0,0 +1,190 @@
+use actix_cors::Cors;
+use actix_http::BoxedPayloadStream;
+use actix_web::{
+    body::{ BoxBody, MessageBody },
+    dev::{ Payload, Service, ServiceRequest, ServiceResponse, Transform },
+    error::PayloadError,
+    http::{ header::{ self, HeaderName }, Method },
+    middleware::DefaultHeaders,
+    Error, HttpMessage, ResponseError,
+};
+use futures::{ future::{ ready, LocalBoxFuture, Ready }, StreamExt };
+use std::rc::Rc;
+use crate::{
+    common::{
+        config::{ CorsConfig, SecurityConfig },
+        rate_limit::{ RATE_LIMIT_LIMIT, RATE_LIMIT_REMAINING, RATE_LIMIT_RESET },
+        telemetry::REQUEST_ID_HEADER,
+    },
+    routes::errors::error_utils::UserError,
+};
+
+/// cross-origin policy for browser clients, origins come from config and everything else is fixed by the api
+pub fn get_cors(config: &CorsConfig) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods([Method::GET, Method::POST])
+        .allowed_headers([header::ACCEPT, header::AUTHORIZATION, header::CONTENT_TYPE, HeaderName::from_static(REQUEST_ID_HEADER)])
+        .expose_headers([
+            header::RETRY_AFTER,
+            header::WWW_AUTHENTICATE,
+            HeaderName::from_static(REQUEST_ID_HEADER),
+            HeaderName::from_static(RATE_LIMIT_LIMIT),
+            HeaderName::from_static(RATE_LIMIT_REMAINING),
+            HeaderName::from_static(RATE_LIMIT_RESET),
+        ])
+        .max_age(config.max_age_secs);
+
+    for origin in &config.allowed_origins {
+        cors = if origin == "*" { cors.allow_any_origin() } else { cors.allowed_origin(origin) };
+    }
+    cors
+}
+
+/// headers added to every response, the api only serves json so nothing may be framed or executed
+pub fn get_security_headers(config: &SecurityConfig) -> DefaultHeaders {
+    let headers = DefaultHeaders::new()
+        .add((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .add((header::X_FRAME_OPTIONS, "DENY"))
+        .add((header::REFERRER_POLICY, "no-referrer"))
+        .add((header::CONTENT_SECURITY_POLICY, "default-src 'none'; frame-ancestors 'none'"));
+
+    if config.hsts {
+        headers.add((header::STRICT_TRANSPORT_SECURITY, "max-age=31536000; includeSubDomains"))
+    } else {
+        headers
+    }
+}
+
+/// the embedded docs ui loads its own scripts and styles, DefaultHeaders keeps a header a response
+/// already has so wrapping the docs scope with this overrides the api wide policy
+pub fn get_docs_security_headers() -> DefaultHeaders {
+    DefaultHeaders::new()
+        .add((header::CONTENT_SECURITY_POLICY, "default-src 'self'; img-src 'self' data:; style-src 'self' 'unsafe-inline'; frame-ancestors 'none'"))
+}
+
+/// caps the size of any request body, a declared Content-Length over the limit is rejected up front
+/// and chunked bodies fail with PayloadError::Overflow as soon as they pass it, so nothing oversize is buffered.
+/// Upgraded connections are left alone, their payload is a stream of frames the codec limits one by one
+#[derive(Clone, Copy)]
+pub struct BodyLimit(pub usize);
+
+impl<S, B> Transform<S, ServiceRequest> for BodyLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = BodyLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(BodyLimitMiddleware { service: Rc::new(service), limit: self.0 }))
+    }
+}
+
+pub struct BodyLimitMiddleware<S> {
+    service: Rc<S>,
+    limit: usize,
+}
+
+impl<S, B> Service<ServiceRequest> for BodyLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let limit = self.limit;
+        if req.head().upgrade() {
+            return Box::pin(async move { Ok(service.call(req).await?.map_into_boxed_body()) });
+        }
+
+        let content_length = req.headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        if content_length.is_some_and(|length| length > limit) {
+            let res = req.into_response(UserError::PayloadTooLarge.error_response());
+            return Box::pin(async move { Ok(res) });
+        }
+
+        let mut received = 0;
+        let payload = req.take_payload().map(move |chunk| {
+            let chunk = chunk?;
+            received += chunk.len();
+            if received > limit {
+                return Err(PayloadError::Overflow);
+            }
+            Ok(chunk)
+        });
+        req.set_payload(Payload::from(Box::pin(payload) as BoxedPayloadStream));
+
+        Box::pin(async move { Ok(service.call(req).await?.map_into_boxed_body()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{ test::{ call_service, init_service, TestRequest }, web, App, HttpResponse, http::StatusCode };
+
+    async fn echo_length(body: web::Bytes) -> HttpResponse {
+        HttpResponse::Ok().body(body.len().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_security_headers_and_cors_for_allowed_origin() {
+        let cors_config = CorsConfig { allowed_origins: vec!["https://app.example".to_string()], max_age_secs: 60 };
+        let app = init_service(
+            App::new()
+                .wrap(get_cors(&cors_config))
+                .wrap(get_security_headers(&SecurityConfig { hsts: true }))
+                .route("/", web::get().to(HttpResponse::Ok))
+        ).await;
+
+        let res = call_service(&app, TestRequest::get().uri("/").insert_header((header::ORIGIN, "https://app.example")).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "https://app.example");
+        assert_eq!(res.headers().get(header::X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+        assert!(res.headers().contains_key(header::STRICT_TRANSPORT_SECURITY));
+
+        let preflight = TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .insert_header((header::ORIGIN, "https://other.example"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+            .to_request();
+        let res = call_service(&app, preflight).await;
+        assert!(!res.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn test_body_limit_rejects_declared_and_streamed_oversize_bodies() {
+        let app = init_service(
+            App::new()
+                .wrap(BodyLimit(10))
+                .app_data(web::PayloadConfig::new(1024))
+                .route("/", web::post().to(echo_length))
+        ).await;
+
+        let res = call_service(&app, TestRequest::post().uri("/").set_payload("0123456789").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = call_service(&app, TestRequest::post().uri("/").set_payload("0123456789a").to_request()).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // without a Content-Length the limit is only noticed while reading the stream
+        let mut chunked = TestRequest::post().uri("/").set_payload("0123456789a").to_request();
+        chunked.headers_mut().remove(header::CONTENT_LENGTH);
+        let res = call_service(&app, chunked).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
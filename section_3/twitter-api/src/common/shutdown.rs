@@ -0,0 +1,276 @@
+use actix_web::{
+    dev::{ Server, Service, ServiceRequest, ServiceResponse, Transform },
+    http::ConnectionType,
+    Error,
+};
+use futures::{ Future, future::{ ready, LocalBoxFuture, Ready } };
+use std::{ rc::Rc, sync::{ Arc, atomic::{ AtomicBool, AtomicUsize, Ordering } }, time::Duration };
+use tokio::{ sync::watch, task::JoinHandle };
+use tracing::{ info, warn };
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// handed to each background task, resolves once the server starts shutting down
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    pub fn is_shutdown(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    pub async fn recv(&mut self) {
+        // an error means every sender is gone, which is also a shutdown
+        _ = self.0.wait_for(|is_shutdown| *is_shutdown).await;
+    }
+}
+
+/// long running tasks owned by the server, stopped after the http server has drained
+pub struct BackgroundTasks {
+    sender: watch::Sender<bool>,
+    tasks: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+impl Default for BackgroundTasks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BackgroundTasks {
+    pub fn new() -> Self {
+        Self { sender: watch::channel(false).0, tasks: vec![] }
+    }
+
+    pub fn spawn<F, Fut>(&mut self, name: &'static str, task: F)
+    where
+        F: FnOnce(ShutdownSignal) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let signal = ShutdownSignal(self.sender.subscribe());
+        self.tasks.push((name, tokio::spawn(task(signal))));
+    }
+
+    /// signals every task and waits for them, tasks still running after the timeout are aborted
+    pub async fn shutdown(self, timeout: Duration) {
+        _ = self.sender.send(true);
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        for (name, mut task) in self.tasks {
+            if tokio::time::timeout_at(deadline, &mut task).await.is_err() {
+                warn!("background task {} did not stop within {:?}, aborting", name, timeout);
+                task.abort();
+            }
+        }
+    }
+}
+
+/// counts requests being handled so shutdown can wait for them, shared by every worker
+#[derive(Clone, Default)]
+pub struct InFlightRequests(Arc<InFlightState>);
+
+#[derive(Default)]
+struct InFlightState {
+    count: AtomicUsize,
+    draining: AtomicBool,
+}
+
+impl InFlightRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.count.load(Ordering::SeqCst)
+    }
+
+    /// waits until no request is in flight, false if the timeout passed first
+    async fn wait_for_idle(&self, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while self.count() > 0 {
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+        true
+    }
+}
+
+/// decrements on drop so requests whose connection went away are not counted forever
+struct InFlightGuard(Arc<InFlightState>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for InFlightRequests
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = InFlightRequestsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(InFlightRequestsMiddleware { service: Rc::new(service), state: self.0.clone() }))
+    }
+}
+
+pub struct InFlightRequestsMiddleware<S> {
+    service: Rc<S>,
+    state: Arc<InFlightState>,
+}
+
+impl<S, B> Service<ServiceRequest> for InFlightRequestsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let state = self.state.clone();
+        state.count.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlightGuard(state);
+
+        Box::pin(async move {
+            let mut res = service.call(req).await?;
+            // while draining, keep-alive clients are told to reconnect elsewhere
+            if guard.0.draining.load(Ordering::SeqCst) {
+                res.response_mut().head_mut().set_connection_type(ConnectionType::Close);
+            }
+            drop(guard);
+            Ok(res)
+        })
+    }
+}
+
+/// runs the server until `shutdown` resolves, then stops accepting connections, waits up to
+/// `drain_timeout` for in-flight requests to finish and only then stops the workers
+///
+/// draining is done here rather than left to actix, whose workers can exit and drop
+/// in-flight connections when the accept thread stops first
+pub async fn serve_until(
+    server: Server,
+    in_flight: InFlightRequests,
+    drain_timeout: Duration,
+    shutdown: impl Future<Output = ()> + Send + 'static
+) -> std::io::Result<()> {
+    let handle = server.handle();
+    tokio::spawn(async move {
+        shutdown.await;
+        info!("shutdown requested, draining {} in-flight requests", in_flight.count());
+        in_flight.0.draining.store(true, Ordering::SeqCst);
+        handle.pause().await;
+
+        if !in_flight.wait_for_idle(drain_timeout).await {
+            warn!("{} requests still in flight after {:?}, stopping anyway", in_flight.count(), drain_timeout);
+        }
+        handle.stop(true).await;
+    });
+
+    server.await
+}
+
+/// resolves on SIGINT, or SIGTERM as sent by `docker compose down`
+pub async fn wait_for_signal() {
+    let ctrl_c = async {
+        _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => { signal.recv().await; },
+            Err(e) => {
+                warn!("unable to listen for SIGTERM: {}", e);
+                futures::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = futures::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => (),
+        _ = terminate => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{ web, App, HttpResponse, HttpServer };
+    use tokio::sync::oneshot;
+
+    const SLOW_REQUEST: Duration = Duration::from_millis(500);
+
+    async fn slow_handler() -> HttpResponse {
+        tokio::time::sleep(SLOW_REQUEST).await;
+        HttpResponse::Ok().body("done")
+    }
+
+    #[actix_web::test]
+    async fn test_serve_until_completes_in_flight_request() {
+        let in_flight = InFlightRequests::new();
+        let app_in_flight = in_flight.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .wrap(app_in_flight.clone())
+                .route("/", web::get().to(HttpResponse::Ok))
+                .route("/slow", web::get().to(slow_handler))
+        })
+            .workers(1)
+            .disable_signals()
+            .shutdown_timeout(5)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let addr = server.addrs()[0];
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let serving = tokio::spawn(serve_until(server.run(), in_flight, Duration::from_secs(5), async { _ = shutdown_rx.await; }));
+        // workers start asynchronously, make sure one is accepting before the real request
+        reqwest::get(format!("http://{}/", addr)).await.unwrap();
+
+        let request = tokio::spawn(reqwest::get(format!("http://{}/slow", addr)));
+        // let the request reach the handler before asking the server to stop
+        tokio::time::sleep(SLOW_REQUEST / 5).await;
+        shutdown_tx.send(()).unwrap();
+
+        let response = request.await.unwrap().unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(response.text().await.unwrap(), "done");
+        assert!(serving.await.unwrap().is_ok());
+        // the listener is closed once drained
+        assert!(reqwest::get(format!("http://{}/slow", addr)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_background_tasks_stop_on_signal_and_abort_after_timeout() {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let mut tasks = BackgroundTasks::new();
+
+        let task_stopped = stopped.clone();
+        tasks.spawn("cooperative", |mut signal| async move {
+            signal.recv().await;
+            task_stopped.store(signal.is_shutdown(), Ordering::SeqCst);
+        });
+        tasks.spawn("stuck", |_| futures::future::pending::<()>());
+
+        tokio::time::timeout(Duration::from_secs(2), tasks.shutdown(Duration::from_millis(100)))
+            .await
+            .expect("shutdown should not wait past its timeout");
+
+        assert!(stopped.load(Ordering::SeqCst));
+    }
+}
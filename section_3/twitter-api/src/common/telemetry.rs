@@ -0,0 +1,131 @@
+use actix_web::{
+    dev::{ Service, ServiceRequest, ServiceResponse, Transform },
+    http::header::{ HeaderName, HeaderValue },
+    Error, HttpMessage,
+};
+use futures::future::{ ready, LocalBoxFuture, Ready };
+use std::rc::Rc;
+use tracing::{ info_span, Instrument };
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+use crate::common::config::{ LogConfig, LogFormat };
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// longer incoming ids are replaced so a client cannot bloat every log line
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// installs the global tracing subscriber, `log` records from dependencies (actix, sqlx) are forwarded into it
+pub fn init_tracing(log: &LogConfig) -> Result<(), String> {
+    let filter = EnvFilter::try_new(&log.filter).map_err(|e| e.to_string())?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    let result = match log.format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).try_init(),
+    };
+    result.map_err(|e| e.to_string())
+}
+
+/// id correlating every log line of one request, available to handlers through request extensions
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestId(pub String);
+
+/// keeps a well formed incoming id so callers can correlate across services, otherwise makes a new one
+fn get_request_id(incoming: Option<&HeaderValue>) -> String {
+    match incoming.and_then(|value| value.to_str().ok()) {
+        Some(id) if !id.is_empty()
+            && id.len() <= MAX_REQUEST_ID_LENGTH
+            && id.chars().all(|c| c.is_ascii_graphic()) => id.to_string(),
+        _ => Uuid::new_v4().to_string(),
+    }
+}
+
+/// middleware opening a span per request that carries its request id, the id is echoed in X-Request-Id
+pub struct RequestTracing;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTracing
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestTracingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTracingMiddleware { service: Rc::new(service) }))
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTracingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let request_id = get_request_id(req.headers().get(REQUEST_ID_HEADER));
+        let span = info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = %req.path(),
+        );
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+
+        Box::pin(async move {
+            let mut res = service.call(req).await?;
+
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+            Ok(res)
+        }.instrument(span))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{ test::{ call_service, init_service, TestRequest }, web, App, HttpResponse };
+
+    #[test]
+    fn test_get_request_id_keeps_valid_and_replaces_invalid() {
+        let valid = HeaderValue::from_static("abc-123");
+        let too_long = HeaderValue::from_str(&"a".repeat(MAX_REQUEST_ID_LENGTH + 1)).unwrap();
+        let with_space = HeaderValue::from_static("abc 123");
+
+        assert_eq!(get_request_id(Some(&valid)), "abc-123");
+        assert!(Uuid::parse_str(&get_request_id(Some(&too_long))).is_ok());
+        assert!(Uuid::parse_str(&get_request_id(Some(&with_space))).is_ok());
+        assert!(Uuid::parse_str(&get_request_id(None)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_request_tracing_echoes_request_id() {
+        let app = init_service(
+            App::new()
+                .wrap(RequestTracing)
+                .route("/", web::get().to(HttpResponse::Ok))
+        ).await;
+
+        let res = call_service(&app, TestRequest::get().uri("/").insert_header((REQUEST_ID_HEADER, "req-1")).to_request()).await;
+        assert_eq!(res.headers().get(REQUEST_ID_HEADER).unwrap(), "req-1");
+
+        let res = call_service(&app, TestRequest::get().uri("/").to_request()).await;
+        let generated = res.headers().get(REQUEST_ID_HEADER).unwrap().to_str().unwrap();
+        assert!(Uuid::parse_str(generated).is_ok());
+    }
+}
@@ -0,0 +1,311 @@
+use chrono::{ DateTime, Utc };
+use futures::future::join_all;
+use hmac::{ Hmac, Mac };
+use reqwest::{ header::CONTENT_TYPE, redirect::Policy, Url };
+use sha2::Sha256;
+use std::{ net::{ IpAddr, SocketAddr }, time::Duration };
+use tokio::sync::broadcast::{ self, error::RecvError };
+use tokio::time::MissedTickBehavior;
+use tracing::{ error, info, warn };
+use crate::common::{
+    config::WebhookConfig,
+    entities::{
+        events::repo::SubscribeEventsFn,
+        webhooks::{
+            model::{ WebhookAttempt, WebhookDeliveryQueryResult, WebhookDeliveryStatus, WebhookDueDelivery },
+            repo::{ ClaimWebhookDeliveriesFn, RecordWebhookAttemptFn },
+        },
+    },
+    events::AppEvent,
+    shutdown::ShutdownSignal,
+};
+
+/// sha256=<hex hmac> of "{timestamp}.{body}", see sign_payload
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+/// unix seconds when the request was signed
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+/// the delivery id, the same across retries so receivers can drop duplicates
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+/// deliveries claimed per poll, they are sent concurrently
+const CLAIM_BATCH_SIZE: i16 = 50;
+/// time to record an attempt after its request timed out, before the claim lapses
+const LEASE_MARGIN_SECS: u64 = 30;
+const LAST_ERROR_MAX_LENGTH: usize = 1000;
+
+/// hex hmac-sha256 of "{timestamp}.{body}" keyed with the subscription's secret. Receivers recompute it
+/// over the raw body and reject old timestamps, so a captured request cannot be sent again later
+pub fn sign_payload(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// how long a claimed delivery is left alone, long enough for its request to time out and be recorded
+pub fn get_lease(config: &WebhookConfig) -> chrono::Duration {
+    chrono::Duration::seconds((config.timeout_secs + LEASE_MARGIN_SECS) as i64)
+}
+
+/// the wait after the nth failed attempt, retry_base_secs doubling with each attempt up to retry_max_secs
+pub fn get_retry_delay(config: &WebhookConfig, attempt_count: i32) -> Duration {
+    let doublings = u32::try_from(attempt_count.saturating_sub(1)).unwrap_or(0);
+    let delay_secs = 2u64
+        .checked_pow(doublings)
+        .and_then(|factor| config.retry_base_secs.checked_mul(factor))
+        .unwrap_or(u64::MAX);
+    Duration::from_secs(delay_secs.min(config.retry_max_secs))
+}
+
+/// the outcome to record for a delivery's attempt_count-th attempt, error is None when the subscriber accepted it
+pub fn get_attempt(
+    config: &WebhookConfig,
+    attempt_count: i32,
+    attempted_at: DateTime<Utc>,
+    status_code: Option<i32>,
+    error: Option<String>
+) -> WebhookAttempt {
+    let (status, next_attempt_at) = match error {
+        None => (WebhookDeliveryStatus::Succeeded, None),
+        Some(_) if attempt_count >= config.max_attempts => (WebhookDeliveryStatus::Failed, None),
+        Some(_) => {
+            let delay = chrono::Duration::from_std(get_retry_delay(config, attempt_count)).unwrap_or_else(|_| chrono::Duration::max_value());
+            (WebhookDeliveryStatus::Pending, attempted_at.checked_add_signed(delay))
+        }
+    };
+    let error = error.map(|error| error.chars().take(LAST_ERROR_MAX_LENGTH).collect());
+    WebhookAttempt { status, attempted_at, status_code, error, next_attempt_at }
+}
+
+/// whether a subscriber may be at the address. Loopback, private, link-local and the other addresses that are
+/// not routed on the internet would let a subscription make this server call its own network
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            let is_shared = first == 100 && (64..128).contains(&second);
+            !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast() ||
+                ip.is_multicast() || ip.is_documentation() || first == 0 || is_shared)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_ip(IpAddr::V4(mapped)),
+            None => !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || ip.is_unique_local() || ip.is_unicast_link_local()),
+        },
+    }
+}
+
+/// the url with its host when a subscriber may be there. Addresses are checked here, names only when they are
+/// resolved before each delivery, see get_subscriber_addrs
+pub fn check_subscriber_url(url: &str, allow_private_hosts: bool) -> Result<(Url, String), String> {
+    let url = Url::parse(url).map_err(|e| format!("invalid url: {}", e))?;
+    let host = url.host_str().ok_or("the url has no host")?.trim_start_matches('[').trim_end_matches(']').to_string();
+    if !allow_private_hosts {
+        let is_private = match host.parse::<IpAddr>() {
+            Ok(ip) => !is_public_ip(ip),
+            Err(_) => host.eq_ignore_ascii_case("localhost") || host.to_ascii_lowercase().ends_with(".localhost"),
+        };
+        if is_private {
+            return Err(format!("{} is not a public host", host));
+        }
+    }
+    Ok((url, host))
+}
+
+/// resolves the subscriber's host, failing when any of its addresses is private
+async fn get_subscriber_addrs(url: &Url, host: &str, allow_private_hosts: bool) -> Result<Vec<SocketAddr>, String> {
+    let port = url.port_or_known_default().ok_or("the url has no port")?;
+    let addrs = tokio::net::lookup_host((host, port)).await
+        .map_err(|e| format!("unable to resolve {}: {}", host, e))?
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        return Err(format!("{} has no addresses", host));
+    }
+    if !allow_private_hosts && addrs.iter().any(|addr| !is_public_ip(addr.ip())) {
+        return Err(format!("{} resolves to a private address", host));
+    }
+    Ok(addrs)
+}
+
+/// a client that only connects to the addresses that were checked, so the name cannot resolve elsewhere
+/// by the time the request is made, and that does not follow redirects to hosts that were never checked
+async fn get_subscriber_client(config: &WebhookConfig, url: &str) -> Result<(reqwest::Client, Url), String> {
+    let (url, host) = check_subscriber_url(url, config.allow_private_hosts)?;
+    let addrs = get_subscriber_addrs(&url, &host, config.allow_private_hosts).await?;
+    let client = reqwest::Client::builder()
+        .redirect(Policy::none())
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .resolve_to_addrs(&host, &addrs)
+        .build()
+        .map_err(|e| e.to_string())?;
+    Ok((client, url))
+}
+
+/// posts a claimed delivery to its subscriber and records the attempt, any 2xx response counts as delivered.
+/// A subscriber that resolves to a private address fails the attempt without a request
+pub async fn send_webhook_delivery<T: RecordWebhookAttemptFn>(
+    db_repo: &T,
+    config: &WebhookConfig,
+    delivery: &WebhookDueDelivery
+) -> Result<Option<WebhookDeliveryQueryResult>, sqlx::Error> {
+    let attempted_at = Utc::now();
+    let timestamp = attempted_at.timestamp();
+
+    let response = match get_subscriber_client(config, &delivery.url).await {
+        Ok((client, url)) => client
+            .post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, delivery.event_type.name())
+            .header(DELIVERY_HEADER, delivery.id)
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(SIGNATURE_HEADER, format!("sha256={}", sign_payload(&delivery.secret, timestamp, &delivery.payload)))
+            .body(delivery.payload.clone())
+            .send().await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+    let (status_code, error) = match response {
+        Ok(response) if response.status().is_success() => (Some(i32::from(response.status().as_u16())), None),
+        Ok(response) => (Some(i32::from(response.status().as_u16())), Some(format!("subscriber responded {}", response.status()))),
+        Err(e) => (None, Some(e)),
+    };
+    if let Some(error) = &error {
+        warn!("webhook delivery {} to {} failed: {}", delivery.id, delivery.url, error);
+    }
+
+    let attempt = get_attempt(config, delivery.attempt_count + 1, attempted_at, status_code, error);
+    db_repo.record_webhook_attempt(delivery.id, &attempt).await
+}
+
+/// sends every due delivery, new ones as soon as their event arrives and retries on each poll. The deliveries
+/// are inserted by the writes that cause their events, so an event this instance misses is still sent on the
+/// next poll. Every instance runs one, see the 0006 migration
+pub struct WebhookDispatcher<T> {
+    db_repo: T,
+    config: WebhookConfig,
+    events: broadcast::Receiver<AppEvent>,
+}
+
+impl<T> WebhookDispatcher<T>
+    where T: SubscribeEventsFn + ClaimWebhookDeliveriesFn + RecordWebhookAttemptFn + Send + Sync
+{
+    /// subscribes immediately, so events published before run starts are sent without waiting for a poll
+    pub fn new(db_repo: T, config: WebhookConfig) -> Self {
+        let events = db_repo.subscribe_events();
+        Self { db_repo, config, events }
+    }
+
+    pub async fn run(mut self, mut shutdown: ShutdownSignal) {
+        let mut interval = tokio::time::interval(Duration::from_millis(self.config.poll_interval_ms));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                event = self.events.recv() => match event {
+                    // notifications have no deliveries
+                    Ok(AppEvent::NotificationCreated(_)) => {}
+                    // an event only wakes the dispatcher, its deliveries were committed with it
+                    Ok(_) => self.send_due_deliveries().await,
+                    // the missed events' deliveries wait for the next poll
+                    Err(RecvError::Lagged(missed)) => warn!("webhook dispatcher missed {} events", missed),
+                    Err(RecvError::Closed) => break,
+                },
+                _ = interval.tick() => self.send_due_deliveries().await,
+                _ = shutdown.recv() => break,
+            }
+        }
+        info!("webhook dispatcher stopped");
+    }
+
+    async fn send_due_deliveries(&self) {
+        let now = Utc::now();
+        let due = match self.db_repo.claim_webhook_deliveries(now, now + get_lease(&self.config), CLAIM_BATCH_SIZE).await {
+            Ok(due) => due,
+            Err(e) => {
+                error!("unable to claim webhook deliveries: {}", e);
+                return;
+            }
+        };
+
+        let results = join_all(
+            due.iter().map(|delivery| send_webhook_delivery(&self.db_repo, &self.config, delivery))
+        ).await;
+        for (delivery, result) in due.iter().zip(results) {
+            if let Err(e) = result {
+                error!("unable to record webhook delivery {}: {}", delivery.id, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_config() -> WebhookConfig {
+        WebhookConfig {
+            enabled: true,
+            max_attempts: 4,
+            retry_base_secs: 10,
+            retry_max_secs: 30,
+            poll_interval_ms: 1000,
+            timeout_secs: 5,
+            allow_private_hosts: false,
+        }
+    }
+
+    #[test]
+    fn test_signature_is_hmac_sha256_of_timestamp_and_body() {
+        assert_eq!(
+            sign_payload("secret", 1700000000, r#"{"type":"followCreated"}"#),
+            "12e21ae7f6330577b872bea1e8b931723654a94b20f71c1a5689e0645ea0aeca"
+        );
+    }
+
+    #[test]
+    fn test_failed_attempts_back_off_exponentially_until_the_last() {
+        let config = get_config();
+        let attempted_at = Utc::now();
+        let failed = |attempt_count| get_attempt(&config, attempt_count, attempted_at, Some(500), Some("subscriber responded 500".to_string()));
+
+        assert_eq!(failed(1).next_attempt_at, Some(attempted_at + chrono::Duration::seconds(10)));
+        assert_eq!(failed(2).next_attempt_at, Some(attempted_at + chrono::Duration::seconds(20)));
+        assert_eq!(failed(3).next_attempt_at, Some(attempted_at + chrono::Duration::seconds(30)));
+        assert_eq!(failed(3).status, WebhookDeliveryStatus::Pending);
+        assert_eq!(failed(4).status, WebhookDeliveryStatus::Failed);
+        assert_eq!(failed(4).next_attempt_at, None);
+        assert_eq!(get_retry_delay(&config, 80), Duration::from_secs(30));
+
+        let succeeded = get_attempt(&config, 2, attempted_at, Some(204), None);
+        assert_eq!(succeeded.status, WebhookDeliveryStatus::Succeeded);
+        assert_eq!(succeeded.next_attempt_at, None);
+    }
+
+    #[test]
+    fn test_private_addresses_are_not_public() {
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1"] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{} is private", ip);
+        }
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{} is public", ip);
+        }
+    }
+
+    #[test]
+    fn test_subscriber_urls_on_private_hosts_are_refused_unless_allowed() {
+        for url in ["http://127.0.0.1:8080/hook", "http://[::1]/hook", "http://localhost/hook", "https://api.localhost/hook", "http://169.254.169.254/latest"] {
+            assert!(check_subscriber_url(url, false).is_err(), "{} is refused", url);
+            assert!(check_subscriber_url(url, true).is_ok(), "{} is allowed", url);
+        }
+        assert_eq!(check_subscriber_url("https://example.com/hook", false).unwrap().1, "example.com");
+    }
+
+    #[tokio::test]
+    async fn test_names_resolving_to_private_addresses_are_refused_before_sending() {
+        let config = get_config();
+
+        assert!(get_subscriber_client(&config, "http://127.0.0.1:9/hook").await.is_err());
+        let url = Url::parse("http://localhost:9/hook").unwrap();
+        let error = get_subscriber_addrs(&url, "localhost", false).await.unwrap_err();
+        assert_eq!(error, "localhost resolves to a private address");
+        assert!(get_subscriber_addrs(&url, "localhost", true).await.is_ok());
+    }
+}
@@ -1,13 +1,15 @@
 use crate::{
-    common::{ app_state::AppState, fs::file_utils::get_avatar_buffer, entities::{base::DbRepo}},
-    routes::{
-        profiles::{ profile_route::{ create_profile, get_profile, get_profile_by_user } }, messages::message_route::{create_message, get_message, get_messages},
+    app::{ create_app, SharedMiddleware },
+    common::{
+        app_state::AppState,
+        auth::Authenticator,
+        config::{ Config, RepositoryBackend },
+        fs::file_utils::get_avatar_buffer,
+        entities::app_repo::AppRepo,
     },
+    routes::profiles::model::REGION_MAX_LENGTH,
 };
-use chrono::{ DateTime, Utc };
-use serde::Deserialize;
-use sqlx::{ FromRow };
-use actix_web::{ App, web::{ self, BytesMut, Bytes }, Error, test, dev::{ Service, ServiceResponse } };
+use actix_web::{ body::MessageBody, web::{ self, BytesMut, Bytes }, Error, HttpServer, test, dev::{ Service, ServiceResponse } };
 use actix_http::Request;
 use fake::{
     Fake,
@@ -19,7 +21,8 @@ use fake::{
 };
 use fake::faker::lorem::en::Sentence;
 use fake::faker::company::en::CompanyName;
-use std::{ ops::Range };
+use chrono::Utc;
+use std::{ net::SocketAddr, ops::Range, sync::atomic::{ AtomicUsize, Ordering } };
 
 pub const PUBLIC_GROUP_TYPE: i32 = 1;
 pub const CIRCLE_GROUP_TYPE: i32 = 2;
@@ -28,37 +31,12 @@ const JPEG_SIGNATURE: [u8; 2] = [0xFF, 0xD8];
 #[allow(unused)]
 const JPEG_END_SIGNATURE: [u8; 2] = [0xFF, 0xD9];
 
-#[allow(unused)]
-#[derive(Deserialize, FromRow)]
-pub struct MessageResponse {
-    pub id: i64,
-    pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
-    pub original_msg_id: i64,
-    pub responding_msg_id: i64,
-}
-
-#[derive(Debug, Clone)]
-pub enum FixtureError {
-    MissingData(String),
-    QueryFailed(String),
-}
-impl std::error::Error for FixtureError {}
-impl std::fmt::Display for FixtureError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::MissingData(msg) => write!(f, "{}", msg),
-            Self::QueryFailed(msg) => write!(f, "{}", msg),
-        }
-    }
-}
-
 #[allow(unused)]
 fn is_jpeg(avatar: Vec<u8>) -> bool {
     let mut is_valid = false;
-    if avatar.len() >= 2 && &avatar[0..2] == JPEG_SIGNATURE {
+    if avatar.len() >= 2 && avatar[0..2] == JPEG_SIGNATURE {
         let end_offset = avatar.len() - 2;
-        if &avatar[end_offset..] == JPEG_END_SIGNATURE {
+        if avatar[end_offset..] == JPEG_END_SIGNATURE {
             println!("The avatar data is a valid JPEG image.");
             is_valid = true;
         } else {
@@ -70,10 +48,46 @@ fn is_jpeg(avatar: Vec<u8>) -> bool {
     is_valid
 }
 
+pub fn get_config() -> Config {
+    Config::load().expect("Test config failed to load, check .env")
+}
+
+/// an Authorization header value acting as the profile, signed with the test config's AUTH_SECRET
+pub fn get_bearer(profile_id: i64) -> String {
+    format!("Bearer {}", Authenticator::new(&get_config().auth).issue(profile_id, Utc::now()))
+}
+
+/// test config for one backend, each sqlite config points at a new database file so test modules
+/// running in parallel never share or race on migrations
+pub fn get_backend_config(backend: RepositoryBackend) -> Config {
+    static SQLITE_DATABASES: AtomicUsize = AtomicUsize::new(0);
+
+    let mut config = get_config();
+    config.repository.backend = backend;
+    if backend == RepositoryBackend::Sqlite {
+        let database = SQLITE_DATABASES.fetch_add(1, Ordering::SeqCst);
+        let path = std::env::temp_dir().join(format!("twitter-api-test-{}-{}.db", std::process::id(), database));
+        _ = std::fs::remove_file(&path);
+        config.sqlite.path = path.to_string_lossy().to_string();
+        config.features.run_migrations = true;
+    }
+    config
+}
+
+/// one repo per persistent backend, repo tests run against each to show they behave the same
+pub async fn get_parity_repos() -> Vec<AppRepo> {
+    let mut repos = vec![];
+    for backend in [RepositoryBackend::Postgres, RepositoryBackend::Sqlite] {
+        repos.push(AppRepo::init(&get_backend_config(backend)).await.unwrap());
+    }
+    repos
+}
+
 #[allow(unused)]
 pub async fn get_app_state<T>(db_repo: T) -> AppState<T> {
     AppState {
         client: reqwest::Client::new(),
+        config: get_config(),
         db_repo,
     }
 }
@@ -82,22 +96,34 @@ pub async fn get_app_data<T>(db_repo: T) -> web::Data<AppState<T>> {
     web::Data::new(get_app_state(db_repo).await)
 }
 
+/// the production app from `create_app`, backed by the repo REPOSITORY_BACKEND selects,
+/// so `REPOSITORY_BACKEND=memory cargo test` runs the http tests without postgres
 #[allow(unused)]
-pub async fn get_app() -> impl Service<Request, Response = ServiceResponse, Error = Error> {
-    let app_data = get_app_data(DbRepo::init().await).await;
-    test::init_service(
-        App::new()
-            .app_data(app_data.clone())            
-            .service(
-                web::scope("/v1")
-                    .service(web::resource("/msg/{id}").route(web::get().to(get_message::<DbRepo>)))
-                    .service(web::resource("/msg").route(web::post().to(create_message::<DbRepo>)))
-                    .service(web::resource("/msgs").route(web::post().to(get_messages::<DbRepo>)))
-                    .service(web::resource("/profile/{id}").route(web::get().to(get_profile::<DbRepo>)))
-                    .service(web::resource("/profile/username/{user_name}").route(web::get().to(get_profile_by_user::<DbRepo>)))
-                    .service(web::resource("/profile").route(web::post().to(create_profile::<DbRepo>)))
-            )
-    ).await
+pub async fn get_app() -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+    let app_data = get_app_data(AppRepo::init(&get_config()).await.unwrap()).await;
+    let shared_middleware = SharedMiddleware::new(&app_data.config);
+    test::init_service(create_app(app_data, shared_middleware)).await
+}
+
+/// the production app served on a free local port, for streams that need a real connection
+/// the test service cannot provide. Runs until the test's runtime stops
+#[allow(unused)]
+pub async fn start_server(db_repo: AppRepo) -> SocketAddr {
+    start_server_with_config(db_repo, get_config()).await
+}
+
+/// start_server with settings the test changed
+#[allow(unused)]
+pub async fn start_server_with_config(db_repo: AppRepo, config: Config) -> SocketAddr {
+    let app_data = web::Data::new(AppState { client: reqwest::Client::new(), config, db_repo });
+    let shared_middleware = SharedMiddleware::new(&app_data.config);
+    let server = HttpServer::new(move || create_app(app_data.clone(), shared_middleware.clone()))
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+    addr
 }
 
 pub fn get_fake_message_body(prefix: Option<String>) -> String {
@@ -106,55 +132,53 @@ pub fn get_fake_message_body(prefix: Option<String>) -> String {
         None => "".to_string(),
     };
 
-    for _ in [..4] {
-        let random_sentence: String = Sentence(Range { start: 5, end: 6 }).fake();
-        body = format!("{}. {}", body, random_sentence);
-    }
+    let random_sentence: String = Sentence(Range { start: 5, end: 6 }).fake();
+    body = format!("{}. {}", body, random_sentence);
     body
 }
 
 /// warning: line breaks are very important when ending any line!!!
 pub fn get_profile_create_multipart(
-    avatar: &Vec<u8>,
+    avatar: &[u8],
     boundary: &str,
     with_avatar: bool
 ) -> BytesMut {
     let mut payload = actix_web::web::BytesMut::new();
     payload.extend(format!("--{}\r\n", boundary).as_bytes());
     payload.extend(
-        format!("Content-Disposition: form-data; name=\"user_name\"\r\n\r\n").as_bytes()
+        b"Content-Disposition: form-data; name=\"user_name\"\r\n\r\n"
     );
     payload.extend(format!("{}\r\n", Username().fake::<String>()).as_bytes());
     payload.extend(format!("--{}\r\n", boundary).as_bytes());
     payload.extend(
-        format!("Content-Disposition: form-data; name=\"full_name\"\r\n\r\n").as_bytes()
+        b"Content-Disposition: form-data; name=\"full_name\"\r\n\r\n"
     );
     payload.extend(
         format!("{} {}\r\n", FirstName().fake::<String>(), LastName().fake::<String>()).as_bytes()
     );
     payload.extend(format!("--{}\r\n", boundary).as_bytes());
     payload.extend(
-        format!("Content-Disposition: form-data; name=\"description\"\r\n\r\n").as_bytes()
+        b"Content-Disposition: form-data; name=\"description\"\r\n\r\n"
     );
     payload.extend(
         format!("{}\r\n", Sentence(Range { start: 8, end: 10 }).fake::<String>()).as_bytes()
     );
     payload.extend(format!("--{}\r\n", boundary).as_bytes());
-    payload.extend(format!("Content-Disposition: form-data; name=\"region\"\r\n\r\n").as_bytes());
-    payload.extend(format!("{}\r\n", CountryName().fake::<String>()).as_bytes());
+    payload.extend(b"Content-Disposition: form-data; name=\"region\"\r\n\r\n");
+    payload.extend(format!("{}\r\n", get_fake_region()).as_bytes());
     payload.extend(format!("--{}\r\n", boundary).as_bytes());
     
-    payload.extend(format!("Content-Disposition: form-data; name=\"main_url\"\r\n\r\n").as_bytes());    
+    payload.extend(b"Content-Disposition: form-data; name=\"main_url\"\r\n\r\n");    
     payload.extend(get_fake_main_url().as_bytes());
     payload.extend(b"\r\n"); // warning: line breaks are very important!!! 
     payload.extend(format!("--{}\r\n", boundary).as_bytes());
 
-    if with_avatar == true {
+    if with_avatar {
         payload.extend(
             b"Content-Disposition: form-data; name=\"avatar\"; filename=\"profile.jpeg\"\r\n"
         );
         payload.extend(b"Content-Type: image/jpeg\r\n\r\n");
-        payload.extend(Bytes::from(avatar.clone()));
+        payload.extend(Bytes::from(avatar.to_vec()));
         payload.extend(b"\r\n"); // warning: line breaks are very important!!!        
     }
     payload.extend(format!("--{}--\r\n", boundary).as_bytes()); // note the extra -- at the end of the boundary
@@ -162,6 +186,16 @@ pub fn get_profile_create_multipart(
     payload
 }
 
+/// a country name that passes validation, a few are longer than a region may be
+pub fn get_fake_region() -> String {
+    loop {
+        let region = CountryName().fake::<String>();
+        if region.chars().count() <= REGION_MAX_LENGTH {
+            return region;
+        }
+    }
+}
+
 pub fn get_fake_main_url() -> String {
     let mut domain = CompanyName().fake::<String>();
     domain.retain(|str| !str.is_whitespace());
@@ -1,5 +1,16 @@
+pub mod app;
 pub mod common {
     pub mod app_state;
+    pub mod auth;
+    pub mod config;
+    pub mod events;
+    pub mod metrics;
+    pub mod rate_limit;
+    pub mod security;
+    pub mod shutdown;
+    pub mod telemetry;
+    pub mod webhooks;
+    pub mod migration;
     pub mod entities {
         pub mod messages {
             pub mod model;
@@ -13,7 +24,24 @@ pub mod common {
             pub mod model;
             pub mod repo;
         }
+        pub mod timeline {
+            pub mod repo;
+        }
+        pub mod events {
+            pub mod repo;
+        }
+        pub mod notifications {
+            pub mod model;
+            pub mod repo;
+        }
+        pub mod webhooks {
+            pub mod model;
+            pub mod repo;
+        }
         pub mod base;
+        pub mod memory;
+        pub mod sqlite;
+        pub mod app_repo;
     }
     pub mod fs {
         pub mod file_utils;
@@ -23,6 +51,7 @@ pub mod common_tests {
     pub mod actix_fixture;
 }
 pub mod routes {
+    pub mod etag;
     pub mod output_id;
     pub mod messages {
         pub mod model;
@@ -35,46 +64,88 @@ pub mod routes {
     pub mod errors {
         pub mod error_utils;
     }
+    pub mod validation {
+        pub mod validator;
+    }
+    pub mod streams {
+        pub mod model;
+        pub mod stream_route;
+        pub mod public_stream_route;
+    }
+    pub mod notifications {
+        pub mod model;
+        pub mod notification_route;
+    }
+    pub mod webhooks {
+        pub mod model;
+        pub mod webhook_route;
+    }
+    pub mod health {
+        pub mod model;
+        pub mod health_route;
+    }
+    pub mod metrics {
+        pub mod metrics_route;
+    }
+    pub mod openapi {
+        pub mod openapi_route;
+    }
 }
 
-use std::env;
-use common::entities::{base::DbRepo};
-use dotenv::dotenv;
-use actix_web::{ web, App, HttpServer, Responder, middleware::Logger };
-use routes::messages::message_route::{get_message, get_messages};
-use routes::profiles::profile_route::{ create_profile, get_profile, get_profile_by_user };
+use actix_web::{ web, HttpServer, Responder };
+use app::{ create_app, SharedMiddleware };
+use common::app_state::AppState;
+use common::config::Config;
+use common::entities::app_repo::AppRepo;
+use common::shutdown::{ serve_until, wait_for_signal, BackgroundTasks };
+use common::telemetry::init_tracing;
+use common::webhooks::WebhookDispatcher;
 use std::error::Error;
-use crate::common::app_state::AppState;
-use crate::routes::messages::message_route::{ create_message };
+use std::time::Duration;
+use tracing::info;
 
-pub async fn run() -> std::io::Result<()> {
-    dotenv().ok();
-    let port = env::var("PORT").unwrap().parse().unwrap();
-    let host = env::var("HOST").unwrap();
-    let db_repo = DbRepo::init().await;
+pub async fn run(config: Config) -> std::io::Result<()> {
+    let host = config.server.host.clone();
+    let port = config.server.port;
+    let shutdown_timeout = config.server.shutdown_timeout_secs;
+    init_tracing(&config.log).map_err(|e| {
+        std::io::Error::other(format!("Unable to initialize logging: {}", e))
+    })?;
+    let db_repo = AppRepo::init(&config).await.map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::ConnectionRefused, format!("Unable to initialize database: {}", e))
+    })?;
+    info!("using the {:?} repository backend", config.repository.backend);
+    let shared_middleware = SharedMiddleware::new(&config);
     let app_data = web::Data::new(AppState {
                     client: reqwest::Client::new(),
+                    config,
                     db_repo,
                 });
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
-    let result = HttpServer::new(move || {
-        App::new()
-            .wrap(Logger::default())
-            .app_data(app_data.clone())
-            .route("/", web::get().to(get_root))
-            .service(
-                web::scope("/v1")
-                    .service(web::resource("/msg/{id}").route(web::get().to(get_message::<DbRepo>)))
-                    .service(web::resource("/msg").route(web::post().to(create_message::<DbRepo>)))
-                    .service(web::resource("/msgs").route(web::post().to(get_messages::<DbRepo>)))
-                    .service(web::resource("/profile/{id}").route(web::get().to(get_profile::<DbRepo>)))
-                    .service(web::resource("/profile/username/{user_name}").route(web::get().to(get_profile_by_user::<DbRepo>)))
-                    .service(web::resource("/profile").route(web::post().to(create_profile::<DbRepo>)))
-            )
-    })
+    let mut background_tasks = BackgroundTasks::new();
+    if app_data.config.rate_limit.enabled {
+        let purge_limiter = shared_middleware.rate_limiter.clone();
+        background_tasks.spawn("rate_limit_purge", |shutdown| purge_limiter.run_purge(shutdown));
+    }
+    if app_data.config.webhook.enabled {
+        let dispatcher = WebhookDispatcher::new(app_data.db_repo.clone(), app_data.config.webhook.clone());
+        background_tasks.spawn("webhook_dispatch", |shutdown| dispatcher.run(shutdown));
+    }
+    let in_flight = shared_middleware.in_flight.clone();
+
+    let server_app_data = app_data.clone();
+    let server = HttpServer::new(move || create_app(server_app_data.clone(), shared_middleware.clone()))
+    .shutdown_timeout(shutdown_timeout)
+    // signals are handled by serve_until so background tasks and the pool are shut down in order
+    .disable_signals()
     .bind((host, port))?
-    .run().await;
+    .run();
+
+    let result = serve_until(server, in_flight, Duration::from_secs(shutdown_timeout), wait_for_signal()).await;
+
+    background_tasks.shutdown(Duration::from_secs(shutdown_timeout)).await;
+    app_data.db_repo.close().await;
+    info!("shutdown complete");
 
     result
 }
@@ -1,6 +1,18 @@
-use twitter_clone_api::run;
+use twitter_clone_api::{ run, common::config::Config };
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    run().await
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = run(config).await {
+        eprintln!("Server stopped: {}", e);
+        std::process::exit(1);
+    }
+    Ok(())
 }
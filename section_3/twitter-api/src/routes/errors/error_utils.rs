@@ -1,8 +1,9 @@
 use actix_web::{ 
-    http::{ header::ContentType, StatusCode },
+    http::{ header::{ ContentType, RETRY_AFTER, WWW_AUTHENTICATE }, StatusCode },
     HttpResponse, 
     ResponseError 
 };
+use crate::common::rate_limit::{ RATE_LIMIT_LIMIT, RATE_LIMIT_REMAINING, RATE_LIMIT_RESET };
 use derive_more::{Display, Error};
 
 /// errors visible by the user
@@ -12,6 +13,16 @@ pub enum UserError {
     InternalError,
     #[display(fmt = "Validation error on field: {}", field)]
     ValidationError { field: String },
+    #[display(fmt = "Service unavailable. Please try again later.")]
+    ServiceUnavailable,
+    #[display(fmt = "Too many requests. Please retry in {} seconds.", retry_after_secs)]
+    TooManyRequests { limit: u32, retry_after_secs: u64 },
+    #[display(fmt = "Payload too large.")]
+    PayloadTooLarge,
+    #[display(fmt = "Not found.")]
+    NotFound,
+    #[display(fmt = "A valid bearer token is required.")]
+    Unauthorized,
 }
 
 impl UserError {
@@ -23,7 +34,6 @@ impl UserError {
             sqlx::Error::PoolTimedOut => UserError::InternalError,
             sqlx::Error::PoolClosed => UserError::InternalError,
             sqlx::Error::WorkerCrashed => UserError::InternalError,
-            #[cfg(feature = "migrate")]
             sqlx::Error::Migrate(_) => UserError::InternalError,
             _ => UserError::InternalError,
         }
@@ -32,21 +42,37 @@ impl UserError {
 
 impl ResponseError for UserError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .insert_header(ContentType::html())
-            .body(self.to_string())
+        let mut builder = HttpResponse::build(self.status_code());
+        builder.insert_header(ContentType::html());
+
+        if let UserError::TooManyRequests { limit, retry_after_secs } = self {
+            builder
+                .insert_header((RETRY_AFTER, retry_after_secs.to_string()))
+                .insert_header((RATE_LIMIT_LIMIT, limit.to_string()))
+                .insert_header((RATE_LIMIT_REMAINING, "0"))
+                .insert_header((RATE_LIMIT_RESET, retry_after_secs.to_string()));
+        }
+        if let UserError::Unauthorized = self {
+            builder.insert_header((WWW_AUTHENTICATE, "Bearer"));
+        }
+        builder.body(self.to_string())
     }
 
     fn status_code(&self) -> StatusCode {
         match *self {
             UserError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
             UserError::ValidationError { .. } => StatusCode::BAD_REQUEST,
+            UserError::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            UserError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            UserError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            UserError::NotFound => StatusCode::NOT_FOUND,
+            UserError::Unauthorized => StatusCode::UNAUTHORIZED,
         }
     }
 }
 
-impl Into<UserError> for sqlx::Error {
-    fn into(self) -> UserError {
-        UserError::convert_to_user_error(self)
+impl From<sqlx::Error> for UserError {
+    fn from(e: sqlx::Error) -> Self {
+        UserError::convert_to_user_error(e)
     }
 }
\ No newline at end of file
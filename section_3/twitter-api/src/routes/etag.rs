@@ -0,0 +1,117 @@
+use actix_web::{ HttpRequest, HttpResponse, HttpMessage, http::{ header::{ self, EntityTag, ETag, HeaderValue, IfNoneMatch }, Method } };
+use chrono::{ DateTime, Utc };
+
+/// FNV-1a, stable across builds and processes unlike std's hasher, so tags survive restarts and replicas
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// collects the fields a representation is derived from into a weak ETag. Compression sends the same
+/// representation as different bytes, which a strong tag would have to tell apart
+#[derive(Clone, Copy)]
+pub struct ETagBuilder(u64);
+
+impl Default for ETagBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ETagBuilder {
+    pub fn new() -> Self {
+        Self(FNV_OFFSET)
+    }
+
+    fn write(mut self, bytes: &[u8]) -> Self {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+        self
+    }
+
+    pub fn id(self, id: i64) -> Self {
+        self.write(&id.to_le_bytes())
+    }
+
+    pub fn updated_at(self, updated_at: DateTime<Utc>) -> Self {
+        self.write(&updated_at.timestamp_micros().to_le_bytes())
+    }
+
+    pub fn count(self, count: impl Into<i64>) -> Self {
+        self.write(&count.into().to_le_bytes())
+    }
+
+    pub fn build(self) -> EntityTag {
+        EntityTag::new_weak(format!("{:016x}", self.0))
+    }
+}
+
+/// true when the client already holds this representation, If-None-Match uses the weak comparison
+fn is_not_modified(req: &HttpRequest, etag: &EntityTag) -> bool {
+    match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        None => false,
+    }
+}
+
+/// for GET and HEAD, 304 when If-None-Match matches, otherwise the json body, both carrying the ETag.
+/// Other methods change or query state rather than fetch a cacheable representation, their body is returned as is
+pub fn respond_with_etag(req: &HttpRequest, etag: EntityTag, body: HttpResponse) -> HttpResponse {
+    if !body.status().is_success() || !matches!(*req.method(), Method::GET | Method::HEAD) {
+        return body;
+    }
+    if is_not_modified(req, &etag) {
+        return HttpResponse::NotModified().insert_header(ETag(etag)).finish();
+    }
+
+    let mut response = body;
+    if let Ok(value) = HeaderValue::from_str(&etag.to_string()) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{ http::StatusCode, test::TestRequest };
+
+    #[test]
+    fn test_etag_changes_with_any_field() {
+        let now = Utc::now();
+        let base = ETagBuilder::new().id(1).updated_at(now).count(3).build();
+
+        assert_eq!(base, ETagBuilder::new().id(1).updated_at(now).count(3).build());
+        assert_ne!(base, ETagBuilder::new().id(2).updated_at(now).count(3).build());
+        assert_ne!(base, ETagBuilder::new().id(1).updated_at(now).count(4).build());
+        assert!(base.weak);
+    }
+
+    #[test]
+    fn test_respond_with_etag_returns_not_modified_on_match() {
+        let etag = ETagBuilder::new().id(1).build();
+        let ok = || HttpResponse::Ok().body("{}");
+
+        let fresh = respond_with_etag(&TestRequest::get().to_http_request(), etag.clone(), ok());
+        assert_eq!(fresh.status(), StatusCode::OK);
+        assert_eq!(fresh.headers().get(header::ETAG).unwrap().to_str().unwrap(), etag.to_string());
+
+        let matching = TestRequest::get().insert_header((header::IF_NONE_MATCH, etag.to_string())).to_http_request();
+        assert_eq!(respond_with_etag(&matching, etag.clone(), ok()).status(), StatusCode::NOT_MODIFIED);
+
+        let stale = TestRequest::get().insert_header((header::IF_NONE_MATCH, "\"other\"")).to_http_request();
+        assert_eq!(respond_with_etag(&stale, etag, ok()).status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_respond_with_etag_leaves_other_methods_unconditional() {
+        let etag = ETagBuilder::new().id(1).build();
+
+        let post = TestRequest::post().insert_header((header::IF_NONE_MATCH, etag.to_string())).to_http_request();
+        let response = respond_with_etag(&post, etag, HttpResponse::Ok().body("{}"));
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::ETAG).is_none());
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{
+    common::{
+        app_state::AppState,
+        entities::base::QuerySchemaVersionFn,
+        migration::get_expected_version,
+    },
+    routes::errors::error_utils::UserError,
+};
+use actix_web::web;
+use tracing::error;
+use super::model::{ HealthResponder, VersionResponder };
+
+/// git commit the binary was built from, set by build.rs
+const GIT_COMMIT: &str = env!("GIT_COMMIT");
+
+/// the process is up and serving requests, says nothing about its dependencies
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses((status = 200, description = "Process is up", body = HealthResponder))
+)]
+pub async fn get_live() -> HealthResponder {
+    HealthResponder { status: "ok".to_string(), schema_version: None }
+}
+
+/// the pool can hand out a connection and the schema matches this build,
+/// otherwise 503 so the orchestrator keeps traffic away
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "Database reachable and schema current", body = HealthResponder),
+        (status = 503, description = "Database unreachable or schema mismatch"),
+    )
+)]
+pub async fn get_ready<T: QuerySchemaVersionFn>(
+    app_data: web::Data<AppState<T>>
+) -> Result<HealthResponder, UserError> {
+    match app_data.db_repo.query_schema_version().await {
+        Ok(schema_version) => Ok(HealthResponder {
+            status: "ready".to_string(),
+            schema_version: Some(schema_version),
+        }),
+        Err(e) => {
+            error!("readiness check failed: {}", e);
+            Err(UserError::ServiceUnavailable)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "health",
+    responses((status = 200, description = "Build and schema version", body = VersionResponder))
+)]
+pub async fn get_version() -> VersionResponder {
+    VersionResponder {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        commit: GIT_COMMIT.to_string(),
+        schema_version: get_expected_version(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common_tests::actix_fixture::get_app_data;
+    use super::*;
+    use async_trait::async_trait;
+
+    #[tokio::test]
+    async fn test_get_version_reports_expected_schema_version() {
+        let result = get_version().await;
+
+        assert!(result.version == env!("CARGO_PKG_VERSION"));
+        assert!(!result.commit.is_empty());
+        assert!(result.schema_version == get_expected_version());
+    }
+
+    mod test_mod_get_ready_failure_returns_service_unavailable {
+        use super::*;
+
+        #[derive(Clone)]
+        struct MockDbRepo;
+
+        #[async_trait]
+        impl QuerySchemaVersionFn for MockDbRepo {
+            async fn query_schema_version(&self) -> Result<i64, sqlx::Error> {
+                Err(sqlx::Error::PoolTimedOut)
+            }
+        }
+
+        #[tokio::test]
+        async fn test_get_ready_failure_returns_service_unavailable() {
+            let app_data = get_app_data(MockDbRepo).await;
+
+            let result = get_ready(app_data).await;
+
+            assert!(result.err().unwrap() == UserError::ServiceUnavailable);
+        }
+    }
+
+    mod test_mod_get_ready_and_check_schema_version {
+        use super::*;
+
+        const SCHEMA_VERSION: i64 = 3;
+
+        #[derive(Clone)]
+        struct MockDbRepo;
+
+        #[async_trait]
+        impl QuerySchemaVersionFn for MockDbRepo {
+            async fn query_schema_version(&self) -> Result<i64, sqlx::Error> {
+                Ok(SCHEMA_VERSION)
+            }
+        }
+
+        #[tokio::test]
+        async fn test_get_ready_and_check_schema_version() {
+            let app_data = get_app_data(MockDbRepo).await;
+
+            let result = get_ready(app_data).await.unwrap();
+
+            assert!(result.status == "ready");
+            assert!(result.schema_version == Some(SCHEMA_VERSION));
+        }
+    }
+}
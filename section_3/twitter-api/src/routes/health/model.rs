@@ -0,0 +1,64 @@
+use actix_http::body::BoxBody;
+use actix_web::http::header::ContentType;
+use actix_web::{ HttpRequest, HttpResponse, Responder };
+use serde::{ Serialize, Deserialize };
+use utoipa::ToSchema;
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthResponder {
+    pub status: String,
+    /// only reported by the readiness check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionResponder {
+    pub version: String,
+    pub commit: String,
+    pub schema_version: i64,
+}
+
+impl Responder for HealthResponder {
+    type Body = BoxBody;
+
+    fn respond_to(self, _: &HttpRequest) -> HttpResponse<Self::Body> {
+        let body_result = serde_json::to_string(&self);
+
+        match body_result {
+            Ok(body) => {
+                HttpResponse::Ok()
+                .content_type(ContentType::json())
+                .body(body)
+            },
+            Err(_) => {
+                HttpResponse::InternalServerError()
+                    .content_type(ContentType::json())
+                    .body("Failed to serialize HealthResponder.")
+            },
+        }
+    }
+}
+
+impl Responder for VersionResponder {
+    type Body = BoxBody;
+
+    fn respond_to(self, _: &HttpRequest) -> HttpResponse<Self::Body> {
+        let body_result = serde_json::to_string(&self);
+
+        match body_result {
+            Ok(body) => {
+                HttpResponse::Ok()
+                .content_type(ContentType::json())
+                .body(body)
+            },
+            Err(_) => {
+                HttpResponse::InternalServerError()
+                    .content_type(ContentType::json())
+                    .body("Failed to serialize VersionResponder.")
+            },
+        }
+    }
+}
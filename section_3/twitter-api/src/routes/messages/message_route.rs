@@ -1,24 +1,33 @@
-use crate::common::entities::messages::model::{MessageWithFollowingAndBroadcastQueryResult};
+use std::collections::HashMap;
+use crate::common::entities::messages::model::{MessageCountsQueryResult, MessageWithFollowingAndBroadcastQueryResult};
 use crate::common::app_state::AppState;
-use crate::common::entities::messages::repo::{InsertMessageFn, QueryMessageFn, QueryMessagesFn};
+use crate::common::entities::messages::repo::{InsertMessageFn, LikeMessageFn, QueryMessageCountsFn, QueryMessageFn, QueryMessagesFn};
 use crate::routes::errors::error_utils::UserError;
 use crate::routes::output_id::OutputId;
 use crate::routes::profiles::model::ProfileShort;
-use actix_web::{web, web::{Path, Json}};
-use super::model::{MessageResponder, MessagePostJson, MessageQuery, MessageByFollowingQuery, MessageResponders};
+use crate::routes::validation::validator::Validate;
+use actix_web::{web, web::{Path, Json}, HttpResponse};
+use tracing::instrument;
+use super::model::{MessageResponder, MessagePostJson, MessageLikeJson, MessageQuery, MessageByFollowingQuery, MessageResponders};
 
 
 #[allow(unused)]
+#[utoipa::path(
+    post,
+    path = "/v1/msg",
+    tag = "messages",
+    request_body = MessagePostJson,
+    responses(
+        (status = 200, description = "Id of the new message", body = OutputId),
+        (status = 400, description = "Body is empty or too long"),
+    )
+)]
+#[instrument(skip_all)]
 pub async fn create_message<T: InsertMessageFn>(app_data: web::Data<AppState<T>>, params: Json<MessagePostJson>) -> Result<OutputId, UserError> {  
-    let max = 141; 
-    let body = if params.body.len() < max {
-        &params.body[..]
-    } else {
-        &params.body[..max]
-    };
+    params.validate()?;
 
     let group_type = params.group_type.clone() as i32;
-    let result = app_data.db_repo.insert_message(params.user_id, body, group_type, params.broadcasting_msg_id).await;
+    let result = app_data.db_repo.insert_message(params.user_id, &params.body, group_type, params.broadcasting_msg_id).await;
     match result {
         Ok(id) => Ok(OutputId { id }),
         Err(e) => Err(e.into())
@@ -26,14 +35,27 @@ pub async fn create_message<T: InsertMessageFn>(app_data: web::Data<AppState<T>>
 }
 
 #[allow(unused)]
-pub async fn get_message<T: QueryMessageFn>(app_data: web::Data<AppState<T>>, path: Path<MessageQuery>) -> Result<Option<MessageResponder>, UserError> {
+#[utoipa::path(
+    get,
+    path = "/v1/msg/{id}",
+    tag = "messages",
+    params(("id" = i64, Path, description = "Message id")),
+    responses(
+        (status = 200, description = "Message with its broadcast, carries an ETag", body = MessageResponder),
+        (status = 304, description = "Unchanged since the ETag sent in If-None-Match"),
+        (status = 404, description = "No message with this id"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn get_message<T: QueryMessageFn + QueryMessageCountsFn>(app_data: web::Data<AppState<T>>, path: Path<MessageQuery>) -> Result<Option<MessageResponder>, UserError> {
     let message_result = app_data.db_repo.query_message(path.id).await;
 
     match message_result {
         Ok(message) => {
             match message {
                 Some(msg) => {
-                    Ok(Some(convert(&msg)))
+                    let counts = get_message_counts(&app_data.db_repo, std::slice::from_ref(&msg)).await?;
+                    Ok(Some(convert(&msg, &counts)))
                 },
                 None => Ok(None)
             }
@@ -43,25 +65,53 @@ pub async fn get_message<T: QueryMessageFn>(app_data: web::Data<AppState<T>>, pa
 }
 
 #[allow(unused)]
-pub async fn get_messages<T: QueryMessagesFn>(app_data: web::Data<AppState<T>>, path: Json<MessageByFollowingQuery>) -> Result<MessageResponders, UserError>  {
-    println!("start get_messages");
-    let page_size = match path.page_size {
-        Some(ps) => ps,
-        None => 10
-    };
+#[utoipa::path(
+    post,
+    path = "/v1/msg/{id}/like",
+    tag = "messages",
+    params(("id" = i64, Path, description = "Message id")),
+    request_body = MessageLikeJson,
+    responses(
+        (status = 204, description = "Liked, liking a message again changes nothing"),
+        (status = 404, description = "No message with this id"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn like_message<T: QueryMessageFn + LikeMessageFn>(app_data: web::Data<AppState<T>>, path: Path<MessageQuery>, params: Json<MessageLikeJson>) -> Result<HttpResponse, UserError> {
+    if app_data.db_repo.query_message(path.id).await?.is_none() {
+        return Err(UserError::NotFound);
+    }
+
+    app_data.db_repo.like_message(params.user_id, path.id).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[allow(unused)]
+#[utoipa::path(
+    post,
+    path = "/v1/msgs",
+    tag = "messages",
+    request_body = MessageByFollowingQuery,
+    responses(
+        (status = 200, description = "Timeline page of followed profiles", body = MessageResponders),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn get_messages<T: QueryMessagesFn + QueryMessageCountsFn>(app_data: web::Data<AppState<T>>, path: Json<MessageByFollowingQuery>) -> Result<MessageResponders, UserError>  {
+    let page_size = path.page_size.unwrap_or(10);
     
     let mut messages_result = app_data.db_repo.query_messages(
         path.follower_id, path.last_updated_at, page_size
     ).await;
-    println!("end get_messages");
     
     let mut msg_collection: Vec<MessageResponder> = vec![];
     match messages_result {
         Ok(messages) => {
+            let counts = get_message_counts(&app_data.db_repo, &messages).await?;
             messages
                 .iter()
                 .for_each(|msg| {
-                    msg_collection.push(convert(msg))
+                    msg_collection.push(convert(msg, &counts))
                 });
 
             Ok(MessageResponders(msg_collection))
@@ -70,31 +120,53 @@ pub async fn get_messages<T: QueryMessagesFn>(app_data: web::Data<AppState<T>>,
     }
 }
 
-fn convert(message: &MessageWithFollowingAndBroadcastQueryResult) -> MessageResponder {
+/// counts of every message and broadcast message on a page, queried once for the whole page
+pub(crate) async fn get_message_counts<T: QueryMessageCountsFn>(
+    db_repo: &T,
+    messages: &[MessageWithFollowingAndBroadcastQueryResult]
+) -> Result<HashMap<i64, MessageCountsQueryResult>, UserError> {
+    let ids = messages
+        .iter()
+        .flat_map(|msg| std::iter::once(msg.id).chain(msg.broadcast_msg_id))
+        .collect::<Vec<i64>>();
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let counts = db_repo.query_message_counts(&ids).await?;
+    Ok(counts.into_iter().map(|count| (count.id, count)).collect())
+}
+
+pub(crate) fn convert(message: &MessageWithFollowingAndBroadcastQueryResult, counts: &HashMap<i64, MessageCountsQueryResult>) -> MessageResponder {
+    let msg_counts = counts.get(&message.id).cloned().unwrap_or_default();
     MessageResponder {
         id: message.id,
         updated_at: message.updated_at,
         body: message.body.clone(),
         likes: message.likes,
-        broadcasting_msg: match message.broadcast_msg_id {
-            Some(id) => {
-                Some(Box::new(MessageResponder { 
-                    id,
-                    updated_at: message.broadcast_msg_updated_at.unwrap(),
-                    body: message.broadcast_msg_body.clone(),
-                    likes: message.broadcast_msg_likes.unwrap(),
-                    broadcasting_msg: None ,
-                    profile: ProfileShort {
-                        id: message.broadcast_msg_user_id.unwrap(),
-                        user_name: message.broadcast_msg_user_name.clone().unwrap(),
-                        full_name: message.broadcast_msg_full_name.clone().unwrap()
-                    }
-                }))
-            },
-            None => None
-        },
+        reply_count: msg_counts.reply_count,
+        rebroadcast_count: msg_counts.rebroadcast_count,
+        quote_count: msg_counts.quote_count,
+        broadcasting_msg: message.broadcast_msg_id.map(|id| {
+            let broadcast_counts = counts.get(&id).cloned().unwrap_or_default();
+            Box::new(MessageResponder { 
+                id,
+                updated_at: message.broadcast_msg_updated_at.unwrap(),
+                body: message.broadcast_msg_body.clone(),
+                likes: message.broadcast_msg_likes.unwrap(),
+                reply_count: broadcast_counts.reply_count,
+                rebroadcast_count: broadcast_counts.rebroadcast_count,
+                quote_count: broadcast_counts.quote_count,
+                broadcasting_msg: None ,
+                profile: ProfileShort {
+                    id: message.broadcast_msg_user_id.unwrap(),
+                    user_name: message.broadcast_msg_user_name.clone().unwrap(),
+                    full_name: message.broadcast_msg_full_name.clone().unwrap()
+                }
+            })
+        }),
         profile: ProfileShort {
-            id: message.id,
+            id: message.user_id,
             user_name: message.user_name.clone(),
             full_name: message.full_name.clone()
         }
@@ -138,9 +210,66 @@ mod tests {
                 MessagePostJson{ user_id: 0, body: get_fake_message_body(None), group_type: crate::routes::messages::model::MessageGroupTypes::Circle, broadcasting_msg_id: None }
             )).await;
 
-            assert!(!result.is_err());
+            assert!(result.is_ok());
+            assert!(result.ok().unwrap().id == ID);
+        }
+    }
+
+    mod test_mod_create_message_validates_body {
+        use crate::routes::{errors::error_utils::UserError, messages::model::{MessageGroupTypes, MESSAGE_BODY_MAX_LENGTH}};
+        use super::*;
+
+        const ID: i64 = 22;
+        struct TestRepo;
+
+        #[allow(unused)]
+        #[async_trait]
+        impl InsertMessageFn for TestRepo {
+            async fn insert_message(
+                &self,
+                user_id: i64,
+                body: &str,
+                group_type: i32,
+                broadcasting_msg_id: Option<i64>
+            ) -> Result<i64, sqlx::Error> {
+                Ok(ID)
+            }
+        }
+
+        #[tokio::test]
+        async fn test_create_message_rejects_over_length_body() {
+            let app_data = get_app_data(TestRepo).await;
+
+            let result = create_message(app_data, Json(
+                MessagePostJson{ user_id: 0, body: "a".repeat(MESSAGE_BODY_MAX_LENGTH + 1), group_type: MessageGroupTypes::Public, broadcasting_msg_id: None }
+            )).await;
+
+            assert!(result.err().unwrap() == UserError::ValidationError { field: "body".to_string() });
+        }
+
+        #[tokio::test]
+        async fn test_create_message_accepts_multi_byte_body_at_max_length() {
+            let app_data = get_app_data(TestRepo).await;
+
+            let result = create_message(app_data, Json(
+                MessagePostJson{ user_id: 0, body: "é".repeat(MESSAGE_BODY_MAX_LENGTH), group_type: MessageGroupTypes::Public, broadcasting_msg_id: None }
+            )).await;
+
             assert!(result.ok().unwrap().id == ID);
         }
+
+        #[tokio::test]
+        async fn test_create_message_accepts_empty_body_only_for_rebroadcast() {
+            let rebroadcast = create_message(get_app_data(TestRepo).await, Json(
+                MessagePostJson{ user_id: 0, body: "".to_string(), group_type: MessageGroupTypes::Public, broadcasting_msg_id: Some(1) }
+            )).await;
+            let empty_message = create_message(get_app_data(TestRepo).await, Json(
+                MessagePostJson{ user_id: 0, body: "".to_string(), group_type: MessageGroupTypes::Public, broadcasting_msg_id: None }
+            )).await;
+
+            assert!(rebroadcast.ok().unwrap().id == ID);
+            assert!(empty_message.err().unwrap() == UserError::ValidationError { field: "body".to_string() });
+        }
     }
 
     mod test_mod_create_message_failure_returns_correct_error {      
@@ -179,7 +308,7 @@ mod tests {
 
     mod test_mod_get_message_failure_returns_correct_error {      
         use actix_web::web::Path;
-        use crate::{routes::{errors::error_utils::UserError, messages::{message_route::get_message, model::MessageQuery}}, common::entities::messages::{repo::QueryMessageFn, model::MessageWithFollowingAndBroadcastQueryResult}};
+        use crate::{routes::{errors::error_utils::UserError, messages::{message_route::get_message, model::MessageQuery}}, common::entities::messages::{repo::{QueryMessageFn, QueryMessageCountsFn}, model::{MessageCountsQueryResult, MessageWithFollowingAndBroadcastQueryResult}}};
         use super::*;
 
         struct TestRepo;
@@ -192,6 +321,14 @@ mod tests {
             }
         }
 
+        #[allow(unused)]
+        #[async_trait]
+        impl QueryMessageCountsFn for TestRepo {
+            async fn query_message_counts(&self, ids: &[i64]) -> Result<Vec<MessageCountsQueryResult>, sqlx::Error> {
+                Ok(vec![])
+            }
+        }
+
         #[tokio::test]
         async fn test_get_message_failure_returns_correct_error () {
             let repo = TestRepo;
@@ -211,11 +348,12 @@ mod tests {
         use fake::Fake;
         use crate::{
             routes::{messages::{message_route::get_message, model::{MessageQuery, MessageGroupTypes}}}, 
-            common::entities::messages::{repo::QueryMessageFn, model::MessageWithFollowingAndBroadcastQueryResult}
+            common::entities::messages::{repo::{QueryMessageFn, QueryMessageCountsFn}, model::{MessageCountsQueryResult, MessageWithFollowingAndBroadcastQueryResult}}
         };
         use super::*;
 
         const ID: i64 = 22;
+        const USER_ID: i64 = 7;
         struct TestRepo;
         
         #[allow(unused)]
@@ -230,7 +368,7 @@ mod tests {
                         likes: 1,
                         image: None,
                         msg_group_type: MessageGroupTypes::Public as i32,
-                        user_id: 0,
+                        user_id: USER_ID,
                         user_name: Username().fake(),
                         full_name: format!("{} {}", FirstName().fake::<String>(), LastName().fake::<String>()),
                         avatar: None,
@@ -248,6 +386,14 @@ mod tests {
             }
         }
 
+        #[allow(unused)]
+        #[async_trait]
+        impl QueryMessageCountsFn for TestRepo {
+            async fn query_message_counts(&self, ids: &[i64]) -> Result<Vec<MessageCountsQueryResult>, sqlx::Error> {
+                Ok(vec![MessageCountsQueryResult { id: ID, reply_count: 2, rebroadcast_count: 1, quote_count: 3 }])
+            }
+        }
+
         #[tokio::test]
         async fn test_get_message_and_check_id() {
             let repo = TestRepo;
@@ -255,8 +401,11 @@ mod tests {
 
             let result = get_message(app_data, Path::from(MessageQuery{ id: 0 })).await;
 
-            assert!(!result.is_err());
-            assert!(result.ok().unwrap().unwrap().id == ID);
+            assert!(result.is_ok());
+            let message = result.ok().unwrap().unwrap();
+            assert!(message.id == ID);
+            assert!(message.profile.id == USER_ID);
+            assert!(message.reply_count == 2 && message.rebroadcast_count == 1 && message.quote_count == 3);
         }
     }
 
@@ -264,7 +413,7 @@ mod tests {
         use chrono::{DateTime, Utc};
         use crate::{
             routes::{errors::error_utils::UserError, messages::{message_route::get_messages, model::MessageByFollowingQuery}}, 
-            common::entities::messages::{repo::QueryMessagesFn, model::MessageWithFollowingAndBroadcastQueryResult}
+            common::entities::messages::{repo::{QueryMessagesFn, QueryMessageCountsFn}, model::{MessageCountsQueryResult, MessageWithFollowingAndBroadcastQueryResult}}
         };
         use super::*;
 
@@ -283,6 +432,14 @@ mod tests {
             }
         }
 
+        #[allow(unused)]
+        #[async_trait]
+        impl QueryMessageCountsFn for TestRepo {
+            async fn query_message_counts(&self, ids: &[i64]) -> Result<Vec<MessageCountsQueryResult>, sqlx::Error> {
+                Ok(vec![])
+            }
+        }
+
         #[tokio::test]
         async fn test_get_messages_failure_returns_correct_error () {
             let repo = TestRepo;
@@ -301,7 +458,7 @@ mod tests {
         use fake::Fake;
         use crate::{
             routes::{messages::{message_route::get_messages, model::{MessageGroupTypes, MessageByFollowingQuery}}}, 
-            common::entities::messages::{repo::QueryMessagesFn, model::MessageWithFollowingAndBroadcastQueryResult}
+            common::entities::messages::{repo::{QueryMessagesFn, QueryMessageCountsFn}, model::{MessageCountsQueryResult, MessageWithFollowingAndBroadcastQueryResult}}
         };
         use super::*;
 
@@ -342,6 +499,14 @@ mod tests {
             }
         }
 
+        #[allow(unused)]
+        #[async_trait]
+        impl QueryMessageCountsFn for TestRepo {
+            async fn query_message_counts(&self, ids: &[i64]) -> Result<Vec<MessageCountsQueryResult>, sqlx::Error> {
+                Ok(vec![])
+            }
+        }
+
         #[tokio::test]
         async fn test_get_messages_and_check_id() {
             let repo = TestRepo;
@@ -349,8 +514,11 @@ mod tests {
 
             let result = get_messages(app_data, Json(MessageByFollowingQuery { follower_id: 0, last_updated_at: Utc::now(), page_size: None })).await;
 
-            assert!(!result.is_err());
-            assert!(result.ok().unwrap().0[0].id == ID);
+            assert!(result.is_ok());
+            let messages = result.ok().unwrap();
+            assert!(messages.0[0].id == ID);
+            // messages without engagement get zero counts
+            assert!(messages.0[0].reply_count == 0 && messages.0[0].quote_count == 0);
         }
     }
 }
\ No newline at end of file
@@ -2,16 +2,21 @@ use actix_http::body::BoxBody;
 use actix_web::{Responder, HttpResponse, HttpRequest, http::header::ContentType};
 use serde::{Deserialize, Serialize};
 use serde_repr::*;
+use utoipa::ToSchema;
 use chrono::prelude::*;
+use crate::routes::etag::{ respond_with_etag, ETagBuilder };
 use crate::routes::profiles::model::ProfileShort;
+use crate::routes::validation::validator::{ Validate, FieldValidation, Constraint };
 use std::vec::Vec;
 
+pub const MESSAGE_BODY_MAX_LENGTH: usize = 140;
+
 #[derive(Deserialize)]
 pub struct MessageQuery {
     pub id: i64
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct MessageByFollowingQuery {
     pub follower_id: i64,
@@ -19,7 +24,7 @@ pub struct MessageByFollowingQuery {
     pub page_size: Option<i16>
 }
 
-#[derive(Deserialize, Serialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct MessagePostJson {
     pub user_id: i64,
@@ -28,28 +33,68 @@ pub struct MessagePostJson {
     pub broadcasting_msg_id: Option<i64>
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+impl Validate for MessagePostJson {
+    fn validations(&self) -> Vec<FieldValidation<'_>> {
+        // a broadcast without a body is a plain rebroadcast, with one it quotes the message
+        let body_constraints: &'static [Constraint] = match self.broadcasting_msg_id {
+            Some(_) => &[Constraint::MaxLength(MESSAGE_BODY_MAX_LENGTH)],
+            None => &[Constraint::Required, Constraint::MaxLength(MESSAGE_BODY_MAX_LENGTH)],
+        };
+        vec![
+            FieldValidation::new("body", Some(&self.body), body_constraints),
+        ]
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageLikeJson {
+    pub user_id: i64
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct MessageResponder {
     pub id: i64,
     pub updated_at: DateTime<Utc>,
     pub body: Option<String>,
     pub likes: i32,
+    pub reply_count: i64,
+    pub rebroadcast_count: i64,
+    pub quote_count: i64,
     pub broadcasting_msg: Option<Box<MessageResponder>>,
     pub profile: ProfileShort
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct MessageResponders(pub Vec<MessageResponder>);
 
+impl MessageResponder {
+    /// likes and the engagement counts change without touching updated_at so they are part of the tag
+    fn add_to_etag(&self, etag: ETagBuilder) -> ETagBuilder {
+        let etag = etag
+            .id(self.id)
+            .updated_at(self.updated_at)
+            .count(self.likes)
+            .count(self.reply_count)
+            .count(self.rebroadcast_count)
+            .count(self.quote_count);
+        match &self.broadcasting_msg {
+            Some(broadcast) => broadcast.add_to_etag(etag),
+            None => etag,
+        }
+    }
+}
+
 impl Responder for MessageResponder {
     type Body = BoxBody;
 
-    fn respond_to(self, _: &HttpRequest) -> HttpResponse<Self::Body> {
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let etag = self.add_to_etag(ETagBuilder::new()).build();
         let body_result = serde_json::to_string(&self);
 
-        match body_result {
+        let response = match body_result {
             Ok(body) => {
                 HttpResponse::Ok()
                 .content_type(ContentType::json())
@@ -60,17 +105,20 @@ impl Responder for MessageResponder {
                     .content_type(ContentType::json())
                     .body("Failed to serialize MessageResponder.")
             },
-        }
+        };
+        respond_with_etag(req, etag, response)
     }
 }
 
 impl Responder for MessageResponders {
     type Body = BoxBody;
 
-    fn respond_to(self, _: &HttpRequest) -> HttpResponse<Self::Body> {
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        // a page is unchanged only if every message on it is, and in the same order
+        let etag = self.0.iter().fold(ETagBuilder::new(), |etag, msg| msg.add_to_etag(etag)).build();
         let body_result = serde_json::to_string(&self);
 
-        match body_result {
+        let response = match body_result {
             Ok(body) => {
                 HttpResponse::Ok()
                 .content_type(ContentType::json())
@@ -81,11 +129,12 @@ impl Responder for MessageResponders {
                     .content_type(ContentType::json())
                     .body("Failed to serialize MessageResponders.")
             },
-        }
+        };
+        respond_with_etag(req, etag, response)
     }
 }
 
-#[derive(Deserialize_repr, Serialize_repr, Clone)]
+#[derive(Deserialize_repr, Serialize_repr, Clone, ToSchema)]
 #[repr(i32)]
 pub enum MessageGroupTypes {
     Public = 1,
@@ -0,0 +1,35 @@
+use crate::{
+    common::{
+        app_state::AppState,
+        entities::base::PoolStatsFn,
+        metrics::{ encode_metrics, record_pool_stats },
+    },
+    routes::errors::error_utils::UserError,
+};
+use actix_web::{ web, HttpResponse };
+use tracing::error;
+
+/// prometheus scrape endpoint, pool gauges are sampled at scrape time
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "metrics",
+    responses((status = 200, description = "Prometheus text exposition format", content_type = "text/plain"))
+)]
+pub async fn get_metrics<T: PoolStatsFn>(
+    app_data: web::Data<AppState<T>>
+) -> Result<HttpResponse, UserError> {
+    record_pool_stats(app_data.db_repo.pool_stats());
+
+    match encode_metrics() {
+        Ok(body) => Ok(
+            HttpResponse::Ok()
+                .content_type("text/plain; version=0.0.4")
+                .body(body)
+        ),
+        Err(e) => {
+            error!("encode metrics error: {}", e);
+            Err(UserError::InternalError)
+        }
+    }
+}
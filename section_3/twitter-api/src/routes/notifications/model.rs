@@ -0,0 +1,122 @@
+use actix_http::body::BoxBody;
+use actix_web::{ Responder, HttpResponse, HttpRequest, http::header::ContentType };
+use chrono::{ DateTime, Utc };
+use serde::{ Deserialize, Serialize };
+use utoipa::ToSchema;
+use crate::common::entities::notifications::model::{ NotificationGroupQueryResult, NotificationKind };
+use crate::routes::profiles::model::ProfileShort;
+
+pub const NOTIFICATIONS_PAGE_SIZE: i16 = 20;
+pub const NOTIFICATIONS_MAX_PAGE_SIZE: i16 = 100;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationQuery {
+    /// the id of the last group of the previous page
+    pub before_id: Option<i64>,
+    pub page_size: Option<i16>,
+}
+
+impl NotificationQuery {
+    pub fn get_page_size(&self) -> i16 {
+        self.page_size.unwrap_or(NOTIFICATIONS_PAGE_SIZE).clamp(1, NOTIFICATIONS_MAX_PAGE_SIZE)
+    }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationsReadJson {
+    /// marks the notifications up to this group id, so ones arriving after the page was read stay unread
+    pub up_to_id: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationGroupReadJson {
+    pub kind: NotificationKind,
+    /// the group's target, none for follows
+    pub target_msg_id: Option<i64>,
+}
+
+/// every notification of one kind about one target, shown as its latest actor "and n others"
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationGroupResponder {
+    /// the latest notification in the group, pass it as beforeId for the next page
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub kind: NotificationKind,
+    /// the recipient's message that was replied to, broadcast or liked, or the message mentioning them
+    pub target_msg_id: Option<i64>,
+    /// the reply, broadcast or mention of the latest notification
+    pub source_msg_id: Option<i64>,
+    pub actor_count: i64,
+    pub unread_count: i64,
+    pub latest_actor: ProfileShort,
+}
+
+impl From<NotificationGroupQueryResult> for NotificationGroupResponder {
+    fn from(group: NotificationGroupQueryResult) -> Self {
+        Self {
+            id: group.latest_id,
+            created_at: group.latest_created_at,
+            kind: group.kind,
+            target_msg_id: group.target_msg_id,
+            source_msg_id: group.source_msg_id,
+            actor_count: group.actor_count,
+            unread_count: group.unread_count,
+            latest_actor: ProfileShort { id: group.actor_id, user_name: group.user_name, full_name: group.full_name },
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct NotificationGroupResponders(pub Vec<NotificationGroupResponder>);
+
+impl Responder for NotificationGroupResponders {
+    type Body = BoxBody;
+
+    fn respond_to(self, _: &HttpRequest) -> HttpResponse<Self::Body> {
+        let body_result = serde_json::to_string(&self);
+
+        match body_result {
+            Ok(body) => {
+                HttpResponse::Ok()
+                .content_type(ContentType::json())
+                .body(body)
+            },
+            Err(_) => {
+                HttpResponse::InternalServerError()
+                    .content_type(ContentType::json())
+                    .body("Failed to serialize NotificationGroupResponders.")
+            },
+        }
+    }
+}
+
+/// the unread notifications, or the ones a mark-as-read request marked
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct NotificationCountResponder {
+    pub count: i64,
+}
+
+impl Responder for NotificationCountResponder {
+    type Body = BoxBody;
+
+    fn respond_to(self, _: &HttpRequest) -> HttpResponse<Self::Body> {
+        let body_result = serde_json::to_string(&self);
+
+        match body_result {
+            Ok(body) => {
+                HttpResponse::Ok()
+                .content_type(ContentType::json())
+                .body(body)
+            },
+            Err(_) => {
+                HttpResponse::InternalServerError()
+                    .content_type(ContentType::json())
+                    .body("Failed to serialize NotificationCountResponder.")
+            },
+        }
+    }
+}
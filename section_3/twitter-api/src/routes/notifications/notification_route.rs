@@ -0,0 +1,109 @@
+use crate::common::{
+    app_state::AppState,
+    auth::AuthenticatedProfile,
+    entities::notifications::repo::{
+        MarkNotificationGroupReadFn, MarkNotificationsReadFn, QueryNotificationGroupsFn, QueryUnreadNotificationCountFn,
+    },
+};
+use crate::routes::errors::error_utils::UserError;
+use actix_web::{ web, web::{ Json, Query } };
+use tracing::instrument;
+use super::model::{
+    NotificationCountResponder,
+    NotificationGroupReadJson,
+    NotificationGroupResponder,
+    NotificationGroupResponders,
+    NotificationQuery,
+    NotificationsReadJson,
+};
+
+#[allow(unused)]
+#[utoipa::path(
+    get,
+    path = "/v1/notifications",
+    tag = "notifications",
+    params(
+        ("beforeId" = Option<i64>, Query, description = "Id of the last group of the previous page"),
+        ("pageSize" = Option<i16>, Query, description = "Groups per page, 20 by default and at most 100"),
+    ),
+    responses(
+        (status = 200, description = "Notifications of the authenticated profile grouped by kind and target, the most recently active first", body = NotificationGroupResponders),
+        (status = 401, description = "No valid bearer token"),
+    ),
+    security(("bearer" = []))
+)]
+#[instrument(skip_all)]
+pub async fn get_notifications<T: QueryNotificationGroupsFn>(
+    app_data: web::Data<AppState<T>>,
+    profile: AuthenticatedProfile,
+    query: Query<NotificationQuery>
+) -> Result<NotificationGroupResponders, UserError> {
+    let groups = app_data.db_repo
+        .query_notification_groups(profile.profile_id, query.before_id, query.get_page_size()).await?;
+    Ok(NotificationGroupResponders(groups.into_iter().map(NotificationGroupResponder::from).collect()))
+}
+
+#[allow(unused)]
+#[utoipa::path(
+    get,
+    path = "/v1/notifications/unread",
+    tag = "notifications",
+    responses(
+        (status = 200, description = "Number of unread notifications of the authenticated profile", body = NotificationCountResponder),
+        (status = 401, description = "No valid bearer token"),
+    ),
+    security(("bearer" = []))
+)]
+#[instrument(skip_all)]
+pub async fn get_unread_notification_count<T: QueryUnreadNotificationCountFn>(
+    app_data: web::Data<AppState<T>>,
+    profile: AuthenticatedProfile
+) -> Result<NotificationCountResponder, UserError> {
+    let count = app_data.db_repo.query_unread_notification_count(profile.profile_id).await?;
+    Ok(NotificationCountResponder { count })
+}
+
+#[allow(unused)]
+#[utoipa::path(
+    post,
+    path = "/v1/notifications/read",
+    tag = "notifications",
+    request_body = NotificationsReadJson,
+    responses(
+        (status = 200, description = "Number of notifications marked as read", body = NotificationCountResponder),
+        (status = 401, description = "No valid bearer token"),
+    ),
+    security(("bearer" = []))
+)]
+#[instrument(skip_all)]
+pub async fn mark_notifications_read<T: MarkNotificationsReadFn>(
+    app_data: web::Data<AppState<T>>,
+    profile: AuthenticatedProfile,
+    params: Json<NotificationsReadJson>
+) -> Result<NotificationCountResponder, UserError> {
+    let marked = app_data.db_repo.mark_notifications_read(profile.profile_id, params.up_to_id).await?;
+    Ok(NotificationCountResponder { count: marked as i64 })
+}
+
+#[allow(unused)]
+#[utoipa::path(
+    post,
+    path = "/v1/notifications/read/group",
+    tag = "notifications",
+    request_body = NotificationGroupReadJson,
+    responses(
+        (status = 200, description = "Number of notifications of the group marked as read", body = NotificationCountResponder),
+        (status = 401, description = "No valid bearer token"),
+    ),
+    security(("bearer" = []))
+)]
+#[instrument(skip_all)]
+pub async fn mark_notification_group_read<T: MarkNotificationGroupReadFn>(
+    app_data: web::Data<AppState<T>>,
+    profile: AuthenticatedProfile,
+    params: Json<NotificationGroupReadJson>
+) -> Result<NotificationCountResponder, UserError> {
+    let marked = app_data.db_repo
+        .mark_notification_group_read(profile.profile_id, params.kind, params.target_msg_id).await?;
+    Ok(NotificationCountResponder { count: marked as i64 })
+}
@@ -0,0 +1,148 @@
+use actix_web::HttpResponse;
+use utoipa::{ openapi::security::{ HttpAuthScheme, HttpBuilder, SecurityScheme }, Modify, OpenApi };
+use utoipa_swagger_ui::{ Config, SwaggerUi };
+use crate::common::entities::{
+    notifications::model::NotificationKind,
+    webhooks::model::{ WebhookDeliveryStatus, WebhookEventType },
+};
+use crate::routes::{
+    health::{ health_route, model::{ HealthResponder, VersionResponder } },
+    messages::{
+        message_route,
+        model::{ MessageByFollowingQuery, MessageGroupTypes, MessageLikeJson, MessagePostJson, MessageResponder, MessageResponders },
+    },
+    metrics::metrics_route,
+    notifications::{
+        notification_route,
+        model::{
+            NotificationCountResponder, NotificationGroupReadJson, NotificationGroupResponder,
+            NotificationGroupResponders, NotificationsReadJson,
+        },
+    },
+    output_id::OutputId,
+    profiles::{ profile_route, model::{ ProfileCreateMultipart, ProfileResponder, ProfileShort } },
+    streams::{ public_stream_route, stream_route },
+    webhooks::{
+        webhook_route,
+        model::{
+            WebhookDeliveryResponder, WebhookDeliveryResponders, WebhookSubscriptionJson,
+            WebhookSubscriptionResponder, WebhookSubscriptionResponders,
+        },
+    },
+};
+
+pub const OPENAPI_PATH: &str = "/v1/openapi.json";
+pub const DOCS_PATH: &str = "/v1/docs";
+
+/// generated from the `#[utoipa::path]` attributes on the handlers and the serde models they use
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Twitter Clone API"),
+    paths(
+        health_route::get_live,
+        health_route::get_ready,
+        health_route::get_version,
+        metrics_route::get_metrics,
+        message_route::get_message,
+        message_route::create_message,
+        message_route::get_messages,
+        message_route::like_message,
+        profile_route::get_profile,
+        profile_route::get_profile_by_user,
+        profile_route::create_profile,
+        stream_route::get_stream,
+        public_stream_route::get_public_stream,
+        notification_route::get_notifications,
+        notification_route::get_unread_notification_count,
+        notification_route::mark_notifications_read,
+        notification_route::mark_notification_group_read,
+        webhook_route::create_webhook,
+        webhook_route::get_webhooks,
+        webhook_route::get_webhook_deliveries,
+        webhook_route::replay_webhook_delivery,
+    ),
+    components(schemas(
+        HealthResponder,
+        VersionResponder,
+        OutputId,
+        MessagePostJson,
+        MessageGroupTypes,
+        MessageByFollowingQuery,
+        MessageResponder,
+        MessageResponders,
+        MessageLikeJson,
+        ProfileShort,
+        ProfileCreateMultipart,
+        ProfileResponder,
+        NotificationKind,
+        NotificationGroupResponder,
+        NotificationGroupResponders,
+        NotificationCountResponder,
+        NotificationsReadJson,
+        NotificationGroupReadJson,
+        WebhookEventType,
+        WebhookDeliveryStatus,
+        WebhookSubscriptionJson,
+        WebhookSubscriptionResponder,
+        WebhookSubscriptionResponders,
+        WebhookDeliveryResponder,
+        WebhookDeliveryResponders,
+    )),
+    modifiers(&BearerSecurity),
+    tags(
+        (name = "health", description = "Liveness, readiness and build information"),
+        (name = "metrics", description = "Prometheus scrape endpoint"),
+        (name = "messages", description = "Posting messages and reading timelines"),
+        (name = "profiles", description = "Creating and reading profiles"),
+        (name = "streams", description = "Real-time delivery of new messages and notifications"),
+        (name = "notifications", description = "Follows, replies, broadcasts, likes and mentions of a profile"),
+        (name = "webhooks", description = "Signed HTTP deliveries of activity to a profile's integrations"),
+    )
+)]
+pub struct ApiDoc;
+
+/// the "bearer" scheme of the routes that act as a profile, see common/auth.rs
+struct BearerSecurity;
+
+impl Modify for BearerSecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "bearer",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("profile token").build())
+            );
+        }
+    }
+}
+
+pub async fn get_openapi() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// swagger ui with its assets embedded in the binary, mounted inside a scope at DOCS_PATH
+pub fn get_docs_service() -> SwaggerUi {
+    SwaggerUi::new("/{_:.*}").config(Config::from(OPENAPI_PATH))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openapi_documents_multipart_fields_in_snake_case() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let profile_form = &spec["components"]["schemas"]["ProfileCreateMultipart"]["properties"];
+        let message_post = &spec["components"]["schemas"]["MessagePostJson"]["properties"];
+
+        assert!(profile_form.get("user_name").is_some());
+        assert!(message_post.get("userId").is_some());
+    }
+
+    #[test]
+    fn test_openapi_documents_bearer_security_of_profile_routes() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+        assert_eq!(spec["components"]["securitySchemes"]["bearer"]["scheme"], "bearer");
+        assert!(spec["paths"]["/v1/notifications"]["get"]["security"][0].get("bearer").is_some());
+    }
+}
@@ -2,8 +2,9 @@ use actix_http::body::BoxBody;
 use actix_web::http::header::ContentType;
 use actix_web::{Responder, HttpResponse, HttpRequest };
 use serde::{Serialize, Deserialize};
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct OutputId {
     pub id: i64
 }
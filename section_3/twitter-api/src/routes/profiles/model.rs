@@ -1,13 +1,24 @@
-use std::{ fmt, pin::Pin };
+use std::pin::Pin;
 use actix_http::body::BoxBody;
-use actix_multipart::{ Multipart, Field };
+use actix_multipart::{ Multipart, MultipartError, Field };
 use actix_web::http::header::ContentType;
 use actix_web::{ FromRequest, HttpRequest, Responder, HttpResponse};
 use actix_web::dev::Payload;
+use actix_web::error::PayloadError;
 use chrono::{ Utc, DateTime };
 use futures::{ Future, TryStreamExt, StreamExt };
 use serde::{ Serialize, Deserialize };
+use utoipa::ToSchema;
+use tracing::error;
+use crate::routes::errors::error_utils::UserError;
+use crate::routes::etag::{ respond_with_etag, ETagBuilder };
+use crate::routes::validation::validator::{ Validate, FieldValidation, Constraint };
 
+pub const USER_NAME_MAX_LENGTH: usize = 50;
+pub const FULL_NAME_MAX_LENGTH: usize = 100;
+pub const DESCRIPTION_MAX_LENGTH: usize = 250;
+pub const REGION_MAX_LENGTH: usize = 50;
+pub const MAIN_URL_MAX_LENGTH: usize = 250;
 
 #[derive(Deserialize)]
 pub struct ProfileQuery {
@@ -19,7 +30,7 @@ pub struct ProfileByUserNameQuery {
     pub user_name: String,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ProfileShort {
     pub id: i64,
@@ -27,29 +38,35 @@ pub struct ProfileShort {
     pub full_name: String,
 }
 
-#[derive(Debug)]
-struct TwitterResponseError;
-impl fmt::Display for TwitterResponseError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self)
-    }
-}
-impl actix_web::ResponseError for TwitterResponseError {}
-
-#[derive(Deserialize, Serialize, Debug, Clone)]
-#[serde(rename_all = "camelCase")]
+/// read from multipart form fields, which keep their snake_case names unlike the json bodies
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
 pub struct ProfileCreateMultipart {
     pub user_name: String,
     pub full_name: String,
     pub description: String,
     pub region: Option<String>,
     pub main_url: Option<String>,
+    #[schema(value_type = Option<String>, format = Binary)]
     pub avatar: Option<Vec<u8>>,
 }
 
+/// per-field caps for ProfileCreateMultipart, registered as app data like JsonConfig
+#[derive(Debug, Clone, Copy)]
+pub struct MultipartLimits {
+    pub max_field_bytes: usize,
+    pub max_avatar_bytes: usize,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        Self { max_field_bytes: 4 * 1024, max_avatar_bytes: 2 * 1024 * 1024 }
+    }
+}
+
 impl ProfileCreateMultipart {
     async fn from_multipart(
-        mut multipart: Multipart
+        mut multipart: Multipart,
+        limits: MultipartLimits
     ) -> Result<Self, <Self as FromRequest>::Error> {
         let mut user_name: Option<String> = None;
         let mut full_name: Option<String> = None;
@@ -60,72 +77,94 @@ impl ProfileCreateMultipart {
         let mut avatar: Option<Vec<u8>> = None; 
 
         while let Some(field_result) = multipart.next().await {
-            if let Err(e) = field_result {
-                println!("error {}", e);
-                break;
-            }
-
-            let mut field = field_result.unwrap();
+            let mut field = match field_result {
+                Ok(field) => field,
+                Err(MultipartError::Payload(PayloadError::Overflow)) => {
+                    return Err(UserError::PayloadTooLarge.into());
+                }
+                Err(e) => {
+                    error!("multipart field error: {}", e);
+                    break;
+                }
+            };
             let content_disposition = field.content_disposition();
-            let field_name = content_disposition.get_name().unwrap();
+            let field_name = content_disposition.get_name().unwrap().to_string();
 
-            match field_name {
+            match field_name.as_str() {
                 "user_name" => {
-                    user_name = Self::read_string(&mut field).await;
+                    user_name = Self::read_string(&mut field, limits.max_field_bytes).await?;
                 }
                 "full_name" => {
-                    full_name = Self::read_string(&mut field).await;
+                    full_name = Self::read_string(&mut field, limits.max_field_bytes).await?;
                 }
                 "description" => {
-                    description = Self::read_string(&mut field).await;
+                    description = Self::read_string(&mut field, limits.max_field_bytes).await?;
                 }
                 "region" => {
-                    region = Self::read_string(&mut field).await;
+                    region = Self::read_string(&mut field, limits.max_field_bytes).await?;
                 }
                 "main_url" => {
-                    main_url = Self::read_string(&mut field).await;                    
+                    main_url = Self::read_string(&mut field, limits.max_field_bytes).await?;                    
                 }
                 "avatar" => {
-                    let mut field_avatar = vec![];
-                    while let Some(chunk) = field.next().await {
-                        let chunk = chunk.unwrap();
-                        field_avatar.extend_from_slice(&chunk);
-                    }
-                    avatar = Some(field_avatar);
+                    avatar = Some(Self::read_bytes(&mut field, limits.max_avatar_bytes).await?);
                 }
                 _ => (),
             }
         }
 
-        if user_name.is_some() && full_name.is_some() && description.is_some() {
-            Ok(Self {
-                user_name: user_name.unwrap(),
-                full_name: full_name.unwrap(),
-                description: description.unwrap(),
+        match (user_name, full_name, description) {
+            (Some(user_name), Some(full_name), Some(description)) => Ok(Self {
+                user_name,
+                full_name,
+                description,
                 region,
                 main_url,
                 avatar,
-            })
-        } else {
-            Err(TwitterResponseError.into())
+            }),
+            (user_name, full_name, description) => {
+                let missing_fields = [("user_name", user_name), ("full_name", full_name), ("description", description)]
+                    .into_iter()
+                    .filter(|(_, value)| value.is_none())
+                    .map(|(name, _)| name)
+                    .collect::<Vec<&str>>();
+
+                Err(UserError::ValidationError { field: missing_fields.join(", ") }.into())
+            }
         }
     }
 
-    #[allow(unused)]
-    async fn read_string(field: &mut Field) -> Option<String> {
-        let bytes = field.try_next().await;
+    /// reads the whole field, failing as soon as it grows past `limit` instead of buffering it
+    async fn read_bytes(field: &mut Field, limit: usize) -> Result<Vec<u8>, UserError> {
+        let mut bytes = vec![];
+        while let Some(chunk) = field.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(MultipartError::Payload(PayloadError::Overflow)) => {
+                    return Err(UserError::PayloadTooLarge);
+                }
+                Err(e) => {
+                    error!("multipart read error: {}", e);
+                    return Err(UserError::ValidationError { field: field.name().to_string() });
+                }
+            };
+            if bytes.len() + chunk.len() > limit {
+                return Err(UserError::PayloadTooLarge);
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    }
 
-        if let Ok(Some(bytes)) = bytes {
-            let result = String::from_utf8(bytes.to_vec());
-            if let Ok(val_str) = result {
-                Some(val_str)
-            } else {
-                println!("read_string error {}", result.err().unwrap().utf8_error());
-                None
+    async fn read_string(field: &mut Field, limit: usize) -> Result<Option<String>, UserError> {
+        let bytes = Self::read_bytes(field, limit).await?;
+
+        match String::from_utf8(bytes) {
+            Ok(val_str) => Ok(Some(val_str)),
+            Err(e) => {
+                error!("read_string error {}", e.utf8_error());
+                Ok(None)
             }
-        } else {
-            println!("read_string error {:?}", bytes.err().unwrap());
-            None
         }
     }
 
@@ -142,28 +181,62 @@ impl ProfileCreateMultipart {
     }
 }
 
+impl Validate for ProfileCreateMultipart {
+    fn validations(&self) -> Vec<FieldValidation<'_>> {
+        vec![
+            FieldValidation::new(
+                "user_name",
+                Some(&self.user_name),
+                &[Constraint::Required, Constraint::MaxLength(USER_NAME_MAX_LENGTH)]
+            ),
+            FieldValidation::new(
+                "full_name",
+                Some(&self.full_name),
+                &[Constraint::Required, Constraint::MaxLength(FULL_NAME_MAX_LENGTH)]
+            ),
+            FieldValidation::new(
+                "description",
+                Some(&self.description),
+                &[Constraint::MaxLength(DESCRIPTION_MAX_LENGTH)]
+            ),
+            FieldValidation::new(
+                "region",
+                self.region.as_deref(),
+                &[Constraint::MaxLength(REGION_MAX_LENGTH)]
+            ),
+            FieldValidation::new(
+                "main_url",
+                self.main_url.as_deref(),
+                &[Constraint::MaxLength(MAIN_URL_MAX_LENGTH)]
+            ),
+        ]
+    }
+}
+
 impl FromRequest for ProfileCreateMultipart {
     type Error = actix_web::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
 
     fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
         let multipart_future = Multipart::from_request(req, payload);
+        let limits = req.app_data::<MultipartLimits>().copied().unwrap_or_default();
 
-        let future = async {
+        let future = async move {
             let multipart = multipart_future.await?;
 
-            Self::from_multipart(multipart).await
+            Self::from_multipart(multipart, limits).await
         };
 
         Box::pin(future)
     }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ProfileResponder {
     pub id: i64,
     pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
     pub user_name: String,
     pub full_name: String,
     pub description: String,
@@ -175,10 +248,11 @@ pub struct ProfileResponder {
 impl Responder for ProfileResponder {
     type Body = BoxBody;
 
-    fn respond_to(self, _: &HttpRequest) -> HttpResponse<Self::Body> {
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let etag = ETagBuilder::new().id(self.id).updated_at(self.updated_at).build();
         let body_result = serde_json::to_string(&self);
 
-        match body_result {
+        let response = match body_result {
             Ok(body) => {
                 HttpResponse::Ok()
                 .content_type(ContentType::json())
@@ -189,6 +263,7 @@ impl Responder for ProfileResponder {
                     .content_type(ContentType::json())
                     .body("Failed to serialize ProfileResponder.")
             },
-        }
+        };
+        respond_with_etag(req, etag, response)
     }
 }
\ No newline at end of file
@@ -6,9 +6,9 @@ use crate::{common::{
             repo::{ InsertProfileFn, QueryProfileFn, QueryProfileByUserFn },
         },
     },
-}, routes::{errors::error_utils::UserError, output_id::OutputId}};
+}, routes::{errors::error_utils::UserError, output_id::OutputId, validation::validator::Validate}};
 use actix_web::{ web, web::Path };
-use log::info;
+use tracing::instrument;
 use super::model::{
     ProfileQuery,
     ProfileByUserNameQuery,
@@ -17,22 +17,30 @@ use super::model::{
 };
 
 #[allow(unused)]
+#[utoipa::path(
+    post,
+    path = "/v1/profile",
+    tag = "profiles",
+    request_body(content = ProfileCreateMultipart, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Id of the new profile", body = OutputId),
+        (status = 400, description = "A field is missing or too long"),
+        (status = 413, description = "A field or the avatar is over its size limit"),
+    )
+)]
+#[instrument(skip_all)]
 pub async fn create_profile<T: InsertProfileFn>(
     app_data: web::Data<AppState<T>>,
     form: ProfileCreateMultipart
 ) -> Result<OutputId, UserError> {
+    form.validate()?;
+
     let result = app_data.db_repo.insert_profile(ProfileCreate {
         user_name: form.user_name.to_owned(),
         full_name: form.full_name.to_owned(),
         description: form.description.to_owned(),
-        region: match form.region.as_ref() {
-            Some(region) => Some(region.to_owned()),
-            None => None,
-        },
-        main_url: match form.main_url.as_ref() {
-            Some(main_url) => Some(main_url.to_owned()),
-            None => None,
-        },
+        region: form.region.to_owned(),
+        main_url: form.main_url.to_owned(),
         avatar: form.avatar,
     }).await;
 
@@ -42,11 +50,22 @@ pub async fn create_profile<T: InsertProfileFn>(
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/profile/{id}",
+    tag = "profiles",
+    params(("id" = i64, Path, description = "Profile id")),
+    responses(
+        (status = 200, description = "Profile, carries an ETag", body = ProfileResponder),
+        (status = 304, description = "Unchanged since the ETag sent in If-None-Match"),
+        (status = 404, description = "No profile with this id"),
+    )
+)]
+#[instrument(skip_all)]
 pub async fn get_profile<T: QueryProfileFn>(
     app_data: web::Data<AppState<T>>,
     path: Path<ProfileQuery>
 ) -> Result<Option<ProfileResponder>, UserError> {
-    info!("start get_profile");
     let result = app_data.db_repo.query_profile(path.id).await;
 
     match result {
@@ -55,6 +74,18 @@ pub async fn get_profile<T: QueryProfileFn>(
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/profile/username/{user_name}",
+    tag = "profiles",
+    params(("user_name" = String, Path, description = "Profile user name")),
+    responses(
+        (status = 200, description = "Profile, carries an ETag", body = ProfileResponder),
+        (status = 304, description = "Unchanged since the ETag sent in If-None-Match"),
+        (status = 404, description = "No profile with this user name"),
+    )
+)]
+#[instrument(skip_all)]
 pub async fn get_profile_by_user<T: QueryProfileByUserFn>(
     app_data: web::Data<AppState<T>>,
     path: Path<ProfileByUserNameQuery>
@@ -75,6 +106,7 @@ fn convert(profile: Option<ProfileQueryResult>) -> Option<ProfileResponder> {
             Some(ProfileResponder {
                 id: item.id,
                 created_at: item.created_at,
+                updated_at: item.updated_at,
                 user_name: item.user_name,
                 full_name: item.full_name,
                 description: item.description,
@@ -93,7 +125,7 @@ mod tests {
         faker::{
             internet::en::Username, 
             name::en::{LastName, FirstName}, 
-            lorem::en::Sentence, address::en::CountryName
+            lorem::en::Sentence
         }, 
         Fake
     };
@@ -101,7 +133,7 @@ mod tests {
         common::{
             entities::{profiles::{repo::InsertProfileFn, model::ProfileCreate}}
         }, 
-        common_tests::actix_fixture::{get_profile_avatar, get_fake_main_url, get_app_data
+        common_tests::actix_fixture::{get_profile_avatar, get_fake_main_url, get_fake_region, get_app_data
         }, routes::{profiles::model::ProfileCreateMultipart, errors::error_utils::UserError}
     };
     use super::*;
@@ -131,16 +163,49 @@ mod tests {
                 user_name: Username().fake::<String>(), 
                 full_name: format!("{} {}", FirstName().fake::<String>(), LastName().fake::<String>()),
                 description: Sentence(1..2).fake::<String>(), 
-                region: Some(CountryName().fake::<String>()), 
+                region: Some(get_fake_region()), 
                 main_url: Some(get_fake_main_url()),
                 avatar: Some(avatar), 
             }).await;
             
-            assert!(result.is_err() == true);
+            assert!(result.is_err());
             assert!(result.err().unwrap() == UserError::InternalError);
         }
     }
 
+    mod test_mod_create_profile_validation_lists_every_failing_field {
+        use crate::routes::profiles::model::{ USER_NAME_MAX_LENGTH, DESCRIPTION_MAX_LENGTH };
+        use super::*;
+
+        #[derive(Clone)]
+        struct MockDbRepo;
+
+        #[async_trait]
+        impl InsertProfileFn for MockDbRepo {
+            async fn insert_profile(&self, _: ProfileCreate) -> Result<i64, sqlx::Error> {
+                Ok(1)
+            }
+        }
+
+        #[tokio::test]
+        async fn test_create_profile_validation_lists_every_failing_field() {
+            let app_data = get_app_data(MockDbRepo).await;
+
+            let result = create_profile(app_data, ProfileCreateMultipart {
+                user_name: "ü".repeat(USER_NAME_MAX_LENGTH + 1),
+                full_name: " ".to_string(),
+                description: "🙂".repeat(DESCRIPTION_MAX_LENGTH + 1),
+                region: Some(get_fake_region()),
+                main_url: Some(get_fake_main_url()),
+                avatar: None,
+            }).await;
+
+            assert!(result.err().unwrap() == UserError::ValidationError {
+                field: "user_name, full_name, description".to_string()
+            });
+        }
+    }
+
     mod test_mod_create_profile_and_check_id {    
         use super::*;
 
@@ -166,12 +231,12 @@ mod tests {
                 user_name: Username().fake::<String>(), 
                 full_name: format!("{} {}", FirstName().fake::<String>(), LastName().fake::<String>()),
                 description: Sentence(1..2).fake::<String>(), 
-                region: Some(CountryName().fake::<String>()), 
+                region: Some(get_fake_region()), 
                 main_url: Some(get_fake_main_url()),
                 avatar: Some(avatar), 
             }).await;
             
-            assert!(!result.is_err());
+            assert!(result.is_ok());
             assert!(result.ok().unwrap().id == ID);
         }
     }
@@ -195,7 +260,7 @@ mod tests {
 
             let get_result = get_profile(app_data, Path::from(ProfileQuery { id: 0 })).await;
 
-            assert!(get_result.is_err() == true);
+            assert!(get_result.is_err());
             assert!(get_result.err().unwrap() == UserError::InternalError);
         }
     }
@@ -232,7 +297,7 @@ mod tests {
 
             let get_result = get_profile(app_data, Path::from(ProfileQuery { id: 0 })).await;
 
-            assert!(!get_result.is_err());
+            assert!(get_result.is_ok());
             assert!(get_result.ok().unwrap().unwrap().id == ID);
         }
     }
@@ -256,7 +321,7 @@ mod tests {
 
             let get_result = get_profile_by_user(app_data, Path::from(ProfileByUserNameQuery { user_name: Username().fake::<String>() })).await;
 
-            assert!(get_result.as_ref().is_err() == true);
+            assert!(get_result.as_ref().is_err());
             assert!(get_result.err().unwrap() == UserError::InternalError);
         }
     }
@@ -293,7 +358,7 @@ mod tests {
 
             let get_result = get_profile_by_user(app_data, Path::from(ProfileByUserNameQuery { user_name: Username().fake() })).await;
 
-            assert!(!get_result.is_err());
+            assert!(get_result.is_ok());
             assert!(get_result.ok().unwrap().unwrap().id == ID);
         }
     }
@@ -0,0 +1,272 @@
+use serde::{ Deserialize, Serialize };
+use std::collections::HashSet;
+use crate::common::{
+    entities::{ messages::model::MessageStreamFilter, notifications::model::NotificationKind },
+    events::{ FollowCreated, MessageCreated, NotificationCreated },
+};
+use crate::routes::{
+    messages::model::{ MessageGroupTypes, MessageResponder },
+    validation::validator::{ Constraint, FieldValidation, Validate },
+};
+
+/// longest hashtag a public stream can filter by, without its #
+pub const HASHTAG_MAX_LENGTH: usize = 100;
+
+/// threads one connection can follow at once
+pub const MAX_THREAD_SUBSCRIPTIONS: usize = 100;
+
+/// filters of the public stream, a message must match every one given
+#[derive(Deserialize)]
+pub struct PublicStreamQuery {
+    /// only messages whose body has this hashtag, given without its #
+    pub tag: Option<String>,
+    /// only messages by this profile
+    pub user_id: Option<i64>,
+}
+
+impl Validate for PublicStreamQuery {
+    fn validations(&self) -> Vec<FieldValidation<'_>> {
+        vec![
+            FieldValidation::new("tag", self.tag.as_deref(), &[Constraint::MaxLength(HASHTAG_MAX_LENGTH), Constraint::Hashtag]),
+        ]
+    }
+}
+
+impl PublicStreamQuery {
+    pub fn get_filter(&self) -> MessageStreamFilter {
+        MessageStreamFilter {
+            group_type: MessageGroupTypes::Public as i32,
+            user_id: self.user_id,
+            tag: self.tag.as_ref().map(|tag| tag.to_ascii_lowercase()),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(tag = "name", rename_all = "camelCase")]
+pub enum StreamChannel {
+    /// new messages of followed profiles
+    Home,
+    /// notifications of the subscriber as they are written
+    Notifications,
+    /// replies to one message
+    Thread {
+        #[serde(rename = "msgId")]
+        msg_id: i64,
+    },
+}
+
+/// sent by the client as a text frame, e.g. {"action":"subscribe","channel":{"name":"thread","msgId":5}}
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum StreamCommand {
+    Subscribe { channel: StreamChannel },
+    Unsubscribe { channel: StreamChannel },
+}
+
+/// sent to the client as a text frame
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum StreamFrame {
+    Subscribed { channel: StreamChannel },
+    Unsubscribed { channel: StreamChannel },
+    /// a new message with every subscribed channel it belongs to
+    Message { channels: Vec<StreamChannel>, message: MessageResponder },
+    /// a new notification of the subscriber, on the notifications channel
+    Notification { notification: StreamNotification },
+    /// the connection fell behind and this many messages were dropped
+    Lagged { skipped: u64 },
+    Error { error: String },
+}
+
+/// one notification as it was written, GET /v1/notifications returns it grouped with the others of its kind and target
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamNotification {
+    pub id: i64,
+    pub actor_id: i64,
+    pub kind: NotificationKind,
+    pub target_msg_id: Option<i64>,
+    pub source_msg_id: Option<i64>,
+}
+
+impl From<&NotificationCreated> for StreamNotification {
+    fn from(event: &NotificationCreated) -> Self {
+        Self {
+            id: event.id,
+            actor_id: event.actor_id,
+            kind: event.kind,
+            target_msg_id: event.target_msg_id,
+            source_msg_id: event.source_msg_id,
+        }
+    }
+}
+
+/// what one connection listens to. The followed profiles are loaded when home is subscribed
+/// and follows made afterwards are added as their events arrive
+pub struct Subscriptions {
+    user_id: i64,
+    home: Option<HashSet<i64>>,
+    notifications: bool,
+    threads: HashSet<i64>,
+}
+
+impl Subscriptions {
+    pub fn new(user_id: i64) -> Self {
+        Self { user_id, home: None, notifications: false, threads: HashSet::new() }
+    }
+
+    pub fn user_id(&self) -> i64 {
+        self.user_id
+    }
+
+    pub fn subscribe_home(&mut self, following_ids: Vec<i64>) {
+        self.home = Some(following_ids.into_iter().collect());
+    }
+
+    /// adds the followed profile to home when the subscriber is the follower
+    pub fn add_follow(&mut self, event: &FollowCreated) {
+        if let Some(following_ids) = self.home.as_mut().filter(|_| event.follower_id == self.user_id) {
+            following_ids.insert(event.following_id);
+        }
+    }
+
+    pub fn subscribe_notifications(&mut self) {
+        self.notifications = true;
+    }
+
+    /// false once MAX_THREAD_SUBSCRIPTIONS threads are followed
+    pub fn subscribe_thread(&mut self, msg_id: i64) -> bool {
+        if self.threads.len() >= MAX_THREAD_SUBSCRIPTIONS && !self.threads.contains(&msg_id) {
+            return false;
+        }
+        self.threads.insert(msg_id);
+        true
+    }
+
+    pub fn unsubscribe(&mut self, channel: StreamChannel) {
+        match channel {
+            StreamChannel::Home => self.home = None,
+            StreamChannel::Notifications => self.notifications = false,
+            StreamChannel::Thread { msg_id } => _ = self.threads.remove(&msg_id),
+        }
+    }
+
+    /// true when notifications are subscribed and the notification is the subscriber's
+    pub fn is_notified(&self, event: &NotificationCreated) -> bool {
+        self.notifications && event.recipient_id == self.user_id
+    }
+
+    /// the subscribed channels a new message belongs to, never the subscriber's own messages.
+    /// Notifications are sent on their own, see is_notified
+    pub fn get_matching_channels(&self, event: &MessageCreated) -> Vec<StreamChannel> {
+        let mut channels = vec![];
+        if event.user_id == self.user_id {
+            return channels;
+        }
+
+        if self.home.as_ref().is_some_and(|following_ids| following_ids.contains(&event.user_id)) {
+            channels.push(StreamChannel::Home);
+        }
+        if let Some(original_msg) = event.original_msg.filter(|msg| self.threads.contains(&msg.id)) {
+            channels.push(StreamChannel::Thread { msg_id: original_msg.id });
+        }
+        channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::events::MessageRef;
+    use super::*;
+
+    const SUBSCRIBER_ID: i64 = 1;
+    const FOLLOWED_ID: i64 = 2;
+    const STRANGER_ID: i64 = 3;
+
+    fn get_event(user_id: i64, original_msg: Option<MessageRef>, broadcasting_msg: Option<MessageRef>) -> MessageCreated {
+        MessageCreated { id: 100, user_id, msg_group_type: 1, original_msg, broadcasting_msg }
+    }
+
+    fn get_subscriptions() -> Subscriptions {
+        let mut subscriptions = Subscriptions::new(SUBSCRIBER_ID);
+        subscriptions.subscribe_home(vec![FOLLOWED_ID]);
+        subscriptions.subscribe_notifications();
+        subscriptions.subscribe_thread(50);
+        subscriptions
+    }
+
+    fn get_notification(recipient_id: i64) -> NotificationCreated {
+        NotificationCreated {
+            id: 7,
+            recipient_id,
+            actor_id: STRANGER_ID,
+            kind: NotificationKind::Follow,
+            target_msg_id: None,
+            source_msg_id: None,
+        }
+    }
+
+    #[test]
+    fn test_matching_channels_by_author_and_thread() {
+        let subscriptions = get_subscriptions();
+        let own_msg = MessageRef { id: 10, user_id: SUBSCRIBER_ID };
+        let thread_msg = MessageRef { id: 50, user_id: STRANGER_ID };
+
+        assert_eq!(subscriptions.get_matching_channels(&get_event(FOLLOWED_ID, None, None)), vec![StreamChannel::Home]);
+        assert!(subscriptions.get_matching_channels(&get_event(STRANGER_ID, None, None)).is_empty());
+        assert_eq!(
+            subscriptions.get_matching_channels(&get_event(FOLLOWED_ID, None, Some(own_msg))),
+            vec![StreamChannel::Home]
+        );
+        assert_eq!(
+            subscriptions.get_matching_channels(&get_event(STRANGER_ID, Some(thread_msg), None)),
+            vec![StreamChannel::Thread { msg_id: 50 }]
+        );
+        assert!(subscriptions.get_matching_channels(&get_event(SUBSCRIBER_ID, Some(thread_msg), None)).is_empty());
+    }
+
+    #[test]
+    fn test_only_the_recipients_notifications_are_sent() {
+        let mut subscriptions = get_subscriptions();
+
+        assert!(subscriptions.is_notified(&get_notification(SUBSCRIBER_ID)));
+        assert!(!subscriptions.is_notified(&get_notification(FOLLOWED_ID)));
+
+        subscriptions.unsubscribe(StreamChannel::Notifications);
+        assert!(!subscriptions.is_notified(&get_notification(SUBSCRIBER_ID)));
+    }
+
+    #[test]
+    fn test_unsubscribe_and_thread_limit() {
+        let mut subscriptions = get_subscriptions();
+        subscriptions.unsubscribe(StreamChannel::Home);
+        subscriptions.unsubscribe(StreamChannel::Thread { msg_id: 50 });
+
+        assert!(subscriptions.get_matching_channels(&get_event(FOLLOWED_ID, None, None)).is_empty());
+        for msg_id in 0..MAX_THREAD_SUBSCRIPTIONS as i64 {
+            assert!(subscriptions.subscribe_thread(msg_id));
+        }
+        assert!(!subscriptions.subscribe_thread(-1));
+        assert!(subscriptions.subscribe_thread(0));
+    }
+
+    #[test]
+    fn test_follows_of_the_subscriber_join_home() {
+        let mut subscriptions = get_subscriptions();
+        subscriptions.add_follow(&FollowCreated { id: 1, follower_id: FOLLOWED_ID, following_id: STRANGER_ID });
+        assert!(subscriptions.get_matching_channels(&get_event(STRANGER_ID, None, None)).is_empty());
+
+        subscriptions.add_follow(&FollowCreated { id: 2, follower_id: SUBSCRIBER_ID, following_id: STRANGER_ID });
+        assert_eq!(subscriptions.get_matching_channels(&get_event(STRANGER_ID, None, None)), vec![StreamChannel::Home]);
+    }
+
+    #[test]
+    fn test_command_json_shape() {
+        let command = serde_json::from_str::<StreamCommand>(
+            r#"{"action":"subscribe","channel":{"name":"thread","msgId":5}}"#
+        ).unwrap();
+
+        assert!(matches!(command, StreamCommand::Subscribe { channel: StreamChannel::Thread { msg_id: 5 } }));
+    }
+}
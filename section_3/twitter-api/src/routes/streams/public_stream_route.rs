@@ -0,0 +1,348 @@
+use crate::common::{
+    app_state::AppState,
+    entities::{
+        events::repo::SubscribeEventsFn,
+        messages::{
+            model::{ mentions_hashtag, MessageStreamFilter, MessageWithFollowingAndBroadcastQueryResult },
+            repo::{ QueryMessageCountsFn, QueryMessageFn, QueryMessagesAfterFn },
+        },
+    },
+    events::AppEvent,
+};
+use crate::routes::{
+    errors::error_utils::UserError,
+    messages::{ message_route::{ convert, get_message_counts }, model::MessageResponder },
+    validation::validator::Validate,
+};
+use actix_web::{ http::header, web, web::{ Bytes, Query }, HttpRequest, HttpResponse };
+use futures_util::StreamExt;
+use std::time::Duration;
+use tokio::sync::{ broadcast::{ self, error::RecvError }, mpsc };
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{ instrument, warn };
+use super::model::PublicStreamQuery;
+
+pub const LAST_EVENT_ID: &str = "last-event-id";
+/// a comment line keeps proxies from closing an idle stream
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// events written ahead of a slow client before the stream waits for it
+const EVENT_BUFFER: usize = 16;
+const REPLAY_PAGE_SIZE: i16 = 100;
+/// messages one connection replays, a client further behind is closed after them and resumes on reconnect
+const MAX_REPLAY_MESSAGES: usize = 1000;
+
+#[allow(unused)]
+#[utoipa::path(
+    get,
+    path = "/v1/stream/public",
+    tag = "streams",
+    params(
+        ("tag" = Option<String>, Query, description = "Only messages with this hashtag, without its #"),
+        ("user_id" = Option<i64>, Query, description = "Only messages by this profile"),
+        ("Last-Event-ID" = Option<i64>, Header, description = "Replay the matching messages after this id first"),
+    ),
+    responses(
+        (status = 200, description = "text/event-stream of new public messages, each event's id is the message id and its data a MessageResponder"),
+        (status = 400, description = "Invalid tag or Last-Event-ID"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn get_public_stream<T>(
+    app_data: web::Data<AppState<T>>,
+    req: HttpRequest,
+    query: Query<PublicStreamQuery>
+) -> Result<HttpResponse, UserError>
+    where T: QueryMessagesAfterFn + QueryMessageFn + QueryMessageCountsFn + SubscribeEventsFn + Send + Sync + 'static
+{
+    query.validate()?;
+    let last_event_id = get_last_event_id(&req)?;
+
+    // subscribed before any replay so messages inserted while it runs are not missed
+    let events = app_data.db_repo.subscribe_events();
+    let (sender, receiver) = mpsc::channel(EVENT_BUFFER);
+    // unlike a websocket session nothing here is tied to the worker thread
+    tokio::spawn(run_public_stream(app_data, query.get_filter(), last_event_id, events, sender));
+
+    Ok(
+        HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header((header::CACHE_CONTROL, "no-cache"))
+            // Compress would hold events back until its buffer fills
+            .insert_header((header::CONTENT_ENCODING, "identity"))
+            .streaming(ReceiverStream::new(receiver).map(Ok::<Bytes, actix_web::Error>))
+    )
+}
+
+/// sent by EventSource when it reconnects, the id of the last event it received
+fn get_last_event_id(req: &HttpRequest) -> Result<Option<i64>, UserError> {
+    match req.headers().get(LAST_EVENT_ID) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|value| value.trim().parse::<i64>().ok())
+            .map(Some)
+            .ok_or(UserError::ValidationError { field: "Last-Event-ID".to_string() }),
+        None => Ok(None),
+    }
+}
+
+async fn run_public_stream<T>(
+    app_data: web::Data<AppState<T>>,
+    filter: MessageStreamFilter,
+    last_event_id: Option<i64>,
+    mut events: broadcast::Receiver<AppEvent>,
+    sender: mpsc::Sender<Bytes>
+)
+    where T: QueryMessagesAfterFn + QueryMessageFn + QueryMessageCountsFn
+{
+    // live events up to the last replayed id were already sent by the replay. Live sends never move it:
+    // ids are allocated on insert but events arrive in commit order, so a lower id can still be on its way
+    let mut replayed_id = None;
+    // where a replay after lagging resumes, before anything is sent the newest message when the stream began
+    let mut last_sent_id = match last_event_id {
+        Some(after_id) => match replay(&app_data.db_repo, &filter, after_id, &sender).await {
+            Some(id) => {
+                replayed_id = Some(id);
+                id
+            }
+            None => return,
+        },
+        None => match app_data.db_repo.query_last_message_id().await {
+            Ok(id) => id.unwrap_or_default(),
+            Err(e) => {
+                warn!("public stream could not find where it starts: {}", e);
+                return;
+            }
+        },
+    };
+
+    // the first tick is immediate, so a client sees the stream open before any message
+    let mut keep_alive = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = keep_alive.tick() => {
+                if sender.send(Bytes::from_static(b": keep-alive\n\n")).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            _ = sender.closed() => return,
+        };
+
+        match event {
+            Ok(AppEvent::MessageCreated(event)) => {
+                // already replayed, or not public or by another author
+                if replayed_id.is_some_and(|id| event.id <= id)
+                    || event.msg_group_type != filter.group_type
+                    || filter.user_id.is_some_and(|user_id| user_id != event.user_id)
+                {
+                    continue;
+                }
+                match app_data.db_repo.query_message(event.id).await {
+                    Ok(Some(message)) if is_tagged(&filter, &message) => {
+                        match send_messages(&app_data.db_repo, &[message], &sender).await {
+                            Some(sent_id) => last_sent_id = last_sent_id.max(sent_id),
+                            None => return,
+                        }
+                    }
+                    Ok(_) => (),
+                    Err(e) => warn!("public stream could not load message {}: {}", event.id, e),
+                }
+            }
+            Ok(_) => (),
+            // the dropped events are read back from the database, like a reconnect with Last-Event-ID would
+            Err(RecvError::Lagged(_)) => {
+                match replay(&app_data.db_repo, &filter, last_sent_id, &sender).await {
+                    Some(id) => {
+                        replayed_id = Some(id);
+                        last_sent_id = id;
+                    }
+                    None => return,
+                }
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+/// writes the matching messages after after_id and returns the last id written, None once the stream should end
+async fn replay<T>(
+    db_repo: &T,
+    filter: &MessageStreamFilter,
+    after_id: i64,
+    sender: &mpsc::Sender<Bytes>
+) -> Option<i64>
+    where T: QueryMessagesAfterFn + QueryMessageCountsFn
+{
+    let mut last_id = after_id;
+    let mut replayed = 0;
+    loop {
+        let page = match db_repo.query_messages_after(last_id, filter, REPLAY_PAGE_SIZE).await {
+            Ok(page) => page,
+            Err(e) => {
+                warn!("public stream could not replay after {}: {}", last_id, e);
+                return None;
+            }
+        };
+        let is_last_page = page.len() < REPLAY_PAGE_SIZE as usize;
+        replayed += page.len();
+        if !page.is_empty() {
+            last_id = send_messages(db_repo, &page, sender).await?;
+        }
+        if is_last_page {
+            return Some(last_id);
+        }
+        if replayed >= MAX_REPLAY_MESSAGES {
+            return None;
+        }
+    }
+}
+
+fn is_tagged(filter: &MessageStreamFilter, message: &MessageWithFollowingAndBroadcastQueryResult) -> bool {
+    match &filter.tag {
+        Some(tag) => message.body.as_deref().is_some_and(|body| mentions_hashtag(body, tag)),
+        None => true,
+    }
+}
+
+/// writes one event per message and returns the last id written, None when the client is gone
+async fn send_messages<T: QueryMessageCountsFn>(
+    db_repo: &T,
+    messages: &[MessageWithFollowingAndBroadcastQueryResult],
+    sender: &mpsc::Sender<Bytes>
+) -> Option<i64> {
+    let counts = match get_message_counts(db_repo, messages).await {
+        Ok(counts) => counts,
+        Err(e) => {
+            warn!("public stream could not load message counts: {}", e);
+            return None;
+        }
+    };
+
+    let mut last_id = None;
+    for message in messages {
+        let event = get_event(&convert(message, &counts));
+        sender.send(Bytes::from(event)).await.ok()?;
+        last_id = Some(message.id);
+    }
+    last_id
+}
+
+/// one server-sent event, the id lets a reconnecting client resume after it
+fn get_event(message: &MessageResponder) -> String {
+    format!("id: {}\ndata: {}\n\n", message.id, serde_json::to_string(message).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use crate::{
+        common::{
+            entities::{
+                app_repo::AppRepo,
+                memory::MemoryRepo,
+                messages::repo::InsertMessageFn,
+                profiles::{ model::ProfileCreate, repo::InsertProfileFn },
+            },
+            events::MessageCreated,
+        },
+        common_tests::actix_fixture::{ get_app_data, PUBLIC_GROUP_TYPE },
+        routes::streams::model::PublicStreamQuery,
+    };
+    use super::*;
+
+    const KEEP_ALIVE: &[u8] = b": keep-alive\n\n";
+
+    /// the id of the next message event, past any keep-alive
+    async fn next_event_id(receiver: &mut mpsc::Receiver<Bytes>) -> i64 {
+        loop {
+            let frame = receiver.recv().await.unwrap();
+            if frame != KEEP_ALIVE {
+                let frame = std::str::from_utf8(&frame).unwrap();
+                return frame.strip_prefix("id: ").and_then(|rest| rest.lines().next()).unwrap().parse().unwrap();
+            }
+        }
+    }
+
+    async fn insert_author(db_repo: &AppRepo) -> i64 {
+        db_repo
+            .insert_profile(ProfileCreate {
+                user_name: "author".to_string(),
+                full_name: "Dave Wave".to_string(),
+                description: "a description".to_string(),
+                region: None,
+                main_url: None,
+                avatar: None,
+            }).await
+            .unwrap()
+    }
+
+    fn get_message_created(id: i64, user_id: i64) -> AppEvent {
+        AppEvent::MessageCreated(MessageCreated { id, user_id, msg_group_type: PUBLIC_GROUP_TYPE, original_msg: None, broadcasting_msg: None })
+    }
+
+    #[tokio::test]
+    async fn test_live_messages_committed_out_of_id_order_are_all_sent() {
+        let db_repo = AppRepo::Memory(MemoryRepo::new());
+        let author_id = insert_author(&db_repo).await;
+        let seen_id = db_repo.insert_message(author_id, "seen", PUBLIC_GROUP_TYPE, None).await.unwrap();
+        let (events_sender, events) = broadcast::channel(16);
+        let (sender, mut receiver) = mpsc::channel(16);
+        let filter = PublicStreamQuery { tag: None, user_id: None }.get_filter();
+        tokio::spawn(run_public_stream(get_app_data(db_repo.clone()).await, filter, Some(seen_id - 1), events, sender));
+        assert_eq!(next_event_id(&mut receiver).await, seen_id);
+
+        let first_id = db_repo.insert_message(author_id, "inserted first", PUBLIC_GROUP_TYPE, None).await.unwrap();
+        let second_id = db_repo.insert_message(author_id, "inserted second", PUBLIC_GROUP_TYPE, None).await.unwrap();
+        // the second insert commits first
+        for id in [second_id, first_id, seen_id] {
+            events_sender.send(get_message_created(id, author_id)).unwrap();
+        }
+
+        assert_eq!(next_event_id(&mut receiver).await, second_id);
+        assert_eq!(next_event_id(&mut receiver).await, first_id);
+        drop(events_sender);
+        // the replayed message is not sent twice, the first keep-alive may still be sent before the stream ends
+        while let Some(frame) = receiver.recv().await {
+            assert_eq!(frame, KEEP_ALIVE);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lagging_before_any_message_replays_from_where_the_stream_began() {
+        let db_repo = AppRepo::Memory(MemoryRepo::new());
+        let author_id = insert_author(&db_repo).await;
+        db_repo.insert_message(author_id, "before the stream", PUBLIC_GROUP_TYPE, None).await.unwrap();
+        let (events_sender, events) = broadcast::channel(1);
+        let (sender, mut receiver) = mpsc::channel(16);
+        let filter = PublicStreamQuery { tag: None, user_id: None }.get_filter();
+        tokio::spawn(run_public_stream(get_app_data(db_repo.clone()).await, filter, None, events, sender));
+        // sent once the stream knows where it began
+        assert_eq!(receiver.recv().await.unwrap(), KEEP_ALIVE);
+
+        let mut ids = vec![];
+        for body in ["first", "second", "third"] {
+            let id = db_repo.insert_message(author_id, body, PUBLIC_GROUP_TYPE, None).await.unwrap();
+            events_sender.send(get_message_created(id, author_id)).unwrap();
+            ids.push(id);
+        }
+
+        for id in ids {
+            assert_eq!(next_event_id(&mut receiver).await, id);
+        }
+        drop(events_sender);
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[test]
+    fn test_last_event_id_is_an_optional_message_id() {
+        let without = TestRequest::default().to_http_request();
+        let with = TestRequest::default().insert_header((LAST_EVENT_ID, "42")).to_http_request();
+        let invalid = TestRequest::default().insert_header((LAST_EVENT_ID, "abc")).to_http_request();
+
+        assert_eq!(get_last_event_id(&without), Ok(None));
+        assert_eq!(get_last_event_id(&with), Ok(Some(42)));
+        assert!(get_last_event_id(&invalid).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use crate::common::{
+    app_state::AppState,
+    auth::AuthenticatedProfile,
+    entities::{
+        events::repo::SubscribeEventsFn,
+        messages::repo::{ QueryMessageCountsFn, QueryMessageFn },
+        profiles::repo::{ QueryFollowingIdsFn, QueryProfileFn },
+    },
+    events::{ AppEvent, MessageCreated },
+};
+use crate::routes::{
+    errors::error_utils::UserError,
+    messages::{ message_route::{ convert, get_message_counts }, model::MessageResponder },
+};
+use actix_web::{ web, HttpRequest, HttpResponse };
+use actix_ws::{ Message, MessageStream, Session };
+use futures_util::StreamExt;
+use std::time::{ Duration, Instant };
+use tokio::sync::broadcast::{ self, error::RecvError };
+use tracing::{ instrument, warn };
+use super::model::{ StreamChannel, StreamCommand, StreamFrame, Subscriptions };
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// connections that answer no ping for this long are closed
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(75);
+/// commands are small json objects, anything larger is a misbehaving client
+const MAX_COMMAND_BYTES: usize = 4 * 1024;
+
+#[allow(unused)]
+#[utoipa::path(
+    get,
+    path = "/v1/stream",
+    tag = "streams",
+    responses(
+        (status = 101, description = "WebSocket of StreamCommand frames from the client and StreamFrame frames to it, for the authenticated profile"),
+        (status = 401, description = "No valid bearer token"),
+        (status = 404, description = "The token's profile no longer exists"),
+    ),
+    security(("bearer" = []))
+)]
+#[instrument(skip_all)]
+pub async fn get_stream<T>(
+    app_data: web::Data<AppState<T>>,
+    profile: AuthenticatedProfile,
+    req: HttpRequest,
+    body: web::Payload
+) -> Result<HttpResponse, actix_web::Error>
+    where T: QueryProfileFn + QueryFollowingIdsFn + QueryMessageFn + QueryMessageCountsFn + SubscribeEventsFn + 'static
+{
+    let user_id = profile.profile_id;
+    if app_data.db_repo.query_profile(user_id).await.map_err(UserError::from)?.is_none() {
+        return Err(UserError::NotFound.into());
+    }
+
+    // subscribed before the upgrade so messages inserted during the handshake are not missed
+    let events = app_data.db_repo.subscribe_events();
+    let (response, session, commands) = actix_ws::handle(&req, body)?;
+    actix_web::rt::spawn(run_stream(app_data, user_id, session, commands.max_frame_size(MAX_COMMAND_BYTES), events));
+
+    Ok(response)
+}
+
+async fn run_stream<T>(
+    app_data: web::Data<AppState<T>>,
+    user_id: i64,
+    mut session: Session,
+    mut commands: MessageStream,
+    mut events: broadcast::Receiver<AppEvent>
+)
+    where T: QueryFollowingIdsFn + QueryMessageFn + QueryMessageCountsFn
+{
+    let mut subscriptions = Subscriptions::new(user_id);
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut last_heard = Instant::now();
+
+    loop {
+        let frame = tokio::select! {
+            command = commands.next() => {
+                last_heard = Instant::now();
+                match command {
+                    Some(Ok(Message::Text(text))) => {
+                        Some(handle_command(&app_data.db_repo, &mut subscriptions, &text).await)
+                    }
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                        None
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => None,
+                }
+            }
+            event = events.recv() => match event {
+                Ok(AppEvent::MessageCreated(event)) => get_message_frame(&app_data.db_repo, &subscriptions, &event).await,
+                Ok(AppEvent::FollowCreated(event)) => {
+                    subscriptions.add_follow(&event);
+                    None
+                }
+                Ok(AppEvent::NotificationCreated(event)) => {
+                    subscriptions.is_notified(&event).then(|| StreamFrame::Notification { notification: (&event).into() })
+                }
+                Ok(AppEvent::MessageLiked(_)) => None,
+                Err(RecvError::Lagged(skipped)) => Some(StreamFrame::Lagged { skipped }),
+                Err(RecvError::Closed) => break,
+            },
+            _ = heartbeat.tick() => {
+                if last_heard.elapsed() > CLIENT_TIMEOUT || session.ping(b"").await.is_err() {
+                    break;
+                }
+                None
+            }
+        };
+
+        if let Some(frame) = frame {
+            let text = serde_json::to_string(&frame).unwrap_or_default();
+            if session.text(text).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    _ = session.close(None).await;
+}
+
+async fn handle_command<T: QueryFollowingIdsFn>(db_repo: &T, subscriptions: &mut Subscriptions, text: &str) -> StreamFrame {
+    let command = match serde_json::from_str::<StreamCommand>(text) {
+        Ok(command) => command,
+        Err(e) => return StreamFrame::Error { error: format!("Invalid command: {}", e) },
+    };
+
+    match command {
+        StreamCommand::Subscribe { channel } => {
+            match channel {
+                StreamChannel::Home => match db_repo.query_following_ids(subscriptions.user_id()).await {
+                    Ok(following_ids) => subscriptions.subscribe_home(following_ids),
+                    Err(e) => {
+                        warn!("stream could not load followed profiles: {}", e);
+                        return StreamFrame::Error { error: UserError::InternalError.to_string() };
+                    }
+                },
+                StreamChannel::Notifications => subscriptions.subscribe_notifications(),
+                StreamChannel::Thread { msg_id } => {
+                    if !subscriptions.subscribe_thread(msg_id) {
+                        return StreamFrame::Error { error: "Too many thread subscriptions.".to_string() };
+                    }
+                }
+            }
+            StreamFrame::Subscribed { channel }
+        }
+        StreamCommand::Unsubscribe { channel } => {
+            subscriptions.unsubscribe(channel);
+            StreamFrame::Unsubscribed { channel }
+        }
+    }
+}
+
+/// the new message as it is returned by GET /v1/msg/{id}, when it belongs to a subscribed channel
+async fn get_message_frame<T>(db_repo: &T, subscriptions: &Subscriptions, event: &MessageCreated) -> Option<StreamFrame>
+    where T: QueryMessageFn + QueryMessageCountsFn
+{
+    let channels = subscriptions.get_matching_channels(event);
+    if channels.is_empty() {
+        return None;
+    }
+
+    match load_message(db_repo, event.id).await {
+        Ok(Some(message)) => Some(StreamFrame::Message { channels, message }),
+        Ok(None) => None,
+        Err(e) => {
+            warn!("stream could not load message {}: {}", event.id, e);
+            None
+        }
+    }
+}
+
+async fn load_message<T>(db_repo: &T, id: i64) -> Result<Option<MessageResponder>, UserError>
+    where T: QueryMessageFn + QueryMessageCountsFn
+{
+    match db_repo.query_message(id).await? {
+        Some(message) => {
+            let counts = get_message_counts(db_repo, std::slice::from_ref(&message)).await?;
+            Ok(Some(convert(&message, &counts)))
+        }
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,169 @@
+use unicode_segmentation::UnicodeSegmentation;
+use crate::common::entities::messages::model::is_hashtag_char;
+use crate::routes::errors::error_utils::UserError;
+
+/// the most code points one user-perceived character may take. Long enough for a base with the 30 combining
+/// marks the Unicode Stream-Safe Text Format allows and for every emoji zwj sequence (at most 10). The
+/// database columns hold their MaxLength times this many code points, see the 0003 migration
+pub const MAX_CODE_POINTS_PER_CHAR: usize = 32;
+
+/// a single rule a field value must satisfy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Constraint {
+    /// value must be present and contain at least one non whitespace character
+    Required,
+    /// value may contain at most this many user-perceived characters (grapheme clusters),
+    /// none of them longer than MAX_CODE_POINTS_PER_CHAR code points
+    MaxLength(usize),
+    /// value, when present, contains at least this many user-perceived characters
+    MinLength(usize),
+    /// value, when present, is a hashtag without its #: ascii letters, digits and underscores
+    Hashtag,
+    /// value, when present, is an absolute http or https url with a host
+    HttpUrl,
+}
+
+/// declares which constraints apply to one field of an input
+pub struct FieldValidation<'a> {
+    pub field: &'static str,
+    pub value: Option<&'a str>,
+    pub constraints: &'static [Constraint],
+}
+
+impl<'a> FieldValidation<'a> {
+    pub fn new(field: &'static str, value: Option<&'a str>, constraints: &'static [Constraint]) -> Self {
+        Self { field, value, constraints }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.constraints.iter().all(|constraint| {
+            match (constraint, self.value) {
+                (Constraint::Required, Some(val)) => !val.trim().is_empty(),
+                (Constraint::Required, None) => false,
+                (Constraint::MaxLength(max), Some(val)) => {
+                    char_count(val) <= *max
+                        && val.graphemes(true).all(|grapheme| grapheme.chars().count() <= MAX_CODE_POINTS_PER_CHAR)
+                }
+                (Constraint::MaxLength(_), None) => true,
+                (Constraint::MinLength(min), Some(val)) => char_count(val) >= *min,
+                (Constraint::MinLength(_), None) => true,
+                (Constraint::Hashtag, Some(val)) => !val.is_empty() && val.chars().all(is_hashtag_char),
+                (Constraint::Hashtag, None) => true,
+                (Constraint::HttpUrl, Some(val)) => is_http_url(val),
+                (Constraint::HttpUrl, None) => true,
+            }
+        })
+    }
+}
+
+/// implemented by every request input so handlers can reject it before touching the repo
+pub trait Validate {
+    fn validations(&self) -> Vec<FieldValidation<'_>>;
+
+    /// checks every field and reports all failing field names at once, input is never truncated
+    fn validate(&self) -> Result<(), UserError> {
+        let failed_fields = self
+            .validations()
+            .iter()
+            .filter(|validation| !validation.is_valid())
+            .map(|validation| validation.field)
+            .collect::<Vec<&str>>();
+
+        if failed_fields.is_empty() {
+            Ok(())
+        } else {
+            Err(UserError::ValidationError { field: failed_fields.join(", ") })
+        }
+    }
+}
+
+/// counts user-perceived characters, so an emoji or an accented letter counts as one
+pub fn char_count(value: &str) -> usize {
+    value.graphemes(true).count()
+}
+
+fn is_http_url(value: &str) -> bool {
+    let Some(rest) = value.strip_prefix("https://").or_else(|| value.strip_prefix("http://")) else {
+        return false;
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    !host.is_empty() && !value.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestInput {
+        name: String,
+        bio: Option<String>,
+    }
+
+    impl Validate for TestInput {
+        fn validations(&self) -> Vec<FieldValidation<'_>> {
+            vec![
+                FieldValidation::new("name", Some(&self.name), &[Constraint::Required, Constraint::MaxLength(5)]),
+                FieldValidation::new("bio", self.bio.as_deref(), &[Constraint::MaxLength(3)]),
+            ]
+        }
+    }
+
+    #[test]
+    fn test_char_count_uses_graphemes() {
+        assert_eq!(char_count("abc"), 3);
+        assert_eq!(char_count("héllo"), 5);
+        assert_eq!(char_count("e\u{301}"), 1);
+        assert_eq!(char_count("👨‍👩‍👧"), 1);
+    }
+
+    #[test]
+    fn test_max_length_caps_code_points_per_character() {
+        let bio = |value: &str| FieldValidation::new("bio", Some(value), &[Constraint::MaxLength(3)]).is_valid();
+
+        assert!(bio("🇫🇷🇩🇪🇮🇹"));
+        // a kiss with two skin tones, the longest kind of emoji zwj sequence
+        assert!(bio(&"👩🏻\u{200d}❤\u{fe0f}\u{200d}💋\u{200d}👨🏼".repeat(3)));
+        assert!(bio(&format!("e{}", "\u{301}".repeat(MAX_CODE_POINTS_PER_CHAR - 1))));
+        assert!(!bio(&format!("e{}", "\u{301}".repeat(MAX_CODE_POINTS_PER_CHAR))));
+    }
+
+    #[test]
+    fn test_validate_passes_valid_input() {
+        let input = TestInput { name: "🎉🎉🎉🎉🎉".to_string(), bio: None };
+
+        assert!(input.validate().is_ok());
+    }
+
+    #[test]
+    fn test_hashtag_allows_word_characters_only() {
+        let hashtag = |value| FieldValidation::new("tag", value, &[Constraint::Hashtag]).is_valid();
+
+        assert!(hashtag(Some("rust_2023")));
+        assert!(hashtag(None));
+        assert!(!hashtag(Some("")));
+        assert!(!hashtag(Some("#rust")));
+        assert!(!hashtag(Some("rust%")));
+    }
+
+    #[test]
+    fn test_http_url_needs_a_scheme_and_host() {
+        let url = |value| FieldValidation::new("url", value, &[Constraint::HttpUrl]).is_valid();
+
+        assert!(url(Some("https://bots.example.com/hooks?team=1")));
+        assert!(url(Some("http://127.0.0.1:8080")));
+        assert!(url(None));
+        assert!(!url(Some("ftp://bots.example.com")));
+        assert!(!url(Some("https:///hooks")));
+        assert!(!url(Some("https://bots.example.com/a b")));
+    }
+
+    #[test]
+    fn test_validate_lists_every_failing_field() {
+        let input = TestInput { name: "  ".to_string(), bio: Some("abcd".to_string()) };
+
+        assert_eq!(
+            input.validate().err().unwrap(),
+            UserError::ValidationError { field: "name, bio".to_string() }
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use actix_http::body::BoxBody;
+use actix_web::{ Responder, HttpResponse, HttpRequest, http::header::ContentType };
+use chrono::{ DateTime, Utc };
+use serde::{ Deserialize, Serialize };
+use utoipa::ToSchema;
+use crate::common::entities::webhooks::model::{
+    WebhookDeliveryQueryResult,
+    WebhookDeliveryStatus,
+    WebhookEventType,
+    WebhookSubscriptionQueryResult,
+};
+use crate::routes::validation::validator::{ Constraint, FieldValidation, Validate };
+
+pub const WEBHOOK_URL_MAX_LENGTH: usize = 2048;
+pub const WEBHOOK_SECRET_MIN_LENGTH: usize = 16;
+pub const WEBHOOK_SECRET_MAX_LENGTH: usize = 256;
+pub const WEBHOOK_DELIVERIES_PAGE_SIZE: i16 = 20;
+pub const WEBHOOK_DELIVERIES_MAX_PAGE_SIZE: i16 = 100;
+
+#[derive(Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookSubscriptionJson {
+    /// receives a POST for every event of the subscribed types
+    pub url: String,
+    /// keys the X-Webhook-Signature of every delivery, it is never returned
+    pub secret: String,
+    pub event_types: Vec<WebhookEventType>,
+}
+
+impl Validate for WebhookSubscriptionJson {
+    fn validations(&self) -> Vec<FieldValidation<'_>> {
+        vec![
+            FieldValidation::new(
+                "url",
+                Some(&self.url),
+                &[Constraint::Required, Constraint::MaxLength(WEBHOOK_URL_MAX_LENGTH), Constraint::HttpUrl]
+            ),
+            FieldValidation::new(
+                "secret",
+                Some(&self.secret),
+                &[
+                    Constraint::Required,
+                    Constraint::MinLength(WEBHOOK_SECRET_MIN_LENGTH),
+                    Constraint::MaxLength(WEBHOOK_SECRET_MAX_LENGTH),
+                ]
+            ),
+        ]
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDeliveryQuery {
+    pub page_size: Option<i16>,
+}
+
+impl WebhookDeliveryQuery {
+    pub fn get_page_size(&self) -> i16 {
+        self.page_size.unwrap_or(WEBHOOK_DELIVERIES_PAGE_SIZE).clamp(1, WEBHOOK_DELIVERIES_MAX_PAGE_SIZE)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct WebhookPath {
+    pub id: i64,
+}
+
+#[derive(Deserialize)]
+pub struct WebhookDeliveryPath {
+    pub id: i64,
+    pub delivery_id: i64,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookSubscriptionResponder {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub url: String,
+    pub event_types: Vec<WebhookEventType>,
+}
+
+impl From<WebhookSubscriptionQueryResult> for WebhookSubscriptionResponder {
+    fn from(subscription: WebhookSubscriptionQueryResult) -> Self {
+        Self {
+            id: subscription.id,
+            created_at: subscription.created_at,
+            url: subscription.url,
+            event_types: WebhookEventType::from_mask(subscription.event_types),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct WebhookSubscriptionResponders(pub Vec<WebhookSubscriptionResponder>);
+
+impl Responder for WebhookSubscriptionResponders {
+    type Body = BoxBody;
+
+    fn respond_to(self, _: &HttpRequest) -> HttpResponse<Self::Body> {
+        let body_result = serde_json::to_string(&self);
+
+        match body_result {
+            Ok(body) => {
+                HttpResponse::Ok()
+                .content_type(ContentType::json())
+                .body(body)
+            },
+            Err(_) => {
+                HttpResponse::InternalServerError()
+                    .content_type(ContentType::json())
+                    .body("Failed to serialize WebhookSubscriptionResponders.")
+            },
+        }
+    }
+}
+
+/// one event sent to a subscription and how its attempts went
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDeliveryResponder {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub subscription_id: i64,
+    pub event_type: WebhookEventType,
+    /// the body that is posted, the event tagged by its type
+    #[schema(value_type = Object)]
+    pub payload: serde_json::Value,
+    pub status: WebhookDeliveryStatus,
+    pub attempt_count: i32,
+    /// when a pending delivery is retried
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    /// the subscriber's response to the last attempt, none when it could not be reached
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+}
+
+impl From<WebhookDeliveryQueryResult> for WebhookDeliveryResponder {
+    fn from(delivery: WebhookDeliveryQueryResult) -> Self {
+        Self {
+            id: delivery.id,
+            created_at: delivery.created_at,
+            subscription_id: delivery.subscription_id,
+            event_type: delivery.event_type,
+            // payloads are always serialized events, a string is kept rather than failing the listing
+            payload: serde_json::from_str(&delivery.payload).unwrap_or(serde_json::Value::String(delivery.payload)),
+            status: delivery.status,
+            attempt_count: delivery.attempt_count,
+            next_attempt_at: delivery.next_attempt_at,
+            last_attempt_at: delivery.last_attempt_at,
+            last_status_code: delivery.last_status_code,
+            last_error: delivery.last_error,
+        }
+    }
+}
+
+impl Responder for WebhookDeliveryResponder {
+    type Body = BoxBody;
+
+    fn respond_to(self, _: &HttpRequest) -> HttpResponse<Self::Body> {
+        let body_result = serde_json::to_string(&self);
+
+        match body_result {
+            Ok(body) => {
+                HttpResponse::Ok()
+                .content_type(ContentType::json())
+                .body(body)
+            },
+            Err(_) => {
+                HttpResponse::InternalServerError()
+                    .content_type(ContentType::json())
+                    .body("Failed to serialize WebhookDeliveryResponder.")
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct WebhookDeliveryResponders(pub Vec<WebhookDeliveryResponder>);
+
+impl Responder for WebhookDeliveryResponders {
+    type Body = BoxBody;
+
+    fn respond_to(self, _: &HttpRequest) -> HttpResponse<Self::Body> {
+        let body_result = serde_json::to_string(&self);
+
+        match body_result {
+            Ok(body) => {
+                HttpResponse::Ok()
+                .content_type(ContentType::json())
+                .body(body)
+            },
+            Err(_) => {
+                HttpResponse::InternalServerError()
+                    .content_type(ContentType::json())
+                    .body("Failed to serialize WebhookDeliveryResponders.")
+            },
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::common::{
+    app_state::AppState,
+    auth::AuthenticatedProfile,
+    entities::webhooks::{
+        model::{ WebhookEventType, WebhookSubscriptionCreate },
+        repo::{
+            InsertWebhookSubscriptionFn, QueryWebhookDeliveriesFn, QueryWebhookSubscriptionsFn, RecordWebhookAttemptFn,
+            ReplayWebhookDeliveryFn,
+        },
+    },
+    webhooks::{ check_subscriber_url, get_lease, send_webhook_delivery },
+};
+use crate::routes::{
+    errors::error_utils::UserError,
+    output_id::OutputId,
+    validation::validator::Validate,
+};
+use actix_web::{ web, web::{ Json, Path, Query } };
+use chrono::Utc;
+use tracing::instrument;
+use super::model::{
+    WebhookDeliveryPath,
+    WebhookDeliveryQuery,
+    WebhookDeliveryResponder,
+    WebhookDeliveryResponders,
+    WebhookPath,
+    WebhookSubscriptionJson,
+    WebhookSubscriptionResponder,
+    WebhookSubscriptionResponders,
+};
+
+/// another profile's subscription is reported as missing rather than forbidden
+async fn check_subscription_owner<T: QueryWebhookSubscriptionsFn>(
+    db_repo: &T,
+    user_id: i64,
+    subscription_id: i64
+) -> Result<(), UserError> {
+    let subscriptions = db_repo.query_webhook_subscriptions(user_id).await?;
+    if subscriptions.iter().any(|subscription| subscription.id == subscription_id) {
+        Ok(())
+    } else {
+        Err(UserError::NotFound)
+    }
+}
+
+#[allow(unused)]
+#[utoipa::path(
+    post,
+    path = "/v1/webhooks",
+    tag = "webhooks",
+    request_body = WebhookSubscriptionJson,
+    responses(
+        (status = 200, description = "Id of the new subscription", body = OutputId),
+        (status = 400, description = "Invalid url, secret or event types, or a url on a loopback, private or link-local host"),
+        (status = 401, description = "No valid bearer token"),
+    ),
+    security(("bearer" = []))
+)]
+#[instrument(skip_all)]
+pub async fn create_webhook<T: InsertWebhookSubscriptionFn>(
+    app_data: web::Data<AppState<T>>,
+    profile: AuthenticatedProfile,
+    params: Json<WebhookSubscriptionJson>
+) -> Result<OutputId, UserError> {
+    params.validate()?;
+    if params.event_types.is_empty() {
+        return Err(UserError::ValidationError { field: "eventTypes".to_string() });
+    }
+    if check_subscriber_url(&params.url, app_data.config.webhook.allow_private_hosts).is_err() {
+        return Err(UserError::ValidationError { field: "url".to_string() });
+    }
+
+    let id = app_data.db_repo
+        .insert_webhook_subscription(WebhookSubscriptionCreate {
+            user_id: profile.profile_id,
+            url: params.url.clone(),
+            secret: params.secret.clone(),
+            event_types: WebhookEventType::to_mask(&params.event_types),
+        }).await?;
+    Ok(OutputId { id })
+}
+
+#[allow(unused)]
+#[utoipa::path(
+    get,
+    path = "/v1/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "The authenticated profile's subscriptions, without their secrets", body = WebhookSubscriptionResponders),
+        (status = 401, description = "No valid bearer token"),
+    ),
+    security(("bearer" = []))
+)]
+#[instrument(skip_all)]
+pub async fn get_webhooks<T: QueryWebhookSubscriptionsFn>(
+    app_data: web::Data<AppState<T>>,
+    profile: AuthenticatedProfile
+) -> Result<WebhookSubscriptionResponders, UserError> {
+    let subscriptions = app_data.db_repo.query_webhook_subscriptions(profile.profile_id).await?;
+    Ok(WebhookSubscriptionResponders(subscriptions.into_iter().map(WebhookSubscriptionResponder::from).collect()))
+}
+
+#[allow(unused)]
+#[utoipa::path(
+    get,
+    path = "/v1/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    params(
+        ("id" = i64, Path, description = "Subscription id"),
+        ("pageSize" = Option<i16>, Query, description = "Deliveries to return, 20 by default and at most 100"),
+    ),
+    responses(
+        (status = 200, description = "The subscription's most recent deliveries, newest first", body = WebhookDeliveryResponders),
+        (status = 401, description = "No valid bearer token"),
+        (status = 404, description = "The profile has no such subscription"),
+    ),
+    security(("bearer" = []))
+)]
+#[instrument(skip_all)]
+pub async fn get_webhook_deliveries<T: QueryWebhookSubscriptionsFn + QueryWebhookDeliveriesFn>(
+    app_data: web::Data<AppState<T>>,
+    profile: AuthenticatedProfile,
+    path: Path<WebhookPath>,
+    query: Query<WebhookDeliveryQuery>
+) -> Result<WebhookDeliveryResponders, UserError> {
+    check_subscription_owner(&app_data.db_repo, profile.profile_id, path.id).await?;
+
+    let deliveries = app_data.db_repo.query_webhook_deliveries(path.id, query.get_page_size()).await?;
+    Ok(WebhookDeliveryResponders(deliveries.into_iter().map(WebhookDeliveryResponder::from).collect()))
+}
+
+#[allow(unused)]
+#[utoipa::path(
+    post,
+    path = "/v1/webhooks/{id}/deliveries/{delivery_id}/replay",
+    tag = "webhooks",
+    params(
+        ("id" = i64, Path, description = "Subscription id"),
+        ("delivery_id" = i64, Path, description = "The delivery to send again"),
+    ),
+    responses(
+        (status = 200, description = "The new delivery after its first attempt, a failed one is retried like any other", body = WebhookDeliveryResponder),
+        (status = 401, description = "No valid bearer token"),
+        (status = 404, description = "The profile has no such subscription or delivery"),
+    ),
+    security(("bearer" = []))
+)]
+#[instrument(skip_all)]
+pub async fn replay_webhook_delivery<T: QueryWebhookSubscriptionsFn + ReplayWebhookDeliveryFn + RecordWebhookAttemptFn>(
+    app_data: web::Data<AppState<T>>,
+    profile: AuthenticatedProfile,
+    path: Path<WebhookDeliveryPath>
+) -> Result<WebhookDeliveryResponder, UserError> {
+    check_subscription_owner(&app_data.db_repo, profile.profile_id, path.id).await?;
+
+    let config = &app_data.config.webhook;
+    let replayed = app_data.db_repo
+        .replay_webhook_delivery(path.id, path.delivery_id, Utc::now() + get_lease(config)).await?
+        .ok_or(UserError::NotFound)?;
+    let delivery = send_webhook_delivery(&app_data.db_repo, config, &replayed).await?
+        .ok_or(UserError::NotFound)?;
+    Ok(WebhookDeliveryResponder::from(delivery))
+}
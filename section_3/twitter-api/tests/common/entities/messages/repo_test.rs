@@ -1,14 +1,40 @@
 use twitter_clone_api::{
-    common_tests::actix_fixture::{ get_app_state, PUBLIC_GROUP_TYPE },
+    common_tests::actix_fixture::{ get_app_state, get_parity_repos, PUBLIC_GROUP_TYPE },
     common::entities::{
-        profiles::{ model::ProfileCreate, repo::{ InsertProfileFn } },
-        messages::repo::{ InsertMessageFn, QueryMessageFn }, base::DbRepo,
+        profiles::{ model::ProfileCreate, repo::{ InsertProfileFn, QueryProfileFn } },
+        messages::repo::{ InsertMessageFn, InsertResponseMessageFn, QueryMessageFn },
+        app_repo::AppRepo,
+        memory::MemoryRepo,
     },
 };
 
+/// every backend, a unit of work is implemented by each
+async fn get_unit_of_work_repos() -> Vec<AppRepo> {
+    let mut repos = get_parity_repos().await;
+    repos.push(AppRepo::Memory(MemoryRepo::new()));
+    repos
+}
+
+fn get_profile_create() -> ProfileCreate {
+    ProfileCreate {
+        user_name: "tester".to_string(),
+        full_name: "Dave Wave".to_string(),
+        description: "a description".to_string(),
+        region: Some("usa".to_string()),
+        main_url: Some("http://whatever.com".to_string()),
+        avatar: Some(vec![]),
+    }
+}
+
 #[tokio::test]
 async fn test_insert_message() {
-    let app_data = get_app_state(DbRepo::init().await).await;
+    for db_repo in get_parity_repos().await {
+        test_insert_message_body(db_repo).await;
+    }
+}
+
+async fn test_insert_message_body(db_repo: AppRepo) {
+    let app_data = get_app_state(db_repo).await;
     let db_repo = app_data.db_repo;
 
     const BODY: &str = "Test chatter post";
@@ -32,7 +58,13 @@ async fn test_insert_message() {
 
 #[tokio::test]
 async fn test_query_message() {
-    let app_data = get_app_state(DbRepo::init().await).await;
+    for db_repo in get_parity_repos().await {
+        test_query_message_body(db_repo).await;
+    }
+}
+
+async fn test_query_message_body(db_repo: AppRepo) {
+    let app_data = get_app_state(db_repo).await;
     let db_repo = app_data.db_repo;
 
     const BODY: &str = "Test chatter post";
@@ -53,5 +85,68 @@ async fn test_query_message() {
     assert!(message_id > 0);
 
     let message = db_repo.query_message(message_id).await.unwrap();
-    assert!(message.is_some() == true);
+    assert!(message.is_some());
+}
+
+#[tokio::test]
+async fn test_unit_of_work_commit_makes_all_writes_visible() {
+    for db_repo in get_unit_of_work_repos().await {
+        test_unit_of_work_commit_makes_all_writes_visible_body(db_repo).await;
+    }
+}
+
+async fn test_unit_of_work_commit_makes_all_writes_visible_body(db_repo: AppRepo) {
+    let uow = db_repo.begin().await.unwrap();
+    let profile_id = uow.insert_profile(get_profile_create()).await.unwrap();
+    let message_id = uow.insert_message(profile_id, "in a unit of work", PUBLIC_GROUP_TYPE, None).await.unwrap();
+    let response_id = uow
+        .insert_response_message(profile_id, "a reply", PUBLIC_GROUP_TYPE, message_id).await
+        .unwrap();
+
+    // reads inside the unit of work see its own writes, other callers do not yet
+    assert!(uow.query_message(message_id).await.unwrap().is_some());
+    assert!(db_repo.query_message(message_id).await.unwrap().is_none());
+
+    uow.commit().await.unwrap();
+
+    assert!(db_repo.query_profile(profile_id).await.unwrap().is_some());
+    assert!(db_repo.query_message(message_id).await.unwrap().is_some());
+    assert!(db_repo.query_message(response_id).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_unit_of_work_rollback_discards_all_writes() {
+    for db_repo in get_unit_of_work_repos().await {
+        test_unit_of_work_rollback_discards_all_writes_body(db_repo).await;
+    }
+}
+
+async fn test_unit_of_work_rollback_discards_all_writes_body(db_repo: AppRepo) {
+    let uow = db_repo.begin().await.unwrap();
+    let profile_id = uow.insert_profile(get_profile_create()).await.unwrap();
+    let message_id = uow.insert_message(profile_id, "rolled back", PUBLIC_GROUP_TYPE, None).await.unwrap();
+    uow.rollback().await.unwrap();
+
+    assert!(db_repo.query_profile(profile_id).await.unwrap().is_none());
+    assert!(db_repo.query_message(message_id).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_unit_of_work_survives_a_failed_operation() {
+    for db_repo in get_unit_of_work_repos().await {
+        test_unit_of_work_survives_a_failed_operation_body(db_repo).await;
+    }
+}
+
+async fn test_unit_of_work_survives_a_failed_operation_body(db_repo: AppRepo) {
+    let uow = db_repo.begin().await.unwrap();
+    let profile_id = uow.insert_profile(get_profile_create()).await.unwrap();
+    // the broadcast points at a message that does not exist, only that operation's savepoint is undone
+    let failed = uow.insert_message(profile_id, "bad broadcast", PUBLIC_GROUP_TYPE, Some(i64::MAX)).await;
+    assert!(failed.is_err());
+    let message_id = uow.insert_message(profile_id, "still fine", PUBLIC_GROUP_TYPE, None).await.unwrap();
+    uow.commit().await.unwrap();
+
+    assert!(db_repo.query_profile(profile_id).await.unwrap().is_some());
+    assert!(db_repo.query_message(message_id).await.unwrap().is_some());
 }
@@ -5,6 +5,25 @@ pub mod routes {
     pub mod profiles {
         pub mod profile_route_test;
     }
+    pub mod health {
+        pub mod health_route_test;
+    }
+    pub mod metrics {
+        pub mod metrics_route_test;
+    }
+    pub mod openapi {
+        pub mod openapi_route_test;
+    }
+    pub mod streams {
+        pub mod stream_route_test;
+        pub mod public_stream_route_test;
+    }
+    pub mod notifications {
+        pub mod notification_route_test;
+    }
+    pub mod webhooks {
+        pub mod webhook_route_test;
+    }
 }
 pub mod common {
     pub mod entities {
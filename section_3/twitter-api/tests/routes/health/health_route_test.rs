@@ -0,0 +1,32 @@
+use twitter_clone_api::{
+    routes::health::model::{ HealthResponder, VersionResponder },
+    common::migration::get_expected_version,
+    common_tests::actix_fixture::get_app,
+};
+use actix_web::test;
+
+#[tokio::test]
+async fn test_route_health_live_and_ready() {
+    let app = get_app().await;
+
+    let live_req = test::TestRequest::get().uri("/health/live").to_request();
+    let live_result = test::call_and_read_body_json::<_, _, HealthResponder>(&app, live_req).await;
+
+    let ready_req = test::TestRequest::get().uri("/health/ready").to_request();
+    let ready_result = test::call_and_read_body_json::<_, _, HealthResponder>(&app, ready_req).await;
+
+    assert!(live_result.status == "ok");
+    assert!(ready_result.status == "ready");
+    assert!(ready_result.schema_version == Some(get_expected_version()));
+}
+
+#[tokio::test]
+async fn test_route_version() {
+    let app = get_app().await;
+
+    let version_req = test::TestRequest::get().uri("/version").to_request();
+    let version_result = test::call_and_read_body_json::<_, _, VersionResponder>(&app, version_req).await;
+
+    assert!(version_result.version == env!("CARGO_PKG_VERSION"));
+    assert!(version_result.schema_version == get_expected_version());
+}
@@ -11,9 +11,21 @@ use twitter_clone_api::{
     common_tests::actix_fixture::get_app,
     routes::messages::model::MessageResponder,
 };
-use twitter_clone_api::routes::messages::model::{ MessagePostJson, MessageGroupTypes, MessageResponders, MessageByFollowingQuery };
-use actix_web::{ test, web::Json };
-use chrono::Utc;
+use twitter_clone_api::routes::messages::model::{ MessagePostJson, MessageGroupTypes };
+use twitter_clone_api::{
+    common::{
+        app_state::AppState,
+        entities::{
+            app_repo::AppRepo,
+            memory::MemoryRepo,
+            messages::repo::InsertMessageFn,
+            profiles::{ model::ProfileCreate, repo::{ InsertProfileFn, QueryProfileByUserFn } },
+        },
+    },
+    common_tests::actix_fixture::{ get_app_data, get_parity_repos, PUBLIC_GROUP_TYPE },
+    routes::errors::error_utils::UserError,
+};
+use actix_web::{ test, web, web::Json, App };
 
 #[tokio::test]
 pub async fn test_route_create_and_get_message() {
@@ -54,4 +66,44 @@ pub async fn test_route_create_and_get_message() {
     ).await;
 
     assert!(get_msg_body.unwrap().body.unwrap().eq(&msg_body));
-}
\ No newline at end of file
+}
+/// a profile and its first message written together, the message broadcasts user_name's missing message id
+async fn create_profile_with_message(
+    app_data: web::Data<AppState<AppRepo>>,
+    user_name: web::Path<String>
+) -> Result<OutputId, UserError> {
+    let uow = app_data.db_repo.begin().await?;
+    let profile_id = uow
+        .insert_profile(ProfileCreate {
+            user_name: user_name.into_inner(),
+            full_name: "Dave Wave".to_string(),
+            description: "a description".to_string(),
+            region: None,
+            main_url: None,
+            avatar: None,
+        }).await?;
+    uow.insert_message(profile_id, "hello", PUBLIC_GROUP_TYPE, Some(i64::MAX)).await?;
+    uow.commit().await?;
+    Ok(OutputId { id: profile_id })
+}
+
+#[tokio::test]
+pub async fn test_route_failing_after_a_write_rolls_its_unit_of_work_back() {
+    let mut repos = get_parity_repos().await;
+    repos.push(AppRepo::Memory(MemoryRepo::new()));
+    for db_repo in repos {
+        let app = test::init_service(
+            App::new()
+                .app_data(get_app_data(db_repo.clone()).await)
+                .route("/profile-with-message/{user_name}", web::post().to(create_profile_with_message))
+        ).await;
+        let user_name = Username().fake::<String>();
+
+        let req = test::TestRequest::post().uri(&format!("/profile-with-message/{}", user_name)).to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert!(res.status().is_server_error());
+        // the error returned before commit, so the profile written first is gone too
+        assert!(db_repo.query_profile_by_user(user_name).await.unwrap().is_none());
+    }
+}
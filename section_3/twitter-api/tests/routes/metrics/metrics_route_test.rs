@@ -0,0 +1,19 @@
+use twitter_clone_api::common_tests::actix_fixture::get_app;
+use actix_web::test;
+
+#[tokio::test]
+async fn test_route_metrics_reports_route_templates_pool_and_queries() {
+    let app = get_app().await;
+
+    let get_msg_req = test::TestRequest::get().uri("/v1/msg/1").to_request();
+    test::call_service(&app, get_msg_req).await;
+
+    let metrics_req = test::TestRequest::get().uri("/metrics").to_request();
+    let body = test::call_and_read_body(&app, metrics_req).await;
+    let metrics = String::from_utf8(body.to_vec()).unwrap();
+
+    assert!(metrics.contains(r#"route="/v1/msg/{id}""#));
+    assert!(!metrics.contains("/v1/msg/1\""));
+    assert!(metrics.contains(r#"db_pool_connections{state="max"}"#));
+    assert!(metrics.contains(r#"db_query_duration_seconds_count{function="query_message_inner"}"#));
+}
@@ -0,0 +1,104 @@
+use reqwest::{ header::AUTHORIZATION, StatusCode };
+use twitter_clone_api::{
+    common::entities::{
+        app_repo::AppRepo,
+        memory::MemoryRepo,
+        messages::repo::{ InsertMessageFn, InsertResponseMessageFn },
+        notifications::model::NotificationKind,
+        profiles::{ model::ProfileCreate, repo::{ FollowUserFn, InsertProfileFn } },
+    },
+    common_tests::actix_fixture::{ get_bearer, start_server, PUBLIC_GROUP_TYPE },
+    routes::{
+        messages::model::MessageLikeJson,
+        notifications::model::{ NotificationCountResponder, NotificationGroupResponders, NotificationsReadJson },
+    },
+};
+
+async fn insert_user(db_repo: &AppRepo, user_name: &str) -> i64 {
+    db_repo
+        .insert_profile(ProfileCreate {
+            user_name: user_name.to_string(),
+            full_name: "Dave Wave".to_string(),
+            description: "a description".to_string(),
+            region: None,
+            main_url: None,
+            avatar: None,
+        }).await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn test_route_likes_and_replies_notify_the_author_until_read() {
+    let db_repo = AppRepo::Memory(MemoryRepo::new());
+    let author_id = insert_user(&db_repo, "author").await;
+    let fan_id = insert_user(&db_repo, "fan").await;
+    let other_id = insert_user(&db_repo, "other").await;
+    let msg_id = db_repo.insert_message(author_id, "Like me", PUBLIC_GROUP_TYPE, None).await.unwrap();
+    db_repo.follow_user(fan_id, author_id).await.unwrap();
+    db_repo.insert_response_message(fan_id, "Agreed", PUBLIC_GROUP_TYPE, msg_id).await.unwrap();
+
+    let addr = start_server(db_repo.clone()).await;
+    let client = reqwest::Client::new();
+    for liker_id in [fan_id, other_id, fan_id] {
+        let response = client
+            .post(format!("http://{}/v1/msg/{}/like", addr, msg_id))
+            .json(&MessageLikeJson { user_id: liker_id })
+            .send().await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+    let missing = client
+        .post(format!("http://{}/v1/msg/{}/like", addr, msg_id + 1000))
+        .json(&MessageLikeJson { user_id: fan_id })
+        .send().await
+        .unwrap();
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+    let author = get_bearer(author_id);
+    let groups = client
+        .get(format!("http://{}/v1/notifications", addr))
+        .header(AUTHORIZATION, &author)
+        .send().await
+        .unwrap()
+        .json::<NotificationGroupResponders>().await
+        .unwrap().0;
+    assert_eq!(groups.len(), 3);
+    assert_eq!(groups[0].kind, NotificationKind::Like);
+    assert_eq!(groups[0].actor_count, 2);
+    assert_eq!(groups[0].latest_actor.id, other_id);
+    assert_eq!(groups[1].kind, NotificationKind::Reply);
+    assert_eq!(groups[2].kind, NotificationKind::Follow);
+
+    let unread_url = format!("http://{}/v1/notifications/unread", addr);
+    let unread = client.get(&unread_url).header(AUTHORIZATION, &author).send().await.unwrap();
+    assert_eq!(unread.json::<NotificationCountResponder>().await.unwrap().count, 4);
+    let fan_unread = client.get(&unread_url).header(AUTHORIZATION, get_bearer(fan_id)).send().await.unwrap();
+    assert_eq!(fan_unread.json::<NotificationCountResponder>().await.unwrap().count, 0);
+
+    let marked = client
+        .post(format!("http://{}/v1/notifications/read", addr))
+        .header(AUTHORIZATION, &author)
+        .json(&NotificationsReadJson { up_to_id: None })
+        .send().await
+        .unwrap()
+        .json::<NotificationCountResponder>().await
+        .unwrap();
+    assert_eq!(marked.count, 4);
+    let unread = client.get(&unread_url).header(AUTHORIZATION, &author).send().await.unwrap();
+    assert_eq!(unread.json::<NotificationCountResponder>().await.unwrap().count, 0);
+}
+
+#[actix_web::test]
+async fn test_route_notifications_need_a_valid_bearer_token() {
+    let addr = start_server(AppRepo::Memory(MemoryRepo::new())).await;
+    let client = reqwest::Client::new();
+    let url = format!("http://{}/v1/notifications", addr);
+
+    let anonymous = client.get(&url).send().await.unwrap();
+    assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(anonymous.headers()["www-authenticate"], "Bearer");
+    let as_user_id = client.get(format!("{}?userId=1", url)).send().await.unwrap();
+    assert_eq!(as_user_id.status(), StatusCode::UNAUTHORIZED);
+    let forged = client.get(&url).header(AUTHORIZATION, "Bearer 1.99999999999.00").send().await.unwrap();
+    assert_eq!(forged.status(), StatusCode::UNAUTHORIZED);
+}
@@ -4,8 +4,8 @@ use twitter_clone_api::{
     routes::{profiles::model::{ ProfileResponder }, output_id::OutputId},
     common_tests::actix_fixture::{ get_profile_create_multipart, get_profile_avatar },
 };
-use actix_web::{ test, http::header };
-use twitter_clone_api::common_tests::actix_fixture::get_app;
+use actix_web::{ test, http::{ header, StatusCode } };
+use twitter_clone_api::common_tests::actix_fixture::{ get_app, get_config };
 
 #[tokio::test]
 async fn test_route_create_profile_with_avatar() {
@@ -35,3 +35,61 @@ async fn test_route_create_profile_with_avatar() {
     assert!(get_profile_result.id == user_id_result.id);
     assert!(get_profile_result.avatar.unwrap() == avatar);
 }
+
+#[tokio::test]
+async fn test_route_create_profile_rejects_oversize_avatar() {
+    let app = get_app().await;
+    let config = get_config();
+    // over the avatar limit but under the body limit, so it is the field check that rejects it
+    let avatar = vec![0u8; config.upload.max_avatar_bytes + 1];
+    let boundary = Username().fake::<String>();
+    let payload = get_profile_create_multipart(&avatar, &boundary, true);
+
+    let header_value_string = format!("multipart/form-data; boundary={}", boundary);
+    let create_profile_req = test::TestRequest
+        ::post()
+        .append_header((header::CONTENT_TYPE, HeaderValue::from_str(&header_value_string).unwrap()))
+        .uri("/v1/profile")
+        .set_payload(payload)
+        .to_request();
+    let response = test::call_service(&app, create_profile_req).await;
+
+    assert!(response.status() == StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn test_route_get_profile_returns_not_modified_for_matching_etag() {
+    let app = get_app().await;
+    let boundary = Username().fake::<String>();
+    let payload = get_profile_create_multipart(&get_profile_avatar(), &boundary, true);
+    let header_value_string = format!("multipart/form-data; boundary={}", boundary);
+    let create_profile_req = test::TestRequest
+        ::post()
+        .append_header((header::CONTENT_TYPE, HeaderValue::from_str(&header_value_string).unwrap()))
+        .uri("/v1/profile")
+        .set_payload(payload)
+        .to_request();
+    let user_id_result = test::call_and_read_body_json::<_, _, OutputId>(&app, create_profile_req).await;
+
+    let get_profile_req = test::TestRequest
+        ::get()
+        .uri(&format!("/v1/profile/{}", user_id_result.id))
+        .append_header((header::ACCEPT_ENCODING, "gzip"))
+        .to_request();
+    let response = test::call_service(&app, get_profile_req).await;
+    assert!(response.status() == StatusCode::OK);
+    assert!(response.headers().get(header::CONTENT_ENCODING).unwrap() == "gzip");
+    let etag = response.headers().get(header::ETAG).unwrap().clone();
+    // the gzip and identity bodies share the tag, so it is weak
+    assert!(etag.to_str().unwrap().starts_with("W/"));
+
+    let cached_profile_req = test::TestRequest
+        ::get()
+        .uri(&format!("/v1/profile/{}", user_id_result.id))
+        .append_header((header::IF_NONE_MATCH, etag.clone()))
+        .to_request();
+    let response = test::call_service(&app, cached_profile_req).await;
+
+    assert!(response.status() == StatusCode::NOT_MODIFIED);
+    assert!(response.headers().get(header::ETAG).unwrap() == etag);
+}
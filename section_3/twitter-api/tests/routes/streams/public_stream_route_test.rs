@@ -0,0 +1,117 @@
+use reqwest::StatusCode;
+use std::time::Duration;
+use twitter_clone_api::{
+    common::entities::{
+        app_repo::AppRepo,
+        memory::MemoryRepo,
+        messages::repo::InsertMessageFn,
+        profiles::{ model::ProfileCreate, repo::InsertProfileFn },
+    },
+    common_tests::actix_fixture::{ start_server, CIRCLE_GROUP_TYPE, PUBLIC_GROUP_TYPE },
+    routes::{ messages::model::MessageResponder, streams::public_stream_route::LAST_EVENT_ID },
+};
+
+/// reads a text/event-stream response one event at a time, skipping keep-alive comments
+struct EventReader {
+    response: reqwest::Response,
+    buffer: String,
+}
+
+impl EventReader {
+    async fn next_event(&mut self) -> (i64, MessageResponder) {
+        loop {
+            if let Some(end) = self.buffer.find("\n\n") {
+                let event = self.buffer[..end].to_string();
+                self.buffer.drain(..end + 2);
+                let field = |name: &str| {
+                    event.lines().find_map(|line| line.strip_prefix(name)).map(|value| value.to_string())
+                };
+                if let (Some(id), Some(data)) = (field("id: "), field("data: ")) {
+                    return (id.parse().unwrap(), serde_json::from_str(&data).unwrap());
+                }
+                continue;
+            }
+
+            let chunk = tokio::time::timeout(Duration::from_secs(5), self.response.chunk()).await
+                .expect("no event within 5 seconds")
+                .unwrap()
+                .expect("stream ended");
+            self.buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+}
+
+async fn insert_user(db_repo: &AppRepo, user_name: &str) -> i64 {
+    db_repo
+        .insert_profile(ProfileCreate {
+            user_name: user_name.to_string(),
+            full_name: "Dave Wave".to_string(),
+            description: "a description".to_string(),
+            region: None,
+            main_url: None,
+            avatar: None,
+        }).await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn test_route_public_stream_replays_after_last_event_id_then_streams_tagged_messages() {
+    let db_repo = AppRepo::Memory(MemoryRepo::new());
+    let author_id = insert_user(&db_repo, "author").await;
+    let other_id = insert_user(&db_repo, "other").await;
+    let seen_id = db_repo.insert_message(author_id, "seen #rust", PUBLIC_GROUP_TYPE, None).await.unwrap();
+    let missed_id = db_repo.insert_message(author_id, "missed #Rust", PUBLIC_GROUP_TYPE, None).await.unwrap();
+    db_repo.insert_message(author_id, "missed #rustlang", PUBLIC_GROUP_TYPE, None).await.unwrap();
+
+    let addr = start_server(db_repo.clone()).await;
+    let response = reqwest::Client::new()
+        .get(format!("http://{}/v1/stream/public?tag=rust", addr))
+        .header(LAST_EVENT_ID, seen_id.to_string())
+        .send().await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("content-type").unwrap(), "text/event-stream");
+    let mut events = EventReader { response, buffer: String::new() };
+
+    db_repo.insert_message(other_id, "#rust in a circle", CIRCLE_GROUP_TYPE, None).await.unwrap();
+    db_repo.insert_message(other_id, "untagged", PUBLIC_GROUP_TYPE, None).await.unwrap();
+    let live_id = db_repo.insert_message(other_id, "live #rust", PUBLIC_GROUP_TYPE, None).await.unwrap();
+
+    let (id, message) = events.next_event().await;
+    assert_eq!((id, message.id), (missed_id, missed_id));
+    let (id, message) = events.next_event().await;
+    assert_eq!(id, live_id);
+    assert_eq!(message.body.as_deref(), Some("live #rust"));
+}
+
+#[actix_web::test]
+async fn test_route_public_stream_filters_by_author() {
+    let db_repo = AppRepo::Memory(MemoryRepo::new());
+    let author_id = insert_user(&db_repo, "author").await;
+    let other_id = insert_user(&db_repo, "other").await;
+
+    let addr = start_server(db_repo.clone()).await;
+    let response = reqwest::get(format!("http://{}/v1/stream/public?user_id={}", addr, author_id)).await.unwrap();
+    let mut events = EventReader { response, buffer: String::new() };
+
+    db_repo.insert_message(other_id, "by someone else", PUBLIC_GROUP_TYPE, None).await.unwrap();
+    let author_msg_id = db_repo.insert_message(author_id, "by the author", PUBLIC_GROUP_TYPE, None).await.unwrap();
+
+    assert_eq!(events.next_event().await.0, author_msg_id);
+}
+
+#[actix_web::test]
+async fn test_route_public_stream_rejects_invalid_tag_and_last_event_id() {
+    let addr = start_server(AppRepo::Memory(MemoryRepo::new())).await;
+    let client = reqwest::Client::new();
+
+    let invalid_tag = client.get(format!("http://{}/v1/stream/public?tag=%23rust", addr)).send().await.unwrap();
+    let invalid_id = client
+        .get(format!("http://{}/v1/stream/public", addr))
+        .header(LAST_EVENT_ID, "latest")
+        .send().await
+        .unwrap();
+
+    assert_eq!(invalid_tag.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(invalid_id.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,150 @@
+use futures_util::{ SinkExt, StreamExt };
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{ client::IntoClientRequest, http::{ header::AUTHORIZATION, Request }, Message },
+    MaybeTlsStream,
+    WebSocketStream,
+};
+use twitter_clone_api::{
+    common::entities::{
+        app_repo::AppRepo,
+        memory::MemoryRepo,
+        messages::repo::{ InsertMessageFn, InsertResponseMessageFn, LikeMessageFn },
+        notifications::model::NotificationKind,
+        profiles::{ model::ProfileCreate, repo::{ FollowUserFn, InsertProfileFn } },
+    },
+    common_tests::actix_fixture::{ get_bearer, start_server, PUBLIC_GROUP_TYPE },
+    routes::streams::model::{ StreamChannel, StreamFrame },
+};
+
+type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn insert_user(db_repo: &AppRepo, user_name: &str) -> i64 {
+    db_repo
+        .insert_profile(ProfileCreate {
+            user_name: user_name.to_string(),
+            full_name: "Dave Wave".to_string(),
+            description: "a description".to_string(),
+            region: None,
+            main_url: None,
+            avatar: None,
+        }).await
+        .unwrap()
+}
+
+async fn next_frame(client: &mut Client) -> StreamFrame {
+    loop {
+        let message = tokio::time::timeout(Duration::from_secs(5), client.next()).await
+            .expect("no frame within 5 seconds")
+            .unwrap()
+            .unwrap();
+        if let Message::Text(text) = message {
+            return serde_json::from_str(&text).unwrap();
+        }
+    }
+}
+
+/// the stream's handshake, with a bearer token acting as the profile when one is given
+fn get_stream_request(addr: std::net::SocketAddr, profile_id: Option<i64>) -> Request<()> {
+    let mut request = format!("ws://{}/v1/stream", addr).into_client_request().unwrap();
+    if let Some(profile_id) = profile_id {
+        request.headers_mut().insert(AUTHORIZATION, get_bearer(profile_id).parse().unwrap());
+    }
+    request
+}
+
+async fn next_notification(client: &mut Client) -> (i64, NotificationKind) {
+    match next_frame(client).await {
+        StreamFrame::Notification { notification } => (notification.actor_id, notification.kind),
+        frame => panic!("expected a notification, got {:?}", frame),
+    }
+}
+
+async fn subscribe(client: &mut Client, channel: &str) -> StreamFrame {
+    let command = format!(r#"{{"action":"subscribe","channel":{}}}"#, channel);
+    client.send(Message::Text(command)).await.unwrap();
+    next_frame(client).await
+}
+
+#[actix_web::test]
+async fn test_route_stream_delivers_home_notification_and_thread_messages() {
+    let db_repo = AppRepo::Memory(MemoryRepo::new());
+    let subscriber_id = insert_user(&db_repo, "subscriber").await;
+    let followed_id = insert_user(&db_repo, "followed").await;
+    let stranger_id = insert_user(&db_repo, "stranger").await;
+    db_repo.follow_user(subscriber_id, followed_id).await.unwrap();
+    let own_msg_id = db_repo.insert_message(subscriber_id, "my message", PUBLIC_GROUP_TYPE, None).await.unwrap();
+    let thread_msg_id = db_repo.insert_message(stranger_id, "a thread", PUBLIC_GROUP_TYPE, None).await.unwrap();
+
+    let addr = start_server(db_repo.clone()).await;
+    let (mut client, _) = connect_async(get_stream_request(addr, Some(subscriber_id))).await.unwrap();
+
+    assert!(matches!(subscribe(&mut client, r#"{"name":"home"}"#).await, StreamFrame::Subscribed { channel: StreamChannel::Home }));
+    assert!(matches!(subscribe(&mut client, r#"{"name":"notifications"}"#).await, StreamFrame::Subscribed { .. }));
+    let thread = format!(r#"{{"name":"thread","msgId":{}}}"#, thread_msg_id);
+    assert!(matches!(subscribe(&mut client, &thread).await, StreamFrame::Subscribed { .. }));
+
+    // neither followed nor about the subscriber, so it is skipped
+    db_repo.insert_message(stranger_id, "unrelated", PUBLIC_GROUP_TYPE, None).await.unwrap();
+    let home_msg_id = db_repo.insert_message(followed_id, "from a follow", PUBLIC_GROUP_TYPE, None).await.unwrap();
+    let reply_id = db_repo.insert_response_message(followed_id, "a reply", PUBLIC_GROUP_TYPE, own_msg_id).await.unwrap();
+    let thread_reply_id = db_repo.insert_response_message(stranger_id, "in thread", PUBLIC_GROUP_TYPE, thread_msg_id).await.unwrap();
+
+    match next_frame(&mut client).await {
+        StreamFrame::Message { channels, message } => {
+            assert_eq!(message.id, home_msg_id);
+            assert_eq!(channels, vec![StreamChannel::Home]);
+        }
+        frame => panic!("expected a message, got {:?}", frame),
+    }
+    // the reply's notification is written and published with it
+    assert_eq!(next_notification(&mut client).await, (followed_id, NotificationKind::Reply));
+    match next_frame(&mut client).await {
+        StreamFrame::Message { channels, message } => {
+            assert_eq!(message.id, reply_id);
+            assert_eq!(channels, vec![StreamChannel::Home]);
+        }
+        frame => panic!("expected a message, got {:?}", frame),
+    }
+    match next_frame(&mut client).await {
+        StreamFrame::Message { channels, message } => {
+            assert_eq!(message.id, thread_reply_id);
+            assert_eq!(channels, vec![StreamChannel::Thread { msg_id: thread_msg_id }]);
+        }
+        frame => panic!("expected a message, got {:?}", frame),
+    }
+}
+
+#[actix_web::test]
+async fn test_route_stream_sends_follow_like_and_mention_notifications_of_the_subscriber() {
+    let db_repo = AppRepo::Memory(MemoryRepo::new());
+    let subscriber_id = insert_user(&db_repo, "subscriber").await;
+    let other_id = insert_user(&db_repo, "other").await;
+    let stranger_id = insert_user(&db_repo, "stranger").await;
+    let own_msg_id = db_repo.insert_message(subscriber_id, "my message", PUBLIC_GROUP_TYPE, None).await.unwrap();
+
+    let addr = start_server(db_repo.clone()).await;
+    let (mut client, _) = connect_async(get_stream_request(addr, Some(subscriber_id))).await.unwrap();
+    assert!(matches!(subscribe(&mut client, r#"{"name":"notifications"}"#).await, StreamFrame::Subscribed { .. }));
+
+    // another profile's notification is skipped
+    db_repo.follow_user(stranger_id, other_id).await.unwrap();
+    db_repo.follow_user(stranger_id, subscriber_id).await.unwrap();
+    db_repo.like_message(stranger_id, own_msg_id).await.unwrap();
+    db_repo.insert_message(stranger_id, "hello @subscriber", PUBLIC_GROUP_TYPE, None).await.unwrap();
+
+    assert_eq!(next_notification(&mut client).await, (stranger_id, NotificationKind::Follow));
+    assert_eq!(next_notification(&mut client).await, (stranger_id, NotificationKind::Like));
+    assert_eq!(next_notification(&mut client).await, (stranger_id, NotificationKind::Mention));
+}
+
+#[actix_web::test]
+async fn test_route_stream_rejects_unauthenticated_and_unknown_subscribers() {
+    let addr = start_server(AppRepo::Memory(MemoryRepo::new())).await;
+
+    assert!(connect_async(get_stream_request(addr, None)).await.is_err());
+    assert!(connect_async(format!("ws://{}/v1/stream?user_id=1", addr)).await.is_err());
+    assert!(connect_async(get_stream_request(addr, Some(999))).await.is_err());
+}
@@ -0,0 +1,266 @@
+use actix_web::{ web, App, HttpRequest, HttpResponse, HttpServer };
+use reqwest::{ header::AUTHORIZATION, StatusCode };
+use std::{ net::SocketAddr, sync::{ Arc, Mutex }, time::Duration };
+use twitter_clone_api::{
+    common::{
+        entities::{
+            app_repo::AppRepo,
+            memory::MemoryRepo,
+            profiles::{ model::ProfileCreate, repo::{ FollowUserFn, InsertProfileFn } },
+            webhooks::{
+                model::{ WebhookDeliveryStatus, WebhookEventType, WebhookSubscriptionCreate },
+                repo::{ InsertWebhookSubscriptionFn, QueryWebhookDeliveriesFn },
+            },
+        },
+        shutdown::BackgroundTasks,
+        webhooks::{ sign_payload, WebhookDispatcher, DELIVERY_HEADER, EVENT_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER },
+    },
+    common_tests::actix_fixture::{ get_bearer, get_config, start_server, start_server_with_config },
+    routes::{
+        output_id::OutputId,
+        webhooks::model::{ WebhookDeliveryResponders, WebhookDeliveryResponder, WebhookSubscriptionJson },
+    },
+};
+
+const SECRET: &str = "a secret of sixteen or more";
+
+/// a request the stand-in subscriber received
+struct ReceivedRequest {
+    event: String,
+    delivery: String,
+    timestamp: i64,
+    signature: String,
+    body: String,
+}
+
+/// records every request and answers the first with a 500, so the dispatcher has to retry
+#[derive(Clone, Default)]
+struct StandInSubscriber {
+    received: Arc<Mutex<Vec<ReceivedRequest>>>,
+}
+
+async fn receive_webhook(subscriber: web::Data<StandInSubscriber>, req: HttpRequest, body: String) -> HttpResponse {
+    let header = |name: &str| req.headers().get(name).unwrap().to_str().unwrap().to_string();
+    let mut received = subscriber.received.lock().unwrap();
+    received.push(ReceivedRequest {
+        event: header(EVENT_HEADER),
+        delivery: header(DELIVERY_HEADER),
+        timestamp: header(TIMESTAMP_HEADER).parse().unwrap(),
+        signature: header(SIGNATURE_HEADER),
+        body,
+    });
+    if received.len() == 1 {
+        HttpResponse::InternalServerError().finish()
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
+
+fn start_stand_in_subscriber(subscriber: StandInSubscriber) -> SocketAddr {
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(subscriber.clone()))
+            .route("/hook", web::post().to(receive_webhook))
+    })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+    addr
+}
+
+async fn insert_user(db_repo: &AppRepo, user_name: &str) -> i64 {
+    db_repo
+        .insert_profile(ProfileCreate {
+            user_name: user_name.to_string(),
+            full_name: "Dave Wave".to_string(),
+            description: "a description".to_string(),
+            region: None,
+            main_url: None,
+            avatar: None,
+        }).await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn test_route_follow_is_delivered_signed_retried_and_replayed() {
+    let db_repo = AppRepo::Memory(MemoryRepo::new());
+    let owner_id = insert_user(&db_repo, "owner").await;
+    let follower_id = insert_user(&db_repo, "follower").await;
+    let subscriber = StandInSubscriber::default();
+    let subscriber_addr = start_stand_in_subscriber(subscriber.clone());
+
+    // the stand-in subscriber listens on loopback
+    let mut config = get_config();
+    config.webhook.allow_private_hosts = true;
+    config.webhook.retry_base_secs = 0;
+    config.webhook.poll_interval_ms = 50;
+    let dispatcher = WebhookDispatcher::new(db_repo.clone(), config.webhook.clone());
+    let mut tasks = BackgroundTasks::new();
+    tasks.spawn("webhook_dispatch", |shutdown| dispatcher.run(shutdown));
+
+    let addr = start_server_with_config(db_repo.clone(), config).await;
+    let client = reqwest::Client::new();
+    let owner = get_bearer(owner_id);
+    let anonymous = client
+        .post(format!("http://{}/v1/webhooks", addr))
+        .json(&WebhookSubscriptionJson {
+            url: format!("http://{}/hook", subscriber_addr),
+            secret: SECRET.to_string(),
+            event_types: vec![WebhookEventType::FollowCreated],
+        })
+        .send().await
+        .unwrap();
+    assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+    let not_a_url = client
+        .post(format!("http://{}/v1/webhooks", addr))
+        .header(AUTHORIZATION, &owner)
+        .json(&WebhookSubscriptionJson {
+            url: "localhost/hook".to_string(),
+            secret: SECRET.to_string(),
+            event_types: vec![WebhookEventType::FollowCreated],
+        })
+        .send().await
+        .unwrap();
+    assert_eq!(not_a_url.status(), StatusCode::BAD_REQUEST);
+    let subscription_id = client
+        .post(format!("http://{}/v1/webhooks", addr))
+        .header(AUTHORIZATION, &owner)
+        .json(&WebhookSubscriptionJson {
+            url: format!("http://{}/hook", subscriber_addr),
+            secret: SECRET.to_string(),
+            event_types: vec![WebhookEventType::FollowCreated],
+        })
+        .send().await
+        .unwrap()
+        .json::<OutputId>().await
+        .unwrap().id;
+    let listed = client
+        .get(format!("http://{}/v1/webhooks", addr))
+        .header(AUTHORIZATION, &owner)
+        .send().await
+        .unwrap()
+        .json::<serde_json::Value>().await
+        .unwrap();
+    assert_eq!(listed[0]["id"], subscription_id);
+    assert_eq!(listed[0]["eventTypes"], serde_json::json!(["followCreated"]));
+    assert!(listed[0].get("secret").is_none());
+
+    // only events involving the owner are delivered to the owner's subscription
+    let third_id = insert_user(&db_repo, "third").await;
+    db_repo.follow_user(third_id, follower_id).await.unwrap();
+    db_repo.follow_user(follower_id, owner_id).await.unwrap();
+
+    let deliveries_url = format!("http://{}/v1/webhooks/{}/deliveries", addr, subscription_id);
+    let mut deliveries = vec![];
+    for _ in 0..100 {
+        deliveries = client.get(&deliveries_url).header(AUTHORIZATION, &owner).send().await.unwrap().json::<WebhookDeliveryResponders>().await.unwrap().0;
+        if deliveries.first().map(|d| d.status) == Some(WebhookDeliveryStatus::Succeeded) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].status, WebhookDeliveryStatus::Succeeded);
+    assert_eq!(deliveries[0].attempt_count, 2);
+    assert_eq!(deliveries[0].last_status_code, Some(204));
+    assert_eq!(deliveries[0].payload["type"], "followCreated");
+    assert_eq!(deliveries[0].payload["follower_id"], follower_id);
+
+    {
+        let received = subscriber.received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        for request in received.iter() {
+            assert_eq!(request.event, "followCreated");
+            assert_eq!(request.delivery, deliveries[0].id.to_string());
+            assert_eq!(request.signature, format!("sha256={}", sign_payload(SECRET, request.timestamp, &request.body)));
+            assert_eq!(serde_json::from_str::<serde_json::Value>(&request.body).unwrap(), deliveries[0].payload);
+        }
+    }
+
+    let replay_url = format!("http://{}/v1/webhooks/{}/deliveries/{}/replay", addr, subscription_id, deliveries[0].id);
+    let stranger = get_bearer(follower_id);
+    let stranger_deliveries = client.get(&deliveries_url).header(AUTHORIZATION, &stranger).send().await.unwrap();
+    assert_eq!(stranger_deliveries.status(), StatusCode::NOT_FOUND);
+    let stranger_replay = client.post(&replay_url).header(AUTHORIZATION, &stranger).send().await.unwrap();
+    assert_eq!(stranger_replay.status(), StatusCode::NOT_FOUND);
+    let anonymous_replay = client.post(&replay_url).send().await.unwrap();
+    assert_eq!(anonymous_replay.status(), StatusCode::UNAUTHORIZED);
+
+    let replayed = client
+        .post(&replay_url)
+        .header(AUTHORIZATION, &owner)
+        .send().await
+        .unwrap()
+        .json::<WebhookDeliveryResponder>().await
+        .unwrap();
+    assert_ne!(replayed.id, deliveries[0].id);
+    assert_eq!(replayed.status, WebhookDeliveryStatus::Succeeded);
+    assert_eq!(replayed.attempt_count, 1);
+    assert_eq!(replayed.payload, deliveries[0].payload);
+    {
+        let received = subscriber.received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[2].delivery, replayed.id.to_string());
+    }
+
+    tasks.shutdown(Duration::from_secs(1)).await;
+}
+
+#[actix_web::test]
+async fn test_route_webhooks_refuse_private_hosts_when_subscribing_and_sending() {
+    let db_repo = AppRepo::Memory(MemoryRepo::new());
+    let owner_id = insert_user(&db_repo, "owner").await;
+    let follower_id = insert_user(&db_repo, "follower").await;
+    let addr = start_server(db_repo.clone()).await;
+    let client = reqwest::Client::new();
+    let owner = get_bearer(owner_id);
+
+    for url in ["http://127.0.0.1:8080/hook", "http://localhost/hook", "http://10.0.0.5/hook", "http://169.254.169.254/latest", "http://[::1]/hook"] {
+        let refused = client
+            .post(format!("http://{}/v1/webhooks", addr))
+            .header(AUTHORIZATION, &owner)
+            .json(&WebhookSubscriptionJson {
+                url: url.to_string(),
+                secret: SECRET.to_string(),
+                event_types: vec![WebhookEventType::FollowCreated],
+            })
+            .send().await
+            .unwrap();
+        assert_eq!(refused.status(), StatusCode::BAD_REQUEST, "{} is refused", url);
+    }
+
+    // a subscription written while private hosts were allowed is still not sent to
+    let subscriber = StandInSubscriber::default();
+    let subscriber_addr = start_stand_in_subscriber(subscriber.clone());
+    let subscription_id = db_repo
+        .insert_webhook_subscription(WebhookSubscriptionCreate {
+            user_id: owner_id,
+            url: format!("http://{}/hook", subscriber_addr),
+            secret: SECRET.to_string(),
+            event_types: WebhookEventType::to_mask(&[WebhookEventType::FollowCreated]),
+        }).await
+        .unwrap();
+    let mut config = get_config().webhook;
+    config.poll_interval_ms = 50;
+    let dispatcher = WebhookDispatcher::new(db_repo.clone(), config);
+    let mut tasks = BackgroundTasks::new();
+    tasks.spawn("webhook_dispatch", |shutdown| dispatcher.run(shutdown));
+
+    db_repo.follow_user(follower_id, owner_id).await.unwrap();
+    let mut deliveries = vec![];
+    for _ in 0..100 {
+        deliveries = db_repo.query_webhook_deliveries(subscription_id, 10).await.unwrap();
+        if deliveries.first().map(|d| d.attempt_count) == Some(1) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(deliveries[0].attempt_count, 1);
+    assert_eq!(deliveries[0].last_status_code, None);
+    assert!(deliveries[0].last_error.as_deref().unwrap().contains("is not a public host"));
+    assert!(subscriber.received.lock().unwrap().is_empty());
+
+    tasks.shutdown(Duration::from_secs(1)).await;
+}